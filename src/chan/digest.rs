@@ -0,0 +1,187 @@
+//! Batching of chat messages into periodic digests
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use futures::future::BoxFuture;
+use rorm::{query, Database, FieldAccess, Model};
+use uuid::Uuid;
+
+use crate::chan::{Notifier, NotifierError, WsMessage};
+use crate::models::ChatRoomMember;
+use crate::server::handler::ChatMessage;
+
+/// The messages of a chatroom an account hasn't received yet
+struct PendingDigest {
+    count: u64,
+    last_message: ChatMessage,
+}
+
+#[derive(Default)]
+struct DigestState {
+    /// The pairs of account and chatroom that receive digests
+    subscriptions: HashSet<(Uuid, Uuid)>,
+    /// The collected messages of the subscriptions
+    pending: HashMap<(Uuid, Uuid), PendingDigest>,
+}
+
+/// A [Notifier] that batches chat messages of chatrooms into digests
+///
+/// For every member that enabled digests for a chatroom, the [WsMessage::IncomingChatMessage]
+/// messages of the chatroom are held back and summarized in a [WsMessage::ChatDigest] message,
+/// once [ChatDigests::flush] is called. All other messages are passed to the wrapped notifier.
+pub struct ChatDigests {
+    inner: Arc<dyn Notifier>,
+    enabled: bool,
+    state: Mutex<DigestState>,
+}
+
+impl ChatDigests {
+    /// Wrap a notifier
+    ///
+    /// If `enabled` is not set, all messages are passed through and the
+    /// settings of the members are ignored.
+    pub fn new(inner: Arc<dyn Notifier>, enabled: bool) -> Self {
+        Self {
+            inner,
+            enabled,
+            state: Mutex::new(DigestState::default()),
+        }
+    }
+
+    /// Load the digest settings of all chatroom members from the database
+    pub async fn reload(&self, db: &Database) -> Result<(), rorm::Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let subscriptions = query!(
+            db,
+            (
+                ChatRoomMember::F.member.uuid,
+                ChatRoomMember::F.chat_room.uuid
+            )
+        )
+        .condition(ChatRoomMember::F.digest.equals(true))
+        .all()
+        .await?;
+
+        // Ok as the lock is never held across an await point or a panic
+        #[allow(clippy::unwrap_used)]
+        let mut state = self.state.lock().unwrap();
+        state.subscriptions = subscriptions.into_iter().collect();
+
+        Ok(())
+    }
+
+    /// Change the digest setting of an account for a chatroom
+    ///
+    /// If digests are disabled, the messages collected so far are sent immediately.
+    pub async fn set_digest(&self, account: Uuid, chat_room: Uuid, digest: bool) {
+        if !self.enabled {
+            return;
+        }
+
+        let pending = {
+            // Ok as the lock is never held across an await point or a panic
+            #[allow(clippy::unwrap_used)]
+            let mut state = self.state.lock().unwrap();
+            if digest {
+                state.subscriptions.insert((account, chat_room));
+                None
+            } else {
+                state.subscriptions.remove(&(account, chat_room));
+                state.pending.remove(&(account, chat_room))
+            }
+        };
+
+        if let Some(pending) = pending {
+            self.inner
+                .send(account, digest_message(chat_room, pending))
+                .await;
+        }
+    }
+
+    /// Send the collected messages as [WsMessage::ChatDigest] messages
+    pub async fn flush(&self) {
+        let pending = {
+            // Ok as the lock is never held across an await point or a panic
+            #[allow(clippy::unwrap_used)]
+            let mut state = self.state.lock().unwrap();
+            std::mem::take(&mut state.pending)
+        };
+
+        for ((account, chat_room), pending) in pending {
+            self.inner
+                .send(account, digest_message(chat_room, pending))
+                .await;
+        }
+    }
+
+    /// Collect the message, if the account receives digests for its chatroom
+    ///
+    /// Returns the message, if it has to be sent immediately.
+    fn collect(&self, account: Uuid, msg: WsMessage) -> Option<WsMessage> {
+        let WsMessage::IncomingChatMessage { chat_uuid, message } = msg else {
+            return Some(msg);
+        };
+
+        // Ok as the lock is never held across an await point or a panic
+        #[allow(clippy::unwrap_used)]
+        let mut state = self.state.lock().unwrap();
+        if !state.subscriptions.contains(&(account, chat_uuid)) {
+            return Some(WsMessage::IncomingChatMessage { chat_uuid, message });
+        }
+
+        state
+            .pending
+            .entry((account, chat_uuid))
+            .and_modify(|pending| {
+                pending.count += 1;
+                pending.last_message = message.clone();
+            })
+            .or_insert(PendingDigest {
+                count: 1,
+                last_message: message,
+            });
+
+        None
+    }
+}
+
+fn digest_message(chat_room: Uuid, pending: PendingDigest) -> WsMessage {
+    WsMessage::ChatDigest {
+        chat_uuid: chat_room,
+        count: pending.count,
+        last_message: pending.last_message,
+    }
+}
+
+impl Notifier for ChatDigests {
+    fn send(&self, account: Uuid, msg: WsMessage) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            if let Some(msg) = self.collect(account, msg) {
+                self.inner.send(account, msg).await;
+            }
+        })
+    }
+
+    fn is_online(&self, account: Uuid) -> BoxFuture<'_, Result<bool, NotifierError>> {
+        self.inner.is_online(account)
+    }
+
+    fn online_states(
+        &self,
+        accounts: Vec<Uuid>,
+    ) -> BoxFuture<'_, Result<Vec<bool>, NotifierError>> {
+        self.inner.online_states(accounts)
+    }
+
+    fn disconnect(&self, account: Uuid) -> BoxFuture<'_, ()> {
+        self.inner.disconnect(account)
+    }
+
+    fn broadcast_lobby_list(&self, msg: WsMessage) -> BoxFuture<'_, ()> {
+        self.inner.broadcast_lobby_list(msg)
+    }
+}
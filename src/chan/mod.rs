@@ -1,5 +1,11 @@
 //! This module holds definitions of channels that communicate cross task
 
+pub use digest::*;
+pub use notifier::*;
+pub use webhooks::*;
 pub use ws_manager_chan::*;
 
+mod digest;
+mod notifier;
+mod webhooks;
 mod ws_manager_chan;
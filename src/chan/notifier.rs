@@ -0,0 +1,280 @@
+//! Abstraction over the transport that delivers messages to accounts
+
+use std::fmt;
+
+use futures::future::BoxFuture;
+use log::warn;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::chan::{WsManagerChan, WsManagerMessage, WsMessage};
+
+/// The transport of a [Notifier] is not available anymore
+#[derive(Debug)]
+pub struct NotifierError(String);
+
+impl fmt::Display for NotifierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Notifier is not available: {}", self.0)
+    }
+}
+
+impl std::error::Error for NotifierError {}
+
+/// Delivers messages to the connected clients of accounts
+///
+/// The handlers and background tasks only use this trait, so the websocket manager
+/// can be replaced by other transports or by a recording implementation in tests.
+///
+/// The [WsManagerChan] is the implementation used by the server.
+pub trait Notifier: Send + Sync + 'static {
+    /// Send a message to all connections of an account
+    ///
+    /// Messages to accounts without a connection are dropped.
+    fn send(&self, account: Uuid, msg: WsMessage) -> BoxFuture<'_, ()>;
+
+    /// Send the same message to multiple accounts
+    fn broadcast(&self, accounts: Vec<Uuid>, msg: WsMessage) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            for account in accounts {
+                self.send(account, msg.clone()).await;
+            }
+        })
+    }
+
+    /// Check if an account has at least one active connection
+    fn is_online(&self, account: Uuid) -> BoxFuture<'_, Result<bool, NotifierError>>;
+
+    /// Check the online state of multiple accounts
+    ///
+    /// The states are returned in the order of `accounts`.
+    fn online_states(&self, accounts: Vec<Uuid>)
+        -> BoxFuture<'_, Result<Vec<bool>, NotifierError>>;
+
+    /// Close all connections of an account
+    fn disconnect(&self, account: Uuid) -> BoxFuture<'_, ()>;
+
+    /// Send a [WsMessage::LobbyListUpdated] message to all connections that subscribed
+    /// to the lobby list
+    fn broadcast_lobby_list(&self, msg: WsMessage) -> BoxFuture<'_, ()>;
+}
+
+impl Notifier for WsManagerChan {
+    fn send(&self, account: Uuid, msg: WsMessage) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            if let Err(err) =
+                WsManagerChan::send(self, WsManagerMessage::SendMessage(account, msg)).await
+            {
+                warn!("Could not send to ws manager chan: {err}");
+            }
+        })
+    }
+
+    fn is_online(&self, account: Uuid) -> BoxFuture<'_, Result<bool, NotifierError>> {
+        Box::pin(async move {
+            let (tx, rx) = oneshot::channel();
+            WsManagerChan::send(self, WsManagerMessage::RetrieveOnlineState(account, tx))
+                .await
+                .map_err(|err| NotifierError(err.to_string()))?;
+            rx.await.map_err(|err| NotifierError(err.to_string()))
+        })
+    }
+
+    fn online_states(
+        &self,
+        accounts: Vec<Uuid>,
+    ) -> BoxFuture<'_, Result<Vec<bool>, NotifierError>> {
+        Box::pin(async move {
+            let (tx, rx) = oneshot::channel();
+            WsManagerChan::send(self, WsManagerMessage::RetrieveOnlineStates(accounts, tx))
+                .await
+                .map_err(|err| NotifierError(err.to_string()))?;
+            rx.await.map_err(|err| NotifierError(err.to_string()))
+        })
+    }
+
+    fn disconnect(&self, account: Uuid) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            if let Err(err) =
+                WsManagerChan::send(self, WsManagerMessage::CloseSocket(account)).await
+            {
+                warn!("Could not send to ws manager chan: {err}");
+            }
+        })
+    }
+
+    fn broadcast_lobby_list(&self, msg: WsMessage) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            if let Err(err) =
+                WsManagerChan::send(self, WsManagerMessage::BroadcastLobbyList(msg)).await
+            {
+                warn!("Could not send to ws manager chan: {err}");
+            }
+        })
+    }
+}
+
+/// A [Notifier] that records all messages instead of delivering them
+///
+/// The accounts in `online` are reported as online.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct RecordingNotifier {
+    pub(crate) online: Vec<Uuid>,
+    pub(crate) sent: std::sync::Mutex<Vec<(Uuid, WsMessage)>>,
+    pub(crate) lobby_list: std::sync::Mutex<Vec<WsMessage>>,
+    pub(crate) disconnected: std::sync::Mutex<Vec<Uuid>>,
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+impl RecordingNotifier {
+    /// The messages that were sent to an account
+    pub(crate) fn sent_to(&self, account: Uuid) -> Vec<WsMessage> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(to, _)| *to == account)
+            .map(|(_, msg)| msg.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+impl Notifier for RecordingNotifier {
+    fn send(&self, account: Uuid, msg: WsMessage) -> BoxFuture<'_, ()> {
+        self.sent.lock().unwrap().push((account, msg));
+        Box::pin(async {})
+    }
+
+    fn is_online(&self, account: Uuid) -> BoxFuture<'_, Result<bool, NotifierError>> {
+        let online = self.online.contains(&account);
+        Box::pin(async move { Ok(online) })
+    }
+
+    fn online_states(
+        &self,
+        accounts: Vec<Uuid>,
+    ) -> BoxFuture<'_, Result<Vec<bool>, NotifierError>> {
+        let states = accounts.iter().map(|x| self.online.contains(x)).collect();
+        Box::pin(async move { Ok(states) })
+    }
+
+    fn disconnect(&self, account: Uuid) -> BoxFuture<'_, ()> {
+        self.disconnected.lock().unwrap().push(account);
+        Box::pin(async {})
+    }
+
+    fn broadcast_lobby_list(&self, msg: WsMessage) -> BoxFuture<'_, ()> {
+        self.lobby_list.lock().unwrap().push(msg);
+        Box::pin(async {})
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{Notifier, RecordingNotifier};
+    use crate::chan::WsMessage;
+    use crate::service::game::start_game;
+    use crate::service::notify::{NotificationSink, Outbox};
+    use crate::service::testing::{create_account, create_lobby, test_db};
+
+    #[tokio::test]
+    async fn outbox_is_sent_through_the_notifier() {
+        let account = Uuid::new_v4();
+        let game_uuid = Uuid::new_v4();
+        let notifier = RecordingNotifier::default();
+
+        let mut outbox = Outbox::new();
+        outbox.notify(
+            account,
+            WsMessage::YourTurn {
+                game_uuid,
+                game_data_id: 1,
+                note: None,
+            },
+        );
+        assert!(notifier.sent.lock().unwrap().is_empty());
+
+        outbox.send(&notifier).await;
+        let sent = notifier.sent_to(account);
+        assert_eq!(sent.len(), 1);
+        assert!(matches!(
+            &sent[0],
+            WsMessage::YourTurn { game_uuid: x, .. } if *x == game_uuid
+        ));
+    }
+
+    #[tokio::test]
+    async fn online_states_follow_the_order_of_the_accounts() {
+        let online = Uuid::new_v4();
+        let offline = Uuid::new_v4();
+        let notifier = RecordingNotifier {
+            online: vec![online],
+            ..Default::default()
+        };
+
+        assert!(notifier.is_online(online).await.unwrap());
+        assert_eq!(
+            notifier.online_states(vec![offline, online]).await.unwrap(),
+            vec![false, true]
+        );
+    }
+
+    #[tokio::test]
+    async fn lobby_list_and_disconnects_are_recorded() {
+        let account = Uuid::new_v4();
+        let lobby_uuid = Uuid::new_v4();
+        let notifier = RecordingNotifier::default();
+
+        let mut outbox = Outbox::new();
+        outbox.notify_lobby_list(WsMessage::LobbyClosed { lobby_uuid });
+        outbox.send(&notifier).await;
+        notifier.disconnect(account).await;
+
+        let lobby_list = notifier.lobby_list.lock().unwrap();
+        assert_eq!(lobby_list.len(), 1);
+        assert!(matches!(
+            &lobby_list[0],
+            WsMessage::LobbyClosed { lobby_uuid: x } if *x == lobby_uuid
+        ));
+        assert_eq!(*notifier.disconnected.lock().unwrap(), vec![account]);
+        assert!(notifier.sent.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs RUNCIV_TEST_CONFIG"]
+    async fn started_game_is_delivered_after_the_commit() {
+        let db = test_db().await;
+        let mut tx = db.start_transaction().await.unwrap();
+
+        let owner = create_account(&mut tx).await;
+        let player = create_account(&mut tx).await;
+        let lobby = create_lobby(&mut tx, owner, &[player], 4).await;
+
+        let notifier = RecordingNotifier::default();
+        let mut outbox = Outbox::new();
+        let started = start_game(&mut tx, &mut outbox, lobby, owner)
+            .await
+            .unwrap();
+        assert!(notifier.sent.lock().unwrap().is_empty());
+
+        // The transaction is dropped without a commit, so the test leaves no data behind
+        drop(tx);
+        outbox.send(&notifier).await;
+
+        let sent = notifier.sent_to(player);
+        assert_eq!(sent.len(), 1);
+        assert!(matches!(
+            &sent[0],
+            WsMessage::GameStarted { game_uuid, .. } if *game_uuid == started.game_uuid
+        ));
+        assert!(notifier.sent_to(owner).is_empty());
+        assert_eq!(notifier.lobby_list.lock().unwrap().len(), 1);
+    }
+}
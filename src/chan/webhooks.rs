@@ -0,0 +1,354 @@
+//! Delivery of the events of accounts to their personal webhooks
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use log::{debug, warn};
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use rorm::{query, Database, FieldAccess, Model};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::chan::{Notifier, NotifierError, WsEnvelope, WsMessage};
+use crate::config::AccountWebhooksConfig;
+use crate::models::AccountWebhook;
+
+/// The header that holds the signature of a delivery
+pub const SIGNATURE_HEADER: &str = "X-Runciv-Signature";
+
+/// The header that holds the unix timestamp of a delivery
+pub const TIMESTAMP_HEADER: &str = "X-Runciv-Timestamp";
+
+/// The window the deliveries of an account are counted in
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// The maximum length of the url of a webhook
+const MAX_URL_LENGTH: usize = 2048;
+
+/// Check if an address may be the target of a webhook
+///
+/// Loopback, private, link-local, unique-local, shared and unspecified addresses are
+/// rejected, so webhooks can't be used to reach the network of the server.
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // The shared address space of carrier-grade NATs, 100.64.0.0/10
+                || (first == 100 && (second & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_address(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local addresses, fc00::/7
+                    || (first & 0xfe00) == 0xfc00
+                    // Link-local addresses, fe80::/10
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// The checked target of a webhook
+pub struct WebhookTarget {
+    url: Url,
+    addr: SocketAddr,
+}
+
+impl WebhookTarget {
+    /// Parse the url of a webhook and resolve its host
+    ///
+    /// The url must be a https url with at most 2048 characters. All addresses its host
+    /// resolves to must be public, loopback, private, link-local, unique-local, shared and
+    /// unspecified addresses are rejected.
+    ///
+    /// Returns `None` if the url is invalid or its host can't be resolved.
+    pub async fn resolve(url: &str) -> Option<Self> {
+        if url.len() > MAX_URL_LENGTH {
+            return None;
+        }
+        let url = Url::parse(url).ok()?;
+        if url.scheme() != "https" || url.host_str().is_none() {
+            return None;
+        }
+
+        let lookup = url.clone();
+        let addrs = match tokio::task::spawn_blocking(move || lookup.socket_addrs(|| None)).await {
+            Ok(Ok(addrs)) => addrs,
+            Ok(Err(err)) => {
+                debug!("Could not resolve the host of the webhook {url}: {err}");
+                return None;
+            }
+            Err(err) => {
+                warn!("Could not resolve the host of the webhook {url}: {err}");
+                return None;
+            }
+        };
+        if !addrs.iter().all(|addr| is_public_address(addr.ip())) {
+            debug!("The webhook {url} resolves to an address that is not public");
+            return None;
+        }
+        let addr = *addrs.first()?;
+
+        Some(Self { url, addr })
+    }
+
+    /// Build a client that connects to the checked address only
+    ///
+    /// The host isn't resolved again and redirects are not followed, so the delivery
+    /// can't be diverted to an address that was not checked.
+    fn client(&self) -> reqwest::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder().redirect(Policy::none());
+        if let Some(host) = self.url.host_str() {
+            builder = builder.resolve(host, self.addr);
+        }
+        builder.build()
+    }
+}
+
+/// Check if a message is posted to the webhooks of its recipients
+fn is_webhook_event(msg: &WsMessage) -> bool {
+    matches!(
+        msg,
+        WsMessage::YourTurn { .. }
+            | WsMessage::IncomingInvite { .. }
+            | WsMessage::IncomingGameInvite { .. }
+    )
+}
+
+/// Sign the body of a delivery
+///
+/// The signature is the hex encoded HMAC-SHA256 of `{timestamp}.{body}` with the secret
+/// of the webhook as key.
+pub fn sign_delivery(secret: &str, timestamp: i64, body: &str) -> String {
+    // Ok as HMAC accepts keys of any length
+    #[allow(clippy::unwrap_used)]
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+struct WebhookState {
+    db: Database,
+    config: AccountWebhooksConfig,
+    /// The start of the current window and the number of deliveries in it per account
+    deliveries: Mutex<HashMap<Uuid, (Instant, u32)>>,
+}
+
+impl WebhookState {
+    /// Count a delivery to the webhook of an account
+    ///
+    /// Returns `false` if the account reached the limit of the current window.
+    fn try_count(&self, account: Uuid) -> bool {
+        let now = Instant::now();
+
+        // Ok as the lock is never held across an await point or a panic
+        #[allow(clippy::unwrap_used)]
+        let mut deliveries = self.deliveries.lock().unwrap();
+        deliveries.retain(|_, (start, _)| now.duration_since(*start) < RATE_LIMIT_WINDOW);
+
+        let (_, count) = deliveries.entry(account).or_insert((now, 0));
+        if *count >= self.config.max_deliveries_per_hour {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    /// Post a message to the webhook of an account, if it registered one
+    async fn deliver(&self, account: Uuid, msg: WsMessage) {
+        let webhook = match query!(&self.db, (AccountWebhook::F.url, AccountWebhook::F.secret))
+            .condition(AccountWebhook::F.account.equals(account))
+            .optional()
+            .await
+        {
+            Ok(Some(webhook)) => webhook,
+            Ok(None) => return,
+            Err(err) => {
+                warn!("Database error: {err}");
+                return;
+            }
+        };
+        let (url, secret) = webhook;
+
+        if !self.try_count(account) {
+            debug!("Skipped webhook delivery for account {account}, the rate limit is reached");
+            return;
+        }
+
+        // The host is checked again, as it may resolve to another address by now
+        let Some(target) = WebhookTarget::resolve(&url).await else {
+            debug!("Skipped webhook delivery for account {account}, the url {url} is not allowed");
+            return;
+        };
+        let client = match target.client() {
+            Ok(client) => client,
+            Err(err) => {
+                warn!("Could not build the webhook client: {err}");
+                return;
+            }
+        };
+
+        let body = match serde_json::to_string(&WsEnvelope::new(msg)) {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("Could not serialize webhook event: {err}");
+                return;
+            }
+        };
+        let timestamp = Utc::now().timestamp();
+
+        let res = client
+            .post(target.url)
+            .timeout(Duration::from_secs(self.config.timeout))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(
+                SIGNATURE_HEADER,
+                format!("sha256={}", sign_delivery(&secret, timestamp, &body)),
+            )
+            .body(body)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        if let Err(err) = res {
+            debug!("Webhook delivery for account {account} failed: {err}");
+        }
+    }
+}
+
+/// A [Notifier] that posts the turn and invite events of accounts to their webhooks
+///
+/// All messages are passed to the wrapped notifier. [WsMessage::YourTurn],
+/// [WsMessage::IncomingInvite] and [WsMessage::IncomingGameInvite] messages are posted
+/// to the webhook of the recipient in addition, if it registered one.
+///
+/// Deliveries run in the background and are not retried. They are only sent to public
+/// addresses and don't follow redirects, see [WebhookTarget].
+pub struct AccountWebhooks {
+    inner: Arc<dyn Notifier>,
+    state: Option<Arc<WebhookState>>,
+}
+
+impl AccountWebhooks {
+    /// Wrap a notifier
+    ///
+    /// If `config` is not set, all messages are only passed through.
+    pub fn new(
+        inner: Arc<dyn Notifier>,
+        db: Database,
+        config: Option<AccountWebhooksConfig>,
+    ) -> Self {
+        Self {
+            inner,
+            state: config.map(|config| {
+                Arc::new(WebhookState {
+                    db,
+                    config,
+                    deliveries: Mutex::new(HashMap::new()),
+                })
+            }),
+        }
+    }
+}
+
+impl Notifier for AccountWebhooks {
+    fn send(&self, account: Uuid, msg: WsMessage) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            if let Some(state) = &self.state {
+                if is_webhook_event(&msg) {
+                    let state = state.clone();
+                    let msg = msg.clone();
+                    tokio::spawn(async move { state.deliver(account, msg).await });
+                }
+            }
+
+            self.inner.send(account, msg).await;
+        })
+    }
+
+    fn is_online(&self, account: Uuid) -> BoxFuture<'_, Result<bool, NotifierError>> {
+        self.inner.is_online(account)
+    }
+
+    fn online_states(
+        &self,
+        accounts: Vec<Uuid>,
+    ) -> BoxFuture<'_, Result<Vec<bool>, NotifierError>> {
+        self.inner.online_states(accounts)
+    }
+
+    fn disconnect(&self, account: Uuid) -> BoxFuture<'_, ()> {
+        self.inner.disconnect(account)
+    }
+
+    fn broadcast_lobby_list(&self, msg: WsMessage) -> BoxFuture<'_, ()> {
+        self.inner.broadcast_lobby_list(msg)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{is_public_address, WebhookTarget};
+
+    #[test]
+    fn internal_addresses_are_rejected() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.178.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_address(ip.parse::<IpAddr>().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn public_addresses_are_accepted() {
+        for ip in ["1.1.1.1", "100.128.0.1", "2606:4700:4700::1111"] {
+            assert!(is_public_address(ip.parse::<IpAddr>().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn only_public_https_urls_are_targets() {
+        assert!(WebhookTarget::resolve("http://1.1.1.1/hook")
+            .await
+            .is_none());
+        assert!(WebhookTarget::resolve("https://127.0.0.1/hook")
+            .await
+            .is_none());
+        assert!(WebhookTarget::resolve("https://[::1]:8443/hook")
+            .await
+            .is_none());
+        assert!(WebhookTarget::resolve("https://1.1.1.1/hook")
+            .await
+            .is_some());
+    }
+}
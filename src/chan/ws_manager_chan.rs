@@ -1,21 +1,77 @@
-use std::collections::HashMap;
-use std::iter;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use actix_toolbox::ws;
 use actix_toolbox::ws::{MailboxError, Message};
+use chrono::{DateTime, Utc};
 use log::{debug, error, info, warn};
 use rorm::{and, delete, query, Database, FieldAccess, Model};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Sender;
 use tokio::sync::{mpsc, oneshot};
 use tokio::task;
+use utoipa::IntoParams;
 use uuid::Uuid;
 
-use crate::models::{Account, ChatRoom, ChatRoomMember, Lobby, LobbyAccount};
-use crate::server::handler::{AccountResponse, ChatMessage};
+use crate::models::{
+    Account, Badge, ChatRoom, ChatRoomMember, Friend, Lobby, LobbyAccount, NegotiationKind,
+    NegotiationState,
+};
+use crate::server::handler::{AccountResponse, ChatMessage, GameSettingsResponse, PlayerNation};
+use crate::service::lobby_list::announce;
+use crate::service::membership::LobbyMembers;
+use crate::service::notify::Outbox;
 
-pub(crate) async fn start_ws_sender(tx: ws::Sender, mut rx: mpsc::Receiver<WsMessage>) {
+/// The options a client chose when opening a connection
+#[derive(Deserialize, IntoParams, Copy, Clone, Debug, Default)]
+#[into_params(parameter_in = Query)]
+pub struct ConnectionOptions {
+    /// Don't send [WsMessage::UpdateGameData] messages through this connection
+    ///
+    /// The connection still receives the compact [WsMessage::GameMetaChanged] messages.
+    #[serde(default)]
+    pub compact_game_updates: bool,
+}
+
+impl ConnectionOptions {
+    /// Check if a message should be sent through a connection with these options
+    pub fn wants(&self, msg: &WsMessage) -> bool {
+        !(self.compact_game_updates && matches!(msg, WsMessage::UpdateGameData { .. }))
+    }
+}
+
+/// The subscriptions a client can change while its connection is open
+///
+/// In contrast to the subscriptions of an account, e.g. to the presence of other accounts,
+/// these only apply to the connection they were made on.
+#[derive(Clone, Default, Debug)]
+pub struct ConnectionSubscriptions {
+    lobby_list: Arc<AtomicBool>,
+}
+
+impl ConnectionSubscriptions {
+    /// Subscribe the connection to [WsMessage::LobbyListUpdated] messages or end the subscription
+    pub fn set_lobby_list(&self, subscribed: bool) {
+        self.lobby_list.store(subscribed, Ordering::Relaxed);
+    }
+
+    /// Check if the connection is subscribed to [WsMessage::LobbyListUpdated] messages
+    pub fn lobby_list(&self) -> bool {
+        self.lobby_list.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) async fn start_ws_sender(
+    tx: ws::Sender,
+    mut rx: mpsc::Receiver<WsMessage>,
+    options: ConnectionOptions,
+) {
     while let Some(msg) = rx.recv().await {
+        if !options.wants(&msg) {
+            continue;
+        }
+
         match msg {
             WsMessage::ServerQuitSocket => {
                 if let Err(err) = tx.close().await {
@@ -28,7 +84,7 @@ pub(crate) async fn start_ws_sender(tx: ws::Sender, mut rx: mpsc::Receiver<WsMes
                 break;
             }
             _ => {
-                let txt = match serde_json::to_string(&msg) {
+                let txt = match serde_json::to_string(&WsEnvelope::new(msg)) {
                     Ok(v) => v,
                     Err(err) => {
                         error!("Error serializing WsMessage: {err}");
@@ -67,9 +123,55 @@ pub enum FriendshipEvent {
     Deleted,
 }
 
+/// The changes of a lobby in the list of open lobbies
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum LobbyListChange {
+    /// The lobby was created or became visible
+    Created,
+    /// The lobby was closed, started or hidden
+    Closed,
+    /// The number of players or the maximum number of players of the lobby changed
+    Updated,
+}
+
+/// The version of the websocket protocol
+///
+/// This is incremented whenever the meaning of an existing [WsMessage] changes.
+/// Adding new variants doesn't require a new version, as clients are expected to
+/// ignore message types they don't know.
+pub const WS_PROTOCOL_VERSION: u32 = 1;
+
+/// The envelope every [WsMessage] is wrapped in before it is sent to a client
+///
+/// The fields of the message (`type` and `content`) are flattened into the envelope,
+/// so clients that don't know the envelope can still read the message.
+#[derive(Serialize)]
+pub struct WsEnvelope {
+    /// The version of the protocol, see [WS_PROTOCOL_VERSION]
+    pub version: u32,
+    /// A unique identifier of this message
+    pub id: Uuid,
+    /// The message itself
+    #[serde(flatten)]
+    pub message: WsMessage,
+}
+
+impl WsEnvelope {
+    /// Wrap a [WsMessage] in a new envelope
+    pub fn new(message: WsMessage) -> Self {
+        Self {
+            version: WS_PROTOCOL_VERSION,
+            id: Uuid::new_v4(),
+            message,
+        }
+    }
+}
+
 /// Message that is sent via websocket
 ///
-/// The messages will get serialized and deserialized using JSON
+/// The messages will get serialized and deserialized using JSON.
+/// Outgoing messages are wrapped in a [WsEnvelope].
 #[derive(Deserialize, Serialize, Clone)]
 #[serde(tag = "type", content = "content", rename_all = "camelCase")]
 pub enum WsMessage {
@@ -107,6 +209,18 @@ pub enum WsMessage {
         /// This can be used by clients to check for updates on a long running game via API.
         game_data_id: u64,
     },
+    /// Notification for a client that it's their turn in a game
+    ///
+    /// This variant is sent in addition to [WsMessage::UpdateGameData] to the player
+    /// that was specified as next player by the uploader of the game state.
+    YourTurn {
+        /// Identifier of the game
+        game_uuid: Uuid,
+        /// The identifier of the game state in which it's the client's turn
+        game_data_id: u64,
+        /// The note the previous player left for the client
+        note: Option<String>,
+    },
     /// Notification for clients if a client in their game disconnected
     ClientDisconnected {
         /// Identifier of the game
@@ -128,6 +242,74 @@ pub enum WsMessage {
         /// The new message
         message: ChatMessage,
     },
+    /// The client was mentioned in a chat message
+    ///
+    /// The message is delivered as [WsMessage::IncomingChatMessage] as well.
+    ChatMention {
+        /// Identifier of the chat, the message originated from
+        chat_uuid: Uuid,
+        /// The message the client was mentioned in
+        message: ChatMessage,
+    },
+    /// Reply to a `sendChatMessage` message of the client, the message was sent
+    ChatMessageSent {
+        /// The `request_id` of the `sendChatMessage` message
+        request_id: Option<String>,
+        /// Identifier of the chat, the message was sent to
+        chat_uuid: Uuid,
+        /// The sent message
+        message: ChatMessage,
+    },
+    /// Reply to a `sendChatMessage` message of the client, the message could not be sent
+    ChatMessageFailed {
+        /// The `request_id` of the `sendChatMessage` message
+        request_id: Option<String>,
+        /// Identifier of the chat, the message should have been sent to
+        chat_uuid: Uuid,
+        /// The status code of the error, like in the responses of the HTTP API
+        status_code: u16,
+        /// The description of the error
+        message: String,
+    },
+    /// Summary of the messages of a chatroom the client receives as digest
+    ///
+    /// Sent periodically instead of a [WsMessage::IncomingChatMessage] message
+    /// for every message of the chatroom.
+    ChatDigest {
+        /// Identifier of the chat, the messages originated from
+        chat_uuid: Uuid,
+        /// The number of new messages since the last digest
+        count: u64,
+        /// The most recent message
+        last_message: ChatMessage,
+    },
+    /// A chat message was translated into the chat language of the client
+    ChatMessageTranslated {
+        /// Identifier of the chat, the message originated from
+        chat_uuid: Uuid,
+        /// Identifier of the translated message
+        message_uuid: Uuid,
+        /// The language of the translation
+        language: String,
+        /// The translated message
+        translation: String,
+    },
+    /// A message was removed from a chat by a moderator
+    ChatMessageDeleted {
+        /// Identifier of the chat the message was removed from
+        chat_uuid: Uuid,
+        /// Identifier of the removed message
+        message_uuid: Uuid,
+    },
+    /// An admin muted or banned the client in a chatroom or lifted the restriction
+    ChatRestrictionChanged {
+        /// Identifier of the chat
+        chat_uuid: Uuid,
+        /// The point in time until which the client may not send messages to the chat
+        muted_until: Option<DateTime<Utc>>,
+        /// Whether the client is banned from the chat
+        banned: bool,
+    },
     /// An invite is sent to the client.
     IncomingInvite {
         /// The uuid of the invite
@@ -137,6 +319,22 @@ pub enum WsMessage {
         /// The lobby to join
         lobby_uuid: Uuid,
     },
+    /// An invite to take over an empty seat of a running game is sent to the client.
+    IncomingGameInvite {
+        /// The uuid of the invite
+        invite_uuid: Uuid,
+        /// The user that invoked the invite
+        from: AccountResponse,
+        /// The game to join
+        game_uuid: Uuid,
+    },
+    /// A new player joined a running game the client is playing
+    GameJoin {
+        /// Identifier of the game
+        game_uuid: Uuid,
+        /// The player that joined the game
+        player: AccountResponse,
+    },
     /// A friend request is sent to the client
     IncomingFriendRequest {
         /// The user that invoked the request
@@ -156,6 +354,74 @@ pub enum WsMessage {
         /// The player that joined in the lobby
         player: AccountResponse,
     },
+    /// A player joined the lobby owned by the client by accepting an invite
+    ///
+    /// If the lobby is protected by a password, the player didn't have to enter it.
+    LobbyInviteAccepted {
+        /// The lobby that was joined
+        lobby_uuid: Uuid,
+        /// The player that joined the lobby
+        player: AccountResponse,
+        /// The player that invited the joined player
+        invited_by: AccountResponse,
+        /// The point in time the invite was created
+        invited_at: DateTime<Utc>,
+        /// Whether the password of the lobby was bypassed by the invite
+        password_bypassed: bool,
+    },
+    /// The client was added to a lobby by automatically accepting an invite of a friend
+    LobbyInviteAutoAccepted {
+        /// The lobby that was joined
+        lobby_uuid: Uuid,
+        /// The friend that invoked the invite
+        from: AccountResponse,
+    },
+    /// The owner changed the settings of a lobby the client is part of
+    LobbyUpdated {
+        /// The uuid of the lobby
+        lobby_uuid: Uuid,
+        /// The new name of the lobby
+        name: String,
+        /// The new maximum number of players
+        max_players: u8,
+        /// Whether the lobby is protected by a password
+        password: bool,
+        /// Whether all players must be ready before the game can be started
+        require_ready: bool,
+        /// Whether the game is started automatically once the lobby is full
+        auto_start: bool,
+        /// Whether the lobby is hidden from the list of open lobbies
+        hidden: bool,
+        /// Whether only the allowed accounts may join the lobby
+        restricted: bool,
+    },
+    /// A player of a lobby the client is part of changed their ready state
+    LobbyPlayerReady {
+        /// The uuid of the lobby
+        lobby_uuid: Uuid,
+        /// The player that changed their ready state
+        player_uuid: Uuid,
+        /// Whether the player is ready
+        ready: bool,
+    },
+    /// The owner of a lobby the client is part of made a player a co-host or revoked it
+    LobbyCoHostChanged {
+        /// The uuid of the lobby
+        lobby_uuid: Uuid,
+        /// The player whose co-host rights changed
+        player_uuid: Uuid,
+        /// Whether the player is a co-host now
+        co_host: bool,
+    },
+    /// A member of a lobby the client is part of changed their nation
+    LobbyNationChanged {
+        /// The uuid of the lobby
+        lobby_uuid: Uuid,
+        /// The member that changed their nation
+        player_uuid: Uuid,
+        /// The chosen nation, `None` if the member cleared their choice
+        nation: Option<String>,
+    },
     /// A lobby closed in which the client was part of
     LobbyClosed {
         /// The uuid of the lobby
@@ -184,6 +450,139 @@ pub enum WsMessage {
     AccountUpdated {
         /// The new account data
         account: AccountResponse,
+        /// The badges of the account
+        #[serde(default)]
+        badges: Vec<Badge>,
+    },
+    /// A trade or pact was proposed to the client
+    IncomingNegotiation {
+        /// The uuid of the negotiation
+        negotiation_uuid: Uuid,
+        /// The game the negotiation belongs to
+        game_uuid: Uuid,
+        /// The player that proposed the negotiation
+        from: AccountResponse,
+        /// The kind of the negotiation
+        kind: NegotiationKind,
+        /// The terms of the negotiation
+        terms: String,
+        /// The point in time after which the negotiation can no longer be answered
+        expires_at: DateTime<Utc>,
+    },
+    /// A negotiation proposed by the client was answered
+    NegotiationChanged {
+        /// The uuid of the negotiation
+        negotiation_uuid: Uuid,
+        /// The game the negotiation belongs to
+        game_uuid: Uuid,
+        /// The new state of the negotiation
+        state: NegotiationState,
+    },
+    /// The host changed the settings of a game the client is playing
+    GameSettingsChanged {
+        /// Identifier of the game
+        game_uuid: Uuid,
+        /// The new settings of the game
+        settings: GameSettingsResponse,
+    },
+    /// The host revealed the nations of all players of a game with hidden identities
+    NationsRevealed {
+        /// Identifier of the game
+        game_uuid: Uuid,
+        /// The nations of all players that have chosen one
+        nations: Vec<PlayerNation>,
+    },
+    /// The host role of a game the client is playing was transferred
+    GameHostChanged {
+        /// Identifier of the game
+        game_uuid: Uuid,
+        /// The player that is the new host of the game
+        host_uuid: Uuid,
+    },
+    /// The metadata of a game the client is playing changed
+    ///
+    /// This is sent in addition to [WsMessage::UpdateGameData] whenever a new game state
+    /// is uploaded, so clients can refresh their list of games without the game data.
+    /// Connections opened with `compact_game_updates` only receive this message.
+    GameMetaChanged {
+        /// Identifier of the game
+        game_uuid: Uuid,
+        /// The turn of the uploaded game state, if it was provided by the uploader
+        turn: Option<u32>,
+        /// The player whose turn it is, if it is known to the server
+        current_player: Option<Uuid>,
+        /// The point in time the game state was uploaded
+        updated_at: DateTime<Utc>,
+    },
+    /// A player of a game the client is playing ended their turn without uploading
+    /// the game state yet
+    ///
+    /// The game state will be sent with a [WsMessage::UpdateGameData] message once
+    /// it was uploaded.
+    TurnEnded {
+        /// Identifier of the game
+        game_uuid: Uuid,
+        /// The player that ended their turn
+        player_uuid: Uuid,
+        /// The player whose turn it is now
+        next_player_uuid: Uuid,
+        /// The point in time until which the game state should be uploaded
+        upload_deadline: DateTime<Utc>,
+    },
+    /// A lobby of the list of open lobbies was created, closed or its number of players changed
+    ///
+    /// This message is only sent through connections that subscribed to the lobby list.
+    /// Hidden lobbies are not part of the list.
+    LobbyListUpdated {
+        /// The uuid of the lobby
+        lobby_uuid: Uuid,
+        /// What happened to the lobby
+        change: LobbyListChange,
+        /// The number of players in the lobby including the owner, `0` if it was closed
+        current_players: u8,
+        /// The maximum number of players of the lobby, `0` if it was closed
+        max_players: u8,
+    },
+    /// An account the client subscribed to came online or went offline
+    ///
+    /// After subscribing, the current state of every account is sent once.
+    PresenceChanged {
+        /// The account whose presence changed
+        account_uuid: Uuid,
+        /// Whether the account has at least one active connection
+        online: bool,
+    },
+    /// A friend of the client opened their first connection
+    FriendOnline {
+        /// The account of the friend
+        account_uuid: Uuid,
+    },
+    /// A friend of the client closed their last connection
+    FriendOffline {
+        /// The account of the friend
+        account_uuid: Uuid,
+    },
+    /// A test notification sent by the server administrator
+    ///
+    /// Clients should display this like any other notification.
+    TestNotification {
+        /// The text of the notification
+        message: String,
+    },
+    /// A game was transferred to another server by the server administrators
+    ///
+    /// This message is sent to the players of the game on the server the game left, and to
+    /// the players on the server it was imported on. The game continues as `new_game_uuid`,
+    /// the old game is not updated anymore.
+    GameTransferred {
+        /// The game on the server it was transferred from
+        game_uuid: Uuid,
+        /// The base url of the server the game continues on, `None` if it is this server
+        server_url: Option<String>,
+        /// The game on the server it continues on
+        new_game_uuid: Uuid,
+        /// The chatroom of the game on the server it continues on
+        new_game_chat_uuid: Uuid,
     },
 }
 
@@ -192,12 +591,26 @@ pub type WsManagerChan = Sender<WsManagerMessage>;
 
 /// Messages to control the websocket manager
 pub enum WsManagerMessage {
-    /// The websocket was closed by the client (timeout, or closed event)
-    WebsocketClosed(Uuid),
+    /// The connection with the given id of an account was closed by the client
+    /// (timeout, or closed event)
+    ///
+    /// The account goes offline and leaves its lobbies once its last connection is closed.
+    WebsocketClosed(Uuid, Uuid),
     /// Close the socket from the server side
     CloseSocket(Uuid),
-    /// Client with given uuid initialized a websocket
-    OpenedSocket(Uuid, ws::Sender),
+    /// Client with given uuid initialized a websocket with the given connection id
+    OpenedSocket(
+        Uuid,
+        Uuid,
+        ws::Sender,
+        ConnectionOptions,
+        ConnectionSubscriptions,
+    ),
+    /// Client with given uuid opened a server-sent events stream with the given connection id
+    ///
+    /// The stream is treated like a websocket connection, the messages for the account
+    /// are forwarded to the provided channel.
+    OpenedEventStream(Uuid, Uuid, Sender<WsMessage>),
     /// Send a message to given uuid
     SendMessage(Uuid, WsMessage),
     /// Retrieve the current websocket count by sending this
@@ -215,13 +628,102 @@ pub enum WsManagerMessage {
     ///
     /// It will respond through the provided channel.
     RetrieveOnlineState(Uuid, oneshot::Sender<bool>),
+    /// Subscribe an account to the presence of the given accounts
+    ///
+    /// The subscriber receives a [WsMessage::PresenceChanged] message with the current
+    /// state of every account and whenever one of them comes online or goes offline.
+    /// The subscriptions end when the subscriber goes offline.
+    SubscribePresence(Uuid, Vec<Uuid>),
+    /// Unsubscribe an account from the presence of the given accounts
+    UnsubscribePresence(Uuid, Vec<Uuid>),
+    /// Send a [WsMessage::LobbyListUpdated] message to all websocket connections that
+    /// subscribed to the lobby list
+    BroadcastLobbyList(WsMessage),
+    /// Send the current presence of an account to the given friends of the account
+    ///
+    /// The friends receive a [WsMessage::FriendOnline] or [WsMessage::FriendOffline]
+    /// message, depending on the state of the account when this message is processed.
+    SendFriendPresence(Uuid, Vec<Uuid>),
+}
+
+/// Send a message to all connections of an account
+async fn send_to_account(
+    lookup: &HashMap<Uuid, Vec<(Uuid, Sender<WsMessage>)>>,
+    account: Uuid,
+    msg: WsMessage,
+) {
+    if let Some(sender) = lookup.get(&account) {
+        for (_, tx) in sender {
+            if let Err(err) = tx.send(msg.clone()).await {
+                error!("Could not send to ws sender: {err}");
+            }
+        }
+    }
+}
+
+/// Send the presence of an account to all of its subscribers
+async fn send_presence(
+    lookup: &HashMap<Uuid, Vec<(Uuid, Sender<WsMessage>)>>,
+    presence: &HashMap<Uuid, HashSet<Uuid>>,
+    account: Uuid,
+    online: bool,
+) {
+    if let Some(subscribers) = presence.get(&account) {
+        for subscriber in subscribers {
+            send_to_account(
+                lookup,
+                *subscriber,
+                WsMessage::PresenceChanged {
+                    account_uuid: account,
+                    online,
+                },
+            )
+            .await;
+        }
+    }
+}
+
+/// Announce the presence of an account to its friends
+///
+/// The friends are queried in the background, the presence is sent once they are known.
+fn announce_to_friends(db: &Database, manager: &WsManagerChan, account: Uuid) {
+    let db = db.clone();
+    let manager = manager.clone();
+    tokio::spawn(async move {
+        let friends = match query!(&db, (Friend::F.to,))
+            .condition(and!(
+                Friend::F.from.equals(account),
+                Friend::F.is_request.equals(false)
+            ))
+            .all()
+            .await
+        {
+            Ok(friends) => friends.into_iter().map(|(x,)| *x.key()).collect(),
+            Err(err) => {
+                error!("Database error: {err}");
+                return;
+            }
+        };
+
+        if let Err(err) = manager
+            .send(WsManagerMessage::SendFriendPresence(account, friends))
+            .await
+        {
+            warn!("Could not send to ws manager chan: {err}");
+        }
+    });
 }
 
 /// Start the websocket manager
 ///
 /// It will return a channel to this manager
 pub async fn start_ws_manager(db: Database) -> Result<WsManagerChan, String> {
-    let mut lookup: HashMap<Uuid, Vec<Sender<WsMessage>>> = HashMap::new();
+    // The connections of each account with their ids
+    let mut lookup: HashMap<Uuid, Vec<(Uuid, Sender<WsMessage>)>> = HashMap::new();
+    // The subscribers of the presence of each account
+    let mut presence: HashMap<Uuid, HashSet<Uuid>> = HashMap::new();
+    // The websocket connections of the accounts with their ids and subscriptions
+    let mut connections: Vec<(Uuid, Uuid, Sender<WsMessage>, ConnectionSubscriptions)> = Vec::new();
 
     let (tx, mut rx) = mpsc::channel(16);
 
@@ -229,8 +731,30 @@ pub async fn start_ws_manager(db: Database) -> Result<WsManagerChan, String> {
     tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
             match msg {
-                WsManagerMessage::WebsocketClosed(uuid) => {
-                    lookup.remove(&uuid);
+                WsManagerMessage::WebsocketClosed(uuid, connection) => {
+                    connections.retain(|(_, id, _, _)| *id != connection);
+
+                    let connected = match lookup.get_mut(&uuid) {
+                        Some(sockets) => {
+                            sockets.retain(|(id, _)| *id != connection);
+                            !sockets.is_empty()
+                        }
+                        None => false,
+                    };
+                    // The account is still online with its other connections
+                    if connected {
+                        continue;
+                    }
+                    if lookup.remove(&uuid).is_some() {
+                        send_presence(&lookup, &presence, uuid, false).await;
+                        announce_to_friends(&db, &rx_tx, uuid);
+                    }
+
+                    // The subscriptions of the account end with its connections
+                    presence.retain(|_, subscribers| {
+                        subscribers.remove(&uuid);
+                        !subscribers.is_empty()
+                    });
 
                     // Start cleanup task
                     let db = db.clone();
@@ -257,21 +781,39 @@ pub async fn start_ws_manager(db: Database) -> Result<WsManagerChan, String> {
                                 }
                             };
 
-                        // Check if the account was a lobby owner
+                        // Collects the changes of the lobby list until the transaction is committed
+                        let mut outbox = Outbox::new();
+
+                        // Close all lobbies owned by the account
                         match query!(&mut tx, Lobby)
                             .condition(Lobby::F.owner.equals(uuid.as_ref()))
-                            .optional()
+                            .all()
                             .await
                         {
-                            Ok(lobby) => {
-                                if let Some(mut lobby) = lobby {
+                            Ok(lobbies) => {
+                                for lobby in lobbies {
                                     info!(
                                         "Closing lobby {} due to missing ws connection of owner {uuid}",
                                         lobby.uuid
                                     );
 
-                                    if let Err(err) =
-                                        Lobby::F.current_player.populate(&mut tx, &mut lobby).await
+                                    let members =
+                                        match LobbyMembers::query(&mut tx, lobby.uuid).await {
+                                            Ok(Some(v)) => v,
+                                            Ok(None) => continue,
+                                            Err(err) => {
+                                                error!("Database error: {err}");
+                                                return;
+                                            }
+                                        };
+
+                                    if let Err(err) = announce(
+                                        &mut tx,
+                                        &mut outbox,
+                                        lobby.uuid,
+                                        LobbyListChange::Closed,
+                                    )
+                                    .await
                                     {
                                         error!("Database error: {err}");
                                         return;
@@ -293,12 +835,10 @@ pub async fn start_ws_manager(db: Database) -> Result<WsManagerChan, String> {
                                         return;
                                     }
 
-                                    // Queried beforehand
-                                    #[allow(clippy::unwrap_used)]
-                                    for player in lobby.current_player.cached.unwrap() {
+                                    for player in members.players {
                                         if let Err(err) = cleanup_tx
                                             .send(WsManagerMessage::SendMessage(
-                                                *player.player.key(),
+                                                player,
                                                 WsMessage::LobbyClosed {
                                                     lobby_uuid: lobby.uuid,
                                                 },
@@ -323,7 +863,7 @@ pub async fn start_ws_manager(db: Database) -> Result<WsManagerChan, String> {
                         {
                             Ok(lobby_accounts) => {
                                 for lobby_account in lobby_accounts {
-                                    let mut lobby = match query!(&mut tx, Lobby)
+                                    let lobby = match query!(&mut tx, Lobby)
                                         .condition(Lobby::F.uuid.equals(*lobby_account.lobby.key()))
                                         .one()
                                         .await
@@ -335,12 +875,15 @@ pub async fn start_ws_manager(db: Database) -> Result<WsManagerChan, String> {
                                         }
                                     };
 
-                                    if let Err(err) =
-                                        Lobby::F.current_player.populate(&mut tx, &mut lobby).await
-                                    {
-                                        error!("Database error: {err}");
-                                        return;
-                                    }
+                                    let members =
+                                        match LobbyMembers::query(&mut tx, lobby.uuid).await {
+                                            Ok(Some(v)) => v,
+                                            Ok(None) => continue,
+                                            Err(err) => {
+                                                error!("Database error: {err}");
+                                                return;
+                                            }
+                                        };
 
                                     if let Err(err) = delete!(&mut tx, ChatRoomMember)
                                         .condition(and!(
@@ -366,17 +909,19 @@ pub async fn start_ws_manager(db: Database) -> Result<WsManagerChan, String> {
                                         return;
                                     }
 
-                                    // Queried beforehand
-                                    #[allow(clippy::unwrap_used)]
-                                    for player in iter::once(*lobby.owner.key()).chain(
-                                        lobby
-                                            .current_player
-                                            .cached
-                                            .unwrap()
-                                            .into_iter()
-                                            .filter(|x| *x.player.key() != uuid)
-                                            .map(|x| *x.player.key()),
-                                    ) {
+                                    if let Err(err) = announce(
+                                        &mut tx,
+                                        &mut outbox,
+                                        lobby.uuid,
+                                        LobbyListChange::Updated,
+                                    )
+                                    .await
+                                    {
+                                        error!("Database error: {err}");
+                                        return;
+                                    }
+
+                                    for player in members.members().filter(|x| *x != uuid) {
                                         if let Err(err) = cleanup_tx
                                             .send(WsManagerMessage::SendMessage(
                                                 player,
@@ -404,13 +949,16 @@ pub async fn start_ws_manager(db: Database) -> Result<WsManagerChan, String> {
 
                         if let Err(err) = tx.commit().await {
                             error!("Database error: {err}");
+                            return;
                         }
+
+                        outbox.send(&cleanup_tx).await;
                     });
                 }
                 WsManagerMessage::CloseSocket(uuid) => {
                     // Trigger close for all websockets associated with uuid
                     if let Some(sockets) = lookup.get(&uuid) {
-                        for s in sockets {
+                        for (_, s) in sockets {
                             if !s.is_closed() {
                                 if let Err(err) = s.send(WsMessage::ServerQuitSocket).await {
                                     error!("Couldn't send close to ws sender: {err}");
@@ -419,30 +967,39 @@ pub async fn start_ws_manager(db: Database) -> Result<WsManagerChan, String> {
                         }
                     }
 
-                    lookup.remove(&uuid);
+                    if lookup.remove(&uuid).is_some() {
+                        send_presence(&lookup, &presence, uuid, false).await;
+                        announce_to_friends(&db, &rx_tx, uuid);
+                    }
+                    connections.retain(|(account, _, _, _)| *account != uuid);
                 }
-                WsManagerMessage::OpenedSocket(uuid, ws_tx) => {
+                WsManagerMessage::OpenedSocket(uuid, connection, ws_tx, options, subscriptions) => {
                     let (tx, rx) = mpsc::channel(16);
-                    task::spawn(start_ws_sender(ws_tx, rx));
+                    task::spawn(start_ws_sender(ws_tx, rx, options));
+                    connections.push((uuid, connection, tx.clone(), subscriptions));
 
                     // Add new client connection to state
                     if let Some(sockets) = lookup.get_mut(&uuid) {
-                        sockets.push(tx);
+                        sockets.push((connection, tx));
                     }
                     // Insert new client connection
                     else {
-                        lookup.insert(uuid, vec![tx]);
+                        lookup.insert(uuid, vec![(connection, tx)]);
+                        send_presence(&lookup, &presence, uuid, true).await;
+                        announce_to_friends(&db, &rx_tx, uuid);
                     }
                 }
-                WsManagerMessage::SendMessage(uuid, msg) => {
-                    if let Some(sender) = lookup.get(&uuid) {
-                        for tx in sender {
-                            if let Err(err) = tx.send(msg.clone()).await {
-                                error!("Could not send to ws sender: {err}");
-                            }
-                        }
+                WsManagerMessage::OpenedEventStream(uuid, connection, tx) => {
+                    let was_online = lookup.contains_key(&uuid);
+                    lookup.entry(uuid).or_default().push((connection, tx));
+                    if !was_online {
+                        send_presence(&lookup, &presence, uuid, true).await;
+                        announce_to_friends(&db, &rx_tx, uuid);
                     }
                 }
+                WsManagerMessage::SendMessage(uuid, msg) => {
+                    send_to_account(&lookup, uuid, msg).await;
+                }
                 WsManagerMessage::RetrieveWsCount(tx) => {
                     let sum = lookup.values().map(|s| s.len() as u64).sum();
                     if tx.send(sum).is_err() {
@@ -464,6 +1021,56 @@ pub async fn start_ws_manager(db: Database) -> Result<WsManagerChan, String> {
                         error!("Could not send through callback channel");
                     }
                 }
+                WsManagerMessage::SubscribePresence(subscriber, accounts) => {
+                    for account in accounts {
+                        presence.entry(account).or_default().insert(subscriber);
+
+                        let online = lookup.contains_key(&account);
+                        send_to_account(
+                            &lookup,
+                            subscriber,
+                            WsMessage::PresenceChanged {
+                                account_uuid: account,
+                                online,
+                            },
+                        )
+                        .await;
+                    }
+                }
+                WsManagerMessage::UnsubscribePresence(subscriber, accounts) => {
+                    for account in accounts {
+                        if let Some(subscribers) = presence.get_mut(&account) {
+                            subscribers.remove(&subscriber);
+                            if subscribers.is_empty() {
+                                presence.remove(&account);
+                            }
+                        }
+                    }
+                }
+                WsManagerMessage::SendFriendPresence(account, friends) => {
+                    let msg = if lookup.contains_key(&account) {
+                        WsMessage::FriendOnline {
+                            account_uuid: account,
+                        }
+                    } else {
+                        WsMessage::FriendOffline {
+                            account_uuid: account,
+                        }
+                    };
+                    for friend in friends {
+                        send_to_account(&lookup, friend, msg.clone()).await;
+                    }
+                }
+                WsManagerMessage::BroadcastLobbyList(msg) => {
+                    connections.retain(|(_, _, tx, _)| !tx.is_closed());
+                    for (_, _, tx, subscriptions) in &connections {
+                        if subscriptions.lobby_list() {
+                            if let Err(err) = tx.send(msg.clone()).await {
+                                error!("Could not send to ws sender: {err}");
+                            }
+                        }
+                    }
+                }
             }
         }
     });
@@ -0,0 +1,124 @@
+//! The ephemeral mode of `runciv start --ephemeral`
+//!
+//! The server runs against a database that is created and migrated on start and dropped
+//! again on shutdown. The game data and log files are written to a directory on tmpfs,
+//! so nothing of the instance survives a restart. This is meant for tests of clients and
+//! for short-lived servers, e.g. at a LAN party.
+
+use std::env::temp_dir;
+use std::fs::remove_dir_all;
+use std::path::{Path, PathBuf};
+
+use log::{error, info};
+use uuid::Uuid;
+
+use crate::config::Config;
+
+/// The directory that is backed by tmpfs on most linux systems
+const SHARED_MEMORY: &str = "/dev/shm";
+
+/// The throwaway database and directory of an ephemeral server
+pub struct EphemeralEnvironment {
+    /// The name of the throwaway database
+    database: String,
+    /// The name of the configured database, which is used to create and drop the other one
+    configured_database: String,
+    /// The directory of the game data and log files
+    directory: PathBuf,
+}
+
+/// Point the configuration to a fresh directory on tmpfs
+///
+/// The game data and the log files are written to the directory, which is created by
+/// the server and the logger. This has to be called before the logger is set up.
+pub fn ephemeral_paths(conf: &mut Config) -> EphemeralEnvironment {
+    let database = format!("runciv_ephemeral_{}", Uuid::new_v4().simple());
+    let directory = if Path::new(SHARED_MEMORY).is_dir() {
+        Path::new(SHARED_MEMORY).join(&database)
+    } else {
+        temp_dir().join(&database)
+    };
+
+    conf.server.game_data_path = directory.join("game_data").to_string_lossy().to_string();
+    conf.logging.path = directory.join("runciv.log").to_string_lossy().to_string();
+    for logger in &mut conf.logging.additional_file_loggers {
+        logger.path = directory
+            .join(format!("{}.log", logger.name))
+            .to_string_lossy()
+            .to_string();
+    }
+    conf.server.ephemeral = true;
+
+    EphemeralEnvironment {
+        database,
+        configured_database: conf.database.name.clone(),
+        directory,
+    }
+}
+
+impl EphemeralEnvironment {
+    /// Create the throwaway database and apply the migrations in `migration_dir`
+    ///
+    /// The configured database user needs the permission to create databases.
+    /// Afterwards the configuration points to the new database.
+    pub async fn create_database(
+        &self,
+        conf: &mut Config,
+        migration_dir: String,
+    ) -> Result<(), String> {
+        let db = crate::get_db(conf).await?;
+        db.raw_sql(
+            &format!("CREATE DATABASE \"{}\";", self.database),
+            None,
+            None,
+        )
+        .await
+        .map_err(|err| format!("Could not create the ephemeral database: {err}"))?;
+        db.close().await;
+
+        conf.database.name = self.database.clone();
+        crate::migrate(conf, migration_dir).await?;
+
+        info!(
+            "Running ephemeral in database {} with the files in {}",
+            self.database,
+            self.directory.display()
+        );
+        Ok(())
+    }
+
+    /// Drop the throwaway database and remove the directory
+    ///
+    /// The configured database is used for the connection, as a database can't be
+    /// dropped while connected to it. Errors are logged, as the server is shutting
+    /// down anyway.
+    pub async fn remove(self, conf: &mut Config) {
+        conf.database.name = self.configured_database.clone();
+        match crate::get_db(conf).await {
+            Ok(db) => {
+                if let Err(err) = db
+                    .raw_sql(
+                        &format!(
+                            "DROP DATABASE IF EXISTS \"{}\" WITH (FORCE);",
+                            self.database
+                        ),
+                        None,
+                        None,
+                    )
+                    .await
+                {
+                    error!("Could not drop the ephemeral database: {err}");
+                }
+                db.close().await;
+            }
+            Err(err) => error!("Could not drop the ephemeral database: {err}"),
+        }
+
+        if let Err(err) = remove_dir_all(&self.directory) {
+            error!(
+                "Could not remove the ephemeral directory {}: {err}",
+                self.directory.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Setup of the logger
+//!
+//! The log4rs configuration is built from the [LoggingConfig] of the configuration file.
+//! The returned [LogFilters] keep the handle of the logger, so the log levels of single
+//! modules can be changed while the server is running.
+//!
+//! The statements logged by sqlx are passed to the [SlowRequests] of the server, as the
+//! database driver doesn't expose the time a statement took otherwise.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{LevelFilter, Record};
+use log4rs::append::console::ConsoleAppender;
+use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
+use log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger;
+use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
+use log4rs::append::rolling_file::RollingFileAppender;
+use log4rs::append::Append;
+use log4rs::config::{Appender, Logger, Root};
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::Handle;
+use serde::{Deserialize, Serialize};
+
+use crate::server::middleware::SlowRequests;
+
+/// The target of the statements logged by sqlx
+const STATEMENT_TARGET: &str = "sqlx::query";
+
+/// The pattern of the log lines, if a logger doesn't specify its own
+const DEFAULT_PATTERN: &str = "{h([{d(%Y-%m-%d %H:%M:%S)} | {({l}):5.5} | {f}:{L}])} {m}{n}";
+
+/// The logging configuration
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct LoggingConfig {
+    /// The level of the main logger, e.g. `info`
+    pub log_level: String,
+    /// The file the main logger writes to, in addition to stdout
+    pub path: String,
+    /// The size after which the file is rotated, e.g. `10 MB`
+    pub rotation_file_size: String,
+    /// The number of rotated files that are kept
+    pub max_rotation_count: u32,
+    /// Loggers for single targets that write to their own files
+    #[serde(default)]
+    pub additional_file_loggers: Vec<FileLoggerConfig>,
+}
+
+/// A logger for a single target that writes to its own file
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct FileLoggerConfig {
+    /// The target that is logged, e.g. `requests`
+    pub name: String,
+    /// Whether the messages are written to the main logger as well
+    #[serde(default)]
+    pub add_to_main_logger: bool,
+    /// The file the logger writes to
+    pub path: String,
+    /// The size after which the file is rotated, e.g. `10 MB`
+    pub rotation_file_size: String,
+    /// The number of rotated files that are kept
+    pub max_rotation_count: u32,
+    /// The pattern of the log lines, see the documentation of log4rs
+    #[serde(default)]
+    pub alternative_pattern: Option<String>,
+}
+
+/// Parse a file size like `10 MB` or `512KiB` into bytes
+fn parse_file_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid file size: {size}"))?;
+    let factor: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return Err(format!("Invalid file size unit: {size}")),
+    };
+
+    Ok(number * factor)
+}
+
+/// Build a rotating file appender
+fn file_appender(
+    path: &str,
+    rotation_file_size: &str,
+    max_rotation_count: u32,
+    pattern: &str,
+) -> Result<RollingFileAppender, String> {
+    let roller = FixedWindowRoller::builder()
+        .build(&format!("{path}.{{}}"), max_rotation_count)
+        .map_err(|err| format!("Invalid log rotation of {path}: {err}"))?;
+    let policy = CompoundPolicy::new(
+        Box::new(SizeTrigger::new(parse_file_size(rotation_file_size)?)),
+        Box::new(roller),
+    );
+
+    RollingFileAppender::builder()
+        .encoder(Box::new(PatternEncoder::new(pattern)))
+        .build(path, Box::new(policy))
+        .map_err(|err| format!("Could not open the log file {path}: {err}"))
+}
+
+/// Passes the statements logged by sqlx to the [SlowRequests]
+#[derive(Debug)]
+struct SlowStatementAppender(Arc<SlowRequests>);
+
+impl SlowStatementAppender {
+    /// Extract the summary and duration of a statement
+    ///
+    /// sqlx logs them as fields of the message:
+    /// `summary="SELECT …" db.statement="…" … elapsed_secs=1.234 …`
+    fn parse(message: &str) -> Option<(String, Duration)> {
+        let summary = message
+            .strip_prefix("summary=\"")?
+            .split("\" db.statement=")
+            .next()?
+            .replace("\\\"", "\"");
+        let elapsed_secs: f64 = message
+            .split(" elapsed_secs=")
+            .nth(1)?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()?;
+
+        Some((summary, Duration::try_from_secs_f64(elapsed_secs).ok()?))
+    }
+}
+
+impl Append for SlowStatementAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        if let Some((summary, duration)) = Self::parse(&record.args().to_string()) {
+            self.0.record_statement(summary, duration);
+        }
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+/// Build the log4rs configuration with additional levels for single modules
+fn build_config(
+    config: &LoggingConfig,
+    filters: &BTreeMap<String, LevelFilter>,
+    slow_requests: Option<&Arc<SlowRequests>>,
+) -> Result<log4rs::Config, String> {
+    let level = LevelFilter::from_str(&config.log_level)
+        .map_err(|_| format!("Invalid log level: {}", config.log_level))?;
+
+    let stdout = ConsoleAppender::builder()
+        .encoder(Box::new(PatternEncoder::new(DEFAULT_PATTERN)))
+        .build();
+    let main = file_appender(
+        &config.path,
+        &config.rotation_file_size,
+        config.max_rotation_count,
+        DEFAULT_PATTERN,
+    )?;
+
+    let mut builder = log4rs::Config::builder()
+        .appender(Appender::builder().build("stdout", Box::new(stdout)))
+        .appender(Appender::builder().build("main", Box::new(main)));
+
+    for logger in &config.additional_file_loggers {
+        let appender = file_appender(
+            &logger.path,
+            &logger.rotation_file_size,
+            logger.max_rotation_count,
+            logger
+                .alternative_pattern
+                .as_deref()
+                .unwrap_or(DEFAULT_PATTERN),
+        )?;
+        builder = builder
+            .appender(Appender::builder().build(&logger.name, Box::new(appender)))
+            .logger(
+                Logger::builder()
+                    .appender(&logger.name)
+                    .additive(logger.add_to_main_logger)
+                    .build(
+                        &logger.name,
+                        filters.get(&logger.name).copied().unwrap_or(level),
+                    ),
+            );
+    }
+
+    // Every statement is logged at debug level, the appender only keeps the slow ones.
+    // They don't end up in the main logger, which would be flooded otherwise.
+    if let Some(slow_requests) = slow_requests {
+        builder = builder
+            .appender(Appender::builder().build(
+                STATEMENT_TARGET,
+                Box::new(SlowStatementAppender(slow_requests.clone())),
+            ))
+            .logger(
+                Logger::builder()
+                    .appender(STATEMENT_TARGET)
+                    .additive(false)
+                    .build(STATEMENT_TARGET, LevelFilter::Debug),
+            );
+    }
+
+    // The module loggers have no appenders of their own, their messages end up in the main logger
+    for (module, level) in filters {
+        if config
+            .additional_file_loggers
+            .iter()
+            .all(|logger| &logger.name != module)
+            && (slow_requests.is_none() || module != STATEMENT_TARGET)
+        {
+            builder = builder.logger(Logger::builder().build(module, *level));
+        }
+    }
+
+    builder
+        .build(
+            Root::builder()
+                .appender("stdout")
+                .appender("main")
+                .build(level),
+        )
+        .map_err(|err| format!("Invalid logging configuration: {err}"))
+}
+
+/// The handle of the logger with the log levels of single modules
+pub struct LogFilters {
+    handle: Handle,
+    config: LoggingConfig,
+    slow_requests: Option<Arc<SlowRequests>>,
+    filters: Mutex<BTreeMap<String, LevelFilter>>,
+}
+
+impl LogFilters {
+    /// Whether the level of a module can't be changed
+    ///
+    /// The statements of sqlx are reserved for the slow log, if it records them.
+    pub fn is_reserved(&self, module: &str) -> bool {
+        self.slow_requests.is_some() && module == STATEMENT_TARGET
+    }
+
+    /// The level of the main logger
+    pub fn default_level(&self) -> &str {
+        &self.config.log_level
+    }
+
+    /// The levels of the modules that differ from the main logger
+    pub fn filters(&self) -> BTreeMap<String, LevelFilter> {
+        // Ok as the lock is never held across an await point or a panic
+        #[allow(clippy::unwrap_used)]
+        self.filters.lock().unwrap().clone()
+    }
+
+    /// Set the level of a module, e.g. `rorm_db`
+    ///
+    /// If `level` is `None`, the module logs with the level of the main logger again.
+    /// Returns the levels of all modules afterwards.
+    pub fn set_filter(
+        &self,
+        module: String,
+        level: Option<LevelFilter>,
+    ) -> Result<BTreeMap<String, LevelFilter>, String> {
+        // Ok as the lock is never held across an await point or a panic
+        #[allow(clippy::unwrap_used)]
+        let mut filters = self.filters.lock().unwrap();
+
+        let mut changed = filters.clone();
+        match level {
+            Some(level) => changed.insert(module, level),
+            None => changed.remove(&module),
+        };
+
+        self.handle.set_config(build_config(
+            &self.config,
+            &changed,
+            self.slow_requests.as_ref(),
+        )?);
+        *filters = changed;
+
+        Ok(filters.clone())
+    }
+}
+
+/// Install the logger of the server
+///
+/// The main logger writes to stdout and to the configured file, the additional file loggers
+/// to their own files. If `slow_requests` is given, the statements logged by sqlx are
+/// recorded in it.
+pub fn setup_logging(
+    config: &LoggingConfig,
+    slow_requests: Option<Arc<SlowRequests>>,
+) -> Result<LogFilters, String> {
+    let handle = log4rs::init_config(build_config(
+        config,
+        &BTreeMap::new(),
+        slow_requests.as_ref(),
+    )?)
+    .map_err(|err| format!("Could not install the logger: {err}"))?;
+
+    Ok(LogFilters {
+        handle,
+        config: config.clone(),
+        slow_requests,
+        filters: Mutex::new(BTreeMap::new()),
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_sizes_are_parsed() {
+        assert_eq!(parse_file_size("10 MB").unwrap(), 10_000_000);
+        assert_eq!(parse_file_size("512KiB").unwrap(), 512 * 1024);
+        assert_eq!(parse_file_size("42").unwrap(), 42);
+        assert!(parse_file_size("MB").is_err());
+        assert!(parse_file_size("10 parsecs").is_err());
+    }
+
+    #[test]
+    fn slow_statements_are_parsed() {
+        let message = "summary=\"SELECT \\\"game\\\".\\\"uuid\\\" …\" db.statement=\"\" \
+            rows_affected=0 rows_returned=1 elapsed=1.5s elapsed_secs=1.5 slow_threshold=1s \
+            slow statement: execution time exceeded alert threshold";
+        let (summary, duration) = SlowStatementAppender::parse(message).unwrap();
+        assert_eq!(summary, "SELECT \"game\".\"uuid\" …");
+        assert_eq!(duration, Duration::from_millis(1500));
+
+        assert!(SlowStatementAppender::parse("Connected to database").is_none());
+    }
+}
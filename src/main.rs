@@ -9,30 +9,54 @@
 
 use std::fs::read_to_string;
 use std::path::Path;
+use std::sync::Arc;
 
-use actix_toolbox::logging::setup_logging;
 use actix_web::cookie::Key;
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
 use clap::{Parser, Subcommand};
-use log::{error, info};
+use log::{error, info, LevelFilter};
 use rorm::cli::config as cli_config;
 use rorm::{cli, Database, DatabaseConfiguration, DatabaseDriver};
 
-use crate::chan::start_ws_manager;
-use crate::config::Config;
+use crate::chan::{start_ws_manager, AccountWebhooks, ChatDigests, Notifier};
+use crate::config::{config_from_env, Config};
+use crate::ephemeral::ephemeral_paths;
+use crate::logging::setup_logging;
+use crate::server::middleware::SlowRequests;
 use crate::server::start_server;
+use crate::setup::{run_setup, SetupArgs};
+use crate::storage::{check_storage_version, migrate_storage};
+use crate::tasks::{
+    check_game_data, start_abandoned_account_cleanup, start_chat_digests, start_chat_retention,
+    start_game_file_cleanup, start_lobby_idle_timeout, verify_game_data,
+};
 
 pub mod chan;
 pub mod config;
+pub mod ephemeral;
+pub mod logging;
 pub mod models;
 pub mod server;
+pub mod service;
+pub mod setup;
+pub mod storage;
+pub mod tasks;
 
 /// The possible commands for runciv
 #[derive(Subcommand)]
 pub enum Command {
     /// Start the server
-    Start,
+    Start {
+        /// Run with a throwaway database and game data on tmpfs, which are removed on shutdown
+        ///
+        /// The configured database is only used to create and drop the throwaway database.
+        #[clap(long)]
+        ephemeral: bool,
+        /// The directory of the migrations that are applied to the throwaway database
+        #[clap(long, default_value_t = String::from("migrations"))]
+        migration_dir: String,
+    },
     /// Generate a secret key
     Keygen,
     /// Run database migrations
@@ -40,6 +64,30 @@ pub enum Command {
         /// The directory where the migrations are located
         migration_dir: String,
     },
+    /// Verify the game data files against their checksums
+    Verify {
+        /// Restore missing or corrupt game states from the latest intact snapshot
+        #[clap(long)]
+        restore: bool,
+    },
+    /// Create the configuration, prepare the database and create the first account
+    ///
+    /// Missing options are asked for interactively.
+    Setup(SetupArgs),
+    /// Manage the game data directory
+    Storage {
+        #[clap(subcommand)]
+        command: StorageCommand,
+    },
+}
+
+/// The commands to manage the game data directory
+#[derive(Subcommand)]
+pub enum StorageCommand {
+    /// Convert the game data files to the storage version of this server
+    ///
+    /// An interrupted migration continues where it stopped, when it is run again.
+    Migrate,
 }
 
 /// The cli parser for runciv
@@ -61,17 +109,92 @@ async fn main() -> Result<(), String> {
     let cli = Cli::parse();
 
     match cli.command {
-        Command::Start => {
-            let conf = get_conf(&cli.config_path)?;
+        Command::Start {
+            ephemeral,
+            migration_dir,
+        } => {
+            let mut conf = get_conf(&cli.config_path)?;
+            let ephemeral = ephemeral.then(|| ephemeral_paths(&mut conf));
+
+            let slow_requests = Arc::new(SlowRequests::new(&conf.server));
+            let log_filters = Arc::new(setup_logging(
+                &conf.logging,
+                slow_requests
+                    .threshold_ms()
+                    .is_some()
+                    .then(|| slow_requests.clone()),
+            )?);
 
-            setup_logging(&conf.logging)?;
+            if let Some(ephemeral) = &ephemeral {
+                ephemeral.create_database(&mut conf, migration_dir).await?;
+            }
 
             let db = get_db(&conf).await?;
             info!("Connected to database");
 
+            if let Err(err) = check_storage_version(Path::new(&conf.server.game_data_path)) {
+                error!("{err}");
+                return Err(err.to_string());
+            }
+
             let ws_manager_chan = start_ws_manager(db.clone()).await?;
+            let chat_digests = Arc::new(ChatDigests::new(
+                Arc::new(ws_manager_chan.clone()),
+                conf.server.chat_digest_interval != 0,
+            ));
+            let notifier: Arc<dyn Notifier> = Arc::new(AccountWebhooks::new(
+                chat_digests.clone(),
+                db.clone(),
+                conf.account_webhooks.clone(),
+            ));
+
+            start_game_file_cleanup(
+                db.clone(),
+                conf.server.game_data_path.clone(),
+                conf.server.game_file_cleanup_interval,
+            );
+            start_lobby_idle_timeout(db.clone(), notifier.clone(), conf.server.lobby_idle_timeout);
+            start_abandoned_account_cleanup(
+                db.clone(),
+                notifier.clone(),
+                conf.abandoned_accounts.clone(),
+            );
+            start_chat_digests(
+                db.clone(),
+                chat_digests.clone(),
+                conf.server.chat_digest_interval,
+            );
+            start_chat_retention(db.clone(), conf.chat_retention.clone());
+
+            let game_data_check = if conf.server.check_game_data_on_start {
+                match check_game_data(&db, &conf.server.game_data_path).await {
+                    Ok(check) => Some(check),
+                    Err(err) => {
+                        error!("Could not check game data files: {err}");
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            let result = start_server(
+                &conf,
+                db,
+                ws_manager_chan,
+                notifier,
+                chat_digests,
+                game_data_check,
+                log_filters,
+                slow_requests,
+            )
+            .await;
+
+            if let Some(ephemeral) = ephemeral {
+                ephemeral.remove(&mut conf).await;
+            }
 
-            if let Err(err) = start_server(&conf, db, ws_manager_chan).await {
+            if let Err(err) = result {
                 error!("Error while starting server: {err}");
                 return Err(err.to_string());
             }
@@ -82,24 +205,31 @@ async fn main() -> Result<(), String> {
         }
         Command::Migrate { migration_dir } => {
             let conf = get_conf(&cli.config_path)?;
-            cli::migrate::run_migrate_custom(
-                cli_config::DatabaseConfig {
-                    last_migration_table_name: None,
-                    driver: DatabaseDriver::Postgres {
-                        host: conf.database.host,
-                        port: conf.database.port,
-                        name: conf.database.name,
-                        user: conf.database.user,
-                        password: conf.database.password,
-                    },
-                },
-                migration_dir,
-                false,
-                None,
-            )
-            .await
-            .map_err(|e| e.to_string())?;
+            migrate(&conf, migration_dir).await?;
+        }
+        Command::Setup(args) => run_setup(&cli.config_path, args).await?,
+        Command::Verify { restore } => {
+            let conf = get_conf(&cli.config_path)?;
+
+            setup_logging(&conf.logging, None)?;
+
+            let db = get_db(&conf).await?;
+
+            let verification = verify_game_data(&db, &conf.server.game_data_path, restore).await?;
+            if !verification.is_intact() {
+                return Err("Some game data files are missing or corrupt".to_string());
+            }
         }
+        Command::Storage { command } => match command {
+            StorageCommand::Migrate => {
+                let conf = get_conf(&cli.config_path)?;
+
+                setup_logging(&conf.logging, None)?;
+
+                migrate_storage(Path::new(&conf.server.game_data_path))
+                    .map_err(|err| err.to_string())?;
+            }
+        },
     }
 
     Ok(())
@@ -107,12 +237,18 @@ async fn main() -> Result<(), String> {
 
 /// Retrieve a [Config] by Path
 ///
+/// If the file doesn't exist, the configuration is built from the environment variables,
+/// if `DATABASE_URL` is set, see [config_from_env].
+///
 /// **Parameter**:
 /// - `config_path`: [&str]
 fn get_conf(config_path: &str) -> Result<Config, String> {
     let path = Path::new(config_path);
 
     if !path.exists() {
+        if let Some(config) = config_from_env()? {
+            return Ok(config);
+        }
         return Err(format!("File {config_path} does not exist"));
     }
 
@@ -129,10 +265,33 @@ fn get_conf(config_path: &str) -> Result<Config, String> {
     Ok(config)
 }
 
+/// Apply the migrations in `migration_dir` to the database of the provided config
+async fn migrate(conf: &Config, migration_dir: String) -> Result<(), String> {
+    cli::migrate::run_migrate_custom(
+        cli_config::DatabaseConfig {
+            last_migration_table_name: None,
+            driver: DatabaseDriver::Postgres {
+                host: conf.database.host.clone(),
+                port: conf.database.port,
+                name: conf.database.name.clone(),
+                user: conf.database.user.clone(),
+                password: conf.database.password.clone(),
+            },
+        },
+        migration_dir,
+        false,
+        None,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
 /// Retrieves the database using the provided config.
 ///
-/// If the connection fails, an error is returned
+/// The statements are logged if slow requests are recorded, so the slow ones can be
+/// recorded as well. If the connection fails, an error is returned
 async fn get_db(config: &Config) -> Result<Database, String> {
+    let slow_statements = config.server.slow_request_threshold > 0;
     let c = DatabaseConfiguration {
         driver: DatabaseDriver::Postgres {
             host: config.database.host.clone(),
@@ -143,9 +302,9 @@ async fn get_db(config: &Config) -> Result<Database, String> {
         },
         min_connections: 2,
         max_connections: 20,
-        disable_logging: Some(true),
-        statement_log_level: None,
-        slow_statement_log_level: None,
+        disable_logging: Some(!slow_statements),
+        statement_log_level: slow_statements.then_some(LevelFilter::Debug),
+        slow_statement_log_level: slow_statements.then_some(LevelFilter::Warn),
     };
 
     Database::connect(c)
@@ -1,8 +1,10 @@
-use rorm::fields::types::BackRef;
-use rorm::{field, Model, Patch};
+use rorm::fields::types::{BackRef, ForeignModel};
+use rorm::{field, DbEnum, Model, Patch};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::models::ChatRoomMember;
+use crate::models::{ChatRoom, ChatRoomMember};
 
 /// A user account
 #[derive(Model)]
@@ -17,6 +19,13 @@ pub struct Account {
     #[rorm(max_length = 255, unique)]
     pub username: String,
 
+    /// The NFKC normalized and lowercased username
+    ///
+    /// It's used for case-insensitive lookups and guarded by a unique index,
+    /// so usernames that only differ in case or unicode representation can't coexist.
+    #[rorm(max_length = 255, default = "")]
+    pub normalized_username: String,
+
     /// The name that is displayed for this user
     #[rorm(max_length = 255)]
     pub display_name: String,
@@ -28,15 +37,69 @@ pub struct Account {
     /// The last time the user has logged in
     pub last_login: Option<chrono::NaiveDateTime>,
 
+    /// The point in time the account was flagged as abandoned
+    ///
+    /// The flag is removed with the next login.
+    pub abandoned_since: Option<chrono::NaiveDateTime>,
+
+    /// Whether the account may use the moderation endpoints
+    ///
+    /// The flag is set by the admin endpoints, moderators can't grant it themselves.
+    #[rorm(default = false)]
+    pub moderator: bool,
+
+    /// The language incoming chat messages are translated to, if the server supports it
+    #[rorm(max_length = 16)]
+    pub chat_language: Option<String>,
+
+    /// The chatroom the welcome message was posted to after the registration
+    #[rorm(on_delete = "SetNull", on_update = "Cascade")]
+    pub welcome_chat_room: Option<ForeignModel<ChatRoom>>,
+
+    /// The accounts that may open a direct chat with this account
+    #[rorm(default = "Friends")]
+    pub allow_dms_from: DirectMessagePolicy,
+
+    /// Whether other accounts may see which of their friends are friends of this account
+    #[rorm(default = true)]
+    pub share_mutual_friends: bool,
+
+    /// The accounts that may send friend requests to this account
+    #[rorm(default = "Everyone")]
+    pub who_can_send_friend_requests: FriendRequestPolicy,
+
     /// The chat rooms this account is part of
     pub chat_rooms: BackRef<field!(ChatRoomMember::F.member)>,
 }
 
+/// The accounts that may send direct messages to an account
+#[derive(DbEnum, Deserialize, Serialize, ToSchema, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DirectMessagePolicy {
+    /// Only friends may send direct messages
+    Friends,
+    /// Every account may send direct messages
+    Everyone,
+}
+
+/// The accounts that may send friend requests to an account
+#[derive(DbEnum, Deserialize, Serialize, ToSchema, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum FriendRequestPolicy {
+    /// Every account may send friend requests
+    Everyone,
+    /// Only accounts that share a friend with the account may send friend requests
+    FriendsOfFriends,
+    /// No account may send friend requests
+    Nobody,
+}
+
 #[derive(Patch)]
 #[rorm(model = "Account")]
 pub(crate) struct AccountInsert {
     pub(crate) uuid: Uuid,
     pub(crate) username: String,
+    pub(crate) normalized_username: String,
     pub(crate) display_name: String,
     pub(crate) password_hash: String,
     pub(crate) last_login: Option<chrono::NaiveDateTime>,
@@ -0,0 +1,48 @@
+use rorm::fields::types::ForeignModel;
+use rorm::{DbEnum, Model, Patch};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::Account;
+
+/// A title the operators of the server can grant to an account
+#[derive(DbEnum, Deserialize, Serialize, ToSchema, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Badge {
+    /// The account administrates the server
+    Admin,
+    /// The account moderates the server
+    Moderator,
+    /// The account won a tournament
+    TournamentWinner,
+    /// The account supports the server
+    Supporter,
+}
+
+/// A badge that was granted to an account
+#[derive(Model)]
+pub struct AccountBadge {
+    /// Primary key of the granted badge
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The account the badge was granted to
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub account: ForeignModel<Account>,
+
+    /// The badge
+    pub badge: Badge,
+
+    /// The point in time the badge was granted
+    #[rorm(auto_create_time)]
+    pub granted_at: chrono::NaiveDateTime,
+}
+
+#[derive(Patch)]
+#[rorm(model = "AccountBadge")]
+pub(crate) struct AccountBadgeInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) account: ForeignModel<Account>,
+    pub(crate) badge: Badge,
+}
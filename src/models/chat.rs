@@ -1,5 +1,7 @@
 use rorm::fields::types::{BackRef, ForeignModel};
-use rorm::{field, Model, Patch};
+use rorm::{field, DbEnum, Model, Patch};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::models::Account;
@@ -19,6 +21,10 @@ pub struct ChatRoom {
 
     /// The uuid of the most recent message
     pub last_message_uuid: Option<Uuid>,
+
+    /// The sequence number of the most recent message
+    #[rorm(default = 0)]
+    pub last_sequence: i64,
 }
 
 #[derive(Patch)]
@@ -43,6 +49,23 @@ pub struct ChatRoomMember {
     #[rorm(on_delete = "Cascade", on_update = "Cascade")]
     pub member: ForeignModel<Account>,
 
+    /// Receive the messages of the chatroom as periodic digest
+    #[rorm(default = false)]
+    pub digest: bool,
+
+    /// The highest sequence number of the messages the member's client acknowledged
+    #[rorm(default = 0)]
+    pub acked_sequence: i64,
+
+    /// The point in time until which the member may not send messages
+    pub muted_until: Option<chrono::NaiveDateTime>,
+
+    /// The member was banned from the chatroom by an admin
+    ///
+    /// Banned members can neither send nor receive messages of the chatroom.
+    #[rorm(default = false)]
+    pub banned: bool,
+
     /// The creation time of the member in a chat aka:
     /// When has the account joined the chat
     #[rorm(auto_create_time)]
@@ -57,6 +80,47 @@ pub(crate) struct ChatRoomMemberInsert {
     pub(crate) member: ForeignModel<Account>,
 }
 
+/// A chatroom for direct messages between two accounts that are not necessarily friends
+///
+/// There is at most one direct chat per pair of accounts, no matter which of them opened it.
+#[derive(Model)]
+pub struct DirectChat {
+    /// The primary key of the direct chat
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The account that sent the first message
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub from: ForeignModel<Account>,
+
+    /// The account that received the first message
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub to: ForeignModel<Account>,
+
+    /// The chatroom of the direct chat
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub chat_room: ForeignModel<ChatRoom>,
+}
+
+#[derive(Patch)]
+#[rorm(model = "DirectChat")]
+pub(crate) struct DirectChatInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) from: ForeignModel<Account>,
+    pub(crate) to: ForeignModel<Account>,
+    pub(crate) chat_room: ForeignModel<ChatRoom>,
+}
+
+/// The type of a chat message
+#[derive(DbEnum, Deserialize, Serialize, ToSchema, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChatMessageType {
+    /// A message sent by an account
+    User,
+    /// A message generated by the server, e.g. when a player joined a lobby
+    System,
+}
+
 /// A message of a chatroom
 #[derive(Model)]
 pub struct ChatRoomMessage {
@@ -65,8 +129,14 @@ pub struct ChatRoomMessage {
     pub uuid: Uuid,
 
     /// The account that send the message
+    ///
+    /// System messages don't have a sender.
     #[rorm(on_delete = "Cascade", on_update = "Cascade")]
-    pub sender: ForeignModel<Account>,
+    pub sender: Option<ForeignModel<Account>>,
+
+    /// The type of the message
+    #[rorm(default = "User")]
+    pub message_type: ChatMessageType,
 
     /// The relation to the chat room
     #[rorm(on_delete = "Cascade", on_update = "Cascade")]
@@ -76,6 +146,12 @@ pub struct ChatRoomMessage {
     #[rorm(max_length = 2048)]
     pub message: String,
 
+    /// The position of the message in its chatroom
+    ///
+    /// Messages sent before sequence numbers were introduced have the sequence number `0`.
+    #[rorm(default = 0)]
+    pub sequence: i64,
+
     /// The timestamp when the message was received
     #[rorm(auto_create_time)]
     pub created_at: chrono::NaiveDateTime,
@@ -86,6 +162,63 @@ pub struct ChatRoomMessage {
 pub(crate) struct ChatRoomMessageInsert {
     pub(crate) uuid: Uuid,
     pub(crate) chat_room: ForeignModel<ChatRoom>,
-    pub(crate) sender: ForeignModel<Account>,
+    pub(crate) sender: Option<ForeignModel<Account>>,
+    pub(crate) message_type: ChatMessageType,
     pub(crate) message: String,
+    pub(crate) sequence: i64,
+}
+
+/// An account that was mentioned in a chat message
+#[derive(Model)]
+pub struct ChatMessageMention {
+    /// The primary key of a mention
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The message the account was mentioned in
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub message: ForeignModel<ChatRoomMessage>,
+
+    /// The mentioned account
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub account: ForeignModel<Account>,
+}
+
+#[derive(Patch)]
+#[rorm(model = "ChatMessageMention")]
+pub(crate) struct ChatMessageMentionInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) message: ForeignModel<ChatRoomMessage>,
+    pub(crate) account: ForeignModel<Account>,
+}
+
+/// The translation of a chat message into a language
+///
+/// Translations are stored, so every message is only translated once per language.
+#[derive(Model)]
+pub struct ChatMessageTranslation {
+    /// The primary key of a translation
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The translated message
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub message: ForeignModel<ChatRoomMessage>,
+
+    /// The language of the translation
+    #[rorm(max_length = 16)]
+    pub language: String,
+
+    /// The translated text
+    #[rorm(max_length = 8192)]
+    pub text: String,
+}
+
+#[derive(Patch)]
+#[rorm(model = "ChatMessageTranslation")]
+pub(crate) struct ChatMessageTranslationInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) message: ForeignModel<ChatRoomMessage>,
+    pub(crate) language: String,
+    pub(crate) text: String,
 }
@@ -0,0 +1,40 @@
+use rorm::fields::types::ForeignModel;
+use rorm::{Model, Patch};
+use uuid::Uuid;
+
+use crate::models::Account;
+
+/// A client an account is logged in with
+///
+/// Every login creates a new device, the session of the client refers to it.
+/// If the device is deleted, the session is no longer valid.
+#[derive(Model)]
+pub struct Device {
+    /// Primary key of the device
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The account that logged in with the device
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub account: ForeignModel<Account>,
+
+    /// The name of the device chosen by the client or the user
+    #[rorm(max_length = 255)]
+    pub name: Option<String>,
+
+    /// The point in time the device logged in
+    #[rorm(auto_create_time)]
+    pub created_at: chrono::NaiveDateTime,
+
+    /// The point in time the device was last used
+    pub last_seen: chrono::NaiveDateTime,
+}
+
+#[derive(Patch)]
+#[rorm(model = "Device")]
+pub(crate) struct DeviceInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) account: ForeignModel<Account>,
+    pub(crate) name: Option<String>,
+    pub(crate) last_seen: chrono::NaiveDateTime,
+}
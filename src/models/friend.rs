@@ -27,6 +27,14 @@ pub struct Friend {
     /// The chatroom of this friend request
     #[rorm(on_update = "Cascade", on_delete = "Cascade")]
     pub chat_room: Option<ForeignModel<ChatRoom>>,
+
+    /// Whether the originating user automatically accepts lobby invites of the other user
+    #[rorm(default = false)]
+    pub auto_accept_invites: bool,
+
+    /// The private alias the originating user gave the other user
+    #[rorm(max_length = 255)]
+    pub nickname: Option<String>,
 }
 
 #[derive(Patch)]
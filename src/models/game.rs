@@ -18,6 +18,10 @@ pub struct Game {
     #[rorm(default = 0)]
     pub data_id: i64,
 
+    /// Hex encoded SHA-256 checksum of the current game data
+    #[rorm(max_length = 64)]
+    pub data_checksum: Option<String>,
+
     /// Name of the game
     #[rorm(max_length = 255)]
     pub name: String,
@@ -39,6 +43,43 @@ pub struct Game {
     /// The chatroom of the game
     #[rorm(on_update = "Cascade", on_delete = "Cascade")]
     pub chat_room: ForeignModel<ChatRoom>,
+
+    /// The player administrating the game
+    ///
+    /// This is the owner of the lobby the game originated from.
+    #[rorm(on_update = "Cascade", on_delete = "SetNull")]
+    pub host: Option<ForeignModel<Account>>,
+
+    /// The player whose turn it is
+    ///
+    /// The server doesn't know the turn order, so this is only set if a client
+    /// provided the next player when uploading a game state or ending a turn.
+    #[rorm(on_update = "Cascade", on_delete = "SetNull")]
+    pub current_player: Option<ForeignModel<Account>>,
+
+    /// The player that ended their turn, but has not uploaded the game state yet
+    #[rorm(on_update = "Cascade", on_delete = "SetNull")]
+    pub pending_upload_by: Option<ForeignModel<Account>>,
+
+    /// The point in time until which the pending game state should be uploaded
+    pub pending_upload_until: Option<chrono::NaiveDateTime>,
+
+    /// The turn of the current game state, if it was provided by the uploader
+    pub turn: Option<i32>,
+
+    /// The in-game year of the current game state, negative years are BC
+    pub year: Option<i32>,
+
+    /// The era the leading player of the current game state is in
+    #[rorm(max_length = 64)]
+    pub era: Option<String>,
+
+    /// The number of players that are not defeated in the current game state
+    pub alive_players: Option<i16>,
+
+    /// Whether the players only see their own nation until the host reveals all nations
+    #[rorm(default = false)]
+    pub hidden_identities: bool,
 }
 
 #[derive(Patch)]
@@ -49,6 +90,48 @@ pub(crate) struct GameInsert {
     pub(crate) max_players: i16,
     pub(crate) updated_by: ForeignModel<Account>,
     pub(crate) chat_room: ForeignModel<ChatRoom>,
+    pub(crate) host: Option<ForeignModel<Account>>,
+    pub(crate) hidden_identities: bool,
+}
+
+/// The settings of a game
+#[derive(Model)]
+pub struct GameSettings {
+    /// Primary key of the game settings
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The game the settings belong to
+    #[rorm(unique, on_delete = "Cascade", on_update = "Cascade")]
+    pub game: ForeignModel<Game>,
+
+    /// The time in seconds a player has to finish a turn
+    pub turn_timer: Option<i32>,
+
+    /// Whether accounts that are not playing may watch the game
+    pub allow_spectators: bool,
+
+    /// Whether players may join the game after it has started
+    pub allow_late_joins: bool,
+
+    /// Whether the game is visible to accounts that are not playing
+    pub public: bool,
+
+    /// Whether players that leave the game are granted an observer token to keep watching it
+    #[rorm(default = false)]
+    pub grant_observer_tokens: bool,
+}
+
+#[derive(Patch)]
+#[rorm(model = "GameSettings")]
+pub(crate) struct GameSettingsInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) game: ForeignModel<Game>,
+    pub(crate) turn_timer: Option<i32>,
+    pub(crate) allow_spectators: bool,
+    pub(crate) allow_late_joins: bool,
+    pub(crate) public: bool,
+    pub(crate) grant_observer_tokens: bool,
 }
 
 /// The m2m relation between games and accounts
@@ -65,6 +148,10 @@ pub struct GameAccount {
     /// The player account in the game
     #[rorm(on_delete = "Cascade", on_update = "Cascade")]
     pub player: ForeignModel<Account>,
+
+    /// The nation the player has chosen in the lobby the game originated from
+    #[rorm(max_length = 255)]
+    pub nation: Option<String>,
 }
 
 #[derive(Patch)]
@@ -74,3 +161,12 @@ pub(crate) struct GameAccountInsert {
     pub(crate) game: ForeignModel<Game>,
     pub(crate) player: ForeignModel<Account>,
 }
+
+#[derive(Patch)]
+#[rorm(model = "GameAccount")]
+pub(crate) struct GameAccountWithNationInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) game: ForeignModel<Game>,
+    pub(crate) player: ForeignModel<Account>,
+    pub(crate) nation: Option<String>,
+}
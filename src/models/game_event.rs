@@ -0,0 +1,63 @@
+use rorm::fields::types::ForeignModel;
+use rorm::{DbEnum, Model, Patch};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::{Account, Game};
+
+/// The kind of an event that happened in a game
+#[derive(DbEnum, Deserialize, Serialize, ToSchema, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum GameEventKind {
+    /// The game was started from a lobby
+    Started,
+    /// A new game state was uploaded
+    Uploaded,
+}
+
+/// An event observed by the server in a game
+#[derive(Model)]
+pub struct GameEvent {
+    /// The primary key of an event
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The game the event happened in
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub game: ForeignModel<Game>,
+
+    /// The kind of the event
+    pub kind: GameEventKind,
+
+    /// The account that caused the event
+    #[rorm(on_delete = "SetNull", on_update = "Cascade")]
+    pub actor: Option<ForeignModel<Account>>,
+
+    /// The account that was affected by the event
+    #[rorm(on_delete = "SetNull", on_update = "Cascade")]
+    pub subject: Option<ForeignModel<Account>>,
+
+    /// The state identifier of the game data at the time of the event
+    pub data_id: Option<i64>,
+
+    /// The note the actor left for the other players, e.g. with an uploaded game state
+    #[rorm(max_length = 255)]
+    pub note: Option<String>,
+
+    /// The point in time the event happened
+    #[rorm(auto_create_time)]
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Patch)]
+#[rorm(model = "GameEvent")]
+pub(crate) struct GameEventInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) game: ForeignModel<Game>,
+    pub(crate) kind: GameEventKind,
+    pub(crate) actor: Option<ForeignModel<Account>>,
+    pub(crate) subject: Option<ForeignModel<Account>>,
+    pub(crate) data_id: Option<i64>,
+    pub(crate) note: Option<String>,
+}
@@ -0,0 +1,50 @@
+use rorm::fields::types::ForeignModel;
+use rorm::{Model, Patch};
+use uuid::Uuid;
+
+use crate::models::{Account, Game};
+
+/// A named snapshot of the state of a game
+///
+/// The game data of a snapshot is stored in the file system, its filename is
+/// derived from the `uuid` of the game and the `uuid` of the snapshot.
+#[derive(Model)]
+pub struct GameSnapshot {
+    /// The primary key of a snapshot
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The game the snapshot was taken of
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub game: ForeignModel<Game>,
+
+    /// The label of the snapshot
+    #[rorm(max_length = 255)]
+    pub label: String,
+
+    /// The state identifier of the game data the snapshot was taken of
+    pub data_id: i64,
+
+    /// The hex encoded SHA-256 checksum of the game data of the snapshot
+    #[rorm(max_length = 64)]
+    pub data_checksum: Option<String>,
+
+    /// The account that created the snapshot
+    #[rorm(on_delete = "SetNull", on_update = "Cascade")]
+    pub created_by: Option<ForeignModel<Account>>,
+
+    /// The point in time the snapshot was created
+    #[rorm(auto_create_time)]
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Patch)]
+#[rorm(model = "GameSnapshot")]
+pub(crate) struct GameSnapshotInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) game: ForeignModel<Game>,
+    pub(crate) label: String,
+    pub(crate) data_id: i64,
+    pub(crate) data_checksum: Option<String>,
+    pub(crate) created_by: Option<ForeignModel<Account>>,
+}
@@ -0,0 +1,66 @@
+use rorm::fields::types::ForeignModel;
+use rorm::{Model, Patch};
+use uuid::Uuid;
+
+use crate::models::{Account, Game};
+
+/// Statistics of a game
+///
+/// They are updated every time a new game state is uploaded.
+#[derive(Model)]
+pub struct GameStats {
+    /// The primary key of the statistics
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The game the statistics belong to
+    #[rorm(unique, on_delete = "Cascade", on_update = "Cascade")]
+    pub game: ForeignModel<Game>,
+
+    /// The number of uploaded game states
+    pub uploads: i64,
+
+    /// The point in time the first game state was uploaded
+    pub first_upload_at: chrono::NaiveDateTime,
+
+    /// The point in time the last game state was uploaded
+    pub last_upload_at: chrono::NaiveDateTime,
+}
+
+#[derive(Patch)]
+#[rorm(model = "GameStats")]
+pub(crate) struct GameStatsInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) game: ForeignModel<Game>,
+    pub(crate) uploads: i64,
+    pub(crate) first_upload_at: chrono::NaiveDateTime,
+    pub(crate) last_upload_at: chrono::NaiveDateTime,
+}
+
+/// Statistics of a player in a game
+#[derive(Model)]
+pub struct GamePlayerStats {
+    /// The primary key of the statistics
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The game the statistics belong to
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub game: ForeignModel<Game>,
+
+    /// The player the statistics belong to
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub player: ForeignModel<Account>,
+
+    /// The number of game states the player uploaded
+    pub uploads: i64,
+}
+
+#[derive(Patch)]
+#[rorm(model = "GamePlayerStats")]
+pub(crate) struct GamePlayerStatsInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) game: ForeignModel<Game>,
+    pub(crate) player: ForeignModel<Account>,
+    pub(crate) uploads: i64,
+}
@@ -2,7 +2,7 @@ use rorm::fields::types::ForeignModel;
 use rorm::{Model, Patch};
 use uuid::Uuid;
 
-use crate::models::{Account, Lobby};
+use crate::models::{Account, Game, Lobby};
 
 /// Representation of an invite to a lobby.
 ///
@@ -38,3 +38,36 @@ pub(crate) struct InviteInsert {
     pub(crate) to: ForeignModel<Account>,
     pub(crate) lobby: ForeignModel<Lobby>,
 }
+
+/// Representation of an invite to take over an empty seat of a running game.
+#[derive(Model)]
+pub struct GameInvite {
+    /// The primary key of an invite
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The user that has invoked the invite
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub from: ForeignModel<Account>,
+
+    /// The invitee
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub to: ForeignModel<Account>,
+
+    /// The game
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub game: ForeignModel<Game>,
+
+    /// The point in time the invite was created
+    #[rorm(auto_create_time)]
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Patch)]
+#[rorm(model = "GameInvite")]
+pub(crate) struct GameInviteInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) from: ForeignModel<Account>,
+    pub(crate) to: ForeignModel<Account>,
+    pub(crate) game: ForeignModel<Game>,
+}
@@ -31,6 +31,9 @@ pub struct Lobby {
     /// The maximum count of players
     pub max_player: i16,
 
+    /// The minimum count of players that is required to start the game
+    pub min_player: Option<i16>,
+
     /// The chatroom of the lobby
     #[rorm(on_update = "Cascade", on_delete = "Cascade")]
     pub chat_room: ForeignModel<ChatRoom>,
@@ -38,6 +41,82 @@ pub struct Lobby {
     /// The point in time, the lobby was created
     #[rorm(auto_create_time)]
     pub created_at: chrono::NaiveDateTime,
+
+    /// The point in time, a player joined the lobby or a message was sent to its chatroom
+    #[rorm(auto_create_time)]
+    pub last_activity: chrono::NaiveDateTime,
+
+    /// The time in seconds a player has to finish a turn in the started game
+    pub turn_timer: Option<i32>,
+
+    /// Whether accounts that are not playing may watch the started game
+    #[rorm(default = false)]
+    pub allow_spectators: bool,
+
+    /// Whether players may join the started game later on
+    #[rorm(default = false)]
+    pub allow_late_joins: bool,
+
+    /// Whether the started game is visible to accounts that are not playing
+    #[rorm(default = true)]
+    pub public_game: bool,
+
+    /// Whether all players must be ready before the game can be started
+    #[rorm(default = false)]
+    pub require_ready: bool,
+
+    /// Whether the game is started automatically once the last free slot is taken
+    #[rorm(default = false)]
+    pub auto_start: bool,
+
+    /// Whether the lobby is hidden from the list of open lobbies
+    ///
+    /// Hidden lobbies can only be joined by an invite or with their join code.
+    #[rorm(default = false)]
+    pub hidden: bool,
+
+    /// The code to join a hidden lobby
+    #[rorm(max_length = 16, unique)]
+    pub join_code: Option<String>,
+
+    /// The base ruleset of the game
+    #[rorm(max_length = 255)]
+    pub ruleset: Option<String>,
+
+    /// The size of the map of the game
+    #[rorm(max_length = 255)]
+    pub map_size: Option<String>,
+
+    /// The speed of the game
+    #[rorm(max_length = 255)]
+    pub game_speed: Option<String>,
+
+    /// The mods that are required to play the game
+    pub mods: BackRef<field!(LobbyMod::F.lobby)>,
+
+    /// Whether only the allowed accounts may join the lobby
+    #[rorm(default = false)]
+    pub restricted: bool,
+
+    /// Whether the friends of the owner may join a restricted lobby as well
+    #[rorm(default = false)]
+    pub allow_friends_of_owner: bool,
+
+    /// The accounts that may join a restricted lobby
+    pub allowed_accounts: BackRef<field!(LobbyAllowedAccount::F.lobby)>,
+
+    /// The accounts that were banned from the lobby
+    pub bans: BackRef<field!(LobbyBan::F.lobby)>,
+
+    /// The nation the owner has chosen to play
+    #[rorm(max_length = 255)]
+    pub owner_nation: Option<String>,
+
+    /// Whether the members only see their own nation
+    ///
+    /// The setting is carried over to the game started from the lobby.
+    #[rorm(default = false)]
+    pub hidden_identities: bool,
 }
 
 #[derive(Patch)]
@@ -49,6 +128,21 @@ pub(crate) struct LobbyInsert {
     pub(crate) password_hash: Option<String>,
     pub(crate) chat_room: ForeignModel<ChatRoom>,
     pub(crate) max_player: i16,
+    pub(crate) min_player: Option<i16>,
+    pub(crate) turn_timer: Option<i32>,
+    pub(crate) allow_spectators: bool,
+    pub(crate) allow_late_joins: bool,
+    pub(crate) public_game: bool,
+    pub(crate) require_ready: bool,
+    pub(crate) auto_start: bool,
+    pub(crate) hidden: bool,
+    pub(crate) join_code: Option<String>,
+    pub(crate) ruleset: Option<String>,
+    pub(crate) map_size: Option<String>,
+    pub(crate) game_speed: Option<String>,
+    pub(crate) restricted: bool,
+    pub(crate) allow_friends_of_owner: bool,
+    pub(crate) hidden_identities: bool,
 }
 
 /// The m2m relation between lobby and accounts
@@ -65,6 +159,24 @@ pub struct LobbyAccount {
     /// The account in the lobby
     #[rorm(on_delete = "Cascade", on_update = "Cascade")]
     pub player: ForeignModel<Account>,
+
+    /// The account that invited the player, if the player joined by accepting an invite
+    #[rorm(on_delete = "SetNull", on_update = "Cascade")]
+    pub invited_by: Option<ForeignModel<Account>>,
+
+    /// Whether the player is ready to start the game
+    #[rorm(default = false)]
+    pub ready: bool,
+
+    /// The nation the player has chosen to play
+    #[rorm(max_length = 255)]
+    pub nation: Option<String>,
+
+    /// Whether the owner made the player a co-host
+    ///
+    /// Co-hosts may change the settings of the lobby and kick players that are not co-hosts.
+    #[rorm(default = false)]
+    pub co_host: bool,
 }
 
 #[derive(Patch)]
@@ -74,3 +186,88 @@ pub(crate) struct LobbyAccountInsert {
     pub(crate) lobby: ForeignModel<Lobby>,
     pub(crate) player: ForeignModel<Account>,
 }
+
+#[derive(Patch)]
+#[rorm(model = "LobbyAccount")]
+pub(crate) struct LobbyAccountWithInviteInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) lobby: ForeignModel<Lobby>,
+    pub(crate) player: ForeignModel<Account>,
+    pub(crate) invited_by: Option<ForeignModel<Account>>,
+}
+
+/// A mod that is required to play the game of a lobby
+#[derive(Model)]
+pub struct LobbyMod {
+    /// Primary key of a lobby mod
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The lobby that requires the mod
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub lobby: ForeignModel<Lobby>,
+
+    /// The name of the mod
+    #[rorm(max_length = 255)]
+    pub name: String,
+}
+
+#[derive(Patch)]
+#[rorm(model = "LobbyMod")]
+pub(crate) struct LobbyModInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) lobby: ForeignModel<Lobby>,
+    pub(crate) name: String,
+}
+
+/// An account that may join a restricted lobby
+#[derive(Model)]
+pub struct LobbyAllowedAccount {
+    /// Primary key of an allowed account
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The restricted lobby
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub lobby: ForeignModel<Lobby>,
+
+    /// The account that may join the lobby
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub account: ForeignModel<Account>,
+}
+
+#[derive(Patch)]
+#[rorm(model = "LobbyAllowedAccount")]
+pub(crate) struct LobbyAllowedAccountInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) lobby: ForeignModel<Lobby>,
+    pub(crate) account: ForeignModel<Account>,
+}
+
+/// An account that was banned from a lobby
+#[derive(Model)]
+pub struct LobbyBan {
+    /// Primary key of a ban
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The lobby the account was banned from
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub lobby: ForeignModel<Lobby>,
+
+    /// The banned account
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub account: ForeignModel<Account>,
+
+    /// The point in time the account was banned
+    #[rorm(auto_create_time)]
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Patch)]
+#[rorm(model = "LobbyBan")]
+pub(crate) struct LobbyBanInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) lobby: ForeignModel<Lobby>,
+    pub(crate) account: ForeignModel<Account>,
+}
@@ -1,15 +1,33 @@
 //! All the database models live here.
 
 pub use account::*;
+pub use badge::*;
 pub use chat::*;
+pub use device::*;
 pub use friend::*;
 pub use game::*;
+pub use game_event::*;
+pub use game_snapshot::*;
+pub use game_stats::*;
 pub use invite::*;
 pub use lobby::*;
+pub use negotiation::*;
+pub use observer_token::*;
+pub use webhook::*;
+pub use welcome_message::*;
 
 mod account;
+mod badge;
 mod chat;
+mod device;
 mod friend;
 mod game;
+mod game_event;
+mod game_snapshot;
+mod game_stats;
 mod invite;
 mod lobby;
+mod negotiation;
+mod observer_token;
+mod webhook;
+mod welcome_message;
@@ -0,0 +1,81 @@
+use rorm::fields::types::ForeignModel;
+use rorm::{DbEnum, Model, Patch};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::{Account, Game};
+
+/// The kind of a negotiation
+#[derive(DbEnum, Deserialize, Serialize, ToSchema, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum NegotiationKind {
+    /// An exchange of goods, resources or gold
+    Trade,
+    /// A diplomatic pact like an alliance or a peace treaty
+    Pact,
+}
+
+/// The state of a negotiation
+#[derive(DbEnum, Deserialize, Serialize, ToSchema, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum NegotiationState {
+    /// The negotiation was proposed, but the recipient has not answered yet
+    Pending,
+    /// The recipient accepted the negotiation
+    Accepted,
+    /// The recipient declined the negotiation
+    Declined,
+}
+
+/// A proposal of a trade or pact between two players of a game
+#[derive(Model)]
+pub struct Negotiation {
+    /// The primary key of a negotiation
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The game this negotiation belongs to
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub game: ForeignModel<Game>,
+
+    /// The player that proposed the negotiation
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub from: ForeignModel<Account>,
+
+    /// The player the negotiation was proposed to
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub to: ForeignModel<Account>,
+
+    /// The kind of the negotiation
+    pub kind: NegotiationKind,
+
+    /// The terms of the negotiation.
+    ///
+    /// They are defined by the client and opaque to the server.
+    #[rorm(max_length = 4096)]
+    pub terms: String,
+
+    /// The current state of the negotiation
+    pub state: NegotiationState,
+
+    /// The point in time the negotiation was proposed
+    #[rorm(auto_create_time)]
+    pub created_at: chrono::NaiveDateTime,
+
+    /// The point in time after which the negotiation can no longer be answered
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Patch)]
+#[rorm(model = "Negotiation")]
+pub(crate) struct NegotiationInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) game: ForeignModel<Game>,
+    pub(crate) from: ForeignModel<Account>,
+    pub(crate) to: ForeignModel<Account>,
+    pub(crate) kind: NegotiationKind,
+    pub(crate) terms: String,
+    pub(crate) state: NegotiationState,
+    pub(crate) expires_at: chrono::NaiveDateTime,
+}
@@ -0,0 +1,44 @@
+use rorm::fields::types::ForeignModel;
+use rorm::{Model, Patch};
+use uuid::Uuid;
+
+use crate::models::{Account, Game};
+
+/// A token that lets a former player watch a game they left
+///
+/// The token itself is only handed out once, the SHA-256 hash of it is stored.
+#[derive(Model)]
+pub struct ObserverToken {
+    /// The primary key of an observer token
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The game that can be watched
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub game: ForeignModel<Game>,
+
+    /// The former player the token was granted to
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub account: ForeignModel<Account>,
+
+    /// Hex encoded SHA-256 hash of the token
+    #[rorm(max_length = 64, unique)]
+    pub token_hash: String,
+
+    /// The point in time after which the token can't be used anymore
+    pub expires_at: chrono::NaiveDateTime,
+
+    /// The point in time the token was granted
+    #[rorm(auto_create_time)]
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Patch)]
+#[rorm(model = "ObserverToken")]
+pub(crate) struct ObserverTokenInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) game: ForeignModel<Game>,
+    pub(crate) account: ForeignModel<Account>,
+    pub(crate) token_hash: String,
+    pub(crate) expires_at: chrono::NaiveDateTime,
+}
@@ -0,0 +1,41 @@
+use rorm::fields::types::ForeignModel;
+use rorm::{Model, Patch};
+use uuid::Uuid;
+
+use crate::models::Account;
+
+/// The personal webhook of an account
+///
+/// The events of the account are posted to the url, signed with the secret.
+/// Every account has at most one webhook.
+#[derive(Model)]
+pub struct AccountWebhook {
+    /// The primary key of the webhook
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The account the webhook belongs to
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub account: ForeignModel<Account>,
+
+    /// The url the events are posted to
+    #[rorm(max_length = 2048)]
+    pub url: String,
+
+    /// The secret the events are signed with
+    #[rorm(max_length = 255)]
+    pub secret: String,
+
+    /// The point in time the webhook was registered
+    #[rorm(auto_create_time)]
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Patch)]
+#[rorm(model = "AccountWebhook")]
+pub(crate) struct AccountWebhookInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) account: ForeignModel<Account>,
+    pub(crate) url: String,
+    pub(crate) secret: String,
+}
@@ -0,0 +1,29 @@
+use rorm::{Model, Patch};
+use uuid::Uuid;
+
+/// The message new accounts receive after their registration
+///
+/// The table holds at most one row, which is managed by the admin API.
+#[derive(Model)]
+pub struct WelcomeMessage {
+    /// The primary key of the welcome message
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The template of the message
+    ///
+    /// `{display_name}` and `{username}` are replaced with the data of the new account.
+    #[rorm(max_length = 1024)]
+    pub message: String,
+
+    /// The point in time the message was changed the last time
+    #[rorm(auto_create_time, auto_update_time)]
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Patch)]
+#[rorm(model = "WelcomeMessage")]
+pub(crate) struct WelcomeMessageInsert {
+    pub(crate) uuid: Uuid,
+    pub(crate) message: String,
+}
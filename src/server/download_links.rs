@@ -0,0 +1,56 @@
+//! Signing of download links for game data
+//!
+//! A download link grants access to a single state of a single game until it expires,
+//! without a session. The signature is an HMAC-SHA256 of the game, the state and the
+//! expiration with a key derived from the secret key of the server.
+
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Creates and verifies the signatures of download links
+pub struct DownloadLinks {
+    key: Vec<u8>,
+    lifetime: Duration,
+}
+
+impl DownloadLinks {
+    /// Create a signer with a key and the time in seconds links are valid
+    pub fn new(key: &[u8], lifetime: u64) -> Self {
+        Self {
+            key: key.to_vec(),
+            lifetime: Duration::seconds(lifetime as i64),
+        }
+    }
+
+    fn mac(&self, game: Uuid, data_id: u64, expires: i64) -> Hmac<Sha256> {
+        // Ok as HMAC accepts keys of any length
+        #[allow(clippy::unwrap_used)]
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).unwrap();
+        mac.update(format!("download:{game}:{data_id}:{expires}").as_bytes());
+        mac
+    }
+
+    /// Sign a link to a state of a game
+    ///
+    /// Returns the point in time the link expires and the hex encoded signature.
+    pub fn sign(&self, game: Uuid, data_id: u64) -> (DateTime<Utc>, String) {
+        let expires_at = Utc::now() + self.lifetime;
+        let signature = self.mac(game, data_id, expires_at.timestamp());
+        (expires_at, hex::encode(signature.finalize().into_bytes()))
+    }
+
+    /// Check that a link was signed by this server and hasn't expired yet
+    pub fn verify(&self, game: Uuid, data_id: u64, expires: i64, signature: &str) -> bool {
+        if expires < Utc::now().timestamp() {
+            return false;
+        }
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        self.mac(game, data_id, expires)
+            .verify_slice(&signature)
+            .is_ok()
+    }
+}
@@ -5,6 +5,8 @@ use std::io;
 
 use actix_web::cookie::KeyError;
 
+use crate::storage::StorageError;
+
 /// The errors that can occur during server startup
 #[derive(Debug)]
 pub enum StartServerError {
@@ -14,6 +16,8 @@ pub enum StartServerError {
     InvalidSecretKey,
     /// Invalid admin token was found
     InvalidAdminToken,
+    /// The game data directory could not be initialized
+    Storage(StorageError),
 }
 
 impl Display for StartServerError {
@@ -26,6 +30,7 @@ impl Display for StartServerError {
                     Consider using the subcommand keygen and update your configuration file"
             ),
             StartServerError::InvalidAdminToken => write!(f, "Invalid admin token was specified"),
+            StartServerError::Storage(err) => write!(f, "{err}"),
         }
     }
 }
@@ -47,3 +52,9 @@ impl From<KeyError> for StartServerError {
         Self::InvalidSecretKey
     }
 }
+
+impl From<StorageError> for StartServerError {
+    fn from(value: StorageError) -> Self {
+        Self::Storage(value)
+    }
+}
@@ -0,0 +1,94 @@
+//! This module holds the admin endpoint to review the accounts flagged as abandoned
+
+use actix_web::get;
+use actix_web::web::{Data, Json};
+use chrono::{DateTime, Utc};
+use rorm::{query, Database, FieldAccess, Model};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::models::Account;
+use crate::server::handler::{AccountResponse, ApiErrorResponse, ApiResult};
+use crate::server::RuntimeSettings;
+use crate::tasks::abandoned_account_deadline;
+
+/// An account that was flagged as abandoned
+#[derive(Serialize, ToSchema)]
+pub struct AbandonedAccountResponse {
+    account: AccountResponse,
+    /// The last time the account has logged in
+    last_login: Option<DateTime<Utc>>,
+    /// The point in time the account was flagged
+    abandoned_since: DateTime<Utc>,
+    /// The point in time the account is anonymized or deleted, if it doesn't log in before
+    deadline: DateTime<Utc>,
+}
+
+/// The accounts that were flagged as abandoned
+#[derive(Serialize, ToSchema)]
+pub struct GetAbandonedAccountsResponse {
+    accounts: Vec<AbandonedAccountResponse>,
+}
+
+/// Retrieve the accounts that were flagged as abandoned
+///
+/// The server can't contact the accounts itself, so operators may use this list to notify
+/// the owners before the accounts are anonymized or deleted. Logging in removes the flag.
+///
+/// The list is empty if the cleanup of abandoned accounts is not configured.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Returns the abandoned accounts", body = GetAbandonedAccountsResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/accounts/abandoned")]
+pub async fn get_abandoned_accounts(
+    db: Data<Database>,
+    runtime_settings: Data<RuntimeSettings>,
+) -> ApiResult<Json<GetAbandonedAccountsResponse>> {
+    let Some(config) = &runtime_settings.abandoned_accounts else {
+        return Ok(Json(GetAbandonedAccountsResponse { accounts: vec![] }));
+    };
+
+    let mut accounts: Vec<_> = query!(
+        db.as_ref(),
+        (
+            Account::F.uuid,
+            Account::F.username,
+            Account::F.display_name,
+            Account::F.last_login,
+            Account::F.abandoned_since
+        )
+    )
+    .all()
+    .await?
+    .into_iter()
+    .filter_map(
+        |(uuid, username, display_name, last_login, abandoned_since)| {
+            let abandoned_since = abandoned_since?;
+            Some(AbandonedAccountResponse {
+                account: AccountResponse {
+                    uuid,
+                    username,
+                    display_name,
+                },
+                last_login: last_login
+                    .map(|last_login| DateTime::from_naive_utc_and_offset(last_login, Utc)),
+                abandoned_since: DateTime::from_naive_utc_and_offset(abandoned_since, Utc),
+                deadline: DateTime::from_naive_utc_and_offset(
+                    abandoned_account_deadline(config, abandoned_since),
+                    Utc,
+                ),
+            })
+        },
+    )
+    .collect();
+    accounts.sort_by_key(|account| account.deadline);
+
+    Ok(Json(GetAbandonedAccountsResponse { accounts }))
+}
@@ -0,0 +1,85 @@
+//! This module holds the admin endpoint to export all accounts
+
+use actix_web::get;
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use rorm::{query, Database, FieldAccess, Model};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::Account;
+use crate::server::handler::ApiErrorResponse;
+use crate::server::ndjson::ndjson_response;
+
+/// An account in the export of all accounts
+#[derive(Serialize, ToSchema)]
+pub struct ExportedAccount {
+    uuid: Uuid,
+    #[schema(example = "user123")]
+    username: String,
+    #[schema(example = "Herbert")]
+    display_name: String,
+    /// The last time the account has logged in
+    last_login: Option<DateTime<Utc>>,
+    /// Whether the account is a moderator
+    moderator: bool,
+    /// The point in time the account was flagged as abandoned
+    abandoned_since: Option<DateTime<Utc>>,
+}
+
+/// Export all accounts
+///
+/// The accounts are streamed as newline delimited JSON (NDJSON), one [ExportedAccount] per line,
+/// so the export doesn't have to fit into memory. If an error occurs while streaming,
+/// the response is aborted.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Returns one account per line", content_type = "application/x-ndjson", body = ExportedAccount),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/accounts/export")]
+pub async fn export_accounts(db: Data<Database>) -> HttpResponse {
+    let db = db.into_inner();
+
+    ndjson_response(|sender| async move {
+        let mut accounts = query!(
+            db.as_ref(),
+            (
+                Account::F.uuid,
+                Account::F.username,
+                Account::F.display_name,
+                Account::F.last_login,
+                Account::F.moderator,
+                Account::F.abandoned_since
+            )
+        )
+        .stream();
+
+        while let Some((uuid, username, display_name, last_login, moderator, abandoned_since)) =
+            accounts.try_next().await?
+        {
+            let account = ExportedAccount {
+                uuid,
+                username,
+                display_name,
+                last_login: last_login.map(|x| DateTime::from_naive_utc_and_offset(x, Utc)),
+                moderator,
+                abandoned_since: abandoned_since
+                    .map(|x| DateTime::from_naive_utc_and_offset(x, Utc)),
+            };
+            if !sender.send(&account).await {
+                break;
+            }
+        }
+
+        Ok(())
+    })
+}
@@ -1,20 +1,39 @@
 //! All handlers for the account endpoints live in here
 
+use std::collections::{HashMap, HashSet};
+
 use actix_toolbox::tb_middleware::Session;
-use actix_web::web::{Data, Json, Path};
+use actix_web::web::{Data, Json, Path, Query};
 use actix_web::{delete, get, post, put, HttpResponse};
 use argon2::password_hash::{Error, SaltString};
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use log::{error, warn};
 use rand::thread_rng;
-use rorm::{insert, query, update, Database, FieldAccess, Model};
+use rorm::{and, insert, query, update, Database, FieldAccess, Model};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use unicode_normalization::UnicodeNormalization;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use crate::chan::{WsManagerChan, WsManagerMessage, WsMessage};
-use crate::models::{Account, AccountInsert};
-use crate::server::handler::{ApiError, ApiErrorResponse, ApiResult, PathUuid};
+use crate::chan::{Notifier, WsMessage};
+use crate::models::{
+    Account, AccountInsert, Badge, DirectMessagePolicy, Friend, FriendRequestPolicy,
+};
+use crate::server::handler::{
+    is_unique_violation, query_badges, ApiError, ApiErrorResponse, ApiResult, PaginationQuery,
+    PathUuid,
+};
+use crate::service::account_stats::{AccountStats, AccountStatsCache};
+use crate::service::membership::{lobbies_of, LobbyMembers};
+use crate::service::notify::Outbox;
+use crate::service::policy::{check_policy, PolicyAction, PolicyValidator};
+use crate::service::welcome::post_welcome_message;
+
+/// Normalize a username for case-insensitive comparisons
+///
+/// The username is NFKC normalized and lowercased.
+pub(crate) fn normalize_username(username: &str) -> String {
+    username.nfkc().collect::<String>().to_lowercase()
+}
 
 /// The content to register a new account
 #[derive(Debug, Deserialize, ToSchema)]
@@ -28,6 +47,12 @@ pub struct AccountRegistrationRequest {
 }
 
 /// Register a new account
+///
+/// If the server operator configured a welcome message, it is posted to a new chatroom
+/// of the account. The chatroom is listed in the `system_chat_rooms` of `GET /api/v2/chats`.
+///
+/// If the policy validator of the server rejects the registration, [ApiError::Policy]
+/// is returned.
 #[utoipa::path(
     tag = "Accounts",
     responses(
@@ -41,7 +66,18 @@ pub struct AccountRegistrationRequest {
 pub async fn register_account(
     req: Json<AccountRegistrationRequest>,
     db: Data<Database>,
+    notifier: Data<dyn Notifier>,
+    policy_validator: Data<Option<PolicyValidator>>,
 ) -> ApiResult<HttpResponse> {
+    check_policy(
+        &policy_validator,
+        PolicyAction::RegisterAccount {
+            username: &req.username,
+            display_name: &req.display_name,
+        },
+    )
+    .await?;
+
     let mut tx = db.start_transaction().await?;
 
     if req.username.is_empty() {
@@ -52,8 +88,9 @@ pub async fn register_account(
         return Err(ApiError::InvalidDisplayName);
     }
 
+    let normalized_username = normalize_username(&req.username);
     if query!(&mut tx, (Account::F.uuid,))
-        .condition(Account::F.username.equals(&req.username))
+        .condition(Account::F.normalized_username.equals(&normalized_username))
         .optional()
         .await?
         .is_some()
@@ -71,14 +108,29 @@ pub async fn register_account(
         .single(&AccountInsert {
             uuid,
             username: req.username.clone(),
+            normalized_username,
             display_name: req.display_name.clone(),
             password_hash,
             last_login: None,
         })
-        .await?;
+        .await
+        .map_err(|err| {
+            // A concurrent registration with the same username may have happened
+            // since the check above
+            if is_unique_violation(&err) {
+                ApiError::UsernameAlreadyOccupied
+            } else {
+                ApiError::DatabaseError(err)
+            }
+        })?;
+
+    let mut outbox = Outbox::new();
+    post_welcome_message(&mut tx, &mut outbox, uuid, &req.username, &req.display_name).await?;
 
     tx.commit().await?;
 
+    outbox.send(notifier.get_ref()).await;
+
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -103,19 +155,80 @@ pub struct OnlineAccountResponse {
     pub(crate) online: bool,
 }
 
+impl OnlineAccountResponse {
+    /// Create the response of an account whose online state is filled in later
+    ///
+    /// See [fill_online_states].
+    pub(crate) fn offline(account: AccountResponse) -> Self {
+        Self {
+            uuid: account.uuid,
+            username: account.username,
+            display_name: account.display_name,
+            online: false,
+        }
+    }
+}
+
+/// A part of a response that carries the online state of an account
+pub(crate) trait WithOnlineState {
+    /// The account whose online state is carried
+    fn account_uuid(&self) -> Uuid;
+
+    /// Set the online state of the account
+    fn set_online(&mut self, online: bool);
+}
+
+impl WithOnlineState for OnlineAccountResponse {
+    fn account_uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    fn set_online(&mut self, online: bool) {
+        self.online = online;
+    }
+}
+
+/// Fill in the online states of the accounts of a response
+///
+/// The states of all accounts are retrieved with a single request to the notifier,
+/// so this should be called once per response instead of once per account.
+pub(crate) async fn fill_online_states<'a, T: WithOnlineState + 'a>(
+    notifier: &dyn Notifier,
+    items: impl IntoIterator<Item = &'a mut T>,
+) -> ApiResult<()> {
+    let mut items: Vec<&mut T> = items.into_iter().collect();
+    if items.is_empty() {
+        return Ok(());
+    }
+
+    let online_states = notifier
+        .online_states(items.iter().map(|x| x.account_uuid()).collect())
+        .await?;
+    for (item, online) in items.iter_mut().zip(online_states) {
+        item.set_online(online);
+    }
+
+    Ok(())
+}
+
 /// Returns the account that is currently logged-in
+///
+/// `badges` contains the badges that were granted to the account, `stats` is never set.
 #[utoipa::path(
     tag = "Accounts",
     context_path = "/api/v2",
     responses(
-        (status = 200, description = "Returns the account data of the current user", body = AccountResponse),
+        (status = 200, description = "Returns the account data of the current user", body = AccountProfileResponse),
         (status = 400, description = "Client error", body = ApiErrorResponse),
         (status = 500, description = "Server error", body = ApiErrorResponse),
     ),
     security(("session_cookie" = []))
 )]
 #[get("/accounts/me")]
-pub async fn get_me(db: Data<Database>, session: Session) -> ApiResult<Json<AccountResponse>> {
+pub async fn get_me(
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<AccountProfileResponse>> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
     let account = query!(db.as_ref(), Account)
@@ -124,10 +237,14 @@ pub async fn get_me(db: Data<Database>, session: Session) -> ApiResult<Json<Acco
         .await?
         .ok_or(ApiError::SessionCorrupt)?;
 
-    Ok(Json(AccountResponse {
-        uuid: account.uuid,
-        username: account.username,
-        display_name: account.display_name,
+    Ok(Json(AccountProfileResponse {
+        badges: query_badges(&db, account.uuid).await?,
+        account: AccountResponse {
+            uuid: account.uuid,
+            username: account.username,
+            display_name: account.display_name,
+        },
+        stats: None,
     }))
 }
 
@@ -146,7 +263,7 @@ pub async fn get_me(db: Data<Database>, session: Session) -> ApiResult<Json<Acco
 pub async fn delete_me(
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
@@ -160,12 +277,7 @@ pub async fn delete_me(
     session.purge();
 
     // Close open websocket connections
-    if let Err(err) = ws_manager_chan
-        .send(WsManagerMessage::CloseSocket(uuid))
-        .await
-    {
-        error!("Could not send to ws manager chan: {err}");
-    }
+    notifier.disconnect(uuid).await;
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -236,6 +348,188 @@ pub async fn set_password(
     Ok(HttpResponse::Ok().finish())
 }
 
+/// The request to set the language incoming chat messages are translated to
+///
+/// Set `language` to `null` to disable the translation.
+#[derive(Deserialize, ToSchema)]
+pub struct SetChatLanguageRequest {
+    #[schema(example = "de")]
+    language: Option<String>,
+}
+
+/// Set the language incoming chat messages are translated to
+///
+/// `language` is a language code like `de` or `pt-BR`. If the server supports the
+/// translation of chat messages (see `GET /api/v2/capabilities`), every incoming chat
+/// message of another account is followed by a [WsMessage::ChatMessageTranslated] message
+/// with its translation.
+///
+/// If `language` is not a valid language code, [ApiError::InvalidLanguage] is returned.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Chat language has been set"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    request_body = SetChatLanguageRequest,
+    security(("session_cookie" = []))
+)]
+#[put("/accounts/me/chatLanguage")]
+pub async fn set_chat_language(
+    req: Json<SetChatLanguageRequest>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let language = req.into_inner().language;
+    if let Some(language) = &language {
+        if !(2..=16).contains(&language.len())
+            || !language
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(ApiError::InvalidLanguage);
+        }
+    }
+
+    update!(db.as_ref(), Account)
+        .condition(Account::F.uuid.equals(uuid))
+        .set(Account::F.chat_language, language)
+        .exec()
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The request to set who may send direct messages
+#[derive(Deserialize, ToSchema)]
+pub struct SetDirectMessagePolicyRequest {
+    allow_dms_from: DirectMessagePolicy,
+}
+
+/// Set which accounts may send direct messages to the currently logged-in account
+///
+/// With `friends`, which is the default, only friends may send direct messages, through the
+/// chatroom of the friendship. With `everyone`, every account may open a direct chat with
+/// `POST /api/v2/chats/direct/{uuid}`.
+///
+/// Accounts can always reply in direct chats they didn't open themselves, regardless of
+/// the policy of the account that opened them.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Direct message policy has been set"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    request_body = SetDirectMessagePolicyRequest,
+    security(("session_cookie" = []))
+)]
+#[put("/accounts/me/directMessages")]
+pub async fn set_direct_message_policy(
+    req: Json<SetDirectMessagePolicyRequest>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    update!(db.as_ref(), Account)
+        .condition(Account::F.uuid.equals(uuid))
+        .set(Account::F.allow_dms_from, req.allow_dms_from)
+        .exec()
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The request to set who may send friend requests
+#[derive(Deserialize, ToSchema)]
+pub struct SetFriendRequestPolicyRequest {
+    who_can_send_friend_requests: FriendRequestPolicy,
+}
+
+/// Set which accounts may send friend requests to the currently logged-in account
+///
+/// With `everyone`, which is the default, every account may send friend requests. With
+/// `friendsOfFriends`, only accounts that have at least one friend in common with this
+/// account may send them, and with `nobody`, no account may.
+///
+/// Rejected requests fail with the status code `FriendRequestsNotAllowed`. Pending requests
+/// are kept when the policy changes.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Friend request policy has been set"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    request_body = SetFriendRequestPolicyRequest,
+    security(("session_cookie" = []))
+)]
+#[put("/accounts/me/friendRequests")]
+pub async fn set_friend_request_policy(
+    req: Json<SetFriendRequestPolicyRequest>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    update!(db.as_ref(), Account)
+        .condition(Account::F.uuid.equals(uuid))
+        .set(
+            Account::F.who_can_send_friend_requests,
+            req.who_can_send_friend_requests,
+        )
+        .exec()
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The request to set whether mutual friends are shared
+#[derive(Deserialize, ToSchema)]
+pub struct SetMutualFriendsVisibilityRequest {
+    share_mutual_friends: bool,
+}
+
+/// Set whether other accounts may see their mutual friends with the currently logged-in
+/// account
+///
+/// Mutual friends are shared by default. If they are not shared,
+/// `GET /api/v2/accounts/{uuid}/mutual-friends` returns an empty list for this account.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Visibility of mutual friends has been set"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    request_body = SetMutualFriendsVisibilityRequest,
+    security(("session_cookie" = []))
+)]
+#[put("/accounts/me/mutualFriends")]
+pub async fn set_mutual_friends_visibility(
+    req: Json<SetMutualFriendsVisibilityRequest>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    update!(db.as_ref(), Account)
+        .condition(Account::F.uuid.equals(uuid))
+        .set(Account::F.share_mutual_friends, req.share_mutual_friends)
+        .exec()
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
 /// Update account request data
 ///
 /// All parameter are optional, but at least one of them is required.
@@ -272,19 +566,24 @@ pub async fn update_me(
     }): Json<UpdateAccountRequest>,
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
     let mut tx = db.start_transaction().await?;
 
-    if let Some(username) = &username {
-        if username.is_empty() {
+    let normalized_username = username.as_deref().map(normalize_username);
+    if let Some(normalized_username) = &normalized_username {
+        if normalized_username.is_empty() {
             return Err(ApiError::InvalidUsername);
         }
 
-        if query!(&mut tx, Account)
-            .condition(Account::F.username.equals(username))
+        // Changing the case of the own username is allowed
+        if query!(&mut tx, (Account::F.uuid,))
+            .condition(and!(
+                Account::F.normalized_username.equals(normalized_username),
+                Account::F.uuid.not_equals(uuid)
+            ))
             .optional()
             .await?
             .is_some()
@@ -303,11 +602,20 @@ pub async fn update_me(
         .condition(Account::F.uuid.equals(uuid))
         .begin_dyn_set()
         .set_if(Account::F.username, username)
+        .set_if(Account::F.normalized_username, normalized_username)
         .set_if(Account::F.display_name, display_name)
         .finish_dyn_set()
         .map_err(|_| ApiError::EmptyJson)?
         .exec()
-        .await?;
+        .await
+        .map_err(|err| {
+            // The username may have been taken concurrently since the check above
+            if is_unique_violation(&err) {
+                ApiError::UsernameAlreadyOccupied
+            } else {
+                ApiError::DatabaseError(err)
+            }
+        })?;
 
     let (uuid, username, display_name) = query!(
         &mut tx,
@@ -331,56 +639,214 @@ pub async fn update_me(
             username,
             display_name,
         },
+        badges: query_badges(&db, uuid).await?,
     };
 
-    if let Err(err) = ws_manager_chan
-        .send(WsManagerMessage::SendMessage(uuid, msg))
-        .await
-    {
-        warn!("Could not send to ws manager chan: {err}");
-    }
+    notifier.send(uuid, msg).await;
 
     Ok(HttpResponse::Ok().finish())
 }
 
+/// The game counts of an account
+///
+/// `games_played` counts the games the account uploaded at least one game state to,
+/// including games it left. `running_games` counts the games the account is currently
+/// a player of.
+#[derive(Serialize, ToSchema)]
+pub struct AccountStatsResponse {
+    #[schema(example = 12)]
+    games_played: u64,
+    #[schema(example = 2)]
+    running_games: u64,
+}
+
+impl From<AccountStats> for AccountStatsResponse {
+    fn from(stats: AccountStats) -> Self {
+        Self {
+            games_played: stats.games_played,
+            running_games: stats.running_games,
+        }
+    }
+}
+
+/// The public profile of an account
+///
+/// `badges` contains the badges the operators of the server granted to the account.
+/// `stats` is only set if it was requested.
+#[derive(Serialize, ToSchema)]
+pub struct AccountProfileResponse {
+    #[serde(flatten)]
+    account: AccountResponse,
+    badges: Vec<Badge>,
+    stats: Option<AccountStatsResponse>,
+}
+
+impl AccountProfileResponse {
+    /// Create the profile of an account, retrieving its badges and, if `with_stats` is set,
+    /// its game counts
+    async fn new(
+        account: AccountResponse,
+        with_stats: bool,
+        db: &Database,
+        cache: &AccountStatsCache,
+    ) -> ApiResult<Self> {
+        let stats = if with_stats {
+            Some(cache.get(db, account.uuid).await?.into())
+        } else {
+            None
+        };
+
+        Ok(Self {
+            badges: query_badges(db, account.uuid).await?,
+            account,
+            stats,
+        })
+    }
+}
+
+/// The query to opt into the game counts of an account
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AccountStatsQuery {
+    /// Whether the game counts of the account should be included
+    #[serde(default)]
+    stats: bool,
+}
+
 /// Retrieve details for an account by uuid
 ///
 /// As usernames are changeable, accounts are identified by uuids, which are used throughout
 /// the API.
 ///
 /// To fetch `display_name` and `username` for a given `uuid`, this endpoint shall be used.
+///
+/// If `stats` is set, the number of played and running games of the account is included.
+/// The counts are cached and may be up to five minutes old. The server doesn't know the
+/// outcome of games, so wins are not counted.
 #[utoipa::path(
     tag = "Accounts",
     context_path = "/api/v2",
     responses(
-        (status = 200, description = "Returns the requested account data", body = AccountResponse),
+        (status = 200, description = "Returns the requested account data", body = AccountProfileResponse),
         (status = 400, description = "Client error", body = ApiErrorResponse),
         (status = 500, description = "Server error", body = ApiErrorResponse),
     ),
-    params(PathUuid),
+    params(PathUuid, AccountStatsQuery),
     security(("session_cookie" = [])))]
 #[get("/accounts/{uuid}")]
 pub async fn lookup_account_by_uuid(
     req: Path<PathUuid>,
+    options: Query<AccountStatsQuery>,
     db: Data<Database>,
-) -> ApiResult<Json<AccountResponse>> {
+    stats_cache: Data<AccountStatsCache>,
+) -> ApiResult<Json<AccountProfileResponse>> {
     let account = query!(&**db, Account)
         .condition(Account::F.uuid.equals(req.uuid))
         .optional()
         .await?
         .ok_or(ApiError::InvalidUuid)?;
 
-    Ok(Json(AccountResponse {
+    let account = AccountResponse {
         uuid: req.uuid,
         username: account.username,
         display_name: account.display_name,
-    }))
+    };
+
+    Ok(Json(
+        AccountProfileResponse::new(account, options.stats, &db, &stats_cache).await?,
+    ))
+}
+
+/// The friends the executing account and another account have in common
+#[derive(Serialize, ToSchema)]
+pub struct GetMutualFriendsResponse {
+    friends: Vec<AccountResponse>,
+}
+
+/// Retrieve the friends the executing account and another account have in common
+///
+/// This helps to decide whether to accept a friend request of an unknown account.
+/// Only established friendships are taken into account, pending requests are not.
+///
+/// If the other account doesn't share its mutual friends, the list is always empty.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the mutual friends", body = GetMutualFriendsResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[get("/accounts/{uuid}/mutual-friends")]
+pub async fn get_mutual_friends(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<GetMutualFriendsResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    let (share_mutual_friends,) = query!(&mut tx, (Account::F.share_mutual_friends,))
+        .condition(Account::F.uuid.equals(path.uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    if !share_mutual_friends || path.uuid == uuid {
+        return Ok(Json(GetMutualFriendsResponse { friends: vec![] }));
+    }
+
+    let own_friends: HashSet<Uuid> = query!(&mut tx, (Friend::F.to.uuid,))
+        .condition(and!(
+            Friend::F.from.equals(uuid),
+            Friend::F.is_request.equals(false)
+        ))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(friend,)| friend)
+        .collect();
+
+    let friends = query!(
+        &mut tx,
+        (
+            Friend::F.to.uuid,
+            Friend::F.to.username,
+            Friend::F.to.display_name,
+        )
+    )
+    .condition(and!(
+        Friend::F.from.equals(path.uuid),
+        Friend::F.is_request.equals(false)
+    ))
+    .all()
+    .await?
+    .into_iter()
+    .filter(|(friend, _, _)| own_friends.contains(friend))
+    .map(|(uuid, username, display_name)| AccountResponse {
+        uuid,
+        username,
+        display_name,
+    })
+    .collect();
+
+    tx.commit().await?;
+
+    Ok(Json(GetMutualFriendsResponse { friends }))
 }
 
 /// The request to lookup an account by its username
+///
+/// If `stats` is set, the game counts of the account are included.
 #[derive(Deserialize, ToSchema)]
 pub struct LookupAccountUsernameRequest {
     username: String,
+    #[serde(default)]
+    stats: bool,
 }
 
 /// Retrieve details for an account by its username
@@ -393,11 +859,15 @@ pub struct LookupAccountUsernameRequest {
 ///
 /// If you receive a username by a user, you should convert them with this endpoint to an uuid.
 /// Those are used in the database to uniquely identify a user and can't be changed, just deleted.
+///
+/// The lookup is case-insensitive.
+///
+/// The game counts are included like for `GET /api/v2/accounts/{uuid}`.
 #[utoipa::path(
     tag = "Accounts", 
     context_path = "/api/v2",    
     responses(
-        (status = 200, description = "Returns the requested account data", body = AccountResponse),
+        (status = 200, description = "Returns the requested account data", body = AccountProfileResponse),
         (status = 400, description = "Client error", body = ApiErrorResponse),
         (status = 500, description = "Server error", body = ApiErrorResponse),
     ),
@@ -408,16 +878,183 @@ pub struct LookupAccountUsernameRequest {
 pub async fn lookup_account_by_username(
     req: Json<LookupAccountUsernameRequest>,
     db: Data<Database>,
-) -> ApiResult<Json<AccountResponse>> {
+    stats_cache: Data<AccountStatsCache>,
+) -> ApiResult<Json<AccountProfileResponse>> {
     let account = query!(&**db, Account)
-        .condition(Account::F.username.equals(&req.username))
+        .condition(
+            Account::F
+                .normalized_username
+                .equals(normalize_username(&req.username)),
+        )
         .optional()
         .await?
         .ok_or(ApiError::InvalidUsername)?;
 
-    Ok(Json(AccountResponse {
+    let account = AccountResponse {
         uuid: account.uuid,
         username: account.username,
         display_name: account.display_name,
+    };
+
+    Ok(Json(
+        AccountProfileResponse::new(account, req.stats, &db, &stats_cache).await?,
+    ))
+}
+
+/// The query to search accounts by their username
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchAccountsQuery {
+    /// The beginning of the username to search for
+    #[param(example = "user")]
+    query: String,
+}
+
+/// The relationship of an account to the executing account
+#[derive(Serialize, ToSchema, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AccountRelation {
+    /// There is no relationship between the accounts
+    None,
+    /// The accounts are friends
+    Friend,
+    /// The executing account sent a friend request to the account
+    OutgoingRequest,
+    /// The account sent a friend request to the executing account
+    IncomingRequest,
+}
+
+/// A single result of an account search
+///
+/// `in_my_lobby` is true if the account is a member of a lobby the executing
+/// account owns or is a member of.
+#[derive(Serialize, ToSchema)]
+pub struct SearchAccountResponse {
+    account: AccountResponse,
+    relation: AccountRelation,
+    in_my_lobby: bool,
+}
+
+/// The results of an account search
+#[derive(Serialize, ToSchema)]
+pub struct SearchAccountsResponse {
+    accounts: Vec<SearchAccountResponse>,
+}
+
+/// Search accounts by the beginning of their username
+///
+/// The search is case-insensitive. The executing account is never part of the results.
+///
+/// Every result is annotated with its relationship to the executing account, so a
+/// client doesn't need to request it for every account separately.
+///
+/// The results are sorted by username.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the matching accounts", body = SearchAccountsResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(SearchAccountsQuery, PaginationQuery),
+    security(("session_cookie" = []))
+)]
+#[get("/accounts/search")]
+pub async fn search_accounts(
+    search: Query<SearchAccountsQuery>,
+    pagination: Query<PaginationQuery>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<SearchAccountsResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let normalized = normalize_username(search.query.trim());
+    if normalized.is_empty() {
+        return Err(ApiError::InvalidUsername);
+    }
+
+    // Escape the wildcards of LIKE, so the query is matched literally
+    let mut pattern = String::with_capacity(normalized.len() + 1);
+    for c in normalized.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+
+    let mut tx = db.start_transaction().await?;
+
+    let accounts = query!(
+        &mut tx,
+        (
+            Account::F.uuid,
+            Account::F.username,
+            Account::F.display_name
+        )
+    )
+    .condition(and!(
+        Account::F.normalized_username.like(pattern),
+        Account::F.uuid.not_equals(uuid)
+    ))
+    .order_asc(Account::F.normalized_username)
+    .limit(pagination.limit())
+    .offset(pagination.offset())
+    .all()
+    .await?;
+
+    // Retrieve all relations of the executing account at once instead of per result
+    let mut relations: HashMap<Uuid, AccountRelation> = HashMap::new();
+    for (to, is_request) in query!(&mut tx, (Friend::F.to, Friend::F.is_request))
+        .condition(Friend::F.from.equals(uuid))
+        .all()
+        .await?
+    {
+        let relation = if is_request {
+            AccountRelation::OutgoingRequest
+        } else {
+            AccountRelation::Friend
+        };
+        relations.insert(*to.key(), relation);
+    }
+    for (from,) in query!(&mut tx, (Friend::F.from,))
+        .condition(and!(
+            Friend::F.to.equals(uuid),
+            Friend::F.is_request.equals(true)
+        ))
+        .all()
+        .await?
+    {
+        relations
+            .entry(*from.key())
+            .or_insert(AccountRelation::IncomingRequest);
+    }
+
+    let mut lobby_members: HashSet<Uuid> = HashSet::new();
+    for lobby in lobbies_of(&mut tx, uuid).await? {
+        if let Some(members) = LobbyMembers::query(&mut tx, lobby).await? {
+            lobby_members.extend(members.members());
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(Json(SearchAccountsResponse {
+        accounts: accounts
+            .into_iter()
+            .map(|(uuid, username, display_name)| SearchAccountResponse {
+                relation: relations
+                    .get(&uuid)
+                    .copied()
+                    .unwrap_or(AccountRelation::None),
+                in_my_lobby: lobby_members.contains(&uuid),
+                account: AccountResponse {
+                    uuid,
+                    username,
+                    display_name,
+                },
+            })
+            .collect(),
     }))
 }
@@ -6,15 +6,17 @@ use actix_web::{get, post, HttpResponse};
 use argon2::password_hash::Error;
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use chrono::Utc;
-use log::error;
-use rorm::{query, update, Database, FieldAccess, Model};
+use rorm::fields::types::ForeignModelByField;
+use rorm::{insert, query, update, Database, FieldAccess, Model};
 use serde::Deserialize;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::chan::{WsManagerChan, WsManagerMessage};
-use crate::models::Account;
-use crate::server::handler::{ApiError, ApiErrorResponse, ApiResult};
+use crate::chan::Notifier;
+use crate::models::{Account, Device, DeviceInsert};
+use crate::server::handler::{
+    normalize_username, validate_device_name, ApiError, ApiErrorResponse, ApiResult,
+};
 
 /// The request data of a login request
 #[derive(ToSchema, Deserialize)]
@@ -23,11 +25,17 @@ pub struct LoginRequest {
     username: String,
     #[schema(example = "super-secure-password")]
     password: String,
+    #[schema(example = "Herbert's phone")]
+    device_name: Option<String>,
 }
 
 /// Login to runciv
 ///
 /// On successful login you will retrieve a cookie.
+///
+/// Every login registers a new device for the account, which can be listed and revoked
+/// with the `/api/v2/accounts/me/devices` endpoints. The optional `device_name` helps
+/// to tell the devices apart, it must not be empty or longer than 255 characters.
 #[utoipa::path(
     tag = "Authentication",
     context_path = "/api/v2/auth",
@@ -44,10 +52,16 @@ pub(crate) async fn login(
     db: Data<Database>,
     session: Session,
 ) -> ApiResult<HttpResponse> {
+    let device_name = validate_device_name(req.device_name.clone())?;
+
     let mut tx = db.start_transaction().await?;
 
     let user = query!(&mut tx, Account)
-        .condition(Account::F.username.equals(&req.username))
+        .condition(
+            Account::F
+                .normalized_username
+                .equals(normalize_username(&req.username)),
+        )
         .optional()
         .await?
         .ok_or(ApiError::LoginFailed)?;
@@ -65,12 +79,24 @@ pub(crate) async fn login(
     update!(&mut tx, Account)
         .condition(Account::F.uuid.equals(user.uuid))
         .set(Account::F.last_login, Some(Utc::now().naive_utc()))
+        .set(Account::F.abandoned_since, None)
         .exec()
         .await?;
 
+    let device = insert!(&mut tx, DeviceInsert)
+        .return_primary_key()
+        .single(&DeviceInsert {
+            uuid: Uuid::new_v4(),
+            account: ForeignModelByField::Key(user.uuid),
+            name: device_name,
+            last_seen: Utc::now().naive_utc(),
+        })
+        .await?;
+
     tx.commit().await?;
 
     session.insert("uuid", user.uuid)?;
+    session.insert("device", device)?;
     session.insert("logged_in", true)?;
 
     Ok(HttpResponse::Ok().finish())
@@ -78,7 +104,7 @@ pub(crate) async fn login(
 
 /// Log out of this session
 ///
-/// Logs a logged-in user out of his session.
+/// Logs a logged-in user out of his session and revokes the device of the session.
 #[utoipa::path(
     tag = "Authentication",
     context_path = "/api/v2/auth",
@@ -90,19 +116,21 @@ pub(crate) async fn login(
 )]
 #[get("/logout")]
 pub(crate) async fn logout(
+    db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
+    if let Some(device) = session.get::<Uuid>("device")? {
+        rorm::delete!(db.as_ref(), Device)
+            .condition(Device::F.uuid.equals(device))
+            .await?;
+    }
+
     session.purge();
 
-    if let Err(err) = ws_manager_chan
-        .send(WsManagerMessage::CloseSocket(uuid))
-        .await
-    {
-        error!("Could not send to ws manager chan: {err}");
-    }
+    notifier.disconnect(uuid).await;
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -0,0 +1,159 @@
+//! This module holds the admin endpoints to grant and revoke badges of accounts
+
+use actix_web::web::{Data, Path};
+use actix_web::{delete, put, HttpResponse};
+use rorm::fields::types::ForeignModelByField;
+use rorm::{and, insert, query, Database, FieldAccess, Model};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+use crate::chan::{Notifier, WsMessage};
+use crate::models::{Account, AccountBadge, AccountBadgeInsert, Badge};
+use crate::server::handler::{AccountResponse, ApiError, ApiErrorResponse, ApiResult};
+
+/// Retrieve the badges that were granted to an account
+///
+/// The badges are sorted by the point in time they were granted.
+pub(crate) async fn query_badges(db: &Database, account: Uuid) -> Result<Vec<Badge>, rorm::Error> {
+    let mut badges = query!(db, (AccountBadge::F.badge, AccountBadge::F.granted_at))
+        .condition(AccountBadge::F.account.equals(account))
+        .all()
+        .await?;
+    badges.sort_by_key(|(_, granted_at)| *granted_at);
+
+    Ok(badges.into_iter().map(|(badge, _)| badge).collect())
+}
+
+/// Send the current account data including the badges to the account
+async fn notify_account_updated(
+    db: &Database,
+    notifier: &dyn Notifier,
+    account: AccountResponse,
+) -> ApiResult<()> {
+    let badges = query_badges(db, account.uuid).await?;
+    notifier
+        .send(account.uuid, WsMessage::AccountUpdated { account, badges })
+        .await;
+
+    Ok(())
+}
+
+/// Retrieve the public account data of an account
+///
+/// Returns [ApiError::InvalidUuid] if the account doesn't exist.
+async fn query_account(db: &Database, uuid: Uuid) -> ApiResult<AccountResponse> {
+    let (uuid, username, display_name) = query!(
+        db,
+        (
+            Account::F.uuid,
+            Account::F.username,
+            Account::F.display_name
+        )
+    )
+    .condition(Account::F.uuid.equals(uuid))
+    .optional()
+    .await?
+    .ok_or(ApiError::InvalidUuid)?;
+
+    Ok(AccountResponse {
+        uuid,
+        username,
+        display_name,
+    })
+}
+
+/// The path of a badge of an account
+#[derive(Deserialize, IntoParams)]
+pub struct AccountBadgePath {
+    /// The account
+    uuid: Uuid,
+    /// The badge
+    badge: Badge,
+}
+
+/// Grant a badge to an account
+///
+/// Granting a badge the account already has is not an error.
+/// On success, the account receives a [WsMessage::AccountUpdated] message with its badges.
+///
+/// If the account doesn't exist, [ApiError::InvalidUuid] is returned.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Badge was granted"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(AccountBadgePath),
+    security(("admin_token" = []))
+)]
+#[put("/accounts/{uuid}/badges/{badge}")]
+pub async fn grant_badge(
+    path: Path<AccountBadgePath>,
+    db: Data<Database>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let account = query_account(&db, path.uuid).await?;
+
+    let granted = query!(db.as_ref(), (AccountBadge::F.uuid,))
+        .condition(and!(
+            AccountBadge::F.account.equals(path.uuid),
+            AccountBadge::F.badge.equals(path.badge)
+        ))
+        .optional()
+        .await?;
+
+    if granted.is_none() {
+        insert!(db.as_ref(), AccountBadgeInsert)
+            .return_nothing()
+            .single(&AccountBadgeInsert {
+                uuid: Uuid::new_v4(),
+                account: ForeignModelByField::Key(path.uuid),
+                badge: path.badge,
+            })
+            .await?;
+    }
+
+    notify_account_updated(&db, notifier.get_ref(), account).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Revoke a badge of an account
+///
+/// Revoking a badge the account doesn't have is not an error.
+/// On success, the account receives a [WsMessage::AccountUpdated] message with its badges.
+///
+/// If the account doesn't exist, [ApiError::InvalidUuid] is returned.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Badge was revoked"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(AccountBadgePath),
+    security(("admin_token" = []))
+)]
+#[delete("/accounts/{uuid}/badges/{badge}")]
+pub async fn revoke_badge(
+    path: Path<AccountBadgePath>,
+    db: Data<Database>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let account = query_account(&db, path.uuid).await?;
+
+    rorm::delete!(db.as_ref(), AccountBadge)
+        .condition(and!(
+            AccountBadge::F.account.equals(path.uuid),
+            AccountBadge::F.badge.equals(path.badge)
+        ))
+        .await?;
+
+    notify_account_updated(&db, notifier.get_ref(), account).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,68 @@
+//! Handler to determine the capabilities and limits of the server
+
+use actix_web::get;
+use actix_web::web::{Data, Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::server::RuntimeSettings;
+
+/// The capabilities and limits of the server
+///
+/// `max_game_data_size` is the maximum size of uploaded game data in bytes.
+/// `pending_upload_timeout` is the time in seconds a player has to upload the game state
+/// after ending a turn.
+///
+/// `max_lobby_players`, `require_lobby_password` and `allowed_mods` are the constraints
+/// the server enforces for lobbies. If `allowed_mods` is not set, all mods are allowed.
+///
+/// If `chat_translation` is set, accounts can choose a language incoming chat messages
+/// are translated to.
+///
+/// If `account_webhooks` is set, accounts can register a personal webhook with
+/// `PUT /api/v2/accounts/me/webhook`.
+///
+/// If `ephemeral` is set, the server runs with a throwaway database and all accounts,
+/// lobbies and games are lost when it is restarted.
+#[derive(Serialize, ToSchema)]
+pub struct CapabilitiesResponse {
+    #[schema(example = 1000000)]
+    max_game_data_size: u64,
+    #[schema(example = 1)]
+    max_owned_lobbies: u16,
+    allow_multiple_lobbies: bool,
+    #[schema(example = 3600)]
+    pending_upload_timeout: u64,
+    #[schema(example = 34)]
+    max_lobby_players: u8,
+    require_lobby_password: bool,
+    allowed_mods: Option<Vec<String>>,
+    chat_translation: bool,
+    account_webhooks: bool,
+    ephemeral: bool,
+}
+
+/// This endpoint is for clients to detect the limits of this server
+///
+/// Clients can use this to warn their users before uploading too large game states.
+#[utoipa::path(
+    tag = "Version",
+    responses(
+        (status = 200, description = "The capabilities of this server", body = CapabilitiesResponse)
+    ),
+)]
+#[get("/api/v2/capabilities")]
+pub async fn capabilities(settings: Data<RuntimeSettings>) -> Json<CapabilitiesResponse> {
+    Json(CapabilitiesResponse {
+        max_game_data_size: settings.max_game_data_size as u64,
+        max_owned_lobbies: settings.max_owned_lobbies,
+        allow_multiple_lobbies: settings.allow_multiple_lobbies,
+        pending_upload_timeout: settings.pending_upload_timeout,
+        max_lobby_players: settings.max_lobby_players,
+        require_lobby_password: settings.require_lobby_password,
+        allowed_mods: settings.allowed_mods.clone(),
+        chat_translation: settings.chat_translation,
+        account_webhooks: settings.account_webhooks,
+        ephemeral: settings.ephemeral,
+    })
+}
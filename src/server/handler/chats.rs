@@ -1,36 +1,60 @@
 //! Handler for chatting
 
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
 
 use actix_toolbox::tb_middleware::Session;
-use actix_web::web::{Data, Json, Path};
-use actix_web::{get, post};
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{get, post, put, HttpResponse};
 use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
 use itertools::Itertools;
-use log::warn;
-use rorm::fields::types::ForeignModelByField;
-use rorm::{and, insert, query, update, Database, FieldAccess, Model};
+use rorm::db::Transaction;
+use rorm::{and, query, update, Database, FieldAccess, Model};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use crate::chan::{WsManagerChan, WsManagerMessage, WsMessage};
+use crate::chan::{ChatDigests, Notifier};
 use crate::models::{
-    ChatRoom, ChatRoomMember, ChatRoomMessage, ChatRoomMessageInsert, Friend, GameAccount,
-    LobbyAccount,
+    Account, ChatMessageMention, ChatMessageType, ChatRoom, ChatRoomMember, ChatRoomMessage,
+    DirectChat, Friend, Game, GameAccount, Lobby, LobbyAccount,
 };
-use crate::server::handler::{AccountResponse, ApiError, ApiErrorResponse, ApiResult, PathUuid};
+use crate::server::handler::{
+    fill_online_states, AccountResponse, ApiError, ApiErrorResponse, ApiResult, PathUuid,
+    WithOnlineState,
+};
+use crate::server::ndjson::ndjson_response;
+use crate::service::chat;
+use crate::service::membership::GameMembers;
+use crate::service::notify::Outbox;
+use crate::service::translation::{spawn_translations, Translator};
 
 /// The message of a chatroom
 ///
 /// The parameter `uuid` is used to uniquely identify a message
+///
+/// Messages of the type `system` are generated by the server, e.g. when a player joined
+/// a lobby, and don't have a `sender`.
+///
+/// `mentions` are the members of the chatroom that were mentioned in the message
+/// with `@username` or `@uuid`.
+///
+/// `sequence` is the position of the message in its chatroom, it increases with every
+/// message. Messages sent before sequence numbers were introduced have the sequence `0`.
 #[derive(Serialize, ToSchema, Eq, Deserialize, Clone, Debug)]
 pub struct ChatMessage {
-    uuid: Uuid,
-    sender: AccountResponse,
+    pub(crate) uuid: Uuid,
+    pub(crate) sender: Option<AccountResponse>,
+    pub(crate) message_type: ChatMessageType,
     #[schema(example = "Hello there!")]
-    message: String,
-    created_at: DateTime<Utc>,
+    pub(crate) message: String,
+    pub(crate) created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub(crate) mentions: Vec<Uuid>,
+    #[serde(default)]
+    pub(crate) sequence: i64,
 }
 
 impl Ord for ChatMessage {
@@ -51,21 +75,90 @@ impl PartialEq for ChatMessage {
     }
 }
 
+/// The role of a member in a chatroom
+///
+/// The role is derived from the lobby or game the chatroom belongs to.
+#[derive(Serialize, ToSchema, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChatMemberRole {
+    /// The owner of the lobby the chatroom belongs to
+    LobbyOwner,
+    /// The host of the game the chatroom belongs to
+    GameHost,
+    /// A player of the game the chatroom belongs to
+    Player,
+    /// A member of a game chatroom that is not playing in the game
+    Spectator,
+    /// Any other member, e.g. of a friend chat or a joined lobby player
+    Member,
+}
+
 /// A member of a chatroom
 #[derive(Serialize, ToSchema)]
 pub struct ChatMember {
     #[serde(flatten)]
     account: AccountResponse,
     joined_at: DateTime<Utc>,
+    role: ChatMemberRole,
+    online: bool,
+}
+
+impl WithOnlineState for ChatMember {
+    fn account_uuid(&self) -> Uuid {
+        self.account.uuid
+    }
+
+    fn set_online(&mut self, online: bool) {
+        self.online = online;
+    }
 }
 
 /// The response to a get chat
 ///
 /// `messages` should be sorted by the datetime of `message.created_at`.
+/// `has_more` is set if there are older messages than the ones returned.
 #[derive(Serialize, ToSchema)]
 pub struct ChatFull {
     members: Vec<ChatMember>,
     messages: Vec<ChatMessage>,
+    has_more: bool,
+}
+
+/// The page of the history of a chatroom to retrieve
+///
+/// `before` is the uuid of a message, only messages older than it are returned.
+/// If it is not set, the most recent messages are returned.
+///
+/// `limit` defaults to 50 and is capped at 200.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChatHistoryQuery {
+    before: Option<Uuid>,
+    #[param(example = 50)]
+    limit: Option<u64>,
+}
+
+impl ChatHistoryQuery {
+    /// The maximum number of messages to return
+    fn limit(&self) -> u64 {
+        self.limit.unwrap_or(50).min(200)
+    }
+}
+
+/// What a chatroom belongs to
+#[derive(Serialize, ToSchema, Copy, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ChatRoomKind {
+    /// The chatroom of a friendship
+    Friend,
+    /// The chatroom of a lobby
+    Lobby,
+    /// The chatroom of a game
+    Game,
+    /// A chatroom of messages of the server
+    System,
+    /// A chatroom of direct messages with an account that is not a friend
+    Direct,
 }
 
 /// The small representation of a chatroom
@@ -73,6 +166,129 @@ pub struct ChatFull {
 pub struct ChatSmall {
     pub(crate) uuid: Uuid,
     pub(crate) last_message_uuid: Option<Uuid>,
+    pub(crate) kind: ChatRoomKind,
+    /// The friend, lobby or game the chatroom belongs to
+    pub(crate) context_uuid: Option<Uuid>,
+    /// The display name of the friend or the name of the lobby or game
+    #[schema(example = "Herbert")]
+    pub(crate) name: Option<String>,
+    /// The most recent message of the chatroom
+    pub(crate) last_message: Option<ChatMessagePreview>,
+}
+
+/// The most recent message of a chatroom, shortened for the list of chatrooms
+#[derive(Serialize, ToSchema)]
+pub struct ChatMessagePreview {
+    pub(crate) uuid: Uuid,
+    pub(crate) sender: Option<AccountResponse>,
+    pub(crate) message_type: ChatMessageType,
+    /// The first characters of the message
+    #[schema(example = "Hello there!")]
+    pub(crate) snippet: String,
+    pub(crate) created_at: DateTime<Utc>,
+}
+
+/// The maximum number of characters of [ChatMessagePreview::snippet]
+const SNIPPET_LENGTH: usize = 100;
+
+/// Shorten a message for a [ChatMessagePreview]
+fn snippet(message: &str) -> String {
+    if message.chars().count() <= SNIPPET_LENGTH {
+        return message.to_string();
+    }
+
+    let mut snippet: String = message.chars().take(SNIPPET_LENGTH - 1).collect();
+    snippet.push('…');
+    snippet
+}
+
+/// Add the previews of the most recent messages to chatrooms of a kind
+///
+/// `chat_rooms` holds the uuid of each chatroom, the uuid of its most recent message,
+/// and the uuid and name of the friend, lobby or game it belongs to.
+async fn with_previews(
+    tx: &mut Transaction,
+    senders: &mut HashMap<Uuid, Option<AccountResponse>>,
+    kind: ChatRoomKind,
+    chat_rooms: Vec<(Uuid, Option<Uuid>, Option<Uuid>, Option<String>)>,
+) -> Result<Vec<ChatSmall>, rorm::Error> {
+    let mut chats = Vec::with_capacity(chat_rooms.len());
+    for (uuid, last_message_uuid, context_uuid, name) in chat_rooms {
+        let last_message = match last_message_uuid {
+            Some(last_message_uuid) => query_preview(&mut *tx, senders, last_message_uuid).await?,
+            None => None,
+        };
+        chats.push(ChatSmall {
+            uuid,
+            last_message_uuid,
+            kind,
+            context_uuid,
+            name,
+            last_message,
+        });
+    }
+    Ok(chats)
+}
+
+/// Query the preview of a message
+///
+/// The senders are cached in `senders`, as they often appear in multiple chatrooms.
+async fn query_preview(
+    tx: &mut Transaction,
+    senders: &mut HashMap<Uuid, Option<AccountResponse>>,
+    message: Uuid,
+) -> Result<Option<ChatMessagePreview>, rorm::Error> {
+    let Some((uuid, message, message_type, created_at, sender)) = query!(
+        &mut *tx,
+        (
+            ChatRoomMessage::F.uuid,
+            ChatRoomMessage::F.message,
+            ChatRoomMessage::F.message_type,
+            ChatRoomMessage::F.created_at,
+            ChatRoomMessage::F.sender,
+        )
+    )
+    .condition(ChatRoomMessage::F.uuid.equals(message))
+    .optional()
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let sender = match sender {
+        Some(sender) => {
+            let sender = *sender.key();
+            if !senders.contains_key(&sender) {
+                let account = query!(
+                    &mut *tx,
+                    (
+                        Account::F.uuid,
+                        Account::F.username,
+                        Account::F.display_name
+                    )
+                )
+                .condition(Account::F.uuid.equals(sender))
+                .optional()
+                .await?
+                .map(|(uuid, username, display_name)| AccountResponse {
+                    uuid,
+                    username,
+                    display_name,
+                });
+                senders.insert(sender, account);
+            }
+            senders.get(&sender).cloned().flatten()
+        }
+        None => None,
+    };
+
+    Ok(Some(ChatMessagePreview {
+        uuid,
+        sender,
+        message_type,
+        snippet: snippet(&message),
+        created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
+    }))
 }
 
 /// Retrieve the messages of a chatroom
@@ -81,8 +297,14 @@ pub struct ChatSmall {
 /// `message.uuid` should be used to uniquely identify chat messages.
 /// This is needed as new messages are delivered via websocket
 ///
+/// The history is returned in pages, starting with the most recent messages.
+/// To retrieve older messages, set `before` to the uuid of the oldest message of the
+/// previous page, as long as `has_more` is set. If `before` is not a message of the
+/// chatroom, [ApiError::InvalidUuid] is returned.
+///
 /// `members` holds information about all members that are currently in the chat room (including
-/// yourself)
+/// yourself). The `role` of a member is derived from the lobby or game the chat room belongs to.
+/// `online` is set if the member has an active connection.
 #[utoipa::path(
     tag = "Chats",
     context_path = "/api/v2",
@@ -91,14 +313,16 @@ pub struct ChatSmall {
         (status = 400, description = "Client error", body = ApiErrorResponse),
         (status = 500, description = "Server error", body = ApiErrorResponse),
     ),
-    params(PathUuid),
+    params(PathUuid, ChatHistoryQuery),
     security(("session_cookie" = []))
 )]
 #[get("/chats/{uuid}")]
 pub async fn get_chat(
     path: Path<PathUuid>,
+    history: Query<ChatHistoryQuery>,
     db: Data<Database>,
     session: Session,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<Json<ChatFull>> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
@@ -114,7 +338,8 @@ pub async fn get_chat(
     let user_count = query!(&mut tx, (ChatRoomMember::F.uuid.count(),))
         .condition(and!(
             ChatRoomMember::F.chat_room.equals(path.uuid),
-            ChatRoomMember::F.member.uuid.equals(uuid)
+            ChatRoomMember::F.member.uuid.equals(uuid),
+            ChatRoomMember::F.banned.equals(false)
         ))
         .one()
         .await?
@@ -137,56 +362,186 @@ pub async fn get_chat(
     .all()
     .await?;
 
-    let messages = query!(
-        &mut tx,
-        (
-            ChatRoomMessage::F.uuid,
-            ChatRoomMessage::F.message,
-            ChatRoomMessage::F.created_at,
-            ChatRoomMessage::F.sender.uuid,
-            ChatRoomMessage::F.sender.username,
-            ChatRoomMessage::F.sender.display_name
+    // Retrieve the relations the roles of the members are derived from
+    let lobby_owner = query!(&mut tx, (Lobby::F.owner,))
+        .condition(Lobby::F.chat_room.equals(path.uuid))
+        .optional()
+        .await?
+        .map(|(owner,)| *owner.key());
+
+    let game = match query!(&mut tx, (Game::F.uuid,))
+        .condition(Game::F.chat_room.equals(path.uuid))
+        .optional()
+        .await?
+    {
+        Some((game_uuid,)) => GameMembers::query(&mut tx, game_uuid).await?,
+        None => None,
+    };
+
+    let role_of = |member: Uuid| {
+        if lobby_owner == Some(member) {
+            ChatMemberRole::LobbyOwner
+        } else if let Some(game) = &game {
+            if game.is_host(member) {
+                ChatMemberRole::GameHost
+            } else if game.is_player(member) {
+                ChatMemberRole::Player
+            } else {
+                ChatMemberRole::Spectator
+            }
+        } else {
+            ChatMemberRole::Member
+        }
+    };
+
+    let before = match history.before {
+        Some(before) => Some(
+            query!(&mut tx, (ChatRoomMessage::F.created_at,))
+                .condition(and!(
+                    ChatRoomMessage::F.uuid.equals(before),
+                    ChatRoomMessage::F.chat_room.equals(path.uuid)
+                ))
+                .optional()
+                .await?
+                .ok_or(ApiError::InvalidUuid)?
+                .0,
+        ),
+        None => None,
+    };
+
+    // One more message than requested is queried to find out if there are older ones
+    let limit = history.limit();
+    let mut messages = match before {
+        Some(before) => {
+            query!(
+                &mut tx,
+                (
+                    ChatRoomMessage::F.uuid,
+                    ChatRoomMessage::F.message,
+                    ChatRoomMessage::F.message_type,
+                    ChatRoomMessage::F.created_at,
+                    ChatRoomMessage::F.sender,
+                    ChatRoomMessage::F.sequence,
+                )
+            )
+            .condition(and!(
+                ChatRoomMessage::F.chat_room.equals(path.uuid),
+                ChatRoomMessage::F.created_at.less_than(before)
+            ))
+            .order_desc(ChatRoomMessage::F.created_at)
+            .limit(limit + 1)
+            .all()
+            .await?
+        }
+        None => {
+            query!(
+                &mut tx,
+                (
+                    ChatRoomMessage::F.uuid,
+                    ChatRoomMessage::F.message,
+                    ChatRoomMessage::F.message_type,
+                    ChatRoomMessage::F.created_at,
+                    ChatRoomMessage::F.sender,
+                    ChatRoomMessage::F.sequence,
+                )
+            )
+            .condition(ChatRoomMessage::F.chat_room.equals(path.uuid))
+            .order_desc(ChatRoomMessage::F.created_at)
+            .limit(limit + 1)
+            .all()
+            .await?
+        }
+    };
+    let has_more = messages.len() as u64 > limit;
+    messages.truncate(limit as usize);
+
+    // System messages don't have a sender, so the senders are resolved separately
+    let mut senders: HashMap<Uuid, AccountResponse> = HashMap::new();
+    for sender in messages
+        .iter()
+        .filter_map(|(_, _, _, _, sender, _)| sender.as_ref().map(|x| *x.key()))
+        .unique()
+    {
+        if let Some((uuid, username, display_name)) = query!(
+            &mut tx,
+            (
+                Account::F.uuid,
+                Account::F.username,
+                Account::F.display_name
+            )
         )
-    )
-    .condition(ChatRoomMessage::F.chat_room.equals(path.uuid))
-    .all()
-    .await?;
+        .condition(Account::F.uuid.equals(sender))
+        .optional()
+        .await?
+        {
+            senders.insert(
+                uuid,
+                AccountResponse {
+                    uuid,
+                    username,
+                    display_name,
+                },
+            );
+        }
+    }
+
+    // Mentions are rare, so the ones of the whole chatroom are filtered in memory
+    let mut mentions: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    if !messages.is_empty() {
+        for (message, account) in query!(
+            &mut tx,
+            (ChatMessageMention::F.message, ChatMessageMention::F.account)
+        )
+        .condition(ChatMessageMention::F.message.chat_room.equals(path.uuid))
+        .all()
+        .await?
+        {
+            mentions
+                .entry(*message.key())
+                .or_default()
+                .push(*account.key());
+        }
+    }
 
     tx.commit().await?;
 
-    Ok(Json(ChatFull {
+    let mut chat = ChatFull {
         messages: messages
             .into_iter()
             .map(
-                |(uuid, message, created_at, sender_uuid, sender_username, sender_display_name)| {
-                    ChatMessage {
-                        uuid,
-                        message,
-                        created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
-                        sender: AccountResponse {
-                            uuid: sender_uuid,
-                            username: sender_username,
-                            display_name: sender_display_name,
-                        },
-                    }
+                |(uuid, message, message_type, created_at, sender, sequence)| ChatMessage {
+                    uuid,
+                    message,
+                    message_type,
+                    created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
+                    sender: sender.and_then(|x| senders.get(x.key()).cloned()),
+                    mentions: mentions.remove(&uuid).unwrap_or_default(),
+                    sequence,
                 },
             )
             .sorted()
             .collect(),
+        has_more,
         members: members
             .into_iter()
             .map(
                 |(created_at, m_uuid, m_username, m_display_name)| ChatMember {
                     joined_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
+                    role: role_of(m_uuid),
                     account: AccountResponse {
                         uuid: m_uuid,
                         username: m_username,
                         display_name: m_display_name,
                     },
+                    online: false,
                 },
             )
             .collect(),
-    }))
+    };
+
+    fill_online_states(notifier.get_ref(), chat.members.iter_mut()).await?;
+
+    Ok(Json(chat))
 }
 
 /// All chat rooms your user has access to
@@ -195,11 +550,19 @@ pub struct GetAllChatsResponse {
     friend_chat_rooms: Vec<ChatSmall>,
     lobby_chat_rooms: Vec<ChatSmall>,
     game_chat_rooms: Vec<ChatSmall>,
+    /// Chatrooms of messages of the server, e.g. the welcome message
+    system_chat_rooms: Vec<ChatSmall>,
+    /// Chatrooms of direct messages with accounts that are not friends
+    direct_chat_rooms: Vec<ChatSmall>,
 }
 
 /// Retrieve all chats the executing user has access to.
 ///
 /// In the response, you will find different categories.
+/// Every chatroom contains a preview of its most recent message, if it has one.
+///
+/// The chatrooms carry their `kind` and the uuid and name of the friend, lobby or game
+/// they belong to, so clients can label them without further requests.
 #[utoipa::path(
     tag = "Chats",
     context_path = "/api/v2",
@@ -219,11 +582,13 @@ pub async fn get_all_chats(
 
     let mut tx = db.start_transaction().await?;
 
-    let friend_chat_room_uuids = query!(
+    let friend_chat_rooms = query!(
         &mut tx,
         (
             Friend::F.chat_room.uuid,
-            Friend::F.chat_room.last_message_uuid
+            Friend::F.chat_room.last_message_uuid,
+            Friend::F.to.uuid,
+            Friend::F.to.display_name
         )
     )
     .condition(and!(
@@ -231,55 +596,151 @@ pub async fn get_all_chats(
         Friend::F.from.uuid.equals(uuid)
     ))
     .all()
-    .await?;
+    .await?
+    .into_iter()
+    .map(|(chat_room, last_message_uuid, friend, display_name)| {
+        (
+            chat_room,
+            last_message_uuid,
+            Some(friend),
+            Some(display_name),
+        )
+    })
+    .collect();
 
-    let lobby_chat_room_uuids = query!(
+    let lobby_chat_rooms = query!(
         &mut tx,
         (
             LobbyAccount::F.lobby.chat_room.uuid,
-            LobbyAccount::F.lobby.chat_room.last_message_uuid
+            LobbyAccount::F.lobby.chat_room.last_message_uuid,
+            LobbyAccount::F.lobby.uuid,
+            LobbyAccount::F.lobby.name
         )
     )
     .condition(LobbyAccount::F.player.uuid.equals(uuid))
     .all()
-    .await?;
+    .await?
+    .into_iter()
+    .map(|(chat_room, last_message_uuid, lobby, name)| {
+        (chat_room, last_message_uuid, Some(lobby), Some(name))
+    })
+    .collect();
 
-    let game_chat_room_uuids = query!(
+    let game_chat_rooms = query!(
         &mut tx,
         (
             GameAccount::F.game.chat_room.uuid,
-            GameAccount::F.game.chat_room.last_message_uuid
+            GameAccount::F.game.chat_room.last_message_uuid,
+            GameAccount::F.game.uuid,
+            GameAccount::F.game.name
         )
     )
-    .condition(GameAccount::F.uuid.equals(uuid))
+    .condition(GameAccount::F.player.equals(uuid))
     .all()
-    .await?;
+    .await?
+    .into_iter()
+    .map(|(chat_room, last_message_uuid, game, name)| {
+        (chat_room, last_message_uuid, Some(game), Some(name))
+    })
+    .collect();
 
-    tx.commit().await?;
+    let (welcome_chat_room,) = query!(&mut tx, (Account::F.welcome_chat_room,))
+        .condition(Account::F.uuid.equals(uuid))
+        .one()
+        .await?;
+    let mut system_chat_rooms = vec![];
+    if let Some(chat_room) = welcome_chat_room {
+        let (chat_room, last_message_uuid) =
+            query!(&mut tx, (ChatRoom::F.uuid, ChatRoom::F.last_message_uuid))
+                .condition(ChatRoom::F.uuid.equals(*chat_room.key()))
+                .one()
+                .await?;
+        system_chat_rooms.push((chat_room, last_message_uuid, None, None));
+    }
 
-    Ok(Json(GetAllChatsResponse {
-        lobby_chat_rooms: lobby_chat_room_uuids
-            .into_iter()
-            .map(|(uuid, last_message_uuid)| ChatSmall {
-                uuid,
-                last_message_uuid,
-            })
-            .collect(),
-        friend_chat_rooms: friend_chat_room_uuids
-            .into_iter()
-            .map(|(uuid, last_message_uuid)| ChatSmall {
-                uuid,
-                last_message_uuid,
-            })
-            .collect(),
-        game_chat_rooms: game_chat_room_uuids
-            .into_iter()
-            .map(|(uuid, last_message_uuid)| ChatSmall {
-                uuid,
+    let mut direct_chat_rooms: Vec<_> = query!(
+        &mut tx,
+        (
+            DirectChat::F.chat_room.uuid,
+            DirectChat::F.chat_room.last_message_uuid,
+            DirectChat::F.to.uuid,
+            DirectChat::F.to.display_name
+        )
+    )
+    .condition(DirectChat::F.from.equals(uuid))
+    .all()
+    .await?
+    .into_iter()
+    .map(|(chat_room, last_message_uuid, account, display_name)| {
+        (
+            chat_room,
+            last_message_uuid,
+            Some(account),
+            Some(display_name),
+        )
+    })
+    .collect();
+    direct_chat_rooms.extend(
+        query!(
+            &mut tx,
+            (
+                DirectChat::F.chat_room.uuid,
+                DirectChat::F.chat_room.last_message_uuid,
+                DirectChat::F.from.uuid,
+                DirectChat::F.from.display_name
+            )
+        )
+        .condition(DirectChat::F.to.equals(uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(chat_room, last_message_uuid, account, display_name)| {
+            (
+                chat_room,
                 last_message_uuid,
-            })
-            .collect(),
-    }))
+                Some(account),
+                Some(display_name),
+            )
+        }),
+    );
+
+    let mut senders = HashMap::new();
+    let response = GetAllChatsResponse {
+        friend_chat_rooms: with_previews(
+            &mut tx,
+            &mut senders,
+            ChatRoomKind::Friend,
+            friend_chat_rooms,
+        )
+        .await?,
+        lobby_chat_rooms: with_previews(
+            &mut tx,
+            &mut senders,
+            ChatRoomKind::Lobby,
+            lobby_chat_rooms,
+        )
+        .await?,
+        game_chat_rooms: with_previews(&mut tx, &mut senders, ChatRoomKind::Game, game_chat_rooms)
+            .await?,
+        system_chat_rooms: with_previews(
+            &mut tx,
+            &mut senders,
+            ChatRoomKind::System,
+            system_chat_rooms,
+        )
+        .await?,
+        direct_chat_rooms: with_previews(
+            &mut tx,
+            &mut senders,
+            ChatRoomKind::Direct,
+            direct_chat_rooms,
+        )
+        .await?,
+    };
+
+    tx.commit().await?;
+
+    Ok(Json(response))
 }
 
 /// The request for sending a message to a chatroom
@@ -292,6 +753,19 @@ pub struct SendMessageRequest {
 /// Send a message to the specified chatroom
 ///
 /// The executing user must be a member of the chatroom and the `message` must not be empty.
+///
+/// Members of the chatroom can be mentioned with `@username` or `@uuid`. Mentioned members
+/// receive a [WsMessage::ChatMention] message in addition to the
+/// [WsMessage::IncomingChatMessage] message all members receive.
+///
+/// If the server supports the translation of chat messages, members that set a chat language
+/// receive a [WsMessage::ChatMessageTranslated] message once the translation is available.
+///
+/// Messages can be sent via websocket as well, see `GET /api/v2/ws`.
+///
+/// [WsMessage::ChatMention]: crate::chan::WsMessage::ChatMention
+/// [WsMessage::IncomingChatMessage]: crate::chan::WsMessage::IncomingChatMessage
+/// [WsMessage::ChatMessageTranslated]: crate::chan::WsMessage::ChatMessageTranslated
 #[utoipa::path(
     tag = "Chats",
     context_path = "/api/v2",
@@ -310,82 +784,290 @@ pub async fn send_message(
     req: Json<SendMessageRequest>,
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
+    translator: Data<Option<Translator>>,
 ) -> ApiResult<Json<ChatMessage>> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
-    // Check if the message is valid
-    if req.message.is_empty() {
-        return Err(ApiError::InvalidMessage);
-    }
+    let mut tx = db.start_transaction().await?;
+
+    let mut outbox = Outbox::new();
+    let sent = chat::send_message(
+        &mut tx,
+        &mut outbox,
+        path.uuid,
+        uuid,
+        req.into_inner().message,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    outbox.send(notifier.get_ref()).await;
+
+    spawn_translations(
+        translator.into_inner(),
+        db.into_inner(),
+        notifier.into_inner(),
+        path.uuid,
+        &sent.message,
+        sent.translations,
+    );
+
+    Ok(Json(sent.message))
+}
+
+/// The response to a direct message
+#[derive(Serialize, ToSchema)]
+pub struct DirectMessageResponse {
+    /// The chatroom the message was sent to
+    chat_uuid: Uuid,
+    /// The sent message
+    message: ChatMessage,
+}
+
+/// Send a direct message to an account
+///
+/// If the executing user and the account are friends, the message is sent to the chatroom
+/// of their friendship. Otherwise it is sent to the direct chat of both accounts, which is
+/// created with the first message. Direct chats are listed in `direct_chat_rooms` of
+/// `GET /api/v2/chats` and behave like any other chatroom afterwards.
+///
+/// Accounts only receive direct messages from accounts that are not their friends if they
+/// allow it, see `PUT /api/v2/accounts/me/directMessages`, otherwise
+/// [ApiError::DirectMessagesNotAllowed] is returned. Replies in a direct chat opened by the
+/// other account are always allowed.
+#[utoipa::path(
+    tag = "Chats",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the sent message", body = DirectMessageResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = SendMessageRequest,
+    security(("session_cookie" = []))
+)]
+#[post("/chats/direct/{uuid}")]
+pub async fn send_direct_message(
+    path: Path<PathUuid>,
+    req: Json<SendMessageRequest>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+    translator: Data<Option<Translator>>,
+) -> ApiResult<Json<DirectMessageResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
     let mut tx = db.start_transaction().await?;
 
-    // Check if executing user is member of the chatroom
-    let (sender_uuid, sender_username, sender_display_name) = query!(
+    let chat_uuid = chat::direct_chat_room(&mut tx, uuid, path.uuid).await?;
+
+    let mut outbox = Outbox::new();
+    let sent = chat::send_message(
         &mut tx,
-        (
-            ChatRoomMember::F.member.uuid,
-            ChatRoomMember::F.member.username,
-            ChatRoomMember::F.member.display_name
-        )
+        &mut outbox,
+        chat_uuid,
+        uuid,
+        req.into_inner().message,
     )
-    .condition(and!(
-        ChatRoomMember::F.chat_room.equals(path.uuid),
-        ChatRoomMember::F.member.equals(uuid)
-    ))
-    .optional()
-    .await?
-    .ok_or(ApiError::MissingPrivileges)?;
-
-    // Create a new chat message
-    let chat_room_message = insert!(&mut tx, ChatRoomMessageInsert)
-        .single(&ChatRoomMessageInsert {
-            uuid: Uuid::new_v4(),
-            sender: ForeignModelByField::Key(uuid),
-            message: req.message.clone(),
-            chat_room: ForeignModelByField::Key(path.uuid),
-        })
-        .await?;
+    .await?;
 
-    update!(&mut tx, ChatRoom)
-        .condition(ChatRoom::F.uuid.equals(path.uuid))
-        .set(ChatRoom::F.last_message_uuid, Some(chat_room_message.uuid))
-        .exec()
-        .await?;
+    tx.commit().await?;
 
-    let chat_room_members = query!(&mut tx, (ChatRoomMember::F.member.uuid,))
-        .condition(ChatRoomMember::F.chat_room.equals(path.uuid))
-        .all()
+    outbox.send(notifier.get_ref()).await;
+
+    spawn_translations(
+        translator.into_inner(),
+        db.into_inner(),
+        notifier.into_inner(),
+        chat_uuid,
+        &sent.message,
+        sent.translations,
+    );
+
+    Ok(Json(DirectMessageResponse {
+        chat_uuid,
+        message: sent.message,
+    }))
+}
+
+/// The request to change the digest setting of a chatroom
+#[derive(Deserialize, ToSchema)]
+pub struct SetChatDigestRequest {
+    digest: bool,
+}
+
+/// Receive the messages of a chatroom as periodic digest
+///
+/// If `digest` is set, the executing user doesn't receive a [WsMessage::IncomingChatMessage]
+/// message for every message of the chatroom anymore. Instead, a [WsMessage::ChatDigest]
+/// message with the number of new messages and the most recent message is sent periodically.
+/// Mentions are still delivered immediately as [WsMessage::ChatMention] message.
+///
+/// If the server has disabled digests, the setting is stored, but all messages are
+/// delivered immediately.
+///
+/// The executing user must be a member of the chatroom.
+///
+/// [WsMessage::IncomingChatMessage]: crate::chan::WsMessage::IncomingChatMessage
+/// [WsMessage::ChatDigest]: crate::chan::WsMessage::ChatDigest
+/// [WsMessage::ChatMention]: crate::chan::WsMessage::ChatMention
+#[utoipa::path(
+    tag = "Chats",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Digest setting has been changed"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = SetChatDigestRequest,
+    security(("session_cookie" = []))
+)]
+#[put("/chats/{uuid}/digest")]
+pub async fn set_chat_digest(
+    path: Path<PathUuid>,
+    req: Json<SetChatDigestRequest>,
+    db: Data<Database>,
+    session: Session,
+    chat_digests: Data<ChatDigests>,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    query!(&mut tx, (ChatRoomMember::F.uuid,))
+        .condition(and!(
+            ChatRoomMember::F.chat_room.equals(path.uuid),
+            ChatRoomMember::F.member.equals(uuid)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::MissingPrivileges)?;
+
+    update!(&mut tx, ChatRoomMember)
+        .condition(and!(
+            ChatRoomMember::F.chat_room.equals(path.uuid),
+            ChatRoomMember::F.member.equals(uuid)
+        ))
+        .set(ChatRoomMember::F.digest, req.digest)
+        .exec()
         .await?;
 
     tx.commit().await?;
 
-    let chat_message = ChatMessage {
-        uuid: chat_room_message.uuid,
-        message: chat_room_message.message,
-        sender: AccountResponse {
-            uuid: sender_uuid,
-            display_name: sender_display_name,
-            username: sender_username,
-        },
-        created_at: DateTime::from_naive_utc_and_offset(chat_room_message.created_at, Utc),
-    };
+    chat_digests.set_digest(uuid, path.uuid, req.digest).await;
 
-    let msg = WsMessage::IncomingChatMessage {
-        message: chat_message.clone(),
-        chat_uuid: path.uuid,
-    };
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Export the whole history of a chatroom
+///
+/// The messages are streamed as newline delimited JSON (NDJSON), one [ChatMessage] per line,
+/// from the oldest to the most recent message. The `mentions` of the messages are not included.
+/// If an error occurs while streaming, the response is aborted.
+///
+/// The executing user must be a member of the chatroom.
+#[utoipa::path(
+    tag = "Chats",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns one message per line", content_type = "application/x-ndjson", body = ChatMessage),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[get("/chats/{uuid}/transcript")]
+pub async fn export_chat_transcript(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    query!(db.as_ref(), (ChatRoomMember::F.uuid,))
+        .condition(and!(
+            ChatRoomMember::F.chat_room.equals(path.uuid),
+            ChatRoomMember::F.member.equals(uuid),
+            ChatRoomMember::F.banned.equals(false)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::MissingPrivileges)?;
+
+    Ok(stream_chat_history(db.into_inner(), path.uuid))
+}
 
-    // Notify all chatroom members that there's a new message
-    for (uuid,) in chat_room_members {
-        if let Err(err) = ws_manager_chan
-            .send(WsManagerMessage::SendMessage(uuid, msg.clone()))
-            .await
+/// Stream the history of a chatroom as NDJSON, from the oldest to the most recent message
+///
+/// The `mentions` of the messages are not included.
+pub(crate) fn stream_chat_history(db: Arc<Database>, chat_room: Uuid) -> HttpResponse {
+    ndjson_response(|lines| async move {
+        let mut messages = query!(
+            db.as_ref(),
+            (
+                ChatRoomMessage::F.uuid,
+                ChatRoomMessage::F.message,
+                ChatRoomMessage::F.message_type,
+                ChatRoomMessage::F.created_at,
+                ChatRoomMessage::F.sender,
+                ChatRoomMessage::F.sequence,
+            )
+        )
+        .condition(ChatRoomMessage::F.chat_room.equals(chat_room))
+        .order_asc(ChatRoomMessage::F.created_at)
+        .stream();
+
+        // System messages don't have a sender, so the senders are resolved separately
+        let mut senders: HashMap<Uuid, Option<AccountResponse>> = HashMap::new();
+        while let Some((uuid, message, message_type, created_at, sender, sequence)) =
+            messages.try_next().await?
         {
-            warn!("Could not send to ws manager chan: {err}");
+            let sender = match sender {
+                Some(sender) => {
+                    let sender = *sender.key();
+                    if !senders.contains_key(&sender) {
+                        let account = query!(
+                            db.as_ref(),
+                            (
+                                Account::F.uuid,
+                                Account::F.username,
+                                Account::F.display_name
+                            )
+                        )
+                        .condition(Account::F.uuid.equals(sender))
+                        .optional()
+                        .await?
+                        .map(|(uuid, username, display_name)| AccountResponse {
+                            uuid,
+                            username,
+                            display_name,
+                        });
+                        senders.insert(sender, account);
+                    }
+                    senders.get(&sender).cloned().flatten()
+                }
+                None => None,
+            };
+
+            let message = ChatMessage {
+                uuid,
+                sender,
+                message_type,
+                message,
+                created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
+                mentions: vec![],
+                sequence,
+            };
+            if !lines.send(&message).await {
+                break;
+            }
         }
-    }
 
-    Ok(Json(chat_message))
+        Ok(())
+    })
 }
@@ -0,0 +1,199 @@
+//! This module holds the endpoints to manage the devices of an account
+
+use actix_toolbox::tb_middleware::Session;
+use actix_web::web::{Data, Json, Path};
+use actix_web::{delete, get, put, HttpResponse};
+use chrono::{DateTime, Utc};
+use rorm::db::Transaction;
+use rorm::{and, query, update, Database, FieldAccess, Model};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::Device;
+use crate::server::handler::{ApiError, ApiErrorResponse, ApiResult, PathUuid};
+
+/// Check that a device name is neither empty nor longer than 255 characters
+///
+/// The name is trimmed before it is checked.
+pub(crate) fn validate_device_name(name: Option<String>) -> ApiResult<Option<String>> {
+    match name {
+        None => Ok(None),
+        Some(name) => {
+            let name = name.trim();
+            if name.is_empty() || name.len() > 255 {
+                return Err(ApiError::InvalidDeviceName);
+            }
+            Ok(Some(name.to_string()))
+        }
+    }
+}
+
+/// Check that a device belongs to the account
+///
+/// Returns [ApiError::InvalidUuid] if it doesn't.
+async fn query_own_device(tx: &mut Transaction, account: Uuid, device: Uuid) -> ApiResult<()> {
+    query!(&mut *tx, (Device::F.uuid,))
+        .condition(and!(
+            Device::F.uuid.equals(device),
+            Device::F.account.equals(account)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    Ok(())
+}
+
+/// A device the account is logged in with
+///
+/// `current` is set for the device of the session that requested the list.
+#[derive(Serialize, ToSchema)]
+pub struct DeviceResponse {
+    uuid: Uuid,
+    #[schema(example = "Herbert's phone")]
+    name: Option<String>,
+    created_at: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    current: bool,
+}
+
+/// The devices of the executing account
+#[derive(Serialize, ToSchema)]
+pub struct GetDevicesResponse {
+    devices: Vec<DeviceResponse>,
+}
+
+/// Retrieves the devices the executing account is logged in with
+///
+/// Every login creates a new device, a logout removes it again.
+/// `last_seen` is updated at most once per minute.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the devices of the account", body = GetDevicesResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    security(("session_cookie" = []))
+)]
+#[get("/accounts/me/devices")]
+pub async fn get_devices(
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<GetDevicesResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+    let current: Option<Uuid> = session.get("device")?;
+
+    let devices = query!(db.as_ref(), Device)
+        .condition(Device::F.account.equals(uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|device| DeviceResponse {
+            uuid: device.uuid,
+            name: device.name,
+            created_at: DateTime::from_naive_utc_and_offset(device.created_at, Utc),
+            last_seen: DateTime::from_naive_utc_and_offset(device.last_seen, Utc),
+            current: current == Some(device.uuid),
+        })
+        .collect();
+
+    Ok(Json(GetDevicesResponse { devices }))
+}
+
+/// The request to rename a device
+///
+/// Set `name` to `null` to remove the name.
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateDeviceRequest {
+    #[schema(example = "Herbert's laptop")]
+    name: Option<String>,
+}
+
+/// Rename a device of the executing account
+///
+/// If the name is empty or longer than 255 characters, [ApiError::InvalidDeviceName]
+/// is returned. If the device doesn't belong to the executing account,
+/// [ApiError::InvalidUuid] is returned.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Device was renamed"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = UpdateDeviceRequest,
+    security(("session_cookie" = []))
+)]
+#[put("/accounts/me/devices/{uuid}")]
+pub async fn update_device(
+    path: Path<PathUuid>,
+    req: Json<UpdateDeviceRequest>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let name = validate_device_name(req.into_inner().name)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    query_own_device(&mut tx, uuid, path.uuid).await?;
+
+    update!(&mut tx, Device)
+        .condition(Device::F.uuid.equals(path.uuid))
+        .set(Device::F.name, name)
+        .exec()
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Revoke a device of the executing account
+///
+/// The session of the device is no longer valid afterwards, the client has to login again.
+/// Revoking the device of the current session logs out the executing account.
+///
+/// If the device doesn't belong to the executing account, [ApiError::InvalidUuid]
+/// is returned.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Device was revoked"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[delete("/accounts/me/devices/{uuid}")]
+pub async fn delete_device(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    query_own_device(&mut tx, uuid, path.uuid).await?;
+
+    rorm::delete!(&mut tx, Device)
+        .condition(Device::F.uuid.equals(path.uuid))
+        .await?;
+
+    tx.commit().await?;
+
+    if session.get::<Uuid>("device")? == Some(path.uuid) {
+        session.purge();
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,154 @@
+//! This module holds the endpoints to download game data with signed links
+
+use std::path::Path as StdPath;
+
+use actix_toolbox::tb_middleware::Session;
+use actix_web::http::header::{ContentEncoding, ContentType};
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{get, post, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::error;
+use rorm::{and, query, Database, FieldAccess, Model};
+use serde::{Deserialize, Serialize};
+use tokio::fs::read;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::models::Game;
+use crate::server::download_links::DownloadLinks;
+use crate::server::handler::{game_data_filename, ApiError, ApiErrorResponse, ApiResult, PathUuid};
+use crate::server::middleware::{ConcurrencyLimit, LimitedOperation};
+use crate::server::RuntimeSettings;
+
+/// A signed link to the current state of a game
+#[derive(Serialize, ToSchema)]
+pub struct DownloadLinkResponse {
+    /// The path of the link, relative to the server
+    #[schema(
+        example = "/api/v2/download/games/ec4b3a8b-1a6c-4a8b-9e51-5c0b7f2c3a1d/42?expires=1700000000&signature=..."
+    )]
+    url: String,
+    /// The state of the game the link points to
+    game_data_id: u64,
+    /// The point in time the link expires
+    expires_at: DateTime<Utc>,
+}
+
+/// Create a signed link to download the current state of a game
+///
+/// The link can be used without a session, e.g. by a replay viewer, until it expires.
+/// It only grants access to the current state of this game.
+///
+/// The executing user must be a player of the game.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the link", body = DownloadLinkResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[post("/games/{uuid}/downloadLink")]
+pub async fn create_download_link(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+    download_links: Data<DownloadLinks>,
+) -> ApiResult<Json<DownloadLinkResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+    let game_uuid = path.uuid;
+
+    let (data_id,) = query!(db.as_ref(), (Game::F.data_id,))
+        .condition(and!(
+            Game::F.uuid.equals(game_uuid),
+            Game::F.current_players.player.uuid.equals(uuid)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::GameNotFound)?;
+    let data_id = data_id as u64;
+
+    let (expires_at, signature) = download_links.sign(game_uuid, data_id);
+
+    Ok(Json(DownloadLinkResponse {
+        url: format!(
+            "/api/v2/download/games/{game_uuid}/{data_id}?expires={}&signature={signature}",
+            expires_at.timestamp()
+        ),
+        game_data_id: data_id,
+        expires_at,
+    }))
+}
+
+/// The path of a signed download link
+#[derive(Deserialize, IntoParams)]
+pub struct DownloadLinkPath {
+    /// The game
+    uuid: Uuid,
+    /// The state of the game
+    data_id: u64,
+}
+
+/// The signature of a download link
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadLinkQuery {
+    /// The unix timestamp the link expires at
+    expires: i64,
+    /// The signature of the link
+    signature: String,
+}
+
+/// Download a state of a game with a signed link
+///
+/// The links are created with `POST /api/v2/games/{uuid}/downloadLink` and don't require
+/// a session. The state is returned as is, without any JSON wrapping.
+///
+/// If the signature is invalid or the link expired, [ApiError::InvalidDownloadLink] is
+/// returned. If the state was replaced by a newer one in the meantime,
+/// [ApiError::GameNotFound] is returned.
+#[utoipa::path(
+    tag = "Games",
+    responses(
+        (status = 200, description = "Returns the state of the game", content_type = "application/octet-stream", body = String),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+        (status = 503, description = "Too many concurrent requests", body = ApiErrorResponse),
+    ),
+    params(DownloadLinkPath, DownloadLinkQuery),
+)]
+#[get(
+    "/api/v2/download/games/{uuid}/{data_id}",
+    wrap = "ConcurrencyLimit(LimitedOperation::GameDownload)"
+)]
+pub async fn download_game_data(
+    path: Path<DownloadLinkPath>,
+    link: Query<DownloadLinkQuery>,
+    settings: Data<RuntimeSettings>,
+    download_links: Data<DownloadLinks>,
+) -> ApiResult<HttpResponse> {
+    if !download_links.verify(path.uuid, path.data_id, link.expires, &link.signature) {
+        return Err(ApiError::InvalidDownloadLink);
+    }
+
+    let filename = game_data_filename(path.uuid, path.data_id as i64);
+    let content = match read(StdPath::new(&settings.game_data_path).join(&filename)).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(ApiError::GameNotFound);
+        }
+        Err(err) => {
+            error!("Game data expected in '{filename}' couldn't be read: {err}");
+            return Err(ApiError::InternalServerError);
+        }
+    };
+
+    // The game state is compressed by the client already
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::octet_stream())
+        .insert_header(ContentEncoding::Identity)
+        .body(content))
+}
@@ -0,0 +1,134 @@
+//! Server-sent events handler
+
+use std::time::Duration;
+
+use actix_toolbox::tb_middleware::Session;
+use actix_web::http::header;
+use actix_web::web::{Bytes, Data, Query};
+use actix_web::{get, HttpResponse};
+use futures::stream;
+use log::{debug, error, warn};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+use crate::chan::{ConnectionOptions, WsEnvelope, WsManagerChan, WsManagerMessage, WsMessage};
+use crate::server::handler::{ApiError, ApiErrorResponse, ApiResult};
+
+/// The interval in which a keep-alive comment is sent if there are no messages
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Unregisters the event stream from the ws manager when the client disconnects
+///
+/// Only the stream with the id `connection` is unregistered, other websockets and
+/// event streams of the account stay open.
+struct EventStreamGuard {
+    uuid: Uuid,
+    connection: Uuid,
+    ws_manager_chan: WsManagerChan,
+}
+
+impl Drop for EventStreamGuard {
+    fn drop(&mut self) {
+        debug!("Event stream closed");
+
+        let uuid = self.uuid;
+        let connection = self.connection;
+        let ws_manager_chan = self.ws_manager_chan.clone();
+        tokio::spawn(async move {
+            if let Err(err) = ws_manager_chan
+                .send(WsManagerMessage::WebsocketClosed(uuid, connection))
+                .await
+            {
+                warn!("Could not send to ws_manager_chan: {err}");
+            }
+        });
+    }
+}
+
+/// Serialize a [WsMessage] as a server-sent event
+fn event(msg: WsMessage) -> Option<Bytes> {
+    match serde_json::to_string(&WsEnvelope::new(msg)) {
+        Ok(txt) => Some(Bytes::from(format!("data: {txt}\n\n"))),
+        Err(err) => {
+            error!("Error serializing WsMessage: {err}");
+            None
+        }
+    }
+}
+
+/// Start a server-sent events stream
+///
+/// This is a fallback for clients that can't open a websocket connection.
+/// The stream delivers the same messages as `GET /api/v2/ws`, each message is sent as
+/// the `data` of a single event, wrapped in a [WsEnvelope].
+///
+/// While the stream is open, the account is considered online, exactly like with a
+/// websocket connection. If the stream was the last websocket or event stream of the
+/// account, closing it has the same effects as closing the last websocket: the account
+/// goes offline and leaves its lobbies.
+///
+/// A keep-alive comment is sent every 15s if there are no messages.
+/// The stream is one-directional, clients can't send messages through it.
+///
+/// The options are the same as for `GET /api/v2/ws`.
+#[utoipa::path(
+    tag = "Websocket",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "The event stream is opened", content_type = "text/event-stream"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(ConnectionOptions),
+    security(("session_cookie" = []))
+)]
+#[get("/events")]
+pub async fn events(
+    options: Query<ConnectionOptions>,
+    session: Session,
+    ws_manager_chan: Data<WsManagerChan>,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    debug!("Initializing event stream");
+
+    // Identifies this stream among the other connections of the account
+    let connection = Uuid::new_v4();
+    let (tx, rx) = mpsc::channel(16);
+    if let Err(err) = ws_manager_chan
+        .send(WsManagerMessage::OpenedEventStream(uuid, connection, tx))
+        .await
+    {
+        error!("Could not send event stream to ws manager: {err}");
+        return Err(ApiError::InternalServerError);
+    }
+
+    let guard = EventStreamGuard {
+        uuid,
+        connection,
+        ws_manager_chan: ws_manager_chan.get_ref().clone(),
+    };
+
+    let options = *options;
+    let events = stream::unfold((rx, guard), move |(mut rx, guard)| async move {
+        loop {
+            let bytes = match timeout(KEEP_ALIVE_INTERVAL, rx.recv()).await {
+                Ok(Some(WsMessage::ServerQuitSocket)) | Ok(None) => return None,
+                Ok(Some(msg)) if !options.wants(&msg) => continue,
+                Ok(Some(msg)) => match event(msg) {
+                    Some(bytes) => bytes,
+                    None => continue,
+                },
+                Err(_) => Bytes::from_static(b": keep-alive\n\n"),
+            };
+
+            return Some((Ok::<_, actix_web::Error>(bytes), (rx, guard)));
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events))
+}
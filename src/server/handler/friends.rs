@@ -1,31 +1,39 @@
 //! Handler for friends
 
+use std::collections::HashSet;
+
 use actix_toolbox::tb_middleware::Session;
 use actix_web::web::{Data, Json, Path};
-use actix_web::{delete, get, post, put, HttpResponse};
-use log::{error, warn};
+use actix_web::{delete, get, patch, post, put, HttpResponse};
+use rorm::db::Transaction;
 use rorm::fields::types::ForeignModelByField;
 use rorm::{and, insert, or, query, update, Database, FieldAccess, Model};
 use serde::{Deserialize, Serialize};
-use tokio::sync::oneshot;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::chan::{FriendshipEvent, WsManagerChan, WsManagerMessage, WsMessage};
+use crate::chan::{FriendshipEvent, Notifier, WsMessage};
 use crate::models::{
     Account, ChatRoom, ChatRoomInsert, ChatRoomMemberInsert, Friend, FriendInsert,
-    FriendWithChatInsert,
+    FriendRequestPolicy, FriendWithChatInsert,
 };
 use crate::server::handler::{
-    AccountResponse, ApiError, ApiErrorResponse, ApiResult, OnlineAccountResponse, PathUuid,
+    fill_online_states, normalize_username, AccountResponse, ApiError, ApiErrorResponse, ApiResult,
+    OnlineAccountResponse, PathUuid,
 };
 
 /// A single friend
+///
+/// If `auto_accept_invites` is true, lobby invites of the friend are accepted automatically.
+/// `nickname` is the private alias you gave the friend, if any.
 #[derive(Serialize, ToSchema)]
 pub struct FriendResponse {
     uuid: Uuid,
     chat_uuid: Uuid,
     friend: OnlineAccountResponse,
+    auto_accept_invites: bool,
+    #[schema(example = "Bob from work")]
+    nickname: Option<String>,
 }
 
 /// A single friend request
@@ -57,6 +65,9 @@ pub struct GetFriendResponse {
 /// friendship, but the destination hasn't accepted yet.
 ///
 /// In the other case, if your username is in `to.uuid`, you have received a friend request.
+///
+/// Afterwards, changes of the online state of your friends are sent as
+/// [WsMessage::FriendOnline] and [WsMessage::FriendOffline] messages.
 #[utoipa::path(
     tag = "Friends",
     context_path = "/api/v2",
@@ -71,7 +82,7 @@ pub struct GetFriendResponse {
 pub async fn get_friends(
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<Json<GetFriendResponse>> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
@@ -87,6 +98,8 @@ pub async fn get_friends(
             Friend::F.to.username,
             Friend::F.to.display_name,
             Friend::F.chat_room,
+            Friend::F.auto_accept_invites,
+            Friend::F.nickname,
         )
     )
     .condition(and!(
@@ -96,51 +109,38 @@ pub async fn get_friends(
     .all()
     .await?;
 
-    let (oneshot_tx, oneshot_rx) = oneshot::channel();
-    let online_state = tokio::spawn(async move { oneshot_rx.await });
-    if let Err(err) = ws_manager_chan
-        .send(WsManagerMessage::RetrieveOnlineStates(
-            friends_raw.iter().map(|raw| raw.1).collect(),
-            oneshot_tx,
-        ))
-        .await
-    {
-        error!("Could not send to ws manager chan: {err}");
-        return Err(ApiError::InternalServerError);
-    }
-
-    let online_state = match online_state.await {
-        Ok(res) => match res {
-            Ok(state) => state,
-            Err(err) => {
-                error!("Error receiving online state from ws manager chan: {err}");
-                return Err(ApiError::InternalServerError);
-            }
-        },
-        Err(err) => {
-            error!("Error joining task: {err}");
-            return Err(ApiError::InternalServerError);
-        }
-    };
-
     // Retrieve all friendships
-    let friends = Vec::from_iter(friends_raw.into_iter().zip(online_state).map(
-        |((uuid, to_uuid, to_username, to_display_name, chat_room), online)| {
+    let mut friends = Vec::from_iter(friends_raw.into_iter().map(
+        |(
+            uuid,
+            to_uuid,
+            to_username,
+            to_display_name,
+            chat_room,
+            auto_accept_invites,
+            nickname,
+        )| {
             // As all friend that are not in request state should have a chat room, this should be
             // fine unless the database is in an invalid state
             #[allow(clippy::unwrap_used)]
             FriendResponse {
                 uuid,
                 chat_uuid: *chat_room.unwrap().key(),
-                friend: OnlineAccountResponse {
+                friend: OnlineAccountResponse::offline(AccountResponse {
                     uuid: to_uuid,
                     username: to_username,
                     display_name: to_display_name,
-                    online,
-                },
+                }),
+                auto_accept_invites,
+                nickname,
             }
         },
     ));
+    fill_online_states(
+        notifier.get_ref(),
+        friends.iter_mut().map(|friend| &mut friend.friend),
+    )
+    .await?;
 
     // Retrieve all incoming requests
     friend_requests.extend(
@@ -252,6 +252,9 @@ pub struct CreateFriendRequest {
 /// Create a new friend request
 ///
 /// The other party is notified via a [WsMessage::IncomingFriendRequest]
+///
+/// If the other party doesn't accept friend requests from the executing account, see
+/// `PUT /api/v2/accounts/me/friendRequests`, [ApiError::FriendRequestsNotAllowed] is returned.
 #[utoipa::path(
     tag = "Friends",
     context_path = "/api/v2",
@@ -268,7 +271,7 @@ pub async fn create_friend_request(
     req: Json<CreateFriendRequest>,
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
@@ -286,8 +289,48 @@ pub async fn create_friend_request(
         .await?
         .ok_or(ApiError::InvalidUuid)?;
 
+    insert_friend_request(&mut tx, uuid, &target).await?;
+
+    let (uuid, username, display_name) = query!(
+        &mut tx,
+        (
+            Account::F.uuid,
+            Account::F.username,
+            Account::F.display_name
+        )
+    )
+    .condition(Account::F.uuid.equals(uuid))
+    .optional()
+    .await?
+    .ok_or(ApiError::SessionCorrupt)?;
+
+    tx.commit().await?;
+
+    // Notify other party about friend request
+    let msg = WsMessage::IncomingFriendRequest {
+        from: AccountResponse {
+            uuid,
+            username,
+            display_name,
+        },
+    };
+    notifier.send(target.uuid, msg).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Insert a friend request from an account to another one
+///
+/// Returns [ApiError::AlreadyFriends] or [ApiError::FriendshipAlreadyRequested] if the
+/// accounts are already in a friendship and [ApiError::FriendRequestsNotAllowed] if
+/// the other account doesn't accept friend requests from the account.
+async fn insert_friend_request(
+    tx: &mut Transaction,
+    uuid: Uuid,
+    target: &Account,
+) -> ApiResult<()> {
     // Check if users are already in a friendship
-    if let Some(friendship) = query!(&mut tx, Friend)
+    if let Some(friendship) = query!(&mut *tx, Friend)
         .condition(or!(
             and!(
                 Friend::F.from.equals(uuid.as_ref()),
@@ -308,8 +351,19 @@ pub async fn create_friend_request(
         };
     }
 
+    // Check if target accepts friend requests of the executing account
+    match target.who_can_send_friend_requests {
+        FriendRequestPolicy::Everyone => {}
+        FriendRequestPolicy::FriendsOfFriends => {
+            if !have_mutual_friends(tx, uuid, target.uuid).await? {
+                return Err(ApiError::FriendRequestsNotAllowed);
+            }
+        }
+        FriendRequestPolicy::Nobody => return Err(ApiError::FriendRequestsNotAllowed),
+    }
+
     // Create new friendship request
-    insert!(&mut tx, FriendInsert)
+    insert!(&mut *tx, FriendInsert)
         .single(&FriendInsert {
             uuid: Uuid::new_v4(),
             is_request: true,
@@ -318,6 +372,130 @@ pub async fn create_friend_request(
         })
         .await?;
 
+    Ok(())
+}
+
+/// The maximum number of usernames of a bulk friend request
+const MAX_BULK_FRIEND_REQUESTS: usize = 100;
+
+/// The request to send friend requests to many accounts at once
+#[derive(Deserialize, ToSchema)]
+pub struct CreateBulkFriendRequest {
+    /// The usernames of the new friends
+    usernames: Vec<String>,
+}
+
+/// The outcome of a single username of a bulk friend request
+#[derive(Serialize, ToSchema, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum BulkFriendRequestStatus {
+    /// The friend request has been created
+    Requested,
+    /// No account with the username exists
+    NotFound,
+    /// The username is the one of the executing account
+    OwnAccount,
+    /// The accounts are already friends
+    AlreadyFriends,
+    /// A friend request between the accounts already exists
+    AlreadyRequested,
+    /// The account doesn't accept friend requests from the executing account
+    NotAllowed,
+}
+
+/// The result of a single username of a bulk friend request
+#[derive(Serialize, ToSchema)]
+pub struct BulkFriendRequestResult {
+    #[schema(example = "user123")]
+    username: String,
+    /// The uuid of the account, if it exists
+    uuid: Option<Uuid>,
+    status: BulkFriendRequestStatus,
+}
+
+/// The response of a bulk friend request
+///
+/// It contains one result per username, in the order of the request.
+#[derive(Serialize, ToSchema)]
+pub struct CreateBulkFriendResponse {
+    results: Vec<BulkFriendRequestResult>,
+}
+
+/// Create friend requests for a list of usernames
+///
+/// This helps accounts that move from another server to restore their friends.
+/// The usernames are matched case-insensitively, like on login. All friend requests
+/// are created in one transaction, and usernames that can't be requested are reported
+/// in the results instead of failing the whole request.
+///
+/// Each new friend is notified via a [WsMessage::IncomingFriendRequest].
+///
+/// If more than 100 usernames are passed, [ApiError::TooManyUsernames] is returned.
+#[utoipa::path(
+    tag = "Friends",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the result of each username", body = CreateBulkFriendResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    request_body = CreateBulkFriendRequest,
+    security(("session_cookie" = []))
+)]
+#[post("/friends/bulk")]
+pub async fn create_bulk_friend_requests(
+    req: Json<CreateBulkFriendRequest>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<CreateBulkFriendResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    if req.usernames.len() > MAX_BULK_FRIEND_REQUESTS {
+        return Err(ApiError::TooManyUsernames);
+    }
+
+    let mut tx = db.start_transaction().await?;
+
+    let mut results = Vec::with_capacity(req.usernames.len());
+    for username in req.into_inner().usernames {
+        let Some(target) = query!(&mut tx, Account)
+            .condition(
+                Account::F
+                    .normalized_username
+                    .equals(normalize_username(&username)),
+            )
+            .optional()
+            .await?
+        else {
+            results.push(BulkFriendRequestResult {
+                username,
+                uuid: None,
+                status: BulkFriendRequestStatus::NotFound,
+            });
+            continue;
+        };
+
+        let status = if target.uuid == uuid {
+            BulkFriendRequestStatus::OwnAccount
+        } else {
+            match insert_friend_request(&mut tx, uuid, &target).await {
+                Ok(()) => BulkFriendRequestStatus::Requested,
+                Err(ApiError::AlreadyFriends) => BulkFriendRequestStatus::AlreadyFriends,
+                Err(ApiError::FriendshipAlreadyRequested) => {
+                    BulkFriendRequestStatus::AlreadyRequested
+                }
+                Err(ApiError::FriendRequestsNotAllowed) => BulkFriendRequestStatus::NotAllowed,
+                Err(err) => return Err(err),
+            }
+        };
+        results.push(BulkFriendRequestResult {
+            username,
+            uuid: Some(target.uuid),
+            status,
+        });
+    }
+
     let (uuid, username, display_name) = query!(
         &mut tx,
         (
@@ -333,22 +511,45 @@ pub async fn create_friend_request(
 
     tx.commit().await?;
 
-    // Notify other party about friend request
-    let msg = WsMessage::IncomingFriendRequest {
-        from: AccountResponse {
-            uuid,
-            username,
-            display_name,
-        },
-    };
-    if let Err(err) = ws_manager_chan
-        .send(WsManagerMessage::SendMessage(target.uuid, msg))
-        .await
-    {
-        warn!("Could not send to ws manager chan: {err}");
+    // Notify the other parties about the friend requests
+    for result in &results {
+        if let (Some(target), BulkFriendRequestStatus::Requested) = (result.uuid, result.status) {
+            let msg = WsMessage::IncomingFriendRequest {
+                from: AccountResponse {
+                    uuid,
+                    username: username.clone(),
+                    display_name: display_name.clone(),
+                },
+            };
+            notifier.send(target, msg).await;
+        }
     }
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(Json(CreateBulkFriendResponse { results }))
+}
+
+/// Check whether two accounts have at least one established friendship in common
+async fn have_mutual_friends(tx: &mut Transaction, account: Uuid, other: Uuid) -> ApiResult<bool> {
+    let friends: HashSet<Uuid> = query!(&mut *tx, (Friend::F.to.uuid,))
+        .condition(and!(
+            Friend::F.from.equals(account),
+            Friend::F.is_request.equals(false)
+        ))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(friend,)| friend)
+        .collect();
+
+    Ok(query!(&mut *tx, (Friend::F.to.uuid,))
+        .condition(and!(
+            Friend::F.from.equals(other),
+            Friend::F.is_request.equals(false)
+        ))
+        .all()
+        .await?
+        .into_iter()
+        .any(|(friend,)| friends.contains(&friend)))
 }
 
 /// Don't want your friends anymore? Just delete them!
@@ -368,7 +569,7 @@ pub async fn delete_friend(
     path: Path<PathUuid>,
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
@@ -437,12 +638,7 @@ pub async fn delete_friend(
         },
     };
 
-    if let Err(err) = ws_manager_chan
-        .send(WsManagerMessage::SendMessage(other_party, msg))
-        .await
-    {
-        warn!("Could not send to ws manager chan: {err}");
-    }
+    notifier.send(other_party, msg).await;
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -464,7 +660,7 @@ pub async fn accept_friend_request(
     path: Path<PathUuid>,
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
@@ -554,12 +750,129 @@ pub async fn accept_friend_request(
     };
 
     // Notify other party about accepted friendship
-    if let Err(err) = ws_manager_chan
-        .send(WsManagerMessage::SendMessage(*f.from.key(), msg))
-        .await
-    {
-        warn!("Could not send to ws manager chan: {err}");
+    notifier.send(*f.from.key(), msg).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The request to update the settings of a friendship
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateFriendRequest {
+    auto_accept_invites: bool,
+}
+
+/// Update the settings of a friendship
+///
+/// The `uuid` is the one of the friendship as returned by `GET /api/v2/friends`.
+/// The settings only apply to the executing user.
+///
+/// If `auto_accept_invites` is set, lobby invites of the friend are accepted automatically,
+/// if the executing user has an active websocket connection and could join the lobby.
+#[utoipa::path(
+    tag = "Friends",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Friendship was updated"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = UpdateFriendRequest,
+    security(("session_cookie" = []))
+)]
+#[patch("/friends/{uuid}")]
+pub async fn update_friend(
+    path: Path<PathUuid>,
+    req: Json<UpdateFriendRequest>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    query!(&mut tx, (Friend::F.uuid,))
+        .condition(and!(
+            Friend::F.uuid.equals(path.uuid),
+            Friend::F.from.equals(uuid),
+            Friend::F.is_request.equals(false)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    update!(&mut tx, Friend)
+        .condition(Friend::F.uuid.equals(path.uuid))
+        .set(Friend::F.auto_accept_invites, req.auto_accept_invites)
+        .exec()
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The request to set the nickname of a friend
+#[derive(Deserialize, ToSchema)]
+pub struct SetFriendNicknameRequest {
+    /// The nickname, `null` or an empty string removes it
+    #[schema(example = "Bob from work")]
+    nickname: Option<String>,
+}
+
+/// Set the nickname of a friend
+///
+/// The `uuid` is the one of the friendship as returned by `GET /api/v2/friends`.
+/// The nickname is private, it's only returned to the executing user.
+#[utoipa::path(
+    tag = "Friends",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Nickname was set"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = SetFriendNicknameRequest,
+    security(("session_cookie" = []))
+)]
+#[put("/friends/{uuid}/nickname")]
+pub async fn set_friend_nickname(
+    path: Path<PathUuid>,
+    req: Json<SetFriendNicknameRequest>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let nickname = req
+        .into_inner()
+        .nickname
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty());
+    if nickname.as_ref().is_some_and(|x| x.chars().count() > 255) {
+        return Err(ApiError::InvalidNickname);
     }
 
+    let mut tx = db.start_transaction().await?;
+
+    query!(&mut tx, (Friend::F.uuid,))
+        .condition(and!(
+            Friend::F.uuid.equals(path.uuid),
+            Friend::F.from.equals(uuid),
+            Friend::F.is_request.equals(false)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    update!(&mut tx, Friend)
+        .condition(Friend::F.uuid.equals(path.uuid))
+        .set(Friend::F.nickname, nickname)
+        .exec()
+        .await?;
+
+    tx.commit().await?;
+
     Ok(HttpResponse::Ok().finish())
 }
@@ -0,0 +1,208 @@
+//! Handler for the event log of games
+
+use actix_toolbox::tb_middleware::Session;
+use actix_web::get;
+use actix_web::web::{Data, Json, Path, Query};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use rorm::conditions::DynamicCollection;
+use rorm::db::Transaction;
+use rorm::fields::types::ForeignModelByField;
+use rorm::{and, insert, query, Database, FieldAccess, Model};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::{Account, GameAccount, GameEvent, GameEventInsert, GameEventKind};
+use crate::server::handler::{
+    AccountResponse, ApiError, ApiErrorResponse, ApiResult, PaginationQuery, PathUuid,
+};
+
+/// Record an event that was observed in a game
+///
+/// **Parameter**:
+/// - `tx`: The transaction the event should be recorded in
+/// - `game`: The game the event happened in
+/// - `kind`: The kind of the event
+/// - `actor`: The account that caused the event
+/// - `subject`: The account that was affected by the event
+/// - `data_id`: The state identifier of the game data at the time of the event
+/// - `note`: The note the actor left for the other players
+pub(crate) async fn record_game_event(
+    tx: &mut Transaction,
+    game: Uuid,
+    kind: GameEventKind,
+    actor: Option<Uuid>,
+    subject: Option<Uuid>,
+    data_id: Option<i64>,
+    note: Option<String>,
+) -> Result<(), rorm::Error> {
+    insert!(tx, GameEventInsert)
+        .return_nothing()
+        .single(&GameEventInsert {
+            uuid: Uuid::new_v4(),
+            game: ForeignModelByField::Key(game),
+            kind,
+            actor: actor.map(ForeignModelByField::Key),
+            subject: subject.map(ForeignModelByField::Key),
+            data_id,
+            note,
+        })
+        .await
+}
+
+/// A single event of a game
+///
+/// `actor` is the account that caused the event, `subject` the account that was
+/// affected by it. Both are `null` if not applicable or if the account was deleted.
+///
+/// `note` is the note the actor left for the next player when uploading a game state.
+#[derive(Serialize, ToSchema)]
+pub struct GameEventResponse {
+    uuid: Uuid,
+    kind: GameEventKind,
+    actor: Option<AccountResponse>,
+    subject: Option<AccountResponse>,
+    #[schema(example = 1337)]
+    game_data_id: Option<u64>,
+    #[schema(example = "Watch out, barbarians near your capital")]
+    note: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+/// A page of events of a game
+///
+/// `total` is the number of all events of the game
+#[derive(Serialize, ToSchema)]
+pub struct GetGameEventsResponse {
+    events: Vec<GameEventResponse>,
+    #[schema(example = 42)]
+    total: u64,
+}
+
+/// Retrieve the event log of a game
+///
+/// The events are sorted by their creation time, most recent first.
+/// The executing user must be a player of the game.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns a page of events of the game", body = GetGameEventsResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid, PaginationQuery),
+    security(("session_cookie" = []))
+)]
+#[get("/games/{uuid}/events")]
+pub async fn get_game_events(
+    path: Path<PathUuid>,
+    pagination: Query<PaginationQuery>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<GetGameEventsResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    // Check if the executing user is playing the game
+    query!(&mut tx, (GameAccount::F.uuid,))
+        .condition(and!(
+            GameAccount::F.game.equals(path.uuid),
+            GameAccount::F.player.equals(uuid)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::GameNotFound)?;
+
+    let (total,) = query!(&mut tx, (GameEvent::F.uuid.count(),))
+        .condition(GameEvent::F.game.equals(path.uuid))
+        .one()
+        .await?;
+
+    let events = query!(
+        &mut tx,
+        (
+            GameEvent::F.uuid,
+            GameEvent::F.kind,
+            GameEvent::F.actor,
+            GameEvent::F.subject,
+            GameEvent::F.data_id,
+            GameEvent::F.note,
+            GameEvent::F.created_at,
+        )
+    )
+    .condition(GameEvent::F.game.equals(path.uuid))
+    .order_desc(GameEvent::F.created_at)
+    .limit(pagination.limit())
+    .offset(pagination.offset())
+    .all()
+    .await?;
+
+    // Retrieve all accounts of the page at once
+    let mut accounts: Vec<Uuid> = events
+        .iter()
+        .flat_map(|(_, _, actor, subject, _, _, _)| [actor, subject])
+        .flatten()
+        .map(|x| *x.key())
+        .collect();
+    accounts.sort();
+    accounts.dedup();
+
+    let accounts: HashMap<Uuid, AccountResponse> = if accounts.is_empty() {
+        HashMap::new()
+    } else {
+        query!(
+            &mut tx,
+            (
+                Account::F.uuid,
+                Account::F.username,
+                Account::F.display_name
+            )
+        )
+        .condition(DynamicCollection::or(
+            accounts
+                .into_iter()
+                .map(|x| Account::F.uuid.equals(x))
+                .collect(),
+        ))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(uuid, username, display_name)| {
+            (
+                uuid,
+                AccountResponse {
+                    uuid,
+                    username,
+                    display_name,
+                },
+            )
+        })
+        .collect()
+    };
+
+    tx.commit().await?;
+
+    let response = events
+        .into_iter()
+        .map(
+            |(uuid, kind, actor, subject, data_id, note, created_at)| GameEventResponse {
+                uuid,
+                kind,
+                actor: actor.and_then(|x| accounts.get(x.key()).cloned()),
+                subject: subject.and_then(|x| accounts.get(x.key()).cloned()),
+                game_data_id: data_id.map(|x| x as u64),
+                note,
+                created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
+            },
+        )
+        .collect();
+
+    Ok(Json(GetGameEventsResponse {
+        events: response,
+        total: total as u64,
+    }))
+}
@@ -0,0 +1,359 @@
+//! Handler for named snapshots of games
+
+use std::path::Path as StdPath;
+
+use actix_toolbox::tb_middleware::Session;
+use actix_web::web::{Data, Json, Path};
+use actix_web::{get, post};
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use rorm::db::Transaction;
+use rorm::fields::types::ForeignModelByField;
+use rorm::{and, insert, query, update, Database, FieldAccess, Model};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs::{copy, read_to_string, remove_file, write};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::chan::{Notifier, WsMessage};
+use crate::models::{Game, GameAccount, GameEventKind, GameSnapshot, GameSnapshotInsert};
+use crate::server::handler::{
+    record_game_event, ApiError, ApiErrorResponse, ApiResult, GameUploadResponse, PathUuid,
+};
+use crate::server::RuntimeSettings;
+
+/// A single snapshot of a game
+///
+/// `game_data_id` is the state identifier of the game data the snapshot was taken of.
+/// `created_by` is `null` if the account that created the snapshot was deleted.
+#[derive(Serialize, ToSchema)]
+pub struct GameSnapshotResponse {
+    uuid: Uuid,
+    #[schema(example = "Before war")]
+    label: String,
+    #[schema(example = 1337)]
+    game_data_id: u64,
+    created_by: Option<Uuid>,
+    created_at: DateTime<Utc>,
+}
+
+/// The snapshots of a game
+#[derive(Serialize, ToSchema)]
+pub struct GetGameSnapshotsResponse {
+    snapshots: Vec<GameSnapshotResponse>,
+}
+
+/// The request to create a snapshot of a game
+///
+/// `label` must not be empty and at most 255 characters long.
+#[derive(Deserialize, ToSchema)]
+pub struct CreateGameSnapshotRequest {
+    #[schema(example = "Before war")]
+    label: String,
+}
+
+/// Create a named snapshot of the current state of a game
+///
+/// Only the host of the game is allowed to create snapshots.
+/// If no game state was uploaded for the game yet, [ApiError::NoGameState] is returned.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Snapshot created", body = GameSnapshotResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = CreateGameSnapshotRequest,
+    security(("session_cookie" = []))
+)]
+#[post("/games/{uuid}/snapshots")]
+pub async fn create_game_snapshot(
+    path: Path<PathUuid>,
+    req: Json<CreateGameSnapshotRequest>,
+    settings: Data<RuntimeSettings>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<GameSnapshotResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+    let game_uuid = path.uuid;
+
+    let label = req.label.trim();
+    if label.is_empty() || label.chars().count() > 255 {
+        return Err(ApiError::InvalidSnapshotLabel);
+    }
+
+    let mut tx = db.start_transaction().await?;
+
+    let (data_id, data_checksum) = query_game_as_host(&mut tx, game_uuid, uuid).await?;
+
+    // There is no file to copy before the first upload
+    if data_id == 0 {
+        return Err(ApiError::NoGameState);
+    }
+
+    let snapshot_uuid = Uuid::new_v4();
+
+    // Copy the current game data to the snapshot file
+    let filename = game_data_filename(game_uuid, data_id);
+    let snapshot_filename = snapshot_filename(game_uuid, snapshot_uuid);
+    let game_data_path = StdPath::new(&settings.game_data_path);
+    if let Err(e) = copy(
+        game_data_path.join(&filename),
+        game_data_path.join(&snapshot_filename),
+    )
+    .await
+    {
+        error!("Game data in '{filename}' could not be copied to '{snapshot_filename}': {e}");
+        return Err(ApiError::InternalServerError);
+    }
+
+    let snapshot = insert!(&mut tx, GameSnapshotInsert)
+        .single(&GameSnapshotInsert {
+            uuid: snapshot_uuid,
+            game: ForeignModelByField::Key(game_uuid),
+            label: label.to_string(),
+            data_id,
+            data_checksum,
+            created_by: Some(ForeignModelByField::Key(uuid)),
+        })
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(GameSnapshotResponse {
+        uuid: snapshot.uuid,
+        label: snapshot.label,
+        game_data_id: snapshot.data_id as u64,
+        created_by: Some(uuid),
+        created_at: DateTime::from_naive_utc_and_offset(snapshot.created_at, Utc),
+    }))
+}
+
+/// Retrieve the snapshots of a game
+///
+/// The snapshots are sorted by their creation time, most recent first.
+/// The executing user must be a player of the game.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the snapshots of the game", body = GetGameSnapshotsResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[get("/games/{uuid}/snapshots")]
+pub async fn get_game_snapshots(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<GetGameSnapshotsResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    // Check if the executing user is playing the game
+    query!(&mut tx, (GameAccount::F.uuid,))
+        .condition(and!(
+            GameAccount::F.game.equals(path.uuid),
+            GameAccount::F.player.equals(uuid)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::GameNotFound)?;
+
+    let snapshots = query!(&mut tx, GameSnapshot)
+        .condition(GameSnapshot::F.game.equals(path.uuid))
+        .order_desc(GameSnapshot::F.created_at)
+        .all()
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(GetGameSnapshotsResponse {
+        snapshots: snapshots
+            .into_iter()
+            .map(|x| GameSnapshotResponse {
+                uuid: x.uuid,
+                label: x.label,
+                game_data_id: x.data_id as u64,
+                created_by: x.created_by.map(|x| *x.key()),
+                created_at: DateTime::from_naive_utc_and_offset(x.created_at, Utc),
+            })
+            .collect(),
+    }))
+}
+
+/// The path parameter of a snapshot of a game
+#[derive(Deserialize, IntoParams)]
+pub struct GameSnapshotPath {
+    uuid: Uuid,
+    snapshot_uuid: Uuid,
+}
+
+/// Restore a snapshot of a game
+///
+/// Only the host of the game is allowed to restore snapshots.
+///
+/// The game data of the snapshot is stored as a new game state with a new `game_data_id`.
+/// All players of the game receive a [WsMessage::UpdateGameData] message.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the new data identifier of the restored game state", body = GameUploadResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(GameSnapshotPath),
+    security(("session_cookie" = []))
+)]
+#[post("/games/{uuid}/snapshots/{snapshot_uuid}/restore")]
+pub async fn restore_game_snapshot(
+    path: Path<GameSnapshotPath>,
+    settings: Data<RuntimeSettings>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<GameUploadResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+    let game_uuid = path.uuid;
+
+    let mut tx = db.start_transaction().await?;
+
+    let (data_id, _) = query_game_as_host(&mut tx, game_uuid, uuid).await?;
+
+    let (snapshot_checksum,) = query!(&mut tx, (GameSnapshot::F.data_checksum,))
+        .condition(and!(
+            GameSnapshot::F.uuid.equals(path.snapshot_uuid),
+            GameSnapshot::F.game.equals(game_uuid)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    let game_data_path = StdPath::new(&settings.game_data_path);
+    let snapshot_filename = snapshot_filename(game_uuid, path.snapshot_uuid);
+    let game_data = read_to_string(game_data_path.join(&snapshot_filename))
+        .await
+        .map_err(|e| {
+            error!("Snapshot expected in '{snapshot_filename}' couldn't be read: {e}");
+            ApiError::InternalServerError
+        })?;
+
+    let checksum = hex::encode(Sha256::digest(game_data.as_bytes()));
+    if let Some(expected) = snapshot_checksum {
+        if expected != checksum {
+            error!("Snapshot in '{snapshot_filename}' doesn't match its checksum");
+            return Err(ApiError::InternalServerError);
+        }
+    }
+
+    let players: Vec<Uuid> = query!(&mut tx, (GameAccount::F.player,))
+        .condition(GameAccount::F.game.equals(game_uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(player,)| *player.key())
+        .collect();
+
+    // Save the restored game state as a new state
+    let new_data_id = data_id + 1;
+    let new_filename = game_data_filename(game_uuid, new_data_id);
+    if let Err(e) = write(game_data_path.join(&new_filename), &game_data).await {
+        error!("Game data could not be saved to '{new_filename}': {e}");
+        return Err(ApiError::InternalServerError);
+    }
+
+    update!(&mut tx, Game)
+        .set(Game::F.data_id, new_data_id)
+        .set(Game::F.data_checksum, Some(checksum.clone()))
+        .set(Game::F.updated_by, ForeignModelByField::Key(uuid))
+        .condition(Game::F.uuid.equals(game_uuid))
+        .await?;
+
+    let (updated_at,) = query!(&mut tx, (Game::F.updated_at,))
+        .condition(Game::F.uuid.equals(game_uuid))
+        .one()
+        .await?;
+
+    record_game_event(
+        &mut tx,
+        game_uuid,
+        GameEventKind::Uploaded,
+        Some(uuid),
+        None,
+        Some(new_data_id),
+        None,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    // Remove the old file from the filesystem
+    let old_filename = game_data_filename(game_uuid, data_id);
+    if let Err(e) = remove_file(game_data_path.join(&old_filename)).await {
+        warn!("Outdated data in '{old_filename}' could not be removed and may leak: {e}");
+    }
+
+    let msg = WsMessage::UpdateGameData {
+        game_uuid,
+        game_data_id: new_data_id as u64,
+        game_data,
+    };
+    for player in &players {
+        notifier.send(*player, msg.clone()).await;
+    }
+
+    Ok(Json(GameUploadResponse {
+        game_data_id: new_data_id as u64,
+        updated_at: DateTime::from_naive_utc_and_offset(updated_at, Utc),
+        game_data_checksum: checksum,
+        notified_players: players,
+    }))
+}
+
+/// The filename the game data of a game with a state identifier is stored in
+///
+/// No file exists for a `data_id` of `0`, which means no game state was uploaded yet.
+pub(crate) fn game_data_filename(game_uuid: Uuid, data_id: i64) -> String {
+    format!("game_{game_uuid}_{data_id}.txt")
+}
+
+/// The filename the game data of a snapshot is stored in
+pub(crate) fn snapshot_filename(game_uuid: Uuid, snapshot_uuid: Uuid) -> String {
+    format!("snapshot_{game_uuid}_{snapshot_uuid}.txt")
+}
+
+/// Query the current state identifier and checksum of a game
+///
+/// Returns [ApiError::GameNotFound] if the executing user is not playing the game
+/// and [ApiError::MissingPrivileges] if the executing user is not the host of the game.
+async fn query_game_as_host(
+    tx: &mut Transaction,
+    game_uuid: Uuid,
+    uuid: Uuid,
+) -> ApiResult<(i64, Option<String>)> {
+    let (data_id, data_checksum, host) = query!(
+        &mut *tx,
+        (Game::F.data_id, Game::F.data_checksum, Game::F.host)
+    )
+    .condition(and!(
+        Game::F.uuid.equals(game_uuid),
+        Game::F.current_players.player.uuid.equals(uuid)
+    ))
+    .optional()
+    .await?
+    .ok_or(ApiError::GameNotFound)?;
+
+    if host.map(|x| *x.key()) != Some(uuid) {
+        return Err(ApiError::MissingPrivileges);
+    }
+
+    Ok((data_id, data_checksum))
+}
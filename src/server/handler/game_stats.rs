@@ -0,0 +1,222 @@
+//! Handler for the statistics of games
+
+use actix_toolbox::tb_middleware::Session;
+use actix_web::get;
+use actix_web::web::{Data, Json, Path};
+use chrono::{DateTime, Utc};
+use rorm::db::Transaction;
+use rorm::fields::types::ForeignModelByField;
+use rorm::{and, insert, query, update, Database, FieldAccess, Model};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::{
+    GameAccount, GamePlayerStats, GamePlayerStatsInsert, GameStats, GameStatsInsert,
+};
+use crate::server::handler::{AccountResponse, ApiError, ApiErrorResponse, ApiResult, PathUuid};
+
+/// Record an uploaded game state in the statistics of a game
+///
+/// **Parameter**:
+/// - `tx`: The transaction the upload should be recorded in
+/// - `game`: The game the state was uploaded to
+/// - `player`: The account that uploaded the state
+pub(crate) async fn record_game_upload(
+    tx: &mut Transaction,
+    game: Uuid,
+    player: Uuid,
+) -> Result<(), rorm::Error> {
+    let now = Utc::now().naive_utc();
+
+    match query!(&mut *tx, (GameStats::F.uuid, GameStats::F.uploads))
+        .condition(GameStats::F.game.equals(game))
+        .optional()
+        .await?
+    {
+        Some((uuid, uploads)) => {
+            update!(&mut *tx, GameStats)
+                .condition(GameStats::F.uuid.equals(uuid))
+                .set(GameStats::F.uploads, uploads + 1)
+                .set(GameStats::F.last_upload_at, now)
+                .exec()
+                .await?;
+        }
+        None => {
+            insert!(&mut *tx, GameStatsInsert)
+                .return_nothing()
+                .single(&GameStatsInsert {
+                    uuid: Uuid::new_v4(),
+                    game: ForeignModelByField::Key(game),
+                    uploads: 1,
+                    first_upload_at: now,
+                    last_upload_at: now,
+                })
+                .await?;
+        }
+    }
+
+    match query!(
+        &mut *tx,
+        (GamePlayerStats::F.uuid, GamePlayerStats::F.uploads)
+    )
+    .condition(and!(
+        GamePlayerStats::F.game.equals(game),
+        GamePlayerStats::F.player.equals(player)
+    ))
+    .optional()
+    .await?
+    {
+        Some((uuid, uploads)) => {
+            update!(&mut *tx, GamePlayerStats)
+                .condition(GamePlayerStats::F.uuid.equals(uuid))
+                .set(GamePlayerStats::F.uploads, uploads + 1)
+                .exec()
+                .await?;
+        }
+        None => {
+            insert!(&mut *tx, GamePlayerStatsInsert)
+                .return_nothing()
+                .single(&GamePlayerStatsInsert {
+                    uuid: Uuid::new_v4(),
+                    game: ForeignModelByField::Key(game),
+                    player: ForeignModelByField::Key(player),
+                    uploads: 1,
+                })
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The number of game states a player uploaded
+#[derive(Serialize, ToSchema)]
+pub struct GamePlayerStatsResponse {
+    player: AccountResponse,
+    #[schema(example = 21)]
+    uploads: u64,
+}
+
+/// The statistics of a game
+///
+/// `play_duration` is the time between the first and the last upload in seconds,
+/// `average_turn_time` is the average time between two uploads in seconds.
+/// Both are `0` as long as less than two game states were uploaded.
+///
+/// `first_upload` and `last_upload` are `null` if no game state was uploaded yet.
+///
+/// Players that deleted their account are not part of `players`.
+#[derive(Serialize, ToSchema)]
+pub struct GameStatsResponse {
+    #[schema(example = 42)]
+    uploads: u64,
+    first_upload: Option<DateTime<Utc>>,
+    last_upload: Option<DateTime<Utc>>,
+    #[schema(example = 86400)]
+    play_duration: u64,
+    #[schema(example = 2057)]
+    average_turn_time: u64,
+    players: Vec<GamePlayerStatsResponse>,
+}
+
+/// Retrieve the statistics of a game
+///
+/// The statistics are updated every time a new game state is uploaded.
+/// The executing user must be a player of the game.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the statistics of the game", body = GameStatsResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[get("/games/{uuid}/stats")]
+pub async fn get_game_stats(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<GameStatsResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    // Check if the executing user is playing the game
+    query!(&mut tx, (GameAccount::F.uuid,))
+        .condition(and!(
+            GameAccount::F.game.equals(path.uuid),
+            GameAccount::F.player.equals(uuid)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::GameNotFound)?;
+
+    let stats = query!(
+        &mut tx,
+        (
+            GameStats::F.uploads,
+            GameStats::F.first_upload_at,
+            GameStats::F.last_upload_at,
+        )
+    )
+    .condition(GameStats::F.game.equals(path.uuid))
+    .optional()
+    .await?;
+
+    let players = query!(
+        &mut tx,
+        (
+            GamePlayerStats::F.player.uuid,
+            GamePlayerStats::F.player.username,
+            GamePlayerStats::F.player.display_name,
+            GamePlayerStats::F.uploads,
+        )
+    )
+    .condition(GamePlayerStats::F.game.equals(path.uuid))
+    .all()
+    .await?
+    .into_iter()
+    .map(
+        |(uuid, username, display_name, uploads)| GamePlayerStatsResponse {
+            player: AccountResponse {
+                uuid,
+                username,
+                display_name,
+            },
+            uploads: uploads as u64,
+        },
+    )
+    .collect();
+
+    tx.commit().await?;
+
+    let response = match stats {
+        Some((uploads, first_upload_at, last_upload_at)) => {
+            let play_duration = (last_upload_at - first_upload_at).num_seconds().max(0) as u64;
+            // The first upload starts the clock, so it doesn't count as a turn
+            let turns = (uploads as u64).saturating_sub(1);
+            GameStatsResponse {
+                uploads: uploads as u64,
+                first_upload: Some(DateTime::from_naive_utc_and_offset(first_upload_at, Utc)),
+                last_upload: Some(DateTime::from_naive_utc_and_offset(last_upload_at, Utc)),
+                play_duration,
+                average_turn_time: play_duration.checked_div(turns).unwrap_or(0),
+                players,
+            }
+        }
+        None => GameStatsResponse {
+            uploads: 0,
+            first_upload: None,
+            last_upload: None,
+            play_duration: 0,
+            average_turn_time: 0,
+            players,
+        },
+    };
+
+    Ok(Json(response))
+}
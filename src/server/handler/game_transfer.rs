@@ -0,0 +1,538 @@
+//! This module holds the admin endpoints to transfer games between servers
+//!
+//! A game is exported as [GameBundle] on the server it leaves and imported on the server
+//! it continues on. The players are remapped by their usernames, as accounts are not
+//! shared between servers.
+
+use std::path::Path as StdPath;
+
+use actix_web::web::{Data, Json, Path};
+use actix_web::{get, post};
+use log::{error, warn};
+use rorm::fields::types::ForeignModelByField;
+use rorm::{insert, query, update, Database, FieldAccess, Model};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs::{read_to_string, write};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::chan::{Notifier, WsMessage};
+use crate::models::{
+    Account, ChatRoomInsert, ChatRoomMemberInsert, Game, GameAccount, GameAccountWithNationInsert,
+    GameEventKind, GameInsert, GameSettings, GameSettingsInsert,
+};
+use crate::server::handler::{
+    game_data_filename, normalize_username, record_game_event, ApiError, ApiErrorResponse,
+    ApiResult, PathUuid,
+};
+use crate::server::RuntimeSettings;
+use crate::service::game;
+
+/// The version of the [GameBundle] format
+///
+/// Bundles with another version are rejected on import.
+pub const GAME_BUNDLE_VERSION: u32 = 1;
+
+/// A player of a transferred game
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct GameBundlePlayer {
+    /// The username of the player on the server the game is transferred from
+    #[schema(example = "herbert")]
+    username: String,
+    /// The nation the player plays, if it is known
+    nation: Option<String>,
+}
+
+/// The settings of a transferred game
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct GameBundleSettings {
+    turn_timer: Option<i32>,
+    allow_spectators: bool,
+    allow_late_joins: bool,
+    public: bool,
+    #[serde(default)]
+    grant_observer_tokens: bool,
+}
+
+/// A game with everything that is needed to continue it on another server
+///
+/// The accounts of the players are referenced by their usernames.
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct GameBundle {
+    /// The version of the bundle format
+    #[schema(example = 1)]
+    version: u32,
+    /// The game on the server it is exported from
+    game_uuid: Uuid,
+    #[schema(example = "Herbert's game")]
+    name: String,
+    #[schema(example = 7)]
+    max_players: i16,
+    /// The current state of the game
+    game_data: String,
+    /// The turn of the current state, if it is known
+    turn: Option<i32>,
+    /// The in-game year of the current state, if it is known
+    #[serde(default)]
+    year: Option<i32>,
+    /// The era of the current state, if it is known
+    #[serde(default)]
+    era: Option<String>,
+    /// The number of players that are not defeated in the current state, if it is known
+    #[serde(default)]
+    alive_players: Option<i16>,
+    /// Whether the players only see their own nation
+    #[serde(default)]
+    hidden_identities: bool,
+    /// The username of the host of the game
+    host: Option<String>,
+    /// The username of the player whose turn it is, if it is known
+    current_player: Option<String>,
+    /// The settings of the game, if any were set
+    settings: Option<GameBundleSettings>,
+    /// All players of the game
+    players: Vec<GameBundlePlayer>,
+}
+
+/// Collect a game into a bundle
+async fn build_bundle(
+    db: &Database,
+    settings: &RuntimeSettings,
+    game_uuid: Uuid,
+) -> ApiResult<GameBundle> {
+    let (
+        data_id,
+        name,
+        max_players,
+        host,
+        current_player,
+        turn,
+        year,
+        era,
+        alive_players,
+        hidden_identities,
+    ) = query!(
+        db,
+        (
+            Game::F.data_id,
+            Game::F.name,
+            Game::F.max_players,
+            Game::F.host,
+            Game::F.current_player,
+            Game::F.turn,
+            Game::F.year,
+            Game::F.era,
+            Game::F.alive_players,
+            Game::F.hidden_identities,
+        )
+    )
+    .condition(Game::F.uuid.equals(game_uuid))
+    .optional()
+    .await?
+    .ok_or(ApiError::GameNotFound)?;
+
+    let players: Vec<(Uuid, String, Option<String>)> = query!(
+        db,
+        (
+            GameAccount::F.player.uuid,
+            GameAccount::F.player.username,
+            GameAccount::F.nation,
+        )
+    )
+    .condition(GameAccount::F.game.equals(game_uuid))
+    .all()
+    .await?;
+
+    let username_of = |account: Option<Uuid>| {
+        account.and_then(|account| {
+            players
+                .iter()
+                .find(|(uuid, _, _)| *uuid == account)
+                .map(|(_, username, _)| username.clone())
+        })
+    };
+    let host = username_of(host.map(|x| *x.key()));
+    let current_player = username_of(current_player.map(|x| *x.key()));
+
+    let game_settings = query!(db, GameSettings)
+        .condition(GameSettings::F.game.equals(game_uuid))
+        .optional()
+        .await?
+        .map(|x| GameBundleSettings {
+            turn_timer: x.turn_timer,
+            allow_spectators: x.allow_spectators,
+            allow_late_joins: x.allow_late_joins,
+            public: x.public,
+            grant_observer_tokens: x.grant_observer_tokens,
+        });
+
+    let filename = game_data_filename(game_uuid, data_id);
+    let path = StdPath::new(&settings.game_data_path).join(&filename);
+    let game_data = read_to_string(&path).await.map_err(|e| {
+        error!("Game data expected in '{filename}' couldn't be read: {e}");
+        ApiError::InternalServerError
+    })?;
+
+    Ok(GameBundle {
+        version: GAME_BUNDLE_VERSION,
+        game_uuid,
+        name,
+        max_players,
+        game_data,
+        turn,
+        year,
+        era,
+        alive_players,
+        hidden_identities,
+        host,
+        current_player,
+        settings: game_settings,
+        players: players
+            .into_iter()
+            .map(|(_, username, nation)| GameBundlePlayer { username, nation })
+            .collect(),
+    })
+}
+
+/// Export a game as bundle
+///
+/// The bundle can be imported on another server with `POST /api/v2/admin/games/import`.
+/// The game is not modified by the export.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Returns the bundle", body = GameBundle),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("admin_token" = []))
+)]
+#[get("/games/{uuid}/bundle")]
+pub async fn export_game_bundle(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    settings: Data<RuntimeSettings>,
+) -> ApiResult<Json<GameBundle>> {
+    Ok(Json(build_bundle(&db, &settings, path.uuid).await?))
+}
+
+/// The result of the import of a game
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ImportGameResponse {
+    /// The game on this server
+    game_uuid: Uuid,
+    /// The chatroom of the game on this server
+    game_chat_uuid: Uuid,
+    /// The usernames of the players that don't have an account on this server
+    ///
+    /// They are not part of the imported game.
+    unmatched_players: Vec<String>,
+}
+
+/// Import a game that was exported on another server
+///
+/// The players are matched to the accounts of this server by their usernames. Players
+/// without an account are left out and returned in `unmatched_players`. The host and the
+/// player whose turn it is are taken over if they could be matched.
+///
+/// The game gets a new uuid and an empty chatroom. All matched players are notified with
+/// a [WsMessage::GameTransferred] message.
+///
+/// If the bundle has an unsupported version, [ApiError::InvalidGameBundle] is returned.
+/// If none of the players has an account on this server, [ApiError::NoMatchingPlayers]
+/// is returned. If the server already hosts its configured maximum of running games,
+/// [ApiError::ServerAtCapacity] is returned.
+///
+/// [WsMessage::GameTransferred]: crate::chan::WsMessage::GameTransferred
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Game was imported", body = ImportGameResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    request_body = GameBundle,
+    security(("admin_token" = []))
+)]
+#[post("/games/import")]
+pub async fn import_game_bundle(
+    req: Json<GameBundle>,
+    db: Data<Database>,
+    settings: Data<RuntimeSettings>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<ImportGameResponse>> {
+    let bundle = req.into_inner();
+
+    if bundle.version != GAME_BUNDLE_VERSION {
+        return Err(ApiError::InvalidGameBundle);
+    }
+    if bundle.game_data.len() > settings.max_game_data_size {
+        return Err(ApiError::PayloadOverflow(format!(
+            "The game data exceeds the maximum size of {} bytes",
+            settings.max_game_data_size
+        )));
+    }
+
+    let mut tx = db.start_transaction().await?;
+
+    game::check_capacity(&mut tx, settings.max_running_games).await?;
+
+    let mut players: Vec<(Uuid, String, Option<String>)> = vec![];
+    let mut unmatched_players = vec![];
+    for player in bundle.players {
+        match query!(&mut tx, (Account::F.uuid,))
+            .condition(
+                Account::F
+                    .normalized_username
+                    .equals(normalize_username(&player.username)),
+            )
+            .optional()
+            .await?
+        {
+            Some((uuid,)) => players.push((uuid, player.username, player.nation)),
+            None => unmatched_players.push(player.username),
+        }
+    }
+
+    let account_of = |username: Option<String>| {
+        username.and_then(|username| {
+            players
+                .iter()
+                .find(|(_, x, _)| *x == username)
+                .map(|(uuid, _, _)| *uuid)
+        })
+    };
+    let host = account_of(bundle.host);
+    let current_player = account_of(bundle.current_player);
+
+    let Some(updated_by) = host.or(players.first().map(|(uuid, _, _)| *uuid)) else {
+        return Err(ApiError::NoMatchingPlayers);
+    };
+
+    let game_chat_uuid = insert!(&mut tx, ChatRoomInsert)
+        .return_primary_key()
+        .single(&ChatRoomInsert {
+            uuid: Uuid::new_v4(),
+            last_message_uuid: None,
+        })
+        .await?;
+
+    insert!(&mut tx, ChatRoomMemberInsert)
+        .return_nothing()
+        .bulk(
+            &players
+                .iter()
+                .map(|(player, _, _)| ChatRoomMemberInsert {
+                    uuid: Uuid::new_v4(),
+                    chat_room: ForeignModelByField::Key(game_chat_uuid),
+                    member: ForeignModelByField::Key(*player),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+
+    let game_uuid = insert!(&mut tx, GameInsert)
+        .return_primary_key()
+        .single(&GameInsert {
+            uuid: Uuid::new_v4(),
+            name: bundle.name,
+            max_players: bundle.max_players,
+            updated_by: ForeignModelByField::Key(updated_by),
+            chat_room: ForeignModelByField::Key(game_chat_uuid),
+            host: host.map(ForeignModelByField::Key),
+            hidden_identities: bundle.hidden_identities,
+        })
+        .await?;
+
+    insert!(&mut tx, GameAccountWithNationInsert)
+        .return_nothing()
+        .bulk(
+            &players
+                .iter()
+                .map(|(player, _, nation)| GameAccountWithNationInsert {
+                    uuid: Uuid::new_v4(),
+                    game: ForeignModelByField::Key(game_uuid),
+                    player: ForeignModelByField::Key(*player),
+                    nation: nation.clone(),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+
+    if let Some(game_settings) = bundle.settings {
+        insert!(&mut tx, GameSettingsInsert)
+            .return_nothing()
+            .single(&GameSettingsInsert {
+                uuid: Uuid::new_v4(),
+                game: ForeignModelByField::Key(game_uuid),
+                turn_timer: game_settings.turn_timer,
+                allow_spectators: game_settings.allow_spectators,
+                allow_late_joins: game_settings.allow_late_joins,
+                public: game_settings.public,
+                grant_observer_tokens: game_settings.grant_observer_tokens,
+            })
+            .await?;
+    }
+
+    let filename = game_data_filename(game_uuid, 1);
+    if let Err(e) = write(
+        StdPath::new(&settings.game_data_path).join(&filename),
+        &bundle.game_data,
+    )
+    .await
+    {
+        error!("Game data in '{filename}' could not be written: {e}");
+        return Err(ApiError::InternalServerError);
+    }
+
+    update!(&mut tx, Game)
+        .condition(Game::F.uuid.equals(game_uuid))
+        .set(Game::F.data_id, 1)
+        .set(
+            Game::F.data_checksum,
+            Some(hex::encode(Sha256::digest(bundle.game_data.as_bytes()))),
+        )
+        .set(Game::F.turn, bundle.turn)
+        .set(Game::F.year, bundle.year)
+        .set(Game::F.era, bundle.era)
+        .set(Game::F.alive_players, bundle.alive_players)
+        .set(
+            Game::F.current_player,
+            current_player.map(ForeignModelByField::Key),
+        )
+        .exec()
+        .await?;
+
+    record_game_event(
+        &mut tx,
+        game_uuid,
+        GameEventKind::Started,
+        None,
+        None,
+        Some(1),
+        Some(format!("Transferred from game {}", bundle.game_uuid)),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    for (player, _, _) in &players {
+        notifier
+            .send(
+                *player,
+                WsMessage::GameTransferred {
+                    game_uuid: bundle.game_uuid,
+                    server_url: None,
+                    new_game_uuid: game_uuid,
+                    new_game_chat_uuid: game_chat_uuid,
+                },
+            )
+            .await;
+    }
+
+    Ok(Json(ImportGameResponse {
+        game_uuid,
+        game_chat_uuid,
+        unmatched_players,
+    }))
+}
+
+/// The request to transfer a game to another server
+#[derive(Deserialize, ToSchema)]
+pub struct TransferGameRequest {
+    /// The base url of the target server
+    ///
+    /// It is sent to the players as the server the game continues on.
+    #[schema(example = "https://runciv.example.com")]
+    server_url: String,
+    /// The admin token of the target server
+    admin_token: String,
+}
+
+/// Transfer a game to another server
+///
+/// The game is exported as bundle and imported on the target server through its
+/// `POST /api/v2/admin/games/import` endpoint, authenticated with its admin token.
+/// Afterwards, all players of the game on this server are notified with a
+/// [WsMessage::GameTransferred] message that tells them the target server and the new
+/// game. The game on this server is kept, it can be deleted once the players moved on.
+///
+/// Returns the result of the import on the target server. If the target server can't
+/// be reached or rejects the import, [ApiError::GameTransferFailed] is returned and no
+/// player is notified.
+///
+/// [WsMessage::GameTransferred]: crate::chan::WsMessage::GameTransferred
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Game was transferred", body = ImportGameResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = TransferGameRequest,
+    security(("admin_token" = []))
+)]
+#[post("/games/{uuid}/transfer")]
+pub async fn transfer_game(
+    path: Path<PathUuid>,
+    req: Json<TransferGameRequest>,
+    db: Data<Database>,
+    settings: Data<RuntimeSettings>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<ImportGameResponse>> {
+    let game_uuid = path.uuid;
+    let TransferGameRequest {
+        server_url,
+        admin_token,
+    } = req.into_inner();
+    let server_url = server_url.trim().trim_end_matches('/').to_string();
+
+    let bundle = build_bundle(&db, &settings, game_uuid).await?;
+
+    let res = reqwest::Client::new()
+        .post(format!("{server_url}/api/v2/admin/games/import"))
+        .bearer_auth(admin_token)
+        .json(&bundle)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status());
+    let imported: ImportGameResponse = match res {
+        Ok(res) => res.json().await.map_err(|e| {
+            warn!("Invalid response of '{server_url}' to the transfer of game {game_uuid}: {e}");
+            ApiError::GameTransferFailed
+        })?,
+        Err(e) => {
+            warn!("Transfer of game {game_uuid} to '{server_url}' failed: {e}");
+            return Err(ApiError::GameTransferFailed);
+        }
+    };
+
+    let players: Vec<Uuid> = query!(db.as_ref(), (GameAccount::F.player,))
+        .condition(GameAccount::F.game.equals(game_uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(player,)| *player.key())
+        .collect();
+    for player in players {
+        notifier
+            .send(
+                player,
+                WsMessage::GameTransferred {
+                    game_uuid,
+                    server_url: Some(server_url.clone()),
+                    new_game_uuid: imported.game_uuid,
+                    new_game_chat_uuid: imported.game_chat_uuid,
+                },
+            )
+            .await;
+    }
+
+    Ok(Json(imported))
+}
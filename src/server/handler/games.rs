@@ -3,32 +3,58 @@
 use std::path::Path as StdPath;
 
 use actix_toolbox::tb_middleware::Session;
+use actix_web::http::header::{
+    ContentDisposition, ContentEncoding, ContentType, DispositionParam, DispositionType,
+};
 use actix_web::web::{Data, Json, Path};
-use actix_web::{get, put};
-use chrono::{DateTime, Utc};
-use log::{debug, error, warn};
+use actix_web::{get, patch, post, put, CustomizeResponder, HttpResponse, Responder};
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, error};
 use rorm::fields::types::ForeignModelByField;
-use rorm::{and, query, update, Database, FieldAccess, Model};
+use rorm::{and, insert, query, update, Database, FieldAccess, Model};
 use serde::{Deserialize, Serialize};
-use tokio::fs::{read_to_string, remove_file, write};
-use utoipa::ToSchema;
+use tokio::fs::{copy, read, read_to_string};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use crate::chan::{WsManagerChan, WsManagerMessage, WsMessage};
-use crate::models::{Game, GameAccount};
-use crate::server::handler::{AccountResponse, ApiError, ApiErrorResponse, ApiResult, PathUuid};
+use crate::chan::{Notifier, WsMessage};
+use crate::models::{
+    Account, ChatRoomInsert, ChatRoomMember, ChatRoomMemberInsert, Game, GameAccount,
+    GameAccountInsert, GameEventKind, GameInsert, GameInviteInsert, GameSettings,
+    GameSettingsInsert,
+};
+use crate::server::handler::{
+    game_data_filename, grant_observer_token, record_game_event, AccountResponse, ApiError,
+    ApiErrorResponse, ApiResult, PathUuid, PlayerNation,
+};
+use crate::server::middleware::{ConcurrencyLimit, LimitedOperation};
 use crate::server::RuntimeSettings;
+use crate::service::game::{self, GameStateUpload};
+use crate::service::notify::Outbox;
 
 /// A single game state identified by its Uuid and state identifier
 ///
 /// If the state (`game_data_id`) of a known game differs from the last known
 /// identifier, the server has a newer state of the game. The `last_activity`
 /// field is a convenience attribute and shouldn't be used for update checks.
+///
+/// `game_data_checksum` is the hex encoded SHA-256 checksum of `game_data` as
+/// computed by the server when the state was uploaded. It can be used to detect
+/// truncated transfers or corrupted data.
+///
+/// `host_uuid` is the player administrating the game. It is `None` if the host deleted
+/// their account.
+///
+/// `current_player_uuid` is the player whose turn it is, if it is known to the server.
+/// `upload_deadline` is set if the last player ended their turn without uploading the
+/// game state yet.
 #[derive(Serialize, ToSchema)]
 pub struct GameStateResponse {
     game_data: String,
     #[schema(example = 1337)]
     game_data_id: u64,
+    #[schema(example = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")]
+    game_data_checksum: Option<String>,
     #[schema(example = "Herbert's game")]
     name: String,
     #[schema(example = 7)]
@@ -36,6 +62,63 @@ pub struct GameStateResponse {
     last_activity: DateTime<Utc>,
     last_player: AccountResponse,
     chat_room_uuid: Uuid,
+    host_uuid: Option<Uuid>,
+    settings: GameSettingsResponse,
+    current_player_uuid: Option<Uuid>,
+    upload_deadline: Option<DateTime<Utc>>,
+    #[schema(example = 42)]
+    turn: Option<u32>,
+}
+
+/// The settings of a game
+///
+/// `turn_timer` is the time in seconds a player has to finish a turn.
+/// If `grant_observer_tokens` is set, players that leave the game receive a token to keep
+/// watching it.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct GameSettingsResponse {
+    #[schema(example = 86400)]
+    turn_timer: Option<u32>,
+    allow_spectators: bool,
+    allow_late_joins: bool,
+    public: bool,
+    #[serde(default)]
+    grant_observer_tokens: bool,
+}
+
+impl Default for GameSettingsResponse {
+    fn default() -> Self {
+        Self {
+            turn_timer: None,
+            allow_spectators: false,
+            allow_late_joins: false,
+            public: true,
+            grant_observer_tokens: false,
+        }
+    }
+}
+
+impl From<GameSettings> for GameSettingsResponse {
+    fn from(value: GameSettings) -> Self {
+        Self {
+            turn_timer: value.turn_timer.map(|x| x as u32),
+            allow_spectators: value.allow_spectators,
+            allow_late_joins: value.allow_late_joins,
+            public: value.public,
+            grant_observer_tokens: value.grant_observer_tokens,
+        }
+    }
+}
+
+/// Check that a turn timer is between one minute and 30 days
+///
+/// Returns the turn timer in the representation used by the database.
+pub(crate) fn validate_turn_timer(turn_timer: Option<u32>) -> ApiResult<Option<i32>> {
+    match turn_timer {
+        None => Ok(None),
+        Some(t) if (60..=30 * 24 * 60 * 60).contains(&t) => Ok(Some(t as i32)),
+        Some(_) => Err(ApiError::InvalidGameSettings),
+    }
 }
 
 /// A shortened game state identified by its ID and state identifier
@@ -43,6 +126,13 @@ pub struct GameStateResponse {
 /// If the state (`game_data_id`) of a known game differs from the last known
 /// identifier, the server has a newer state of the game. The `last_activity`
 /// field is a convenience attribute and shouldn't be used for update checks.
+///
+/// `nations` contains the nations the players have chosen in the lobby the game
+/// originated from, players without a choice are left out. If `hidden_identities` is set,
+/// it only contains your own nation until the host reveals all nations.
+///
+/// `turn`, `year`, `era` and `alive_players` summarize the current game state,
+/// they are only set if the uploader of the state provided them.
 #[derive(Serialize, ToSchema)]
 pub struct GameOverviewResponse {
     game_uuid: Uuid,
@@ -56,6 +146,16 @@ pub struct GameOverviewResponse {
     last_player: AccountResponse,
     chat_room_uuid: Uuid,
     players: Vec<AccountResponse>,
+    nations: Vec<PlayerNation>,
+    #[schema(example = 42)]
+    turn: Option<u32>,
+    #[schema(example = -2400)]
+    year: Option<i32>,
+    #[schema(example = "Classical era")]
+    era: Option<String>,
+    #[schema(example = 5)]
+    alive_players: Option<u16>,
+    hidden_identities: bool,
 }
 
 /// An overview of games a player participates in
@@ -102,6 +202,11 @@ pub async fn get_open_games(
             Game::F.updated_by.username,
             Game::F.updated_by.display_name,
             Game::F.chat_room,
+            Game::F.turn,
+            Game::F.year,
+            Game::F.era,
+            Game::F.alive_players,
+            Game::F.hidden_identities,
         )
     )
     .condition(Game::F.current_players.player.equals(uuid))
@@ -119,6 +224,11 @@ pub async fn get_open_games(
             updated_by_username,
             updated_by_display_name,
             chat_room,
+            turn,
+            year,
+            era,
+            alive_players,
+            hidden_identities,
         )| {
             GameOverviewResponse {
                 game_uuid,
@@ -133,31 +243,46 @@ pub async fn get_open_games(
                 },
                 chat_room_uuid: *chat_room.key(),
                 players: vec![],
+                nations: vec![],
+                turn: turn.map(|x| x as u32),
+                year,
+                era,
+                alive_players: alive_players.map(|x| x as u16),
+                hidden_identities,
             }
         },
     )
     .collect();
 
     for game in &mut open_games {
-        game.players.extend(
-            query!(
-                &mut tx,
-                (
-                    GameAccount::F.player.uuid,
-                    GameAccount::F.player.username,
-                    GameAccount::F.player.display_name
-                )
+        let players = query!(
+            &mut tx,
+            (
+                GameAccount::F.player.uuid,
+                GameAccount::F.player.username,
+                GameAccount::F.player.display_name,
+                GameAccount::F.nation,
             )
-            .condition(GameAccount::F.game.uuid.equals(game.game_uuid))
-            .all()
-            .await?
-            .into_iter()
-            .map(|(uuid, username, display_name)| AccountResponse {
-                uuid,
+        )
+        .condition(GameAccount::F.game.uuid.equals(game.game_uuid))
+        .all()
+        .await?;
+
+        for (player_uuid, username, display_name, nation) in players {
+            // The nations of the other players are withheld until they are revealed
+            let visible = !game.hidden_identities || player_uuid == uuid;
+            if let Some(nation) = nation.filter(|_| visible) {
+                game.nations.push(PlayerNation {
+                    player_uuid,
+                    nation,
+                });
+            }
+            game.players.push(AccountResponse {
+                uuid: player_uuid,
                 username,
                 display_name,
-            }),
-        );
+            });
+        }
     }
 
     Ok(Json(GetGameOverviewResponse { games: open_games }))
@@ -167,6 +292,9 @@ pub async fn get_open_games(
 ///
 /// If the game has been completed or aborted, it
 /// will respond with a `GameNotFound` in `ApiErrorResponse`.
+///
+/// If too many downloads of game states are running at the same time, the request is rejected
+/// with a `ServerBusy` error and should be retried after the time in the `Retry-After` header.
 #[utoipa::path(
     tag = "Games",
     context_path = "/api/v2",
@@ -174,22 +302,27 @@ pub async fn get_open_games(
         (status = 200, description = "Returns a single game state", body = GameStateResponse),
         (status = 400, description = "Client error", body = ApiErrorResponse),
         (status = 500, description = "Server error", body = ApiErrorResponse),
+        (status = 503, description = "Too many concurrent requests", body = ApiErrorResponse),
     ),
     params(PathUuid),
     security(("session_cookie" = []))
 )]
-#[get("/games/{uuid}")]
+#[get(
+    "/games/{uuid}",
+    wrap = "ConcurrencyLimit(LimitedOperation::GameDownload)"
+)]
 pub async fn get_game(
     path: Path<PathUuid>,
     settings: Data<RuntimeSettings>,
     db: Data<Database>,
     session: Session,
-) -> ApiResult<Json<GameStateResponse>> {
+) -> ApiResult<CustomizeResponder<Json<GameStateResponse>>> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
     let game_uuid = path.uuid;
 
     let (
         data_id,
+        data_checksum,
         name,
         max_players,
         updated_at,
@@ -197,10 +330,15 @@ pub async fn get_game(
         updated_by_username,
         updated_by_display_name,
         chat_room,
+        host,
+        current_player,
+        pending_upload_until,
+        turn,
     ) = query!(
         db.as_ref(),
         (
             Game::F.data_id,
+            Game::F.data_checksum,
             Game::F.name,
             Game::F.max_players,
             Game::F.updated_at,
@@ -208,6 +346,10 @@ pub async fn get_game(
             Game::F.updated_by.username,
             Game::F.updated_by.display_name,
             Game::F.chat_room,
+            Game::F.host,
+            Game::F.current_player,
+            Game::F.pending_upload_until,
+            Game::F.turn,
         )
     )
     .condition(and!(
@@ -221,7 +363,14 @@ pub async fn get_game(
         ApiError::GameNotFound
     })?;
 
-    let filename = format!("game_{game_uuid}_{data_id}.txt");
+    let game_settings = query!(db.as_ref(), GameSettings)
+        .condition(GameSettings::F.game.equals(game_uuid))
+        .optional()
+        .await?
+        .map(GameSettingsResponse::from)
+        .unwrap_or_default();
+
+    let filename = game_data_filename(game_uuid, data_id);
     let path = StdPath::new(&settings.game_data_path).join(&filename);
     let content = read_to_string(&path).await.map_err(|e| {
         error!("Game data expected in '{filename}' couldn't be read: {e}");
@@ -230,6 +379,7 @@ pub async fn get_game(
     Ok(Json(GameStateResponse {
         game_data: content,
         game_data_id: data_id as u64,
+        game_data_checksum: data_checksum,
         name,
         max_players,
         last_activity: DateTime::from_naive_utc_and_offset(updated_at, Utc),
@@ -239,54 +389,349 @@ pub async fn get_game(
             display_name: updated_by_display_name.to_string(),
         },
         chat_room_uuid: *chat_room.key(),
-    }))
+        host_uuid: host.map(|x| *x.key()),
+        settings: game_settings,
+        current_player_uuid: current_player.map(|x| *x.key()),
+        upload_deadline: pending_upload_until.map(|x| DateTime::from_naive_utc_and_offset(x, Utc)),
+        turn: turn.map(|x| x as u32),
+    })
+    // The game state makes up most of the response and is compressed by the client already
+    .customize()
+    .insert_header(ContentEncoding::Identity))
 }
 
-/// The response a user receives after uploading a new game state successfully
-#[derive(Serialize, ToSchema)]
-pub struct GameUploadResponse {
-    #[schema(example = 1337)]
-    game_data_id: u64,
+/// Download the current save of a game as a file
+///
+/// The save is returned as is, without any JSON wrapping, so it can be stored
+/// locally, e.g. as a backup or to continue the game in single player.
+/// The filename is derived from the name of the game.
+///
+/// The executing user must be a player of the game.
+///
+/// If too many exports are running at the same time, the request is rejected with a
+/// `ServerBusy` error and should be retried after the time in the `Retry-After` header.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the save of the game", content_type = "application/octet-stream", body = String),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+        (status = 503, description = "Too many concurrent requests", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[get(
+    "/games/{uuid}/export",
+    wrap = "ConcurrencyLimit(LimitedOperation::GameExport)"
+)]
+pub async fn export_game(
+    path: Path<PathUuid>,
+    settings: Data<RuntimeSettings>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+    let game_uuid = path.uuid;
+
+    let (data_id, name) = query!(db.as_ref(), (Game::F.data_id, Game::F.name))
+        .condition(and!(
+            Game::F.uuid.equals(game_uuid),
+            Game::F.current_players.player.uuid.equals(uuid)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::GameNotFound)?;
+
+    let filename = game_data_filename(game_uuid, data_id);
+    let path = StdPath::new(&settings.game_data_path).join(&filename);
+    let content = read(&path).await.map_err(|e| {
+        error!("Game data expected in '{filename}' couldn't be read: {e}");
+        ApiError::InternalServerError
+    })?;
+
+    // Only keep characters that are safe to use in filenames on all platforms
+    let mut download_name: String = name
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+        .collect::<String>()
+        .trim()
+        .to_string();
+    if download_name.is_empty() {
+        download_name = game_uuid.to_string();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::octet_stream())
+        // The game state is compressed by the client already
+        .insert_header(ContentEncoding::Identity)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(download_name)],
+        })
+        .body(content))
 }
 
-/// The request a user sends to the server to upload a new game state
-#[derive(Deserialize, ToSchema)]
-pub struct GameUploadRequest {
-    game_data: String,
+/// The response when cloning a game
+#[derive(Serialize, ToSchema)]
+pub struct CloneGameResponse {
+    game_uuid: Uuid,
+    game_chat_uuid: Uuid,
 }
 
-/// Upload a new game state for an existing game
+/// Create a new game from the current state of a game
 ///
-/// If the game can't be updated (maybe it has been already completed or
-/// aborted), it will respond with a `GameNotFound` in `ApiErrorResponse`.
+/// This can be used to continue playing a game, e.g. after a victory.
+/// Only the host of the game is allowed to clone it.
+///
+/// The new game starts with the current game data, the settings of the game and a new chat
+/// room. The executing user is the host and only player of the new game. All other
+/// players of the game are invited to it and have to accept the invite to take part.
+/// They are notified with a [WsMessage::IncomingGameInvite] message.
+///
+/// If the server already hosts its configured maximum of running games,
+/// a [ApiError::ServerAtCapacity] error is returned.
 #[utoipa::path(
     tag = "Games",
     context_path = "/api/v2",
     responses(
-        (status = 200, description = "Returns the new data identifier of the uploaded game state", body = GameUploadResponse),
+        (status = 200, description = "Game was cloned", body = CloneGameResponse),
         (status = 400, description = "Client error", body = ApiErrorResponse),
         (status = 500, description = "Server error", body = ApiErrorResponse),
     ),
     params(PathUuid),
-    request_body = GameUploadRequest,
     security(("session_cookie" = []))
 )]
-#[put("/games/{uuid}")]
-pub async fn push_game_update(
+#[post("/games/{uuid}/clone")]
+pub async fn clone_game(
     path: Path<PathUuid>,
-    req: Json<GameUploadRequest>,
     settings: Data<RuntimeSettings>,
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
-) -> ApiResult<Json<GameUploadResponse>> {
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<CloneGameResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
     let game_uuid = path.uuid;
+
+    let mut tx = db.start_transaction().await?;
+
+    let (data_id, data_checksum, name, max_players, host) = query!(
+        &mut tx,
+        (
+            Game::F.data_id,
+            Game::F.data_checksum,
+            Game::F.name,
+            Game::F.max_players,
+            Game::F.host,
+        )
+    )
+    .condition(and!(
+        Game::F.uuid.equals(game_uuid),
+        Game::F.current_players.player.uuid.equals(uuid)
+    ))
+    .optional()
+    .await?
+    .ok_or(ApiError::GameNotFound)?;
+
+    if host.map(|x| *x.key()) != Some(uuid) {
+        return Err(ApiError::MissingPrivileges);
+    }
+
+    game::check_capacity(&mut tx, settings.max_running_games).await?;
+
+    let players: Vec<Uuid> = query!(&mut tx, (GameAccount::F.player,))
+        .condition(GameAccount::F.game.equals(game_uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(player,)| *player.key())
+        .filter(|player| *player != uuid)
+        .collect();
+
+    // Create chatroom for the new game
+    let game_chat_uuid = insert!(&mut tx, ChatRoomInsert)
+        .return_primary_key()
+        .single(&ChatRoomInsert {
+            uuid: Uuid::new_v4(),
+            last_message_uuid: None,
+        })
+        .await?;
+
+    insert!(&mut tx, ChatRoomMemberInsert)
+        .return_nothing()
+        .single(&ChatRoomMemberInsert {
+            uuid: Uuid::new_v4(),
+            chat_room: ForeignModelByField::Key(game_chat_uuid),
+            member: ForeignModelByField::Key(uuid),
+        })
+        .await?;
+
+    let new_game_uuid = insert!(&mut tx, GameInsert)
+        .return_primary_key()
+        .single(&GameInsert {
+            uuid: Uuid::new_v4(),
+            name,
+            max_players,
+            updated_by: ForeignModelByField::Key(uuid),
+            chat_room: ForeignModelByField::Key(game_chat_uuid),
+            host: Some(ForeignModelByField::Key(uuid)),
+            // The players join the clone without nations, so there's nothing to hide
+            hidden_identities: false,
+        })
+        .await?;
+
+    insert!(&mut tx, GameAccountInsert)
+        .return_nothing()
+        .single(&GameAccountInsert {
+            uuid: Uuid::new_v4(),
+            game: ForeignModelByField::Key(new_game_uuid),
+            player: ForeignModelByField::Key(uuid),
+        })
+        .await?;
+
+    if let Some(game_settings) = query!(&mut tx, GameSettings)
+        .condition(GameSettings::F.game.equals(game_uuid))
+        .optional()
+        .await?
+    {
+        insert!(&mut tx, GameSettingsInsert)
+            .return_nothing()
+            .single(&GameSettingsInsert {
+                uuid: Uuid::new_v4(),
+                game: ForeignModelByField::Key(new_game_uuid),
+                turn_timer: game_settings.turn_timer,
+                allow_spectators: game_settings.allow_spectators,
+                allow_late_joins: game_settings.allow_late_joins,
+                public: game_settings.public,
+                grant_observer_tokens: game_settings.grant_observer_tokens,
+            })
+            .await?;
+    }
+
+    // Take over the current game data, if any was uploaded yet
+    if data_id > 0 {
+        let filename = game_data_filename(game_uuid, data_id);
+        let new_filename = game_data_filename(new_game_uuid, 1);
+        let game_data_path = StdPath::new(&settings.game_data_path);
+        if let Err(e) = copy(
+            game_data_path.join(&filename),
+            game_data_path.join(&new_filename),
+        )
+        .await
+        {
+            error!("Game data in '{filename}' could not be copied to '{new_filename}': {e}");
+            return Err(ApiError::InternalServerError);
+        }
+
+        update!(&mut tx, Game)
+            .condition(Game::F.uuid.equals(new_game_uuid))
+            .set(Game::F.data_id, 1)
+            .set(Game::F.data_checksum, data_checksum)
+            .exec()
+            .await?;
+    }
+
+    record_game_event(
+        &mut tx,
+        new_game_uuid,
+        GameEventKind::Started,
+        Some(uuid),
+        None,
+        None,
+        None,
+    )
+    .await?;
+
+    // Invite all other players of the game
+    let invites: Vec<(Uuid, Uuid)> = players
+        .into_iter()
+        .map(|player| (Uuid::new_v4(), player))
+        .collect();
+    insert!(&mut tx, GameInviteInsert)
+        .return_nothing()
+        .bulk(
+            &invites
+                .iter()
+                .map(|(invite_uuid, player)| GameInviteInsert {
+                    uuid: *invite_uuid,
+                    from: ForeignModelByField::Key(uuid),
+                    to: ForeignModelByField::Key(*player),
+                    game: ForeignModelByField::Key(new_game_uuid),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+
+    let (username, display_name) = query!(&mut tx, (Account::F.username, Account::F.display_name))
+        .condition(Account::F.uuid.equals(uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::SessionCorrupt)?;
+
+    tx.commit().await?;
+
+    let from = AccountResponse {
+        uuid,
+        username,
+        display_name,
+    };
+    for (invite_uuid, player) in invites {
+        notifier
+            .send(
+                player,
+                WsMessage::IncomingGameInvite {
+                    invite_uuid,
+                    from: from.clone(),
+                    game_uuid: new_game_uuid,
+                },
+            )
+            .await;
+    }
+
+    Ok(Json(CloneGameResponse {
+        game_uuid: new_game_uuid,
+        game_chat_uuid,
+    }))
+}
+
+/// The path parameter to transfer the host role of a game
+#[derive(Deserialize, IntoParams)]
+pub struct TransferHostPath {
+    uuid: Uuid,
+    player_uuid: Uuid,
+}
+
+/// Transfer the host role of a game to another player
+///
+/// This endpoint can only be used by the current host of the game.
+/// The new host must be a player of the game.
+///
+/// On success, all players of the game receive a [WsMessage::GameHostChanged] message.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Host role transferred"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(TransferHostPath),
+    security(("session_cookie" = []))
+)]
+#[post("/games/{uuid}/transfer-host/{player_uuid}")]
+pub async fn transfer_game_host(
+    path: Path<TransferHostPath>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+    let game_uuid = path.uuid;
 
     let mut tx = db.start_transaction().await?;
 
-    // Lookup the game and verify that the player is actually participating in it
-    let mut game = query!(&mut tx, Game)
+    let (host,) = query!(&mut tx, (Game::F.host,))
         .condition(and!(
             Game::F.uuid.equals(game_uuid),
             Game::F.current_players.player.uuid.equals(uuid)
@@ -295,64 +740,572 @@ pub async fn push_game_update(
         .await?
         .ok_or(ApiError::GameNotFound)?;
 
-    // Retrieve uuids of all players from the game
-    Game::F.current_players.populate(&mut tx, &mut game).await?;
-    let players: Vec<Uuid> = if let Some(current_players) = game.current_players.cached {
-        current_players
-            .into_iter()
-            .map(|x| *x.player.key())
-            .collect()
+    // Check if the executing user is the host of the game
+    if host.map(|x| *x.key()) != Some(uuid) {
+        return Err(ApiError::MissingPrivileges);
+    }
+
+    let players: Vec<Uuid> = query!(&mut tx, (GameAccount::F.player,))
+        .condition(GameAccount::F.game.equals(game_uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(player,)| *player.key())
+        .collect();
+
+    // Check if the new host is a player of the game
+    if !players.contains(&path.player_uuid) {
+        return Err(ApiError::InvalidUuid);
+    }
+
+    update!(&mut tx, Game)
+        .condition(Game::F.uuid.equals(game_uuid))
+        .set(
+            Game::F.host,
+            Some(ForeignModelByField::Key(path.player_uuid)),
+        )
+        .exec()
+        .await?;
+
+    tx.commit().await?;
+
+    let msg = WsMessage::GameHostChanged {
+        game_uuid,
+        host_uuid: path.player_uuid,
+    };
+    for player in players {
+        notifier.send(player, msg.clone()).await;
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The response after leaving a game
+///
+/// `observer_token` is only set if the game grants observer tokens to former players.
+/// It can be used with `GET /api/v2/observe/games/{uuid}?token=...` to download the
+/// current state of the game until `observer_token_expires_at`, unless the host revokes it.
+#[derive(Serialize, ToSchema)]
+pub struct LeaveGameResponse {
+    observer_token: Option<String>,
+    observer_token_expires_at: Option<DateTime<Utc>>,
+}
+
+/// Leave a running game
+///
+/// The executing user is removed from the players and the chat room of the game.
+/// The host has to transfer the host role to another player first, otherwise
+/// [ApiError::HostCannotLeave] is returned.
+///
+/// If the `grant_observer_tokens` setting of the game is enabled, the response contains a
+/// token to keep watching the game. The host can revoke it with
+/// `DELETE /api/v2/games/{uuid}/observerTokens/{player_uuid}`.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Left the game", body = LeaveGameResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[post("/games/{uuid}/leave")]
+pub async fn leave_game(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+    settings: Data<RuntimeSettings>,
+) -> ApiResult<Json<LeaveGameResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+    let game_uuid = path.uuid;
+
+    let mut tx = db.start_transaction().await?;
+
+    let (host, chat_room) = query!(&mut tx, (Game::F.host, Game::F.chat_room))
+        .condition(and!(
+            Game::F.uuid.equals(game_uuid),
+            Game::F.current_players.player.uuid.equals(uuid)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::GameNotFound)?;
+
+    if host.map(|x| *x.key()) == Some(uuid) {
+        return Err(ApiError::HostCannotLeave);
+    }
+
+    rorm::delete!(&mut tx, GameAccount)
+        .condition(and!(
+            GameAccount::F.game.equals(game_uuid),
+            GameAccount::F.player.equals(uuid)
+        ))
+        .await?;
+    rorm::delete!(&mut tx, ChatRoomMember)
+        .condition(and!(
+            ChatRoomMember::F.chat_room.equals(*chat_room.key()),
+            ChatRoomMember::F.member.equals(uuid)
+        ))
+        .await?;
+
+    let grant_observer_tokens = query!(&mut tx, (GameSettings::F.grant_observer_tokens,))
+        .condition(GameSettings::F.game.equals(game_uuid))
+        .optional()
+        .await?
+        .is_some_and(|(grant,)| grant);
+    let observer_token = if grant_observer_tokens {
+        Some(
+            grant_observer_token(&mut tx, game_uuid, uuid, settings.observer_token_lifetime)
+                .await?,
+        )
     } else {
-        error!("Cache of populated field current_players was empty");
-        return Err(ApiError::InternalServerError);
+        None
     };
 
-    // Increment the data identifier used to determine whether a game state has changed
-    let new_data_id = game.data_id + 1;
+    tx.commit().await?;
 
-    // Save a new file with the updated game state to disk
-    let new_filename = format!("game_{game_uuid}_{new_data_id}.txt");
-    let new_path = StdPath::new(&settings.game_data_path).join(&new_filename);
-    if let Err(e) = write(&new_path, &req.game_data).await {
-        error!("Game data could not be saved to '{new_filename}': {e}");
-        return Err(ApiError::InternalServerError);
+    let (observer_token, observer_token_expires_at) = observer_token.unzip();
+    Ok(Json(LeaveGameResponse {
+        observer_token,
+        observer_token_expires_at,
+    }))
+}
+
+/// Reveal the nations of all players of a game with hidden identities
+///
+/// This endpoint can only be used by the host of the game, usually once the game ended.
+/// Afterwards, the nations of all players are visible in the game overview again.
+///
+/// On success, all players of the game receive a [WsMessage::NationsRevealed] message.
+/// Revealing the nations of a game without hidden identities does nothing.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Nations were revealed"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[post("/games/{uuid}/revealNations")]
+pub async fn reveal_nations(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+    let game_uuid = path.uuid;
+
+    let mut tx = db.start_transaction().await?;
+
+    let (host, hidden_identities) = query!(&mut tx, (Game::F.host, Game::F.hidden_identities))
+        .condition(and!(
+            Game::F.uuid.equals(game_uuid),
+            Game::F.current_players.player.uuid.equals(uuid)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::GameNotFound)?;
+
+    // Check if the executing user is the host of the game
+    if host.map(|x| *x.key()) != Some(uuid) {
+        return Err(ApiError::MissingPrivileges);
+    }
+
+    if !hidden_identities {
+        return Ok(HttpResponse::Ok().finish());
     }
 
-    // Update the game state identifier and last player in the database,
-    // which also updates the last access time automatically
     update!(&mut tx, Game)
-        .set(Game::F.data_id, new_data_id)
-        .set(Game::F.updated_by, ForeignModelByField::Key(uuid))
         .condition(Game::F.uuid.equals(game_uuid))
+        .set(Game::F.hidden_identities, false)
+        .exec()
+        .await?;
+
+    let players = query!(&mut tx, (GameAccount::F.player, GameAccount::F.nation))
+        .condition(GameAccount::F.game.equals(game_uuid))
+        .all()
         .await?;
 
     tx.commit().await?;
 
-    // Remove the old file from the filesystem
-    let old_filename = format!("game_{game_uuid}_{old}.txt", old = game.data_id);
-    let old_path = StdPath::new(&settings.game_data_path).join(&old_filename);
-    if let Err(e) = remove_file(&old_path).await {
-        if new_data_id != 1 {
-            warn!("Outdated data in '{old_filename}' could not be removed and may leak: {e}");
-        }
+    let msg = WsMessage::NationsRevealed {
+        game_uuid,
+        nations: players
+            .iter()
+            .filter_map(|(player, nation)| {
+                nation.clone().map(|nation| PlayerNation {
+                    player_uuid: *player.key(),
+                    nation,
+                })
+            })
+            .collect(),
+    };
+    for (player, _) in players {
+        notifier.send(*player.key(), msg.clone()).await;
     }
 
-    // Notify all remaining players about the new game data
-    let msg = WsMessage::UpdateGameData {
-        game_uuid: game.uuid,
-        game_data_id: new_data_id as u64,
-        game_data: req.game_data.clone(),
-    };
-    for player in players.into_iter().filter(|x| *x != uuid) {
-        if let Err(err) = ws_manager_chan
-            .send(WsManagerMessage::SendMessage(player, msg.clone()))
-            .await
-        {
-            error!("Could not send to ws manager chan: {err}");
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The request to update the settings of a game
+///
+/// All parameters are optional, but at least one of them is required.
+/// `turn_timer` is specified in seconds, it can't be unset once it was set.
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateGameSettingsRequest {
+    #[schema(example = 86400)]
+    turn_timer: Option<u32>,
+    allow_spectators: Option<bool>,
+    allow_late_joins: Option<bool>,
+    public: Option<bool>,
+    grant_observer_tokens: Option<bool>,
+}
+
+/// Update the settings of a game
+///
+/// Only the host of the game is allowed to change its settings.
+///
+/// On success, all players of the game receive a [WsMessage::GameSettingsChanged] message.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the new settings of the game", body = GameSettingsResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = UpdateGameSettingsRequest,
+    security(("session_cookie" = []))
+)]
+#[patch("/games/{uuid}/settings")]
+pub async fn update_game_settings(
+    path: Path<PathUuid>,
+    req: Json<UpdateGameSettingsRequest>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<GameSettingsResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+    let game_uuid = path.uuid;
+
+    if req.turn_timer.is_none()
+        && req.allow_spectators.is_none()
+        && req.allow_late_joins.is_none()
+        && req.public.is_none()
+        && req.grant_observer_tokens.is_none()
+    {
+        return Err(ApiError::EmptyJson);
+    }
+    let turn_timer = validate_turn_timer(req.turn_timer)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    let (host,) = query!(&mut tx, (Game::F.host,))
+        .condition(and!(
+            Game::F.uuid.equals(game_uuid),
+            Game::F.current_players.player.uuid.equals(uuid)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::GameNotFound)?;
+
+    // Check if the executing user is the host of the game
+    if host.map(|x| *x.key()) != Some(uuid) {
+        return Err(ApiError::MissingPrivileges);
+    }
+
+    let current = query!(&mut tx, GameSettings)
+        .condition(GameSettings::F.game.equals(game_uuid))
+        .optional()
+        .await?;
+
+    let settings = match current {
+        Some(current) => {
+            let settings = GameSettingsResponse {
+                turn_timer: turn_timer.or(current.turn_timer).map(|x| x as u32),
+                allow_spectators: req.allow_spectators.unwrap_or(current.allow_spectators),
+                allow_late_joins: req.allow_late_joins.unwrap_or(current.allow_late_joins),
+                public: req.public.unwrap_or(current.public),
+                grant_observer_tokens: req
+                    .grant_observer_tokens
+                    .unwrap_or(current.grant_observer_tokens),
+            };
+
+            update!(&mut tx, GameSettings)
+                .condition(GameSettings::F.uuid.equals(current.uuid))
+                .set(
+                    GameSettings::F.turn_timer,
+                    settings.turn_timer.map(|x| x as i32),
+                )
+                .set(GameSettings::F.allow_spectators, settings.allow_spectators)
+                .set(GameSettings::F.allow_late_joins, settings.allow_late_joins)
+                .set(GameSettings::F.public, settings.public)
+                .set(
+                    GameSettings::F.grant_observer_tokens,
+                    settings.grant_observer_tokens,
+                )
+                .exec()
+                .await?;
+
+            settings
         }
+        None => {
+            let default = GameSettingsResponse::default();
+            let settings = GameSettingsResponse {
+                turn_timer: turn_timer.map(|x| x as u32),
+                allow_spectators: req.allow_spectators.unwrap_or(default.allow_spectators),
+                allow_late_joins: req.allow_late_joins.unwrap_or(default.allow_late_joins),
+                public: req.public.unwrap_or(default.public),
+                grant_observer_tokens: req
+                    .grant_observer_tokens
+                    .unwrap_or(default.grant_observer_tokens),
+            };
+
+            insert!(&mut tx, GameSettingsInsert)
+                .return_nothing()
+                .single(&GameSettingsInsert {
+                    uuid: Uuid::new_v4(),
+                    game: ForeignModelByField::Key(game_uuid),
+                    turn_timer,
+                    allow_spectators: settings.allow_spectators,
+                    allow_late_joins: settings.allow_late_joins,
+                    public: settings.public,
+                    grant_observer_tokens: settings.grant_observer_tokens,
+                })
+                .await?;
+
+            settings
+        }
+    };
+
+    let players = query!(&mut tx, (GameAccount::F.player,))
+        .condition(GameAccount::F.game.equals(game_uuid))
+        .all()
+        .await?;
+
+    tx.commit().await?;
+
+    let msg = WsMessage::GameSettingsChanged {
+        game_uuid,
+        settings: settings.clone(),
+    };
+    for (player,) in players {
+        notifier.send(*player.key(), msg.clone()).await;
     }
 
+    Ok(Json(settings))
+}
+
+/// The response a user receives after uploading a new game state successfully
+///
+/// `updated_at` is the point in time the server stored the game state and
+/// `game_data_checksum` the hex encoded SHA-256 checksum of the stored game data.
+/// `notified_players` are the players that were sent the new game state.
+#[derive(Serialize, ToSchema)]
+pub struct GameUploadResponse {
+    #[schema(example = 1337)]
+    pub(crate) game_data_id: u64,
+    pub(crate) updated_at: DateTime<Utc>,
+    #[schema(example = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")]
+    pub(crate) game_data_checksum: String,
+    pub(crate) notified_players: Vec<Uuid>,
+}
+
+/// The request a user sends to the server to upload a new game state
+///
+/// `game_data_checksum` is the optional hex encoded SHA-256 checksum of `game_data`.
+///
+/// `next_player` is the optional player whose turn it is in the uploaded state.
+/// As the server doesn't know the turn order, it has to be provided by the client.
+///
+/// `turn` is the optional turn of the uploaded state, it is only used for display purposes.
+/// The same applies to the optional summary of the state: `year` is the in-game year,
+/// negative for years BC, `era` the era of the leading player of at most 64 characters and
+/// `alive_players` the number of players that are not defeated.
+///
+/// `note` is an optional note of at most 255 characters for `next_player`.
+#[derive(Deserialize, ToSchema)]
+pub struct GameUploadRequest {
+    game_data: String,
+    #[schema(example = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")]
+    game_data_checksum: Option<String>,
+    next_player: Option<Uuid>,
+    #[schema(example = 42)]
+    turn: Option<u32>,
+    #[schema(example = -2400)]
+    year: Option<i32>,
+    #[schema(example = "Classical era")]
+    era: Option<String>,
+    #[schema(example = 5)]
+    alive_players: Option<u16>,
+    #[schema(example = "Watch out, barbarians near your capital")]
+    note: Option<String>,
+}
+
+/// Upload a new game state for an existing game
+///
+/// If the game can't be updated (maybe it has been already completed or
+/// aborted), it will respond with a `GameNotFound` in `ApiErrorResponse`.
+///
+/// If `game_data_checksum` is specified and doesn't match the SHA-256 checksum of
+/// the received `game_data`, the state is rejected with an `InvalidChecksum` error.
+///
+/// If `game_data` is larger than the maximum game data size of the server, the state is
+/// rejected with a `PayloadOverflow` error. The limit can be retrieved from
+/// `GET /api/v2/capabilities`.
+///
+/// If `next_player` is specified, this player receives a [WsMessage::YourTurn] message
+/// containing the `note` in addition to the [WsMessage::UpdateGameData] message all other
+/// players receive. The `note` is also recorded in the event log of the game, a note longer
+/// than 255 characters is rejected with an `InvalidTurnNote` error.
+/// All other players also receive a compact [WsMessage::GameMetaChanged] message.
+///
+/// If too many uploads of game states are running at the same time, the request is rejected
+/// with a `ServerBusy` error and should be retried after the time in the `Retry-After` header.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the new data identifier of the uploaded game state", body = GameUploadResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+        (status = 503, description = "Too many concurrent requests", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = GameUploadRequest,
+    security(("session_cookie" = []))
+)]
+#[put(
+    "/games/{uuid}",
+    wrap = "ConcurrencyLimit(LimitedOperation::GameUpload)"
+)]
+pub async fn push_game_update(
+    path: Path<PathUuid>,
+    req: Json<GameUploadRequest>,
+    settings: Data<RuntimeSettings>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<GameUploadResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+    let GameUploadRequest {
+        game_data,
+        game_data_checksum,
+        next_player,
+        turn,
+        year,
+        era,
+        alive_players,
+        note,
+    } = req.into_inner();
+
+    let mut tx = db.start_transaction().await?;
+
+    let mut outbox = Outbox::new();
+    let uploaded = game::upload_game_state(
+        &mut tx,
+        &mut outbox,
+        &settings.game_data_path,
+        settings.max_game_data_size,
+        path.uuid,
+        uuid,
+        GameStateUpload {
+            game_data,
+            game_data_checksum,
+            next_player,
+            turn,
+            year,
+            era,
+            alive_players,
+            note,
+        },
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    uploaded.remove_outdated_file().await;
+    outbox.send(notifier.get_ref()).await;
+
     Ok(Json(GameUploadResponse {
-        game_data_id: new_data_id as u64,
+        game_data_id: uploaded.data_id,
+        updated_at: uploaded.updated_at,
+        game_data_checksum: uploaded.checksum,
+        notified_players: uploaded.notified_players,
     }))
 }
+
+/// The request to end a turn without uploading the game state
+#[derive(Deserialize, ToSchema)]
+pub struct EndTurnRequest {
+    next_player: Uuid,
+}
+
+/// The response to ending a turn
+///
+/// The game state has to be uploaded with `PUT /api/v2/games/{uuid}` before `upload_deadline`.
+#[derive(Serialize, ToSchema)]
+pub struct EndTurnResponse {
+    upload_deadline: DateTime<Utc>,
+}
+
+/// End the turn of the executing account and upload the game state later
+///
+/// This is meant for clients that want to defer the upload of the game state, e.g.
+/// on metered connections. The turn passes to `next_player`, which must be a player
+/// of the game. All other players receive a [WsMessage::TurnEnded] message.
+///
+/// If the server knows whose turn it is and it's not the turn of the executing account,
+/// a `NotYourTurn` error is returned. If another player ended their turn and has not
+/// uploaded the game state yet, an `UploadPending` error is returned until the upload
+/// deadline of that player passed. An expired pending upload is replaced.
+///
+/// The pending upload is resolved by the next upload of the executing account.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "The turn was ended", body = EndTurnResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = EndTurnRequest,
+    security(("session_cookie" = []))
+)]
+#[post("/games/{uuid}/endTurn")]
+pub async fn end_turn(
+    path: Path<PathUuid>,
+    req: Json<EndTurnRequest>,
+    settings: Data<RuntimeSettings>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<EndTurnResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    let mut outbox = Outbox::new();
+    let upload_deadline = game::end_turn(
+        &mut tx,
+        &mut outbox,
+        Duration::seconds(settings.pending_upload_timeout as i64),
+        path.uuid,
+        uuid,
+        req.next_player,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    outbox.send(notifier.get_ref()).await;
+
+    Ok(Json(EndTurnResponse { upload_deadline }))
+}
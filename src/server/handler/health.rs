@@ -1,16 +1,19 @@
 //! Handler for server health endpoints
 
-use actix_web::get;
-use actix_web::web::{Data, Json};
+use actix_web::web::{Data, Json, Query};
+use actix_web::{get, post};
 use log::error;
 use rorm::{query, Database, Model};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tokio::sync::oneshot;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::chan::{WsManagerChan, WsManagerMessage};
-use crate::models::Account;
+use crate::models::{Account, Game, Lobby};
 use crate::server::handler::{ApiError, ApiErrorResponse, ApiResult};
+use crate::server::middleware::{SlowRequest, SlowRequests, SlowStatement};
+use crate::server::RuntimeSettings;
+use crate::tasks::{verify_game_data, GameDataCheck, GameDataVerification};
 
 /// The health data of this server
 #[derive(Serialize, ToSchema)]
@@ -19,12 +22,15 @@ pub struct HealthResponse {
     registered_accounts: u64,
     #[schema(example = 31337)]
     open_connections: u64,
+    game_data_check: Option<GameDataCheck>,
 }
 
 /// Request health data from this server.
 ///
 /// `registered_accounts` are the currently registered user accounts on the server
 /// `open_connections` are the currently open connections
+/// `game_data_check` is the result of the consistency check of the game data files,
+/// it is only available if `CheckGameDataOnStart` is enabled
 #[utoipa::path(
     tag = "Server status",
     context_path = "/api/v2/admin",
@@ -39,6 +45,7 @@ pub struct HealthResponse {
 pub async fn health(
     db: Data<Database>,
     ws_manager_chan: Data<WsManagerChan>,
+    game_data_check: Data<Option<GameDataCheck>>,
 ) -> ApiResult<Json<HealthResponse>> {
     let accounts = query!(db.as_ref(), (Account::F.uuid.count(),))
         .one()
@@ -71,5 +78,132 @@ pub async fn health(
     Ok(Json(HealthResponse {
         registered_accounts: accounts,
         open_connections: connections,
+        game_data_check: game_data_check.as_ref().clone(),
     }))
 }
+
+/// The utilization of this server
+#[derive(Serialize, ToSchema)]
+pub struct UtilizationResponse {
+    #[schema(example = 12)]
+    open_lobbies: u64,
+    /// The configured maximum of open lobbies, `0` if unlimited
+    #[schema(example = 50)]
+    max_open_lobbies: u64,
+    #[schema(example = 42)]
+    running_games: u64,
+    /// The configured maximum of running games, `0` if unlimited
+    #[schema(example = 100)]
+    max_running_games: u64,
+}
+
+/// Request the utilization of this server.
+///
+/// `open_lobbies` and `running_games` are the lobbies and games that currently exist on the
+/// server. If one of the configured maximums is reached, creating lobbies or starting games
+/// fails with [ApiError::ServerAtCapacity].
+#[utoipa::path(
+    tag = "Server status",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Utilization of this server", body = UtilizationResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/utilization")]
+pub async fn utilization(
+    db: Data<Database>,
+    settings: Data<RuntimeSettings>,
+) -> ApiResult<Json<UtilizationResponse>> {
+    let (open_lobbies,) = query!(db.as_ref(), (Lobby::F.uuid.count(),)).one().await?;
+    let (running_games,) = query!(db.as_ref(), (Game::F.uuid.count(),)).one().await?;
+
+    Ok(Json(UtilizationResponse {
+        open_lobbies: open_lobbies as u64,
+        max_open_lobbies: settings.max_open_lobbies,
+        running_games: running_games as u64,
+        max_running_games: settings.max_running_games,
+    }))
+}
+
+/// The query parameters to verify the game data files
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VerifyGameDataQuery {
+    /// Restore missing or corrupt game states from the latest intact snapshot of the game
+    #[serde(default)]
+    restore: bool,
+}
+
+/// Verify the game data files of all games against their checksums
+///
+/// This is the same check as the `verify` command of the server. Games uploaded before
+/// checksums were introduced are only checked for readability.
+///
+/// If `restore` is set, missing or corrupt game states are restored from the latest
+/// intact snapshot of the game. The players are not notified about restored game states.
+#[utoipa::path(
+    tag = "Server status",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Result of the verification", body = GameDataVerification),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(VerifyGameDataQuery),
+    security(("admin_token" = []))
+)]
+#[post("/game-data/verify")]
+pub async fn verify_game_data_files(
+    options: Query<VerifyGameDataQuery>,
+    db: Data<Database>,
+    settings: Data<RuntimeSettings>,
+) -> ApiResult<Json<GameDataVerification>> {
+    let verification = verify_game_data(&db, &settings.game_data_path, options.restore)
+        .await
+        .map_err(|err| {
+            error!("Could not verify game data files: {err}");
+            ApiError::InternalServerError
+        })?;
+
+    Ok(Json(verification))
+}
+
+/// The slow requests and database statements recorded by this server
+#[derive(Serialize, ToSchema)]
+pub struct SlowLogResponse {
+    /// The time in milliseconds after which a request or statement is recorded,
+    /// `None` if disabled
+    #[schema(example = 1000)]
+    threshold_ms: Option<u64>,
+    /// The slowest requests, the slowest first
+    requests: Vec<SlowRequest>,
+    /// The slowest database statements, the slowest first
+    statements: Vec<SlowStatement>,
+}
+
+/// Retrieve the requests and database statements that exceeded the `SlowRequestThreshold`
+///
+/// The server keeps the `SlowRequestLogSize` slowest requests and statements in memory,
+/// they are lost on restart. Only the route pattern of a request and the summary of a
+/// statement are recorded, without their parameters.
+#[utoipa::path(
+    tag = "Server status",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Returns the slow requests", body = SlowLogResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/slowlog")]
+pub async fn get_slow_log(slow_requests: Data<SlowRequests>) -> Json<SlowLogResponse> {
+    Json(SlowLogResponse {
+        threshold_ms: slow_requests.threshold_ms(),
+        requests: slow_requests.slowest(),
+        statements: slow_requests.slowest_statements(),
+    })
+}
@@ -1,25 +1,26 @@
 //! Handler for invites
 
-use std::iter;
-
 use actix_toolbox::tb_middleware::Session;
 use actix_web::web::{Data, Json, Path};
 use actix_web::{delete, get, post, HttpResponse};
 use chrono::{DateTime, Utc};
-use log::{error, warn};
 use rorm::fields::types::ForeignModelByField;
 use rorm::{and, insert, query, Database, FieldAccess, Model};
 use serde::{Deserialize, Serialize};
-use tokio::sync::oneshot;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::chan::{WsManagerChan, WsManagerMessage, WsMessage};
+use crate::chan::{Notifier, WsMessage};
 use crate::models::{
-    Account, ChatRoomMemberInsert, Friend, Invite, InviteInsert, Lobby, LobbyAccount,
-    LobbyAccountInsert,
+    Account, ChatRoomMemberInsert, Friend, Game, GameAccountInsert, GameInvite, GameInviteInsert,
+    Invite, InviteInsert, Lobby,
 };
 use crate::server::handler::{AccountResponse, ApiError, ApiErrorResponse, ApiResult, PathUuid};
+use crate::server::RuntimeSettings;
+use crate::service::game;
+use crate::service::invite::{self, join_lobby_by_invite};
+use crate::service::membership::{is_banned, is_in_a_lobby, may_join, GameMembers, LobbyMembers};
+use crate::service::notify::{NotificationSink, Outbox};
 
 /// The request to invite a friend into a lobby
 #[derive(Deserialize, ToSchema)]
@@ -32,6 +33,13 @@ pub struct CreateInviteRequest {
 ///
 /// The executing user must be in the specified open lobby.
 /// The invited `friend` must not be in a friend request state.
+///
+/// If the friend enabled `auto_accept_invites` for the executing user, has an active
+/// websocket connection, is not in a lobby (unless the server allows multiple lobbies), may
+/// join the lobby and the lobby is not full, the invite is accepted immediately. In this case, the friend
+/// receives a [WsMessage::LobbyInviteAutoAccepted] message and the lobby is notified like
+/// for an accepted invite, which includes starting the game of a lobby with `auto_start`.
+/// Otherwise, the friend receives a [WsMessage::IncomingInvite] message.
 #[utoipa::path(
     tag = "Invites",
     context_path = "/api/v2",
@@ -47,35 +55,26 @@ pub struct CreateInviteRequest {
 pub async fn create_invite(
     req: Json<CreateInviteRequest>,
     session: Session,
+    settings: Data<RuntimeSettings>,
     db: Data<Database>,
-    ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
     let mut tx = db.start_transaction().await?;
 
     // Check if lobby is currently open
-    let mut lobby = query!(&mut tx, Lobby)
+    let lobby = query!(&mut tx, Lobby)
         .condition(Lobby::F.uuid.equals(req.lobby_uuid))
         .optional()
         .await?
         .ok_or(ApiError::InvalidLobbyUuid)?;
-    Lobby::F
-        .current_player
-        .populate(&mut tx, &mut lobby)
-        .await?;
+    let members = LobbyMembers::query(&mut tx, lobby.uuid)
+        .await?
+        .ok_or(ApiError::InvalidLobbyUuid)?;
 
     // Check if the executing account has the privileges to invite to the specified lobby
-    if *lobby.owner.key() != uuid
-        && query!(&mut tx, LobbyAccount)
-            .condition(and!(
-                LobbyAccount::F.lobby.equals(lobby.uuid),
-                LobbyAccount::F.player.equals(uuid)
-            ))
-            .optional()
-            .await?
-            .is_none()
-    {
+    if !members.is_member(uuid) {
         return Err(ApiError::MissingPrivileges);
     }
 
@@ -98,23 +97,11 @@ pub async fn create_invite(
         .ok_or(ApiError::InvalidFriendState)?;
 
     // Check if the target of the invite is already in the specified lobby
-    if *lobby.owner.key() == req.friend_uuid {
-        return Err(ApiError::AlreadyInThisLobby);
-    }
-    // Ok as current_player is populated before
-    #[allow(clippy::unwrap_used)]
-    if lobby
-        .current_player
-        .cached
-        .unwrap()
-        .iter()
-        .any(|x| *x.player.key() == uuid)
-    {
+    if members.is_member(friend_account.uuid) {
         return Err(ApiError::AlreadyInThisLobby);
     }
 
-    let invite_uuid = insert!(&mut tx, InviteInsert)
-        .return_primary_key()
+    let invite = insert!(&mut tx, InviteInsert)
         .single(&InviteInsert {
             uuid: Uuid::new_v4(),
             from: ForeignModelByField::Key(uuid),
@@ -123,6 +110,45 @@ pub async fn create_invite(
         })
         .await?;
 
+    // Check if the friend accepts the invite automatically
+    let (auto_accept_invites,) = query!(&mut tx, (Friend::F.auto_accept_invites,))
+        .condition(and!(
+            Friend::F.is_request.equals(false),
+            Friend::F.from.equals(friend_account.uuid),
+            Friend::F.to.equals(uuid)
+        ))
+        .optional()
+        .await?
+        .unwrap_or((false,));
+    let auto_accept = auto_accept_invites
+        && !members.is_full()
+        && (settings.allow_multiple_lobbies
+            || !is_in_a_lobby(&mut tx, friend_account.uuid).await?)
+        && !is_banned(&mut tx, lobby.uuid, friend_account.uuid).await?
+        && may_join(&mut tx, &lobby, friend_account.uuid).await?
+        && notifier.is_online(friend_account.uuid).await?;
+
+    if auto_accept {
+        let mut outbox = Outbox::new();
+        let (player, inviter) =
+            join_lobby_by_invite(&mut tx, &mut outbox, &lobby, &members, &invite).await?;
+        outbox.notify(
+            player.uuid,
+            WsMessage::LobbyInviteAutoAccepted {
+                lobby_uuid: lobby.uuid,
+                from: inviter,
+            },
+        );
+
+        game::auto_start(&mut tx, &mut outbox, lobby.uuid, settings.max_running_games).await?;
+
+        tx.commit().await?;
+
+        outbox.send(notifier.get_ref()).await;
+
+        return Ok(HttpResponse::Ok().finish());
+    }
+
     let executing_account = query!(&mut tx, Account)
         .condition(Account::F.uuid.equals(uuid))
         .optional()
@@ -132,7 +158,7 @@ pub async fn create_invite(
     tx.commit().await?;
 
     let invite = WsMessage::IncomingInvite {
-        invite_uuid,
+        invite_uuid: invite.uuid,
         lobby_uuid: lobby.uuid,
         from: AccountResponse {
             uuid: executing_account.uuid,
@@ -141,12 +167,7 @@ pub async fn create_invite(
         },
     };
 
-    if let Err(err) = ws_manager_chan
-        .send(WsManagerMessage::SendMessage(friend_account.uuid, invite))
-        .await
-    {
-        error!("Could not send to ws manager chan: {err}");
-    }
+    notifier.send(friend_account.uuid, invite).await;
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -160,10 +181,23 @@ pub struct GetInvite {
     lobby_uuid: Uuid,
 }
 
+/// A single invite to a running game
+#[derive(Serialize, ToSchema)]
+pub struct GetGameInvite {
+    uuid: Uuid,
+    created_at: DateTime<Utc>,
+    from: AccountResponse,
+    game_uuid: Uuid,
+}
+
 /// The invites that an account has received
+///
+/// `invites` are invites to lobbies, `game_invites` are invites to take over
+/// an empty seat of a running game.
 #[derive(Serialize, ToSchema)]
 pub struct GetInvitesResponse {
     invites: Vec<GetInvite>,
+    game_invites: Vec<GetGameInvite>,
 }
 
 /// Retrieve all invites for the executing user
@@ -199,7 +233,39 @@ pub async fn get_invites(
     .all()
     .await?;
 
+    let game_invites = query!(
+        db.as_ref(),
+        (
+            GameInvite::F.uuid,
+            GameInvite::F.from.uuid,
+            GameInvite::F.from.username,
+            GameInvite::F.from.display_name,
+            GameInvite::F.game,
+            GameInvite::F.created_at
+        )
+    )
+    .condition(GameInvite::F.to.equals(uuid))
+    .all()
+    .await?;
+
     Ok(Json(GetInvitesResponse {
+        game_invites: game_invites
+            .into_iter()
+            .map(
+                |(uuid, from_uuid, from_username, from_display_name, game, created_at)| {
+                    GetGameInvite {
+                        uuid,
+                        game_uuid: *game.key(),
+                        created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
+                        from: AccountResponse {
+                            uuid: from_uuid,
+                            username: from_username,
+                            display_name: from_display_name,
+                        },
+                    }
+                },
+            )
+            .collect(),
         invites: invites
             .into_iter()
             .map(
@@ -269,9 +335,21 @@ pub async fn delete_invite(
 /// To be placed in a lobby, a active websocket connection is required.
 ///
 /// If the lobby is already full, a [ApiError::LobbyFull] error is returned.
+/// If the lobby is restricted and the executing user is not allowed to join it,
+/// a [ApiError::NotAllowedToJoin] error is returned.
+/// If the executing user was banned from the lobby, a [ApiError::BannedFromLobby] error
+/// is returned.
 ///
 /// On success, all players that were in the lobby before, are notified about the new player with a
 /// [WsMessage::LobbyJoin] message.
+///
+/// As accepting an invite bypasses the password of a lobby, the acceptance is recorded in the
+/// audit log and the owner of the lobby additionally receives a [WsMessage::LobbyInviteAccepted]
+/// message, which contains the inviting player.
+/// The invite is consumed.
+///
+/// If the lobby has `auto_start` set and the executing user took its last free slot, the game
+/// is started like described in `POST /api/v2/lobbies/{uuid}/join`.
 #[utoipa::path(
     tag = "Invites",
     context_path = "/api/v2",
@@ -288,88 +366,260 @@ pub async fn accept_invite(
     path: Path<PathUuid>,
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    settings: Data<RuntimeSettings>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<HttpResponse> {
-    let session_uuid: Uuid = session.get("session")?.ok_or(ApiError::SessionCorrupt)?;
+    let session_uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    // Check if the websocket is connected
+    if !notifier.is_online(session_uuid).await? {
+        return Err(ApiError::WsNotConnected);
+    }
 
     let mut tx = db.start_transaction().await?;
 
-    // Check if the invite exists
-    let invite = query!(&mut tx, Invite)
-        .condition(Invite::F.uuid.equals(path.uuid))
-        .optional()
+    let mut outbox = Outbox::new();
+    let lobby = invite::accept_invite(&mut tx, &mut outbox, path.uuid, session_uuid).await?;
+    game::auto_start(&mut tx, &mut outbox, lobby, settings.max_running_games).await?;
+
+    tx.commit().await?;
+
+    outbox.send(notifier.get_ref()).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The request to invite a friend into a running game
+#[derive(Deserialize, ToSchema)]
+pub struct CreateGameInviteRequest {
+    friend_uuid: Uuid,
+}
+
+/// Invite a friend to take over an empty seat of a running game.
+///
+/// The executing user must be a player of the game and the game must have an empty seat,
+/// e.g. after a player was kicked or resigned.
+/// The invited `friend` must not be in a friend request state and not already be a player.
+///
+/// On success, the friend receives a [WsMessage::IncomingGameInvite] message.
+#[utoipa::path(
+    tag = "Invites",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Friend got invited"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = CreateGameInviteRequest,
+    security(("session_cookie" = []))
+)]
+#[post("/games/{uuid}/invites")]
+pub async fn create_game_invite(
+    path: Path<PathUuid>,
+    req: Json<CreateGameInviteRequest>,
+    session: Session,
+    db: Data<Database>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    let members = GameMembers::query(&mut tx, path.uuid)
         .await?
-        .ok_or(ApiError::InvalidUuid)?;
+        .ok_or(ApiError::GameNotFound)?;
+    let game_uuid = members.game;
 
-    // Check if executing user is the receiver of the invite
-    if *invite.to.key() != session_uuid {
-        return Err(ApiError::MissingPrivileges);
+    // Check if the executing account is playing the game
+    if !members.is_player(uuid) {
+        return Err(ApiError::GameNotFound);
+    }
+
+    // Check if there's an empty seat
+    if members.is_full() {
+        return Err(ApiError::GameFull);
     }
 
-    let mut lobby = query!(&mut tx, Lobby)
-        .condition(LobbyAccount::F.lobby.equals(*invite.lobby.key()))
+    // Check if there's a valid friendship
+    let friend = query!(&mut tx, Friend)
+        .condition(and!(
+            Friend::F.is_request.equals(false),
+            Friend::F.from.equals(uuid),
+            Friend::F.to.equals(req.friend_uuid)
+        ))
         .optional()
         .await?
-        .ok_or(ApiError::InternalServerError)?;
-    Lobby::F
-        .current_player
-        .populate(&mut tx, &mut lobby)
+        .ok_or(ApiError::InvalidFriendState)?;
+
+    // Check if the target of the invite is already playing the game
+    if members.is_player(req.friend_uuid) {
+        return Err(ApiError::AlreadyInThisGame);
+    }
+
+    let invite_uuid = insert!(&mut tx, GameInviteInsert)
+        .return_primary_key()
+        .single(&GameInviteInsert {
+            uuid: Uuid::new_v4(),
+            from: ForeignModelByField::Key(uuid),
+            to: friend.to,
+            game: ForeignModelByField::Key(game_uuid),
+        })
         .await?;
 
-    // Ok as current_player is populated before
-    #[allow(clippy::unwrap_used)]
-    let current_player = lobby.current_player.cached.unwrap();
+    let (username, display_name) = query!(&mut tx, (Account::F.username, Account::F.display_name))
+        .condition(Account::F.uuid.equals(uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::SessionCorrupt)?;
+
+    tx.commit().await?;
 
-    // Check if lobby is full
+    let invite = WsMessage::IncomingGameInvite {
+        invite_uuid,
+        game_uuid,
+        from: AccountResponse {
+            uuid,
+            username,
+            display_name,
+        },
+    };
 
-    if current_player.len() + 1 == lobby.max_player as usize {
-        return Err(ApiError::LobbyFull);
-    }
+    notifier.send(req.friend_uuid, invite).await;
 
-    // Check if player is part of the lobby
-    if *lobby.owner.key() == *invite.to.key() {
-        return Err(ApiError::AlreadyInThisLobby);
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Reject or retract an invite to a running game
+///
+/// This endpoint can be used either by the sender of the invite to retract the invite or
+/// by the receiver to reject the invite.
+#[utoipa::path(
+    tag = "Invites",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Invite was rejected"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[delete("/game-invites/{uuid}")]
+pub async fn delete_game_invite(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    let invite = query!(&mut tx, GameInvite)
+        .condition(GameInvite::F.uuid.equals(path.uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    // Check if the executing user has the privileges to delete the invite
+    if *invite.to.key() != uuid && *invite.from.key() != uuid {
+        return Err(ApiError::MissingPrivileges);
     }
-    // Ok as current_player is populated before
-    #[allow(clippy::unwrap_used)]
-    if current_player
-        .iter()
-        .any(|x| *x.player.key() == *invite.to.key())
-    {
-        return Err(ApiError::AlreadyInThisLobby);
+
+    rorm::delete!(&mut tx, GameInvite).single(&invite).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Accept an invite to a running game
+///
+/// The executing user is added as player to the game and to the chat room of the game.
+///
+/// If the game has no empty seat anymore, a [ApiError::GameFull] error is returned.
+///
+/// On success, all players of the game are notified about the new player with a
+/// [WsMessage::GameJoin] message.
+#[utoipa::path(
+    tag = "Invites",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Invitation was accepted"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[post("/game-invites/{uuid}/accept")]
+pub async fn accept_game_invite(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    // Check if the invite exists
+    let invite = query!(&mut tx, GameInvite)
+        .condition(GameInvite::F.uuid.equals(path.uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    // Check if executing user is the receiver of the invite
+    if *invite.to.key() != uuid {
+        return Err(ApiError::MissingPrivileges);
     }
 
-    // Check if the websocket is connected
-    let (sender, rx) = oneshot::channel();
+    let members = GameMembers::query(&mut tx, *invite.game.key())
+        .await?
+        .ok_or(ApiError::InternalServerError)?;
+    let game_uuid = members.game;
 
-    let msg = WsManagerMessage::RetrieveOnlineState(*invite.to.key(), sender);
-    if let Err(err) = ws_manager_chan.send(msg).await {
-        warn!("Could not send to ws manager chan: {err}");
-        return Err(ApiError::InternalServerError);
+    if members.is_player(uuid) {
+        return Err(ApiError::AlreadyInThisGame);
     }
 
-    match rx.await {
-        Ok(is_online) => {
-            if !is_online {
-                return Err(ApiError::WsNotConnected);
-            }
-        }
-        Err(err) => {
-            warn!("Error while receiving from oneshot channel: {err}");
-            return Err(ApiError::InternalServerError);
-        }
+    if members.is_full() {
+        return Err(ApiError::GameFull);
     }
 
-    // Add player to lobby
-    insert!(&mut tx, LobbyAccountInsert)
+    let (chat_room,) = query!(&mut tx, (Game::F.chat_room,))
+        .condition(Game::F.uuid.equals(game_uuid))
+        .one()
+        .await?;
+
+    // Add player to game
+    insert!(&mut tx, GameAccountInsert)
         .return_nothing()
-        .single(&LobbyAccountInsert {
+        .single(&GameAccountInsert {
             uuid: Uuid::new_v4(),
-            lobby: ForeignModelByField::Key(lobby.uuid),
-            player: ForeignModelByField::Key(*invite.to.key()),
+            game: ForeignModelByField::Key(game_uuid),
+            player: ForeignModelByField::Key(uuid),
+        })
+        .await?;
+
+    // Add player to chatroom
+    insert!(&mut tx, ChatRoomMemberInsert)
+        .single(&ChatRoomMemberInsert {
+            uuid: Uuid::new_v4(),
+            member: ForeignModelByField::Key(uuid),
+            chat_room: ForeignModelByField::Key(*chat_room.key()),
         })
         .await?;
 
+    // All invites to this game for the executing user are obsolete now
+    rorm::delete!(&mut tx, GameInvite)
+        .condition(and!(
+            GameInvite::F.game.equals(game_uuid),
+            GameInvite::F.to.equals(uuid)
+        ))
+        .await?;
+
     let (uuid, username, display_name) = query!(
         &mut tx,
         (
@@ -378,28 +628,15 @@ pub async fn accept_invite(
             Account::F.display_name
         )
     )
-    .condition(Account::F.uuid.equals(*invite.to.key()))
+    .condition(Account::F.uuid.equals(uuid))
     .optional()
     .await?
     .ok_or(ApiError::SessionCorrupt)?;
 
-    // Add player to chatroom
-    insert!(&mut tx, ChatRoomMemberInsert)
-        .single(&ChatRoomMemberInsert {
-            uuid: Uuid::new_v4(),
-            member: ForeignModelByField::Key(uuid),
-            chat_room: ForeignModelByField::Key(*lobby.chat_room.key()),
-        })
-        .await?;
-
     tx.commit().await?;
 
-    let players: Vec<Uuid> = iter::once(*lobby.owner.key())
-        .chain(current_player.into_iter().map(|x| *x.player.key()))
-        .collect();
-
-    let msg = WsMessage::LobbyJoin {
-        lobby_uuid: lobby.uuid,
+    let msg = WsMessage::GameJoin {
+        game_uuid,
         player: AccountResponse {
             uuid,
             username,
@@ -408,13 +645,8 @@ pub async fn accept_invite(
     };
 
     // Notify other players
-    for player in players {
-        if let Err(err) = ws_manager_chan
-            .send(WsManagerMessage::SendMessage(player, msg.clone()))
-            .await
-        {
-            warn!("Could not send to ws manager chan: {err}");
-        }
+    for player in members.players {
+        notifier.send(player, msg.clone()).await;
     }
 
     Ok(HttpResponse::Ok().finish())
@@ -1,30 +1,56 @@
 //! Handler for lobbies
 
+use std::collections::{HashMap, HashSet};
 use std::iter;
 
 use actix_toolbox::tb_middleware::Session;
-use actix_web::web::{Data, Json, Path};
-use actix_web::{delete, get, post, HttpResponse};
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{delete, get, patch, post, put, HttpResponse};
 use argon2::password_hash::{Error, SaltString};
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use chrono::{DateTime, Utc};
-use log::{error, warn};
-use rand::thread_rng;
-use rorm::fields::types::{BackRef, ForeignModelByField};
+use rand::{thread_rng, Rng};
+use rorm::conditions::DynamicCollection;
+use rorm::db::sql::value::Value;
+use rorm::db::Transaction;
+use rorm::fields::types::ForeignModelByField;
 use rorm::{and, insert, query, update, Database, FieldAccess, Model};
 use serde::{Deserialize, Serialize};
-use tokio::sync::oneshot;
 use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use crate::chan::{WsManagerChan, WsManagerMessage, WsMessage};
+use crate::chan::{LobbyListChange, Notifier, WsMessage};
 use crate::models::{
-    Account, ChatRoomInsert, ChatRoomMember, ChatRoomMemberInsert, ChatRoomMessage,
-    GameAccountInsert, GameInsert, Invite, Lobby, LobbyAccount, LobbyAccountInsert, LobbyInsert,
+    Account, ChatRoomInsert, ChatRoomMember, ChatRoomMemberInsert, Friend, Invite, InviteInsert,
+    Lobby, LobbyAccount, LobbyAccountInsert, LobbyAllowedAccount, LobbyAllowedAccountInsert,
+    LobbyBan, LobbyInsert, LobbyMod, LobbyModInsert,
 };
-use crate::server::handler::{AccountResponse, ApiError, ApiErrorResponse, ApiResult, PathUuid};
+use crate::server::handler::{
+    fill_online_states, validate_turn_timer, AccountResponse, ApiError, ApiErrorResponse,
+    ApiResult, OnlineAccountResponse, PaginationQuery, PathUuid,
+};
+use crate::server::RuntimeSettings;
+use crate::service::chat::post_system_message;
+use crate::service::game;
+use crate::service::lobby;
+use crate::service::lobby_list;
+use crate::service::membership::{is_banned, is_in_a_lobby, lobbies_of, may_join, LobbyMembers};
+use crate::service::notify::{NotificationSink, Outbox};
+use crate::service::policy::{check_policy, PolicyAction, PolicyValidator};
 
 /// A single lobby
+///
+/// `current_players` includes the owner of the lobby.
+///
+/// `is_joinable` is `true` if the executing account could join the lobby right now:
+/// the lobby is not full, the executing account is neither the owner nor a member of
+/// the lobby, the lobby is either not protected by a password or the executing
+/// account has an invite to the lobby, the executing account was not banned from the
+/// lobby and the executing account may join the lobby if it is `restricted`.
+///
+/// `ruleset`, `map_size`, `game_speed` and `mods` describe the setup of the game
+/// that is started from the lobby. They are chosen by the client and not interpreted
+/// by the server.
 #[derive(Serialize, ToSchema)]
 pub struct LobbyResponse {
     uuid: Uuid,
@@ -36,8 +62,17 @@ pub struct LobbyResponse {
     current_players: u8,
     created_at: DateTime<Utc>,
     password: bool,
+    is_joinable: bool,
     owner: AccountResponse,
     chat_room_uuid: Uuid,
+    #[schema(example = "Civ V - Gods & Kings")]
+    ruleset: Option<String>,
+    #[schema(example = "Medium")]
+    map_size: Option<String>,
+    #[schema(example = "Quick")]
+    game_speed: Option<String>,
+    mods: Vec<String>,
+    restricted: bool,
 }
 
 /// The lobbies that are open
@@ -46,9 +81,63 @@ pub struct GetLobbiesResponse {
     lobbies: Vec<LobbyResponse>,
 }
 
+/// The order of the lobby list
+#[derive(Deserialize, ToSchema, Copy, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum LobbySortOrder {
+    /// The most recently created lobbies first
+    #[default]
+    Newest,
+    /// The oldest lobbies first
+    Oldest,
+    /// The lobbies with the most players first
+    MostPlayers,
+    /// The lobbies with the fewest players first
+    FewestPlayers,
+}
+
+/// The filters of the lobby list
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetLobbiesQuery {
+    /// Only return lobbies whose name contains this text, ignoring the case
+    #[param(example = "Herbert")]
+    search: Option<String>,
+    /// Only return lobbies that have at least one free slot
+    #[serde(default)]
+    free_slots: bool,
+    /// Only return lobbies that aren't secured by a password
+    #[serde(default)]
+    no_password: bool,
+    /// Only return lobbies with this ruleset, ignoring the case
+    ruleset: Option<String>,
+    /// Only return lobbies with this map size, ignoring the case
+    map_size: Option<String>,
+    /// Only return lobbies with this game speed, ignoring the case
+    game_speed: Option<String>,
+    /// Comma separated list of the mods available to the client
+    ///
+    /// If set, only lobbies whose required mods are all in this list are returned.
+    #[param(example = "Better Combat AI,Tweaks")]
+    available_mods: Option<String>,
+    /// The order of the returned lobbies
+    #[serde(default)]
+    sort: LobbySortOrder,
+}
+
 /// Retrieves all open lobbies.
 ///
-/// If `password` is `true`, the lobby is secured by a user-set password
+/// Hidden lobbies are never part of the list.
+///
+/// If `password` is `true`, the lobby is secured by a user-set password.
+/// If `is_joinable` is `true`, the executing account may join the lobby.
+///
+/// The lobbies can be filtered by their name, free slots, password and game setup.
+/// `available_mods` filters out all lobbies that require a mod which is not in the list.
+/// The filters, the order and the pagination are applied by the database.
+///
+/// Instead of polling this endpoint, a websocket connection can subscribe to the changes
+/// of the list with a `subscribeLobbyList` message.
 #[utoipa::path(
     tag = "Lobbies",
     context_path = "/api/v2",
@@ -57,13 +146,27 @@ pub struct GetLobbiesResponse {
         (status = 400, description = "Client error", body = ApiErrorResponse),
         (status = 500, description = "Server error", body = ApiErrorResponse),
     ),
+    params(GetLobbiesQuery, PaginationQuery),
     security(("session_cookie" = []))
 )]
 #[get("/lobbies")]
-pub async fn get_all_lobbies(db: Data<Database>) -> ApiResult<Json<GetLobbiesResponse>> {
+pub async fn get_all_lobbies(
+    filter: Query<GetLobbiesQuery>,
+    pagination: Query<PaginationQuery>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<GetLobbiesResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
     let mut tx = db.start_transaction().await?;
 
-    let lobbies = query!(
+    let page = query_lobby_page(&db, &mut tx, &filter, &pagination).await?;
+    if page.is_empty() {
+        tx.commit().await?;
+        return Ok(Json(GetLobbiesResponse { lobbies: vec![] }));
+    }
+
+    let mut lobbies: HashMap<Uuid, _> = query!(
         &mut tx,
         (
             Lobby::F.uuid,
@@ -75,78 +178,275 @@ pub async fn get_all_lobbies(db: Data<Database>) -> ApiResult<Json<GetLobbiesRes
             Lobby::F.max_player,
             Lobby::F.password_hash,
             Lobby::F.chat_room,
+            Lobby::F.ruleset,
+            Lobby::F.map_size,
+            Lobby::F.game_speed,
+            Lobby::F.restricted,
+            Lobby::F.allow_friends_of_owner,
         )
     )
+    .condition(DynamicCollection::or(
+        page.iter()
+            .map(|(lobby, _)| Lobby::F.uuid.equals(*lobby))
+            .collect(),
+    ))
     .all()
-    .await?;
+    .await?
+    .into_iter()
+    .map(|lobby| (lobby.0, lobby))
+    .collect();
+
+    let mut mods: HashMap<Uuid, Vec<String>> = HashMap::new();
+    for (lobby, name) in query!(&mut tx, (LobbyMod::F.lobby, LobbyMod::F.name))
+        .condition(DynamicCollection::or(
+            page.iter()
+                .map(|(lobby, _)| LobbyMod::F.lobby.equals(*lobby))
+                .collect(),
+        ))
+        .all()
+        .await?
+    {
+        mods.entry(*lobby.key()).or_default().push(name);
+    }
 
-    let mut lobbies: Vec<Lobby> = lobbies
+    // The lobbies the executing account is a member of
+    let joined: HashSet<Uuid> = query!(&mut tx, (LobbyAccount::F.lobby,))
+        .condition(LobbyAccount::F.player.equals(uuid))
+        .all()
+        .await?
         .into_iter()
-        .map(
-            |(
-                uuid,
-                o_uuid,
-                o_username,
-                o_display_name,
-                name,
-                created_at,
-                max_player,
-                password_hash,
-                chat_room_uuid,
-            )| Lobby {
-                uuid,
-                name,
-                current_player: BackRef { cached: None },
-                owner: ForeignModelByField::Instance(Box::new(Account {
-                    uuid: o_uuid,
-                    username: o_username,
-                    display_name: o_display_name,
-                    last_login: None,
-                    password_hash: String::new(),
-                    chat_rooms: BackRef { cached: None },
-                })),
-                created_at,
-                max_player,
-                password_hash,
-                chat_room: ForeignModelByField::Key(*chat_room_uuid.key()),
-            },
-        )
+        .map(|(lobby,)| *lobby.key())
         .collect();
 
-    Lobby::F
-        .current_player
-        .populate_bulk(&mut tx, &mut lobbies)
-        .await?;
+    let invited: HashSet<Uuid> = query!(&mut tx, (Invite::F.lobby,))
+        .condition(Invite::F.to.equals(uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(lobby,)| *lobby.key())
+        .collect();
+
+    // The restricted lobbies the executing account was allowed to join
+    let allowed: HashSet<Uuid> = query!(&mut tx, (LobbyAllowedAccount::F.lobby,))
+        .condition(LobbyAllowedAccount::F.account.equals(uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(lobby,)| *lobby.key())
+        .collect();
+
+    let banned: HashSet<Uuid> = query!(&mut tx, (LobbyBan::F.lobby,))
+        .condition(LobbyBan::F.account.equals(uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(lobby,)| *lobby.key())
+        .collect();
+
+    let friends: HashSet<Uuid> = query!(&mut tx, (Friend::F.to,))
+        .condition(and!(
+            Friend::F.is_request.equals(false),
+            Friend::F.from.equals(uuid)
+        ))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(friend,)| *friend.key())
+        .collect();
+
+    tx.commit().await?;
+
+    // Lobbies that were closed since the page was selected are skipped
+    let lobbies = page
+        .into_iter()
+        .filter_map(|(lobby, current_players)| Some((lobbies.remove(&lobby)?, current_players)))
+        .map(
+            |(
+                (
+                    lobby_uuid,
+                    owner_uuid,
+                    owner_username,
+                    owner_display_name,
+                    name,
+                    created_at,
+                    max_player,
+                    password_hash,
+                    chat_room,
+                    ruleset,
+                    map_size,
+                    game_speed,
+                    restricted,
+                    allow_friends_of_owner,
+                ),
+                current_players,
+            )| {
+                let is_joinable = current_players < max_player as i64
+                    && owner_uuid != uuid
+                    && !joined.contains(&lobby_uuid)
+                    && (password_hash.is_none() || invited.contains(&lobby_uuid))
+                    && !banned.contains(&lobby_uuid)
+                    && (!restricted
+                        || allowed.contains(&lobby_uuid)
+                        || (allow_friends_of_owner && friends.contains(&owner_uuid)));
 
-    Ok(Json(GetLobbiesResponse {
-        lobbies: lobbies
-            .into_iter()
-            .map(|l| {
-                let Some(owner) = l.owner.instance() else {
-                    unreachable!("Owner should be queried!")
-                };
-                // Ok as current_player is populated before
-                #[allow(clippy::unwrap_used)]
                 LobbyResponse {
-                    uuid: l.uuid,
-                    name: l.name,
+                    uuid: lobby_uuid,
+                    name,
                     owner: AccountResponse {
-                        uuid: owner.uuid,
-                        username: owner.username.clone(),
-                        display_name: owner.display_name.clone(),
+                        uuid: owner_uuid,
+                        username: owner_username,
+                        display_name: owner_display_name,
                     },
-                    current_players: l.current_player.cached.unwrap().len() as u8 + 1,
-                    max_players: l.max_player as u8,
-                    password: l.password_hash.is_some(),
-                    created_at: DateTime::from_naive_utc_and_offset(l.created_at, Utc),
-                    chat_room_uuid: *l.chat_room.key(),
+                    current_players: current_players as u8,
+                    max_players: max_player as u8,
+                    password: password_hash.is_some(),
+                    is_joinable,
+                    created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
+                    chat_room_uuid: *chat_room.key(),
+                    ruleset,
+                    map_size,
+                    game_speed,
+                    mods: mods.remove(&lobby_uuid).unwrap_or_default(),
+                    restricted,
                 }
-            })
-            .collect(),
-    }))
+            },
+        )
+        .collect();
+
+    Ok(Json(GetLobbiesResponse { lobbies }))
+}
+
+/// Select a page of the listed lobbies with their number of players
+///
+/// The filters, the order and the pagination of the request are applied by the database,
+/// which counts the members of the lobbies in the same query. The number of players
+/// includes the owner.
+async fn query_lobby_page(
+    db: &Database,
+    tx: &mut Transaction,
+    filter: &GetLobbiesQuery,
+    pagination: &PaginationQuery,
+) -> Result<Vec<(Uuid, i64)>, rorm::Error> {
+    let mut params: Vec<String> = Vec::new();
+    let mut param = |value: String| {
+        params.push(value);
+        format!("${}", params.len())
+    };
+
+    let mut conditions = vec!["lobby.hidden = FALSE".to_string()];
+    if let Some(search) = filter
+        .search
+        .as_deref()
+        .map(|x| x.trim().to_lowercase())
+        .filter(|x| !x.is_empty())
+    {
+        let pattern = search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        conditions.push(format!(
+            "LOWER(lobby.name) LIKE {} ESCAPE '\\'",
+            param(format!("%{pattern}%"))
+        ));
+    }
+    if filter.no_password {
+        conditions.push("lobby.password_hash IS NULL".to_string());
+    }
+    for (column, value) in [
+        ("ruleset", &filter.ruleset),
+        ("map_size", &filter.map_size),
+        ("game_speed", &filter.game_speed),
+    ] {
+        if let Some(value) = value {
+            conditions.push(format!(
+                "LOWER(lobby.{column}) = {}",
+                param(value.to_lowercase())
+            ));
+        }
+    }
+    if let Some(available_mods) = filter.available_mods.as_deref() {
+        let available: Vec<String> = available_mods
+            .split(',')
+            .map(|x| x.trim().to_lowercase())
+            .filter(|x| !x.is_empty())
+            .map(&mut param)
+            .collect();
+        let missing_mod = if available.is_empty() {
+            String::new()
+        } else {
+            format!(
+                " AND LOWER(lobbymod.name) NOT IN ({})",
+                available.join(", ")
+            )
+        };
+        conditions.push(format!(
+            "NOT EXISTS (SELECT 1 FROM lobbymod WHERE lobbymod.lobby = lobby.uuid{missing_mod})"
+        ));
+    }
+
+    let having = if filter.free_slots {
+        "HAVING COUNT(lobbyaccount.uuid) + 1 < lobby.max_player"
+    } else {
+        ""
+    };
+    let order = match filter.sort {
+        LobbySortOrder::Newest => "lobby.created_at DESC",
+        LobbySortOrder::Oldest => "lobby.created_at ASC",
+        LobbySortOrder::MostPlayers => "COUNT(lobbyaccount.uuid) DESC, lobby.created_at DESC",
+        LobbySortOrder::FewestPlayers => "COUNT(lobbyaccount.uuid) ASC, lobby.created_at DESC",
+    };
+
+    let sql = format!(
+        "SELECT lobby.uuid, COUNT(lobbyaccount.uuid) + 1 FROM lobby \
+         LEFT JOIN lobbyaccount ON lobbyaccount.lobby = lobby.uuid \
+         WHERE {} GROUP BY lobby.uuid {having} ORDER BY {order} LIMIT {} OFFSET {};",
+        conditions.join(" AND "),
+        pagination.limit(),
+        pagination.offset(),
+    );
+    let values: Vec<Value> = params.iter().map(|x| Value::String(x.as_str())).collect();
+
+    let mut page = Vec::new();
+    for row in db.raw_sql(&sql, Some(&values), Some(tx)).await? {
+        let lobby: Vec<u8> = row.get(0)?;
+        let current_players: i64 = row.get(1)?;
+        // Ok as the primary keys of lobbies are always uuids
+        #[allow(clippy::unwrap_used)]
+        page.push((Uuid::from_slice(&lobby).unwrap(), current_players));
+    }
+    Ok(page)
+}
+
+/// The nation a member of a lobby or a player of a game has chosen
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct PlayerNation {
+    pub(crate) player_uuid: Uuid,
+    #[schema(example = "Babylon")]
+    pub(crate) nation: String,
 }
 
 /// A single lobby
+///
+/// `ready_players` contains the uuids of all joined players that are ready,
+/// the owner is always considered ready. If `require_ready` is set, the game can
+/// only be started if all players are ready.
+///
+/// If `auto_start` is set, the game is started automatically once the lobby is full.
+///
+/// `join_code` is only set for hidden lobbies and only visible to the owner and the
+/// joined players.
+///
+/// If `restricted` is set, only the `allowed_players` and, if `allow_friends_of_owner`
+/// is set, the friends of the owner may join the lobby. `allowed_players` is only
+/// visible to the owner and the co-hosts.
+///
+/// `co_hosts` contains the joined players the owner made co-hosts. They may change the
+/// settings of the lobby and kick players that are not co-hosts.
+///
+/// `nations` contains the nations the members have chosen, members without a choice
+/// are left out. If `hidden_identities` is set, it only contains your own nation.
+///
+/// `online` of the owner and the players is set if they have an active connection.
 #[derive(Serialize, ToSchema)]
 pub struct GetLobbyResponse {
     uuid: Uuid,
@@ -154,11 +454,59 @@ pub struct GetLobbyResponse {
     name: String,
     #[schema(example = 4)]
     max_players: u8,
+    #[schema(example = 2)]
+    min_players: Option<u8>,
     created_at: DateTime<Utc>,
     password: bool,
-    owner: AccountResponse,
-    current_players: Vec<AccountResponse>,
+    owner: OnlineAccountResponse,
+    current_players: Vec<OnlineAccountResponse>,
+    co_hosts: Vec<Uuid>,
     chat_room_uuid: Uuid,
+    require_ready: bool,
+    auto_start: bool,
+    ready_players: Vec<Uuid>,
+    hidden: bool,
+    #[schema(example = "K7QM2XPA")]
+    join_code: Option<String>,
+    #[schema(example = "Civ V - Gods & Kings")]
+    ruleset: Option<String>,
+    #[schema(example = "Medium")]
+    map_size: Option<String>,
+    #[schema(example = "Quick")]
+    game_speed: Option<String>,
+    mods: Vec<String>,
+    restricted: bool,
+    allow_friends_of_owner: bool,
+    allowed_players: Vec<Uuid>,
+    nations: Vec<PlayerNation>,
+    hidden_identities: bool,
+}
+
+impl GetLobbyResponse {
+    /// Check if the account owns or joined the lobby
+    fn is_member(&self, account: Uuid) -> bool {
+        self.owner.uuid == account || self.current_players.iter().any(|x| x.uuid == account)
+    }
+
+    /// The owner and the joined players of the lobby
+    ///
+    /// Their online states can be filled in with [fill_online_states].
+    fn accounts_mut(&mut self) -> impl Iterator<Item = &mut OnlineAccountResponse> {
+        iter::once(&mut self.owner).chain(self.current_players.iter_mut())
+    }
+
+    /// Remove the fields the account is not allowed to see
+    fn hide_private_fields(&mut self, account: Uuid) {
+        if !self.is_member(account) {
+            self.join_code = None;
+        }
+        if self.owner.uuid != account && !self.co_hosts.contains(&account) {
+            self.allowed_players.clear();
+        }
+        if self.hidden_identities {
+            self.nations.retain(|x| x.player_uuid == account);
+        }
+    }
 }
 
 /// Retrieves an open lobbies.
@@ -179,10 +527,153 @@ pub struct GetLobbyResponse {
 pub async fn get_lobby(
     path: Path<PathUuid>,
     db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<GetLobbyResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    let mut lobby = query_lobby(&mut tx, path.uuid)
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    tx.commit().await?;
+
+    lobby.hide_private_fields(uuid);
+    fill_online_states(notifier.get_ref(), lobby.accounts_mut()).await?;
+
+    Ok(Json(lobby))
+}
+
+/// The path parameter of a join code
+#[derive(Deserialize, IntoParams)]
+pub struct JoinCodePath {
+    join_code: String,
+}
+
+/// Retrieves a hidden lobby by its join code.
+///
+/// The lobby can then be joined using `POST /api/v2/lobbies/{uuid}/join` with the join code.
+/// Join codes are case-insensitive.
+///
+/// If no lobby has the join code, [ApiError::InvalidJoinCode] is returned.
+#[utoipa::path(
+    tag = "Lobbies",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the lobby with the join code", body = GetLobbyResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(JoinCodePath),
+    security(("session_cookie" = []))
+)]
+#[get("/lobbies/code/{join_code}")]
+pub async fn get_lobby_by_join_code(
+    path: Path<JoinCodePath>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<Json<GetLobbyResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    let (lobby_uuid,) = query!(&mut tx, (Lobby::F.uuid,))
+        .condition(Lobby::F.join_code.equals(path.join_code.to_uppercase()))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidJoinCode)?;
+
+    let mut lobby = query_lobby(&mut tx, lobby_uuid)
+        .await?
+        .ok_or(ApiError::InvalidJoinCode)?;
+
+    tx.commit().await?;
+
+    lobby.hide_private_fields(uuid);
+    fill_online_states(notifier.get_ref(), lobby.accounts_mut()).await?;
+
+    Ok(Json(lobby))
+}
+
+/// The characters a join code is made of
+///
+/// Characters that are easily confused with each other are left out.
+const JOIN_CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// Generate a new random join code for a hidden lobby
+fn generate_join_code() -> String {
+    let mut rng = thread_rng();
+    (0..8)
+        .map(|_| JOIN_CODE_CHARS[rng.gen_range(0..JOIN_CODE_CHARS.len())] as char)
+        .collect()
+}
+
+/// The lobbies the executing account is part of
+#[derive(Serialize, ToSchema)]
+pub struct GetMyLobbiesResponse {
+    lobbies: Vec<GetLobbyResponse>,
+}
+
+/// Retrieves the lobbies the executing account owns or has joined.
+///
+/// This can be used to resync the lobby state, e.g. after a crash of the client.
+/// Owned lobbies are listed before joined lobbies.
+///
+/// If the executing account is not part of any lobby, [ApiError::NotInALobby] is returned.
+#[utoipa::path(
+    tag = "Lobbies",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the lobbies of the executing account", body = GetMyLobbiesResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    security(("session_cookie" = []))
+)]
+#[get("/lobbies/me")]
+pub async fn get_my_lobbies(
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<GetMyLobbiesResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
     let mut tx = db.start_transaction().await?;
 
-    let (
+    let lobby_uuids = lobbies_of(&mut tx, uuid).await?;
+
+    let mut lobbies = Vec::with_capacity(lobby_uuids.len());
+    for lobby_uuid in lobby_uuids {
+        if let Some(mut lobby) = query_lobby(&mut tx, lobby_uuid).await? {
+            lobby.hide_private_fields(uuid);
+            lobbies.push(lobby);
+        }
+    }
+
+    tx.commit().await?;
+
+    if lobbies.is_empty() {
+        return Err(ApiError::NotInALobby);
+    }
+
+    fill_online_states(
+        notifier.get_ref(),
+        lobbies.iter_mut().flat_map(GetLobbyResponse::accounts_mut),
+    )
+    .await?;
+
+    Ok(Json(GetMyLobbiesResponse { lobbies }))
+}
+
+/// Query a lobby including its owner and joined players
+async fn query_lobby(
+    tx: &mut Transaction,
+    lobby_uuid: Uuid,
+) -> ApiResult<Option<GetLobbyResponse>> {
+    let Some((
         uuid,
         owner_uuid,
         owner_username,
@@ -190,10 +681,22 @@ pub async fn get_lobby(
         name,
         created_at,
         max_player,
+        min_player,
         password_hash,
         chat_room_uuid,
-    ) = query!(
-        &mut tx,
+        require_ready,
+        auto_start,
+        hidden,
+        join_code,
+        ruleset,
+        map_size,
+        game_speed,
+        restricted,
+        allow_friends_of_owner,
+        owner_nation,
+        hidden_identities,
+    )) = query!(
+        &mut *tx,
         (
             Lobby::F.uuid,
             Lobby::F.owner.uuid,
@@ -202,57 +705,152 @@ pub async fn get_lobby(
             Lobby::F.name,
             Lobby::F.created_at,
             Lobby::F.max_player,
+            Lobby::F.min_player,
             Lobby::F.password_hash,
             Lobby::F.chat_room.uuid,
+            Lobby::F.require_ready,
+            Lobby::F.auto_start,
+            Lobby::F.hidden,
+            Lobby::F.join_code,
+            Lobby::F.ruleset,
+            Lobby::F.map_size,
+            Lobby::F.game_speed,
+            Lobby::F.restricted,
+            Lobby::F.allow_friends_of_owner,
+            Lobby::F.owner_nation,
+            Lobby::F.hidden_identities,
         )
     )
-    .condition(Lobby::F.uuid.equals(path.uuid))
+    .condition(Lobby::F.uuid.equals(lobby_uuid))
     .optional()
     .await?
-    .ok_or(ApiError::InvalidUuid)?;
+    else {
+        return Ok(None);
+    };
 
     let current_players = query!(
-        &mut tx,
+        &mut *tx,
         (
             LobbyAccount::F.player.uuid,
             LobbyAccount::F.player.username,
             LobbyAccount::F.player.display_name,
+            LobbyAccount::F.ready,
+            LobbyAccount::F.nation,
+            LobbyAccount::F.co_host,
         )
     )
     .condition(LobbyAccount::F.lobby.equals(uuid))
     .all()
     .await?;
 
-    tx.commit().await?;
+    let mods = query!(&mut *tx, (LobbyMod::F.name,))
+        .condition(LobbyMod::F.lobby.equals(uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(name,)| name)
+        .collect();
+
+    let allowed_players = query!(&mut *tx, (LobbyAllowedAccount::F.account,))
+        .condition(LobbyAllowedAccount::F.lobby.equals(uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(account,)| *account.key())
+        .collect();
+
+    let ready_players = iter::once(owner_uuid)
+        .chain(
+            current_players
+                .iter()
+                .filter(|(_, _, _, ready, _, _)| *ready)
+                .map(|(uuid, _, _, _, _, _)| *uuid),
+        )
+        .collect();
+
+    let nations = iter::once((owner_uuid, owner_nation))
+        .chain(
+            current_players
+                .iter()
+                .map(|(uuid, _, _, _, nation, _)| (*uuid, nation.clone())),
+        )
+        .filter_map(|(player_uuid, nation)| {
+            nation.map(|nation| PlayerNation {
+                player_uuid,
+                nation,
+            })
+        })
+        .collect();
+
+    let co_hosts = current_players
+        .iter()
+        .filter(|(_, _, _, _, _, co_host)| *co_host)
+        .map(|(uuid, _, _, _, _, _)| *uuid)
+        .collect();
 
-    // Ok as current_player is populated before
-    #[allow(clippy::unwrap_used)]
-    Ok(Json(GetLobbyResponse {
+    Ok(Some(GetLobbyResponse {
         uuid,
         name,
-        owner: AccountResponse {
+        owner: OnlineAccountResponse::offline(AccountResponse {
             uuid: owner_uuid,
             username: owner_username,
             display_name: owner_display_name,
-        },
+        }),
         current_players: current_players
             .into_iter()
-            .map(|(uuid, username, display_name)| AccountResponse {
-                uuid,
-                username,
-                display_name,
+            .map(|(uuid, username, display_name, _, _, _)| {
+                OnlineAccountResponse::offline(AccountResponse {
+                    uuid,
+                    username,
+                    display_name,
+                })
             })
             .collect(),
+        co_hosts,
         max_players: max_player as u8,
+        min_players: min_player.map(|x| x as u8),
         password: password_hash.is_some(),
         created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
         chat_room_uuid,
+        require_ready,
+        auto_start,
+        ready_players,
+        hidden,
+        join_code,
+        ruleset,
+        map_size,
+        game_speed,
+        mods,
+        restricted,
+        allow_friends_of_owner,
+        allowed_players,
+        nations,
+        hidden_identities,
     }))
 }
 
 /// The parameters to create a lobby
 ///
 /// `max_players` must be greater or equals 2
+///
+/// `min_players` is the number of players, including the owner, that is required to
+/// start the game. It must be between 2 and `max_players` (inclusive).
+///
+/// `turn_timer`, `allow_spectators`, `allow_late_joins` and `public_game` are the settings
+/// of the game that is started from the lobby. `turn_timer` is specified in seconds.
+///
+/// `ruleset`, `map_size`, `game_speed` and `mods` describe the setup of the game, so
+/// other clients can check whether they are able to play it before joining.
+///
+/// If `allowed_players` is set, the lobby is restricted and only these accounts may
+/// join it. If `allow_friends_of_owner` is set as well, the friends of the owner may
+/// join the restricted lobby, too.
+///
+/// `invite_friends` are the friends that are invited to the lobby right away.
+///
+/// If `hidden_identities` is set, the members only see their own nation, in the lobby as
+/// well as in the game started from it, until the host of the game reveals all nations.
+/// The setting can't be changed once the lobby was created.
 #[derive(Deserialize, ToSchema)]
 pub struct CreateLobbyRequest {
     #[schema(example = "Herbert's lobby")]
@@ -261,91 +859,337 @@ pub struct CreateLobbyRequest {
     password: Option<String>,
     #[schema(example = 4)]
     max_players: u8,
+    #[schema(example = 2)]
+    min_players: Option<u8>,
+    #[schema(example = 86400)]
+    turn_timer: Option<u32>,
+    #[serde(default)]
+    allow_spectators: bool,
+    #[serde(default)]
+    allow_late_joins: bool,
+    #[serde(default = "default_public_game")]
+    public_game: bool,
+    #[serde(default)]
+    require_ready: bool,
+    #[serde(default)]
+    auto_start: bool,
+    #[serde(default)]
+    hidden: bool,
+    #[schema(example = "Civ V - Gods & Kings")]
+    ruleset: Option<String>,
+    #[schema(example = "Medium")]
+    map_size: Option<String>,
+    #[schema(example = "Quick")]
+    game_speed: Option<String>,
+    #[serde(default)]
+    mods: Vec<String>,
+    allowed_players: Option<Vec<Uuid>>,
+    #[serde(default)]
+    allow_friends_of_owner: bool,
+    #[serde(default)]
+    invite_friends: Vec<Uuid>,
+    #[serde(default)]
+    hidden_identities: bool,
 }
 
-/// The response of a create lobby request.
-///
-/// It contains the uuid of the created lobby and the uuid of the created chatroom for the lobby
-#[derive(Serialize, ToSchema)]
-pub struct CreateLobbyResponse {
-    lobby_uuid: Uuid,
-    lobby_chat_room_uuid: Uuid,
+impl CreateLobbyRequest {
+    /// Check that the game setup of the lobby is within the limits of the database
+    fn validate_game_setup(&self) -> ApiResult<()> {
+        let valid = |x: &str| !x.trim().is_empty() && x.len() <= 255;
+
+        if [&self.ruleset, &self.map_size, &self.game_speed]
+            .into_iter()
+            .flatten()
+            .any(|x| !valid(x))
+            || self.mods.len() > MAX_LOBBY_MODS
+            || self.mods.iter().any(|x| !valid(x))
+        {
+            return Err(ApiError::InvalidGameSettings);
+        }
+
+        Ok(())
+    }
 }
 
-/// Create a new lobby
-///
-/// If you are already in another lobby, an error is returned.
-/// `max_players` must be between 2 and 34 (inclusive).
-/// If `password` is an empty string, an error is returned.
-/// If you are not connected via websocket, an error is returned.
+/// Check that a lobby complies with the lobby policy of the server
 ///
-/// You are placed in the lobby and in the corresponding chatroom
-#[utoipa::path(
-    tag = "Lobbies",
-    context_path = "/api/v2",
-    responses(
-        (status = 200, description = "Lobby got created", body = CreateLobbyResponse),
-        (status = 400, description = "Client error", body = ApiErrorResponse),
-        (status = 500, description = "Server error", body = ApiErrorResponse),
-    ),
-    request_body = CreateLobbyRequest,
-    security(("session_cookie" = []))
-)]
-#[post("/lobbies")]
-pub async fn create_lobby(
-    req: Json<CreateLobbyRequest>,
-    db: Data<Database>,
-    session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
-) -> ApiResult<Json<CreateLobbyResponse>> {
-    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+/// Only the specified settings are checked, so this can be used for updates, too.
+fn check_lobby_policy(
+    settings: &RuntimeSettings,
+    max_players: Option<u8>,
+    password: Option<bool>,
+    mods: &[String],
+) -> ApiResult<()> {
+    if let Some(max_players) = max_players {
+        if max_players > settings.max_lobby_players {
+            return Err(ApiError::LobbyPolicyViolation(format!(
+                "The server allows at most {} players per lobby",
+                settings.max_lobby_players
+            )));
+        }
+    }
 
-    let mut tx = db.start_transaction().await?;
+    if settings.require_lobby_password && password == Some(false) {
+        return Err(ApiError::LobbyPolicyViolation(
+            "The server requires a password for every lobby".to_string(),
+        ));
+    }
+
+    if let Some(allowed_mods) = &settings.allowed_mods {
+        if let Some(name) = mods
+            .iter()
+            .find(|x| !allowed_mods.iter().any(|y| y.eq_ignore_ascii_case(x)))
+        {
+            return Err(ApiError::LobbyPolicyViolation(format!(
+                "The mod '{name}' is not allowed on this server"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Invite friends of the owner to a new lobby
+///
+/// Returns [ApiError::InvalidFriendState] if one of the accounts is not a friend of the owner.
+async fn invite_friends(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    lobby: Uuid,
+    owner: Uuid,
+    friends: &[Uuid],
+) -> ApiResult<()> {
+    let (uuid, username, display_name) = query!(
+        &mut *tx,
+        (
+            Account::F.uuid,
+            Account::F.username,
+            Account::F.display_name
+        )
+    )
+    .condition(Account::F.uuid.equals(owner))
+    .optional()
+    .await?
+    .ok_or(ApiError::SessionCorrupt)?;
+    let from = AccountResponse {
+        uuid,
+        username,
+        display_name,
+    };
+
+    let friends: HashSet<Uuid> = friends.iter().copied().collect();
+    for friend in friends {
+        // Check if there's a valid friendship
+        query!(&mut *tx, (Friend::F.uuid,))
+            .condition(and!(
+                Friend::F.is_request.equals(false),
+                Friend::F.from.equals(owner),
+                Friend::F.to.equals(friend)
+            ))
+            .optional()
+            .await?
+            .ok_or(ApiError::InvalidFriendState)?;
+
+        let invite_uuid = insert!(&mut *tx, InviteInsert)
+            .return_primary_key()
+            .single(&InviteInsert {
+                uuid: Uuid::new_v4(),
+                from: ForeignModelByField::Key(owner),
+                to: ForeignModelByField::Key(friend),
+                lobby: ForeignModelByField::Key(lobby),
+            })
+            .await?;
+
+        notifications.notify(
+            friend,
+            WsMessage::IncomingInvite {
+                invite_uuid,
+                from: from.clone(),
+                lobby_uuid: lobby,
+            },
+        );
+    }
+
+    Ok(())
+}
+
+/// Replace the accounts that may join a restricted lobby
+///
+/// Returns [ApiError::InvalidUuid] if one of the accounts doesn't exist.
+async fn set_allowed_players(
+    tx: &mut Transaction,
+    lobby: Uuid,
+    allowed_players: &[Uuid],
+) -> ApiResult<()> {
+    let allowed_players: HashSet<Uuid> = allowed_players.iter().copied().collect();
+
+    for account in &allowed_players {
+        query!(&mut *tx, (Account::F.uuid,))
+            .condition(Account::F.uuid.equals(*account))
+            .optional()
+            .await?
+            .ok_or(ApiError::InvalidUuid)?;
+    }
+
+    rorm::delete!(&mut *tx, LobbyAllowedAccount)
+        .condition(LobbyAllowedAccount::F.lobby.equals(lobby))
+        .await?;
+
+    if !allowed_players.is_empty() {
+        insert!(&mut *tx, LobbyAllowedAccountInsert)
+            .return_nothing()
+            .bulk(
+                &allowed_players
+                    .into_iter()
+                    .map(|account| LobbyAllowedAccountInsert {
+                        uuid: Uuid::new_v4(),
+                        lobby: ForeignModelByField::Key(lobby),
+                        account: ForeignModelByField::Key(account),
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// The maximum number of mods a lobby may require
+const MAX_LOBBY_MODS: usize = 64;
+
+fn default_public_game() -> bool {
+    true
+}
+
+/// The response of a create lobby request.
+///
+/// It contains the uuid of the created lobby and the uuid of the created chatroom for the lobby.
+/// If the lobby is hidden, it also contains the join code of the lobby.
+#[derive(Serialize, ToSchema)]
+pub struct CreateLobbyResponse {
+    lobby_uuid: Uuid,
+    lobby_chat_room_uuid: Uuid,
+    #[schema(example = "K7QM2XPA")]
+    join_code: Option<String>,
+}
+
+/// Create a new lobby
+///
+/// If you are already in another lobby, [ApiError::AlreadyInALobby] is returned. If you
+/// own as many lobbies as the server allows per account (one by default),
+/// [ApiError::LobbyQuotaReached] is returned.
+/// `max_players` must be between 2 and 34 (inclusive).
+/// `min_players` is optional and must be between 2 and `max_players` (inclusive),
+/// otherwise [ApiError::InvalidMinPlayersCount] is returned. The game can only be
+/// started once at least `min_players` members, including the owner, are in the lobby.
+/// If `password` is an empty string, an error is returned.
+/// If you are not connected via websocket, an error is returned.
+/// If `require_ready` is set, the game can only be started once all players are ready.
+/// If `auto_start` is set, the game is started on behalf of you once the last free slot
+/// of the lobby is taken, see `POST /api/v2/lobbies/{uuid}/join`.
+/// If `hidden` is set, the lobby is not listed in `GET /api/v2/lobbies` and can only be
+/// joined by an invite or with the join code that is returned.
+/// If one of the game setup values is empty or longer than 255 characters, or more than
+/// 64 mods are required, [ApiError::InvalidGameSettings] is returned.
+/// If the lobby violates the lobby policy of the server, e.g. it has more players than the
+/// server allows or requires a mod that is not allowed, [ApiError::LobbyPolicyViolation]
+/// is returned. The policy can be retrieved from `GET /api/v2/capabilities`.
+/// If `allowed_players` contains an unknown account, [ApiError::InvalidUuid] is returned.
+/// Restricted lobbies can't be joined by other accounts, regardless of the password
+/// or an invite.
+/// If one of `invite_friends` is not a friend of you, [ApiError::InvalidFriendState] is
+/// returned and no lobby is created. Otherwise, every friend receives an invite and a
+/// [WsMessage::IncomingInvite] message. The invites are never accepted automatically.
+/// If the server already hosts its configured maximum of open lobbies,
+/// [ApiError::ServerAtCapacity] is returned.
+/// If the policy validator of the server rejects the lobby, [ApiError::Policy] is returned.
+///
+/// You are placed in the lobby and in the corresponding chatroom
+#[utoipa::path(
+    tag = "Lobbies",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Lobby got created", body = CreateLobbyResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    request_body = CreateLobbyRequest,
+    security(("session_cookie" = []))
+)]
+#[post("/lobbies")]
+pub async fn create_lobby(
+    req: Json<CreateLobbyRequest>,
+    db: Data<Database>,
+    session: Session,
+    settings: Data<RuntimeSettings>,
+    notifier: Data<dyn Notifier>,
+    policy_validator: Data<Option<PolicyValidator>>,
+) -> ApiResult<Json<CreateLobbyResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    check_policy(
+        &policy_validator,
+        PolicyAction::CreateLobby {
+            account: uuid,
+            name: &req.name,
+            max_players: req.max_players,
+            mods: &req.mods,
+        },
+    )
+    .await?;
+
+    let mut tx = db.start_transaction().await?;
 
     // Check if the request is valid
     if req.max_players < 2 || req.max_players > 34 {
         return Err(ApiError::InvalidMaxPlayersCount);
     }
-
-    // Check if the websocket of the executing user is connected
-    let (sender, receiver) = oneshot::channel();
-    if let Err(err) = ws_manager_chan
-        .send(WsManagerMessage::RetrieveOnlineState(uuid, sender))
-        .await
+    if req
+        .min_players
+        .is_some_and(|x| !(2..=req.max_players).contains(&x))
     {
-        warn!("Could not send to ws manager chan: {err}");
-        return Err(ApiError::InternalServerError);
+        return Err(ApiError::InvalidMinPlayersCount);
     }
+    let turn_timer = validate_turn_timer(req.turn_timer)?;
+    req.validate_game_setup()?;
+    check_lobby_policy(
+        &settings,
+        Some(req.max_players),
+        Some(req.password.is_some()),
+        &req.mods,
+    )?;
 
-    match receiver.await {
-        Ok(online) => {
-            if !online {
-                return Err(ApiError::WsNotConnected);
-            }
-        }
-        Err(err) => {
-            warn!("Error receiving online state: {err}");
-            return Err(ApiError::InternalServerError);
-        }
+    // Check if the websocket of the executing user is connected
+    if !notifier.is_online(uuid).await? {
+        return Err(ApiError::WsNotConnected);
     }
 
     // Check if the executing account is already in a lobby
-    if query!(&mut tx, (LobbyAccount::F.uuid,))
-        .condition(LobbyAccount::F.player.equals(uuid))
-        .optional()
-        .await?
-        .is_some()
+    if !settings.allow_multiple_lobbies
+        && query!(&mut tx, (LobbyAccount::F.uuid,))
+            .condition(LobbyAccount::F.player.equals(uuid))
+            .optional()
+            .await?
+            .is_some()
     {
         return Err(ApiError::AlreadyInALobby);
     }
 
-    if query!(&mut tx, (Lobby::F.uuid,))
+    // Check if the executing account may own another lobby
+    let (owned_lobbies,) = query!(&mut tx, (Lobby::F.uuid.count(),))
         .condition(Lobby::F.owner.equals(uuid))
-        .optional()
-        .await?
-        .is_some()
-    {
-        return Err(ApiError::AlreadyInALobby);
+        .one()
+        .await?;
+    if owned_lobbies >= settings.max_owned_lobbies as i64 {
+        return Err(ApiError::LobbyQuotaReached);
+    }
+
+    // Check if the server may host another lobby
+    if settings.max_open_lobbies > 0 {
+        let (open_lobbies,) = query!(&mut tx, (Lobby::F.uuid.count(),)).one().await?;
+        if open_lobbies as u64 >= settings.max_open_lobbies {
+            return Err(ApiError::ServerAtCapacity);
+        }
     }
 
     // Hash the password
@@ -383,7 +1227,10 @@ pub async fn create_lobby(
         })
         .await?;
 
+    let join_code = req.hidden.then(generate_join_code);
+
     // Create lobby
+    let owner = uuid;
     let uuid = insert!(&mut tx, LobbyInsert)
         .return_primary_key()
         .single(&LobbyInsert {
@@ -391,16 +1238,62 @@ pub async fn create_lobby(
             name: req.name.clone(),
             password_hash: pw_hash,
             max_player: req.max_players as i16,
+            min_player: req.min_players.map(i16::from),
             owner: ForeignModelByField::Key(uuid),
             chat_room: ForeignModelByField::Key(chat_room_uuid),
+            turn_timer,
+            allow_spectators: req.allow_spectators,
+            allow_late_joins: req.allow_late_joins,
+            public_game: req.public_game,
+            require_ready: req.require_ready,
+            auto_start: req.auto_start,
+            hidden: req.hidden,
+            join_code: join_code.clone(),
+            ruleset: req.ruleset.clone(),
+            map_size: req.map_size.clone(),
+            game_speed: req.game_speed.clone(),
+            restricted: req.allowed_players.is_some(),
+            allow_friends_of_owner: req.allow_friends_of_owner,
+            hidden_identities: req.hidden_identities,
         })
         .await?;
 
+    if let Some(allowed_players) = &req.allowed_players {
+        set_allowed_players(&mut tx, uuid, allowed_players).await?;
+    }
+
+    let mut outbox = Outbox::new();
+    if !req.invite_friends.is_empty() {
+        invite_friends(&mut tx, &mut outbox, uuid, owner, &req.invite_friends).await?;
+    }
+
+    // Attach the required mods
+    if !req.mods.is_empty() {
+        insert!(&mut tx, LobbyModInsert)
+            .return_nothing()
+            .bulk(
+                &req.mods
+                    .iter()
+                    .map(|name| LobbyModInsert {
+                        uuid: Uuid::new_v4(),
+                        lobby: ForeignModelByField::Key(uuid),
+                        name: name.clone(),
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .await?;
+    }
+
+    lobby_list::announce(&mut tx, &mut outbox, uuid, LobbyListChange::Created).await?;
+
     tx.commit().await?;
 
+    outbox.send(notifier.get_ref()).await;
+
     Ok(Json(CreateLobbyResponse {
         lobby_uuid: uuid,
         lobby_chat_room_uuid: chat_room_uuid,
+        join_code,
     }))
 }
 
@@ -422,6 +1315,18 @@ pub struct StartGameResponse {
 /// members of the lobby to inform them which lobby was started. It also contains the the new and
 /// old chatroom uuids to make mapping for the clients easier.
 ///
+/// The lobby owner becomes the host of the game and the game settings chosen in the lobby
+/// are applied to the game.
+///
+/// If the lobby requires all players to be ready and a player is not ready yet,
+/// a [ApiError::PlayersNotReady] error is returned.
+///
+/// If the lobby has less members than its `min_players`, a [ApiError::NotEnoughPlayers]
+/// error is returned.
+///
+/// If the server already hosts its configured maximum of running games,
+/// a [ApiError::ServerAtCapacity] error is returned.
+///
 /// After the game started, the lobby owner must use the `PUT /api/v2/games/{uuid}` endpoint to
 /// upload the initial game state.
 ///
@@ -446,134 +1351,246 @@ pub async fn start_game(
     path: Path<PathUuid>,
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    settings: Data<RuntimeSettings>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<Json<StartGameResponse>> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
     let mut tx = db.start_transaction().await?;
 
-    let mut lobby = query!(&mut tx, Lobby)
-        .condition(Lobby::F.uuid.equals(path.uuid))
-        .optional()
-        .await?
-        .ok_or(ApiError::InvalidUuid)?;
+    game::check_capacity(&mut tx, settings.max_running_games).await?;
 
-    Lobby::F
-        .current_player
-        .populate(&mut tx, &mut lobby)
-        .await?;
+    let mut outbox = Outbox::new();
+    let game = game::start_game(&mut tx, &mut outbox, path.uuid, uuid).await?;
 
-    // Check if the executing user owns the lobby
-    if *lobby.owner.key() != uuid {
-        return Err(ApiError::MissingPrivileges);
-    }
+    tx.commit().await?;
 
-    // Create chatroom for the game
-    let game_chat_uuid = insert!(&mut tx, ChatRoomInsert)
-        .return_primary_key()
-        .single(&ChatRoomInsert {
-            uuid: Uuid::new_v4(),
-            last_message_uuid: None,
-        })
-        .await?;
+    outbox.send(notifier.get_ref()).await;
 
-    // Move messages from lobby chat to game chat
-    update!(&mut tx, ChatRoomMessage)
-        .condition(ChatRoomMessage::F.chat_room.equals(*lobby.chat_room.key()))
-        .set(
-            ChatRoomMessage::F.chat_room,
-            ForeignModelByField::Key(game_chat_uuid),
-        )
+    Ok(Json(StartGameResponse {
+        game_uuid: game.game_uuid,
+        game_chat_uuid: game.game_chat_uuid,
+    }))
+}
+
+/// The request to change the ready state in a lobby
+#[derive(Deserialize, ToSchema)]
+pub struct SetReadyRequest {
+    ready: bool,
+}
+
+/// Change the ready state of the executing user in a lobby
+///
+/// The executing user must be a joined player of the lobby. The owner of a lobby
+/// is always considered ready.
+/// Changing the ready state counts as activity of the lobby, so it isn't closed as idle.
+///
+/// On success, the owner and all other players of the lobby receive a
+/// [WsMessage::LobbyPlayerReady] message.
+///
+/// If the lobby has `auto_start` set and is full, becoming ready starts the game once
+/// all players are ready, as described in `POST /api/v2/lobbies/{uuid}/join`.
+#[utoipa::path(
+    tag = "Lobbies",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Ready state was changed"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = SetReadyRequest,
+    security(("session_cookie" = []))
+)]
+#[post("/lobbies/{uuid}/ready")]
+pub async fn set_lobby_ready(
+    path: Path<PathUuid>,
+    req: Json<SetReadyRequest>,
+    db: Data<Database>,
+    session: Session,
+    settings: Data<RuntimeSettings>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    let members = LobbyMembers::query(&mut tx, path.uuid)
+        .await?
+        .ok_or(ApiError::InvalidLobbyUuid)?;
+
+    if !members.is_player(uuid) {
+        return Err(ApiError::NotInALobby);
+    }
+
+    update!(&mut tx, LobbyAccount)
+        .condition(and!(
+            LobbyAccount::F.lobby.equals(path.uuid),
+            LobbyAccount::F.player.equals(uuid)
+        ))
+        .set(LobbyAccount::F.ready, req.ready)
         .exec()
         .await?;
 
-    // Move chatroom member to new chatroom
-    update!(&mut tx, ChatRoomMember)
-        .condition(ChatRoomMember::F.chat_room.equals(*lobby.chat_room.key()))
-        .set(
-            ChatRoomMember::F.chat_room,
-            ForeignModelByField::Key(game_chat_uuid),
-        )
+    // Changing the ready state keeps the lobby from being closed as idle
+    update!(&mut tx, Lobby)
+        .condition(Lobby::F.uuid.equals(path.uuid))
+        .set(Lobby::F.last_activity, Utc::now().naive_utc())
         .exec()
         .await?;
 
-    // Create new game and attach lobby chat
-    let game_uuid = insert!(&mut tx, GameInsert)
-        .return_primary_key()
-        .single(&GameInsert {
-            uuid: Uuid::new_v4(),
-            chat_room: ForeignModelByField::Key(game_chat_uuid),
-            max_players: lobby.max_player,
-            name: lobby.name,
-            updated_by: ForeignModelByField::Key(uuid),
-        })
-        .await?;
+    let mut outbox = Outbox::new();
+    outbox.notify_all(
+        members.members().filter(|x| *x != uuid),
+        WsMessage::LobbyPlayerReady {
+            lobby_uuid: path.uuid,
+            player_uuid: uuid,
+            ready: req.ready,
+        },
+    );
 
-    // Retrieve players from lobby
-    let player: Vec<Uuid> = if let Some(lobby_player) = lobby.current_player.cached {
-        lobby_player
-            .into_iter()
-            .map(|x: LobbyAccount| *x.player.key())
-            .collect()
-    } else {
-        error!("Cache of populated field current_player was empty");
-        return Err(ApiError::InternalServerError);
-    };
+    if req.ready {
+        game::auto_start(&mut tx, &mut outbox, path.uuid, settings.max_running_games).await?;
+    }
 
-    // Attach all players from lobby to game
-    insert!(&mut tx, GameAccountInsert)
-        .return_nothing()
-        .bulk(
-            &player
-                .iter()
-                .map(|x| GameAccountInsert {
-                    uuid: Uuid::new_v4(),
-                    game: ForeignModelByField::Key(game_uuid),
-                    player: ForeignModelByField::Key(*x),
-                })
-                .collect::<Vec<_>>(),
-        )
-        .await?;
+    tx.commit().await?;
 
-    // Attach owner
-    insert!(&mut tx, GameAccountInsert)
-        .return_nothing()
-        .single(&GameAccountInsert {
-            uuid: Uuid::new_v4(),
-            game: ForeignModelByField::Key(game_uuid),
-            player: ForeignModelByField::Key(*lobby.owner.key()),
-        })
-        .await?;
+    outbox.send(notifier.get_ref()).await;
 
-    // Delete lobby
-    rorm::delete!(&mut tx, Lobby)
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The request to choose a nation in a lobby
+///
+/// Set `nation` to `null` to clear the choice.
+#[derive(Deserialize, ToSchema)]
+pub struct SetNationRequest {
+    #[schema(example = "Babylon")]
+    nation: Option<String>,
+}
+
+/// Choose the nation the executing user wants to play in a lobby
+///
+/// The executing user must be the owner or a joined player of the lobby.
+/// A nation can only be chosen by one member of the lobby, names are compared
+/// case-insensitively. If another member has already chosen the nation,
+/// [ApiError::NationAlreadyTaken] is returned. If the nation is empty or longer than
+/// 255 characters, [ApiError::InvalidGameSettings] is returned.
+///
+/// Lobbies that hide the identities of their members accept any nation, as the error
+/// would tell the executing user which nations the others have chosen. If two members
+/// chose the same nation, starting the game fails with [ApiError::NationAlreadyTaken].
+///
+/// The chosen nations are carried over to the game when it is started, so the initial
+/// game state can be checked against them.
+///
+/// On success, all other members of the lobby receive a
+/// [WsMessage::LobbyNationChanged] message, unless the lobby hides the identities of
+/// its members.
+#[utoipa::path(
+    tag = "Lobbies",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Nation was changed"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = SetNationRequest,
+    security(("session_cookie" = []))
+)]
+#[put("/lobbies/{uuid}/nation")]
+pub async fn set_lobby_nation(
+    path: Path<PathUuid>,
+    req: Json<SetNationRequest>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let nation = req.into_inner().nation;
+    if let Some(nation) = &nation {
+        if nation.trim().is_empty() || nation.len() > 255 {
+            return Err(ApiError::InvalidGameSettings);
+        }
+    }
+
+    let mut tx = db.start_transaction().await?;
+
+    let members = LobbyMembers::query(&mut tx, path.uuid)
+        .await?
+        .ok_or(ApiError::InvalidLobbyUuid)?;
+
+    if !members.is_member(uuid) {
+        return Err(ApiError::NotInALobby);
+    }
+
+    let (hidden_identities,) = query!(&mut tx, (Lobby::F.hidden_identities,))
         .condition(Lobby::F.uuid.equals(path.uuid))
+        .one()
         .await?;
 
+    // Check if another member has already chosen the nation. In lobbies that hide the
+    // identities of their members, the error would reveal the nations of the others,
+    // so conflicts are only reported when the game is started.
+    if let (Some(nation), false) = (&nation, hidden_identities) {
+        let (owner_nation,) = query!(&mut tx, (Lobby::F.owner_nation,))
+            .condition(Lobby::F.uuid.equals(path.uuid))
+            .one()
+            .await?;
+        let player_nations = query!(&mut tx, (LobbyAccount::F.player, LobbyAccount::F.nation))
+            .condition(LobbyAccount::F.lobby.equals(path.uuid))
+            .all()
+            .await?;
+
+        let taken = iter::once((members.owner, owner_nation))
+            .chain(
+                player_nations
+                    .into_iter()
+                    .map(|(player, nation)| (*player.key(), nation)),
+            )
+            .filter(|(player, _)| *player != uuid)
+            .filter_map(|(_, nation)| nation)
+            .any(|x| x.to_lowercase() == nation.to_lowercase());
+        if taken {
+            return Err(ApiError::NationAlreadyTaken);
+        }
+    }
+
+    if members.is_owner(uuid) {
+        update!(&mut tx, Lobby)
+            .condition(Lobby::F.uuid.equals(path.uuid))
+            .set(Lobby::F.owner_nation, nation.clone())
+            .exec()
+            .await?;
+    } else {
+        update!(&mut tx, LobbyAccount)
+            .condition(and!(
+                LobbyAccount::F.lobby.equals(path.uuid),
+                LobbyAccount::F.player.equals(uuid)
+            ))
+            .set(LobbyAccount::F.nation, nation.clone())
+            .exec()
+            .await?;
+    }
+
     tx.commit().await?;
 
-    // Send notifications to all remaining players
-    let msg = WsMessage::GameStarted {
-        game_uuid,
-        game_chat_uuid,
-        lobby_uuid: lobby.uuid,
-        lobby_chat_uuid: *lobby.chat_room.key(),
-    };
+    if hidden_identities {
+        return Ok(HttpResponse::Ok().finish());
+    }
 
-    for p in player.into_iter().filter(|x| *x != uuid) {
-        if let Err(err) = ws_manager_chan
-            .send(WsManagerMessage::SendMessage(p, msg.clone()))
-            .await
-        {
-            error!("Could not send to ws manager chan: {err}");
-            return Err(ApiError::InternalServerError);
-        }
+    let msg = WsMessage::LobbyNationChanged {
+        lobby_uuid: path.uuid,
+        player_uuid: uuid,
+        nation,
+    };
+    for member in members.members().filter(|x| *x != uuid) {
+        notifier.send(member, msg.clone()).await;
     }
 
-    Ok(Json(StartGameResponse {
-        game_uuid,
-        game_chat_uuid,
-    }))
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// The request to join a lobby
@@ -581,21 +1598,39 @@ pub async fn start_game(
 pub struct JoinLobbyRequest {
     #[schema(example = "super-secure-password")]
     password: Option<String>,
+    #[schema(example = "K7QM2XPA")]
+    join_code: Option<String>,
 }
 
 /// Join an existing lobby
 ///
-/// The executing user must not be the owner of a lobby or member of a lobby.
+/// The executing user must not be the owner of a lobby or member of a lobby, unless
+/// the server allows the membership in multiple lobbies. In any case, the executing user
+/// must not already be the owner or a member of the lobby to join.
 /// To be placed in a lobby, a active websocket connection is required.
 ///
 /// As a lobby might be protected by password, the optional parameter `password` may be specified.
 /// If the provided password was incorrect, the error [ApiError::MissingPrivileges] is returned.
 /// If the lobby isn't protected, but a password was found in the request, it is ignored.
 ///
+/// Hidden lobbies additionally require the `join_code` of the lobby. If it is missing or
+/// incorrect, the error [ApiError::InvalidJoinCode] is returned.
+///
+/// Restricted lobbies can only be joined by the allowed accounts and, if enabled, the friends
+/// of the owner. Other accounts receive the error [ApiError::NotAllowedToJoin], even if they
+/// know the password.
+///
+/// Accounts that were banned from the lobby receive the error [ApiError::BannedFromLobby].
+///
 /// If the lobby is already full, a [ApiError::LobbyFull] error is returned.
 ///
 /// On success, all players that were in the lobby before, are notified about the new player with a
-/// [WsMessage::LobbyJoin] message.
+/// [WsMessage::LobbyJoin] message and a system message is posted to the chatroom of the lobby.
+///
+/// If the lobby has `auto_start` set and the executing user took its last free slot, the game
+/// is started on behalf of the owner, as long as all players are ready if the lobby requires it
+/// and the server is not at capacity. All members, including the owner, receive a
+/// [WsMessage::GameStarted] message in this case.
 #[utoipa::path(
     tag = "Lobbies",
     context_path = "/api/v2",
@@ -614,58 +1649,77 @@ pub async fn join_lobby(
     req: Json<JoinLobbyRequest>,
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    settings: Data<RuntimeSettings>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
+    join(&db, &settings, notifier.get_ref(), path.uuid, uuid, &req).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Add an account to a lobby
+///
+/// Returns the joined lobby.
+async fn join(
+    db: &Database,
+    settings: &RuntimeSettings,
+    notifier: &dyn Notifier,
+    lobby_uuid: Uuid,
+    uuid: Uuid,
+    req: &JoinLobbyRequest,
+) -> ApiResult<Lobby> {
     let mut tx = db.start_transaction().await?;
 
     // Check if lobby exists
-    let mut lobby = query!(&mut tx, Lobby)
-        .condition(Lobby::F.uuid.equals(path.uuid))
+    let lobby = query!(&mut tx, Lobby)
+        .condition(Lobby::F.uuid.equals(lobby_uuid))
         .optional()
         .await?
         .ok_or(ApiError::InvalidUuid)?;
-
-    Lobby::F
-        .current_player
-        .populate(&mut tx, &mut lobby)
-        .await?;
-
-    // Ok as current_player is populated before
-    #[allow(clippy::unwrap_used)]
-    let current_player: Vec<LobbyAccount> = lobby.current_player.cached.unwrap();
+    let members = LobbyMembers::query(&mut tx, lobby.uuid)
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
 
     // Check if the lobby is already full
-
-    if lobby.max_player as usize == current_player.len() + 1 {
+    if members.is_full() {
         return Err(ApiError::LobbyFull);
     }
 
+    // Check if the executing account is already part of this lobby
+    if members.is_member(uuid) {
+        return Err(ApiError::AlreadyInThisLobby);
+    }
+
     // Check if the executing account is already in a lobby
-    if query!(&mut tx, (LobbyAccount::F.uuid,))
-        .condition(LobbyAccount::F.player.equals(uuid))
-        .optional()
-        .await?
-        .is_some()
-    {
+    if !settings.allow_multiple_lobbies && is_in_a_lobby(&mut tx, uuid).await? {
         return Err(ApiError::AlreadyInALobby);
     }
 
-    if query!(&mut tx, (Lobby::F.uuid,))
-        .condition(Lobby::F.owner.equals(uuid))
-        .optional()
-        .await?
-        .is_some()
+    // Hidden lobbies can only be joined with their join code
+    if lobby.hidden
+        && !req
+            .join_code
+            .as_ref()
+            .zip(lobby.join_code.as_ref())
+            .is_some_and(|(code, lobby_code)| code.eq_ignore_ascii_case(lobby_code))
     {
-        return Err(ApiError::AlreadyInALobby);
+        return Err(ApiError::InvalidJoinCode);
+    }
+
+    if is_banned(&mut tx, lobby.uuid, uuid).await? {
+        return Err(ApiError::BannedFromLobby);
+    }
+    if !may_join(&mut tx, &lobby, uuid).await? {
+        return Err(ApiError::NotAllowedToJoin);
     }
 
     // If the lobby is password protected, check the hash
-    if let Some(password_hash) = lobby.password_hash {
+    if let Some(password_hash) = &lobby.password_hash {
         let req_pw = req.password.clone().ok_or(ApiError::MissingPrivileges)?;
         Argon2::default()
-            .verify_password(req_pw.as_bytes(), &PasswordHash::new(&password_hash)?)
+            .verify_password(req_pw.as_bytes(), &PasswordHash::new(password_hash)?)
             .map_err(|e| match e {
                 Error::Password => ApiError::MissingPrivileges,
                 _ => ApiError::InvalidHash(e),
@@ -673,24 +1727,8 @@ pub async fn join_lobby(
     }
 
     // Check if the websocket is connected
-    let (sender, rx) = oneshot::channel();
-
-    let msg = WsManagerMessage::RetrieveOnlineState(uuid, sender);
-    if let Err(err) = ws_manager_chan.send(msg).await {
-        warn!("Could not send to ws manager chan: {err}");
-        return Err(ApiError::InternalServerError);
-    }
-
-    match rx.await {
-        Ok(is_online) => {
-            if !is_online {
-                return Err(ApiError::WsNotConnected);
-            }
-        }
-        Err(err) => {
-            warn!("Error while receiving from oneshot channel: {err}");
-            return Err(ApiError::InternalServerError);
-        }
+    if !notifier.is_online(uuid).await? {
+        return Err(ApiError::WsNotConnected);
     }
 
     // Add player to lobby
@@ -703,54 +1741,344 @@ pub async fn join_lobby(
         })
         .await?;
 
-    let (uuid, username, display_name) = query!(
+    update!(&mut tx, Lobby)
+        .condition(Lobby::F.uuid.equals(lobby.uuid))
+        .set(Lobby::F.last_activity, Utc::now().naive_utc())
+        .exec()
+        .await?;
+
+    let (uuid, username, display_name) = query!(
+        &mut tx,
+        (
+            Account::F.uuid,
+            Account::F.username,
+            Account::F.display_name
+        )
+    )
+    .condition(Account::F.uuid.equals(uuid))
+    .optional()
+    .await?
+    .ok_or(ApiError::SessionCorrupt)?;
+
+    // Add player to chatroom
+    insert!(&mut tx, ChatRoomMemberInsert)
+        .single(&ChatRoomMemberInsert {
+            uuid: Uuid::new_v4(),
+            member: ForeignModelByField::Key(uuid),
+            chat_room: ForeignModelByField::Key(*lobby.chat_room.key()),
+        })
+        .await?;
+
+    let mut outbox = Outbox::new();
+    post_system_message(
+        &mut tx,
+        &mut outbox,
+        *lobby.chat_room.key(),
+        format!("{display_name} joined the lobby"),
+    )
+    .await?;
+
+    // Notify other players
+    outbox.notify_all(
+        members.members(),
+        WsMessage::LobbyJoin {
+            lobby_uuid: lobby.uuid,
+            player: AccountResponse {
+                uuid,
+                username,
+                display_name,
+            },
+        },
+    );
+
+    lobby_list::announce(&mut tx, &mut outbox, lobby.uuid, LobbyListChange::Updated).await?;
+    game::auto_start(&mut tx, &mut outbox, lobby.uuid, settings.max_running_games).await?;
+
+    tx.commit().await?;
+
+    outbox.send(notifier).await;
+
+    Ok(lobby)
+}
+
+/// The request to join a lobby by its join code
+#[derive(Deserialize, ToSchema)]
+pub struct JoinLobbyByCodeRequest {
+    #[schema(example = "K7QM2XPA")]
+    join_code: String,
+    #[schema(example = "super-secure-password")]
+    password: Option<String>,
+}
+
+/// The response of joining a lobby by its join code
+#[derive(Serialize, ToSchema)]
+pub struct JoinLobbyByCodeResponse {
+    lobby_uuid: Uuid,
+    lobby_chat_room_uuid: Uuid,
+}
+
+/// Join a hidden lobby by its join code
+///
+/// This allows sharing a hidden lobby by its join code only, e.g. in external chats.
+/// Join codes are case-insensitive. If no lobby has the join code,
+/// [ApiError::InvalidJoinCode] is returned.
+///
+/// Apart from that, the same rules as for `POST /api/v2/lobbies/{uuid}/join` apply.
+/// If the lobby is protected by a password, it is required as well.
+#[utoipa::path(
+    tag = "Lobbies",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Joined lobby successfully", body = JoinLobbyByCodeResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    request_body = JoinLobbyByCodeRequest,
+    security(("session_cookie" = []))
+)]
+#[post("/lobbies/join-by-code")]
+pub async fn join_lobby_by_code(
+    req: Json<JoinLobbyByCodeRequest>,
+    db: Data<Database>,
+    session: Session,
+    settings: Data<RuntimeSettings>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<JoinLobbyByCodeResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+    let req = req.into_inner();
+
+    let (lobby_uuid,) = query!(db.as_ref(), (Lobby::F.uuid,))
+        .condition(Lobby::F.join_code.equals(req.join_code.to_uppercase()))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidJoinCode)?;
+
+    let lobby = join(
+        &db,
+        &settings,
+        notifier.get_ref(),
+        lobby_uuid,
+        uuid,
+        &JoinLobbyRequest {
+            password: req.password,
+            join_code: Some(req.join_code),
+        },
+    )
+    .await?;
+
+    Ok(Json(JoinLobbyByCodeResponse {
+        lobby_uuid: lobby.uuid,
+        lobby_chat_room_uuid: *lobby.chat_room.key(),
+    }))
+}
+
+/// The request to update a lobby
+///
+/// All fields are optional, only the specified ones are changed.
+/// `password` sets a new password, `remove_password` removes the password of the lobby.
+/// `allowed_players` restricts the lobby to the given accounts, replacing the previously
+/// allowed accounts. `remove_restriction` allows everyone to join the lobby again, it is
+/// ignored if `allowed_players` is set.
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateLobbyRequest {
+    #[schema(example = "Herbert's lobby")]
+    name: Option<String>,
+    #[schema(example = "super-secure-password")]
+    password: Option<String>,
+    #[serde(default)]
+    remove_password: bool,
+    #[schema(example = 4)]
+    max_players: Option<u8>,
+    require_ready: Option<bool>,
+    auto_start: Option<bool>,
+    hidden: Option<bool>,
+    allowed_players: Option<Vec<Uuid>>,
+    #[serde(default)]
+    remove_restriction: bool,
+    allow_friends_of_owner: Option<bool>,
+}
+
+/// Update the name, password, maximum number of players, ready requirement, automatic start,
+/// visibility or restriction of a lobby
+///
+/// This endpoint can only be used by the lobby owner and the co-hosts of the lobby.
+///
+/// The maximum number of players can't be set below the number of players
+/// that are currently in the lobby, including the owner, or below the minimum
+/// number of players of the lobby.
+///
+/// If `hidden` is set to `true`, the lobby gets a new join code. If it is set to `false`,
+/// the join code is removed.
+///
+/// Enabling `auto_start` doesn't start the game of a lobby that is already full,
+/// it takes effect the next time a player joins or becomes ready.
+///
+/// Players that already joined the lobby are not removed if they are not allowed to join
+/// it anymore.
+///
+/// Changes that violate the lobby policy of the server are rejected with
+/// [ApiError::LobbyPolicyViolation].
+///
+/// On success, all other members of the lobby receive a [WsMessage::LobbyUpdated] message
+/// and a system message is posted to the chatroom of the lobby.
+#[utoipa::path(
+    tag = "Lobbies",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the updated lobby", body = GetLobbyResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = UpdateLobbyRequest,
+    security(("session_cookie" = []))
+)]
+#[patch("/lobbies/{uuid}")]
+pub async fn update_lobby(
+    path: Path<PathUuid>,
+    req: Json<UpdateLobbyRequest>,
+    db: Data<Database>,
+    session: Session,
+    settings: Data<RuntimeSettings>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<GetLobbyResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let req = req.into_inner();
+    if req.remove_password && req.password.is_some() {
+        return Err(ApiError::InvalidPassword);
+    }
+
+    let mut tx = db.start_transaction().await?;
+
+    let members = LobbyMembers::query(&mut tx, path.uuid)
+        .await?
+        .ok_or(ApiError::InvalidLobbyUuid)?;
+
+    // Check if the executing user owns the lobby or is a co-host
+    if !members.may_manage(uuid) {
+        return Err(ApiError::MissingPrivileges);
+    }
+
+    if let Some(max_players) = req.max_players {
+        let (min_player,) = query!(&mut tx, (Lobby::F.min_player,))
+            .condition(Lobby::F.uuid.equals(path.uuid))
+            .one()
+            .await?;
+
+        if !(2..=34).contains(&max_players)
+            || (max_players as usize) < members.occupancy()
+            || min_player.is_some_and(|x| i16::from(max_players) < x)
+        {
+            return Err(ApiError::InvalidMaxPlayersCount);
+        }
+    }
+    check_lobby_policy(
+        &settings,
+        req.max_players,
+        req.remove_password.then_some(false),
+        &[],
+    )?;
+
+    let password_hash = if let Some(pw) = &req.password {
+        if pw.is_empty() {
+            return Err(ApiError::InvalidPassword);
+        }
+
+        let salt = SaltString::generate(&mut thread_rng());
+        Some(Some(
+            Argon2::default()
+                .hash_password(pw.as_bytes(), &salt)?
+                .to_string(),
+        ))
+    } else if req.remove_password {
+        Some(None)
+    } else {
+        None
+    };
+
+    let join_code = req.hidden.map(|hidden| hidden.then(generate_join_code));
+
+    let mut outbox = Outbox::new();
+
+    // Hiding the lobby removes it from the lobby list, so it is announced before
+    if req.hidden == Some(true) {
+        lobby_list::announce(&mut tx, &mut outbox, path.uuid, LobbyListChange::Closed).await?;
+    }
+    let (was_hidden,) = query!(&mut tx, (Lobby::F.hidden,))
+        .condition(Lobby::F.uuid.equals(path.uuid))
+        .one()
+        .await?;
+
+    let restricted = if let Some(allowed_players) = &req.allowed_players {
+        set_allowed_players(&mut tx, path.uuid, allowed_players).await?;
+        Some(true)
+    } else if req.remove_restriction {
+        rorm::delete!(&mut tx, LobbyAllowedAccount)
+            .condition(LobbyAllowedAccount::F.lobby.equals(path.uuid))
+            .await?;
+        Some(false)
+    } else {
+        None
+    };
+
+    update!(&mut tx, Lobby)
+        .condition(Lobby::F.uuid.equals(path.uuid))
+        .begin_dyn_set()
+        .set_if(Lobby::F.name, req.name)
+        .set_if(Lobby::F.password_hash, password_hash)
+        .set_if(Lobby::F.max_player, req.max_players.map(i16::from))
+        .set_if(Lobby::F.require_ready, req.require_ready)
+        .set_if(Lobby::F.auto_start, req.auto_start)
+        .set_if(Lobby::F.hidden, req.hidden)
+        .set_if(Lobby::F.join_code, join_code)
+        .set_if(Lobby::F.restricted, restricted)
+        .set_if(Lobby::F.allow_friends_of_owner, req.allow_friends_of_owner)
+        .finish_dyn_set()
+        .map_err(|_| ApiError::EmptyJson)?
+        .exec()
+        .await?;
+
+    let mut lobby = query_lobby(&mut tx, path.uuid)
+        .await?
+        .ok_or(ApiError::InternalServerError)?;
+
+    if was_hidden && req.hidden == Some(false) {
+        lobby_list::announce(&mut tx, &mut outbox, path.uuid, LobbyListChange::Created).await?;
+    } else if req.max_players.is_some() {
+        lobby_list::announce(&mut tx, &mut outbox, path.uuid, LobbyListChange::Updated).await?;
+    }
+
+    post_system_message(
         &mut tx,
-        (
-            Account::F.uuid,
-            Account::F.username,
-            Account::F.display_name
-        )
+        &mut outbox,
+        lobby.chat_room_uuid,
+        "The settings of the lobby were changed".to_string(),
     )
-    .condition(Account::F.uuid.equals(uuid))
-    .optional()
-    .await?
-    .ok_or(ApiError::SessionCorrupt)?;
-
-    // Add player to chatroom
-    insert!(&mut tx, ChatRoomMemberInsert)
-        .single(&ChatRoomMemberInsert {
-            uuid: Uuid::new_v4(),
-            member: ForeignModelByField::Key(uuid),
-            chat_room: ForeignModelByField::Key(*lobby.chat_room.key()),
-        })
-        .await?;
+    .await?;
 
     tx.commit().await?;
 
-    let players: Vec<Uuid> = iter::once(*lobby.owner.key())
-        .chain(current_player.into_iter().map(|x| *x.player.key()))
-        .collect();
+    outbox.send(notifier.get_ref()).await;
 
-    let msg = WsMessage::LobbyJoin {
+    let msg = WsMessage::LobbyUpdated {
         lobby_uuid: lobby.uuid,
-        player: AccountResponse {
-            uuid,
-            username,
-            display_name,
-        },
+        name: lobby.name.clone(),
+        max_players: lobby.max_players,
+        password: lobby.password,
+        require_ready: lobby.require_ready,
+        auto_start: lobby.auto_start,
+        hidden: lobby.hidden,
+        restricted: lobby.restricted,
     };
-
-    // Notify other players
-    for player in players {
-        if let Err(err) = ws_manager_chan
-            .send(WsManagerMessage::SendMessage(player, msg.clone()))
-            .await
-        {
-            warn!("Could not send to ws manager chan: {err}");
-        }
+    for member in members.members().filter(|member| *member != uuid) {
+        notifier.send(member, msg.clone()).await;
     }
 
-    Ok(HttpResponse::Ok().finish())
+    lobby.hide_private_fields(uuid);
+    fill_online_states(notifier.get_ref(), lobby.accounts_mut()).await?;
+
+    Ok(Json(lobby))
 }
 
 /// Close an open lobby
@@ -775,51 +2103,40 @@ pub async fn close_lobby(
     path: Path<PathUuid>,
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
     let mut tx = db.start_transaction().await?;
 
     // Check if lobby exists
-    let mut lobby = query!(&mut tx, Lobby)
-        .condition(Lobby::F.uuid.equals(path.uuid))
-        .optional()
+    let members = LobbyMembers::query(&mut tx, path.uuid)
         .await?
         .ok_or(ApiError::InvalidUuid)?;
 
-    Lobby::F
-        .current_player
-        .populate(&mut tx, &mut lobby)
-        .await?;
-
-    // Ok as current_player is populated before
-    #[allow(clippy::unwrap_used)]
-    let current_player: Vec<LobbyAccount> = lobby.current_player.cached.unwrap();
-
     // Check if user has the privileges to close the lobby
-    if *lobby.owner.key() != uuid {
+    if !members.is_owner(uuid) {
         return Err(ApiError::MissingPrivileges);
     }
 
+    let mut outbox = Outbox::new();
+    lobby_list::announce(&mut tx, &mut outbox, members.lobby, LobbyListChange::Closed).await?;
+
     rorm::delete!(&mut tx, Lobby)
-        .condition(Lobby::F.uuid.equals(lobby.uuid))
+        .condition(Lobby::F.uuid.equals(members.lobby))
         .await?;
 
     tx.commit().await?;
 
+    outbox.send(notifier.get_ref()).await;
+
     let msg = WsMessage::LobbyClosed {
-        lobby_uuid: lobby.uuid,
+        lobby_uuid: members.lobby,
     };
 
     // Notify other players
-    for player in current_player.into_iter().map(|x| *x.player.key()) {
-        if let Err(err) = ws_manager_chan
-            .send(WsManagerMessage::SendMessage(player, msg.clone()))
-            .await
-        {
-            warn!("Error while sending message to ws manager chan: {err}");
-        }
+    for player in members.players {
+        notifier.send(player, msg.clone()).await;
     }
 
     Ok(HttpResponse::Ok().finish())
@@ -831,6 +2148,7 @@ pub async fn close_lobby(
 /// For the lobby owner, you want to use `DELETE /lobbies/{uuid}`.
 ///
 /// All players in the lobby will receive a [WsMessage::LobbyLeave] message via websocket on success.
+/// A system message is posted to the chatroom of the lobby.
 #[utoipa::path(
     tag = "Lobbies",
     context_path = "/api/v2",
@@ -847,30 +2165,24 @@ pub async fn leave_lobby(
     path: Path<PathUuid>,
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
     let mut tx = db.start_transaction().await?;
 
     // Check if lobby exists
-    let mut lobby = query!(&mut tx, Lobby)
+    let lobby = query!(&mut tx, Lobby)
         .condition(Lobby::F.uuid.equals(path.uuid))
         .optional()
         .await?
         .ok_or(ApiError::InvalidUuid)?;
-
-    Lobby::F
-        .current_player
-        .populate(&mut tx, &mut lobby)
-        .await?;
-
-    // Ok as current_player is populated before
-    #[allow(clippy::unwrap_used)]
-    let current_player: Vec<LobbyAccount> = lobby.current_player.cached.unwrap();
+    let members = LobbyMembers::query(&mut tx, lobby.uuid)
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
 
     // Check if executing user is in the lobby
-    if !current_player.iter().any(|x| *x.player.key() == uuid) {
+    if !members.is_player(uuid) {
         return Err(ApiError::MissingPrivileges);
     }
 
@@ -888,9 +2200,12 @@ pub async fn leave_lobby(
         ))
         .await?;
 
-    // Delete all invites of this player
+    // Delete all invites of this player to this lobby
     rorm::delete!(&mut tx, Invite)
-        .condition(Invite::F.from.equals(uuid))
+        .condition(and!(
+            Invite::F.from.equals(uuid),
+            Invite::F.lobby.equals(lobby.uuid)
+        ))
         .await?;
 
     let (uuid, username, display_name) = query!(
@@ -906,8 +2221,20 @@ pub async fn leave_lobby(
     .await?
     .ok_or(ApiError::SessionCorrupt)?;
 
+    let mut outbox = Outbox::new();
+    post_system_message(
+        &mut tx,
+        &mut outbox,
+        *lobby.chat_room.key(),
+        format!("{display_name} left the lobby"),
+    )
+    .await?;
+    lobby_list::announce(&mut tx, &mut outbox, lobby.uuid, LobbyListChange::Updated).await?;
+
     tx.commit().await?;
 
+    outbox.send(notifier.get_ref()).await;
+
     let msg = WsMessage::LobbyLeave {
         lobby_uuid: lobby.uuid,
         player: AccountResponse {
@@ -917,17 +2244,9 @@ pub async fn leave_lobby(
         },
     };
 
-    let players =
-        iter::once(*lobby.owner.key()).chain(current_player.into_iter().map(|x| *x.player.key()));
-
     // Notify other players
-    for player in players {
-        if let Err(err) = ws_manager_chan
-            .send(WsManagerMessage::SendMessage(player, msg.clone()))
-            .await
-        {
-            warn!("Error while sending message to ws manager chan: {err}");
-        }
+    for player in members.members() {
+        notifier.send(player, msg.clone()).await;
     }
 
     Ok(HttpResponse::Ok().finish())
@@ -936,16 +2255,30 @@ pub async fn leave_lobby(
 /// The path parameter to kick a player
 #[derive(Deserialize, IntoParams)]
 pub struct PlayerKickPath {
-    lobby_uuid: Uuid,
-    player_uuid: Uuid,
+    pub(crate) lobby_uuid: Uuid,
+    pub(crate) player_uuid: Uuid,
+}
+
+/// The query parameters to kick a player
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PlayerKickQuery {
+    /// Ban the player from the lobby as well
+    #[serde(default)]
+    pub(crate) ban: bool,
 }
 
 /// Kick a player from an open lobby
 ///
-/// This endpoint can only be used by the lobby owner.
+/// This endpoint can only be used by the lobby owner and the co-hosts of the lobby.
+/// Co-hosts can't kick other co-hosts.
+///
+/// If `ban` is set, the player is banned from the lobby and can't join it again, neither
+/// directly nor by accepting an invite. The ban can be lifted with
+/// `DELETE /api/v2/lobbies/{lobby_uuid}/bans/{player_uuid}`.
 ///
 /// All players in the lobby as well as the kick player will receive a [WsMessage::LobbyKick]
-/// message via websocket on success.
+/// message via websocket on success. A system message is posted to the chatroom of the lobby.
 #[utoipa::path(
     tag = "Lobbies",
     context_path = "/api/v2",
@@ -954,101 +2287,278 @@ pub struct PlayerKickPath {
         (status = 400, description = "Client error", body = ApiErrorResponse),
         (status = 500, description = "Server error", body = ApiErrorResponse),
     ),
-    params(PlayerKickPath),
+    params(PlayerKickPath, PlayerKickQuery),
     security(("session_cookie" = []))
 )]
 #[delete("/lobbies/{lobby_uuid}/{player_uuid}")]
 pub async fn kick_player_from_lobby(
     path: Path<PlayerKickPath>,
+    options: Query<PlayerKickQuery>,
     db: Data<Database>,
     session: Session,
-    ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
 ) -> ApiResult<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
     let mut tx = db.start_transaction().await?;
 
-    // Check if lobby exists
-    let mut lobby = query!(&mut tx, Lobby)
-        .condition(Lobby::F.uuid.equals(path.lobby_uuid))
+    // Check if executing user owns the lobby or is a co-host
+    let members = LobbyMembers::query(&mut tx, path.lobby_uuid)
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+    if !members.may_kick(uuid, path.player_uuid) {
+        return Err(ApiError::MissingPrivileges);
+    }
+
+    let mut outbox = Outbox::new();
+    lobby::kick_player(
+        &mut tx,
+        &mut outbox,
+        path.lobby_uuid,
+        path.player_uuid,
+        options.ban,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    outbox.send(notifier.get_ref()).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// A ban of an account from a lobby
+#[derive(Serialize, ToSchema)]
+pub struct LobbyBanResponse {
+    account: AccountResponse,
+    created_at: DateTime<Utc>,
+}
+
+/// The bans of a lobby
+#[derive(Serialize, ToSchema)]
+pub struct GetLobbyBansResponse {
+    bans: Vec<LobbyBanResponse>,
+}
+
+/// Retrieves the accounts that were banned from a lobby
+///
+/// This endpoint can only be used by the lobby owner.
+#[utoipa::path(
+    tag = "Lobbies",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the bans of the lobby", body = GetLobbyBansResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[get("/lobbies/{uuid}/bans")]
+pub async fn get_lobby_bans(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<GetLobbyBansResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    let (owner,) = query!(&mut tx, (Lobby::F.owner,))
+        .condition(Lobby::F.uuid.equals(path.uuid))
         .optional()
         .await?
         .ok_or(ApiError::InvalidUuid)?;
+    if *owner.key() != uuid {
+        return Err(ApiError::MissingPrivileges);
+    }
 
-    Lobby::F
-        .current_player
-        .populate(&mut tx, &mut lobby)
-        .await?;
+    let bans = query!(
+        &mut tx,
+        (
+            LobbyBan::F.account.uuid,
+            LobbyBan::F.account.username,
+            LobbyBan::F.account.display_name,
+            LobbyBan::F.created_at,
+        )
+    )
+    .condition(LobbyBan::F.lobby.equals(path.uuid))
+    .all()
+    .await?
+    .into_iter()
+    .map(
+        |(uuid, username, display_name, created_at)| LobbyBanResponse {
+            account: AccountResponse {
+                uuid,
+                username,
+                display_name,
+            },
+            created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
+        },
+    )
+    .collect();
+
+    tx.commit().await?;
+
+    Ok(Json(GetLobbyBansResponse { bans }))
+}
+
+/// Lift the ban of an account from a lobby
+///
+/// This endpoint can only be used by the lobby owner.
+/// If the account isn't banned from the lobby, [ApiError::InvalidPlayerUuid] is returned.
+#[utoipa::path(
+    tag = "Lobbies",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Ban was lifted"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PlayerKickPath),
+    security(("session_cookie" = []))
+)]
+#[delete("/lobbies/{lobby_uuid}/bans/{player_uuid}")]
+pub async fn unban_player_from_lobby(
+    path: Path<PlayerKickPath>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
-    // Ok as current_player is populated before
-    #[allow(clippy::unwrap_used)]
-    let current_player: Vec<LobbyAccount> = lobby.current_player.cached.unwrap();
+    let mut tx = db.start_transaction().await?;
 
-    // Check if executing user owns the lobby
-    if *lobby.owner.key() != uuid {
+    let (owner,) = query!(&mut tx, (Lobby::F.owner,))
+        .condition(Lobby::F.uuid.equals(path.lobby_uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+    if *owner.key() != uuid {
         return Err(ApiError::MissingPrivileges);
     }
 
-    // Check if the user to kick is in the lobby
-    if !current_player
-        .iter()
-        .any(|x| *x.player.key() == path.player_uuid)
-    {
+    if !is_banned(&mut tx, path.lobby_uuid, path.player_uuid).await? {
         return Err(ApiError::InvalidPlayerUuid);
     }
 
-    rorm::delete!(&mut tx, LobbyAccount)
+    rorm::delete!(&mut tx, LobbyBan)
         .condition(and!(
-            LobbyAccount::F.lobby.equals(lobby.uuid),
-            LobbyAccount::F.player.equals(path.player_uuid)
+            LobbyBan::F.lobby.equals(path.lobby_uuid),
+            LobbyBan::F.account.equals(path.player_uuid)
         ))
         .await?;
 
-    rorm::delete!(&mut tx, ChatRoomMember)
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Make a joined player a co-host of the lobby or revoke it
+///
+/// Returns [ApiError::MissingPrivileges] if the executing account doesn't own the lobby
+/// and [ApiError::InvalidPlayerUuid] if the player has not joined the lobby.
+async fn set_co_host(
+    db: &Database,
+    notifier: &dyn Notifier,
+    account: Uuid,
+    path: &PlayerKickPath,
+    co_host: bool,
+) -> ApiResult<()> {
+    let mut tx = db.start_transaction().await?;
+
+    let members = LobbyMembers::query(&mut tx, path.lobby_uuid)
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+    if !members.is_owner(account) {
+        return Err(ApiError::MissingPrivileges);
+    }
+    if !members.is_player(path.player_uuid) {
+        return Err(ApiError::InvalidPlayerUuid);
+    }
+
+    update!(&mut tx, LobbyAccount)
         .condition(and!(
-            ChatRoomMember::F.chat_room.equals(lobby.chat_room.key()),
-            ChatRoomMember::F.member.equals(path.player_uuid),
+            LobbyAccount::F.lobby.equals(path.lobby_uuid),
+            LobbyAccount::F.player.equals(path.player_uuid)
         ))
+        .set(LobbyAccount::F.co_host, co_host)
+        .exec()
         .await?;
 
-    // Delete all invites of this player
-    rorm::delete!(&mut tx, Invite)
-        .condition(Invite::F.from.equals(path.player_uuid))
-        .await?;
-
-    let (uuid, username, display_name) = query!(
-        &mut tx,
-        (
-            Account::F.uuid,
-            Account::F.username,
-            Account::F.display_name
-        )
-    )
-    .condition(Account::F.uuid.equals(path.player_uuid))
-    .optional()
-    .await?
-    .ok_or(ApiError::SessionCorrupt)?;
-
     tx.commit().await?;
 
-    let msg = WsMessage::LobbyKick {
-        lobby_uuid: lobby.uuid,
-        player: AccountResponse {
-            uuid,
-            username,
-            display_name,
-        },
+    let msg = WsMessage::LobbyCoHostChanged {
+        lobby_uuid: path.lobby_uuid,
+        player_uuid: path.player_uuid,
+        co_host,
     };
-
-    // Notify joined players and kicked player
-    for player in current_player.into_iter().map(|x| *x.player.key()) {
-        if let Err(err) = ws_manager_chan
-            .send(WsManagerMessage::SendMessage(player, msg.clone()))
-            .await
-        {
-            warn!("Error while sending message to ws manager chan: {err}");
-        }
+    for member in members.members() {
+        notifier.send(member, msg.clone()).await;
     }
 
+    Ok(())
+}
+
+/// Make a joined player a co-host of the lobby
+///
+/// Co-hosts may change the settings of the lobby and kick players that are not co-hosts.
+///
+/// This endpoint can only be used by the lobby owner.
+/// If the player has not joined the lobby, [ApiError::InvalidPlayerUuid] is returned.
+///
+/// On success, all members of the lobby receive a [WsMessage::LobbyCoHostChanged] message.
+#[utoipa::path(
+    tag = "Lobbies",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Player is a co-host"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PlayerKickPath),
+    security(("session_cookie" = []))
+)]
+#[put("/lobbies/{lobby_uuid}/co-hosts/{player_uuid}")]
+pub async fn add_lobby_co_host(
+    path: Path<PlayerKickPath>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    set_co_host(&db, notifier.get_ref(), uuid, &path, true).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Revoke the co-host rights of a player of the lobby
+///
+/// This endpoint can only be used by the lobby owner.
+/// If the player has not joined the lobby, [ApiError::InvalidPlayerUuid] is returned.
+///
+/// On success, all members of the lobby receive a [WsMessage::LobbyCoHostChanged] message.
+#[utoipa::path(
+    tag = "Lobbies",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Player is no co-host anymore"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PlayerKickPath),
+    security(("session_cookie" = []))
+)]
+#[delete("/lobbies/{lobby_uuid}/co-hosts/{player_uuid}")]
+pub async fn remove_lobby_co_host(
+    path: Path<PlayerKickPath>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    set_co_host(&db, notifier.get_ref(), uuid, &path, false).await?;
+
     Ok(HttpResponse::Ok().finish())
 }
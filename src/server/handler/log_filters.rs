@@ -0,0 +1,122 @@
+//! This module holds the admin endpoints to change the log levels of single modules
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use actix_web::web::{Data, Json};
+use actix_web::{get, put};
+use log::{error, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::logging::LogFilters;
+use crate::server::handler::{ApiError, ApiErrorResponse, ApiResult};
+
+/// The log levels of the server
+#[derive(Serialize, ToSchema)]
+pub struct LogFiltersResponse {
+    /// The level of the main logger from the configuration file
+    #[schema(example = "info")]
+    default_level: String,
+    /// The levels of the modules that differ from the main logger
+    #[schema(example = json!({"rorm_db": "debug"}))]
+    filters: BTreeMap<String, String>,
+}
+
+impl LogFiltersResponse {
+    fn new(log_filters: &LogFilters, filters: BTreeMap<String, LevelFilter>) -> Self {
+        Self {
+            default_level: log_filters.default_level().to_string(),
+            filters: filters
+                .into_iter()
+                .map(|(module, level)| (module, level.as_str().to_lowercase()))
+                .collect(),
+        }
+    }
+}
+
+/// Retrieve the log levels of the server
+#[utoipa::path(
+    tag = "Server status",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Returns the log levels", body = LogFiltersResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/loglevel")]
+pub async fn get_log_filters(log_filters: Data<LogFilters>) -> ApiResult<Json<LogFiltersResponse>> {
+    Ok(Json(LogFiltersResponse::new(
+        &log_filters,
+        log_filters.filters(),
+    )))
+}
+
+/// The request to change the log level of a module
+#[derive(Deserialize, ToSchema)]
+pub struct SetLogFilterRequest {
+    /// The module path, e.g. `runciv::chan` or `rorm_db`
+    #[schema(example = "rorm_db")]
+    module: String,
+    /// One of `off`, `error`, `warn`, `info`, `debug` and `trace`,
+    /// `null` to use the level of the main logger again
+    #[schema(example = "debug")]
+    level: Option<String>,
+}
+
+/// Change the log level of a module
+///
+/// The level applies to the module and all of its submodules until the server is restarted.
+/// It may be lower or higher than the level of the main logger, so single modules can be
+/// debugged without flooding the log with the messages of all others.
+///
+/// `sqlx::query` is reserved while slow database statements are recorded.
+///
+/// If the module is empty or reserved or the level is unknown,
+/// [ApiError::InvalidLogFilter] is returned.
+#[utoipa::path(
+    tag = "Server status",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Log level was changed", body = LogFiltersResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    request_body = SetLogFilterRequest,
+    security(("admin_token" = []))
+)]
+#[put("/loglevel")]
+pub async fn set_log_filter(
+    req: Json<SetLogFilterRequest>,
+    log_filters: Data<LogFilters>,
+) -> ApiResult<Json<LogFiltersResponse>> {
+    let SetLogFilterRequest { module, level } = req.into_inner();
+
+    let module = module.trim().to_string();
+    if module.is_empty()
+        || module.chars().any(char::is_whitespace)
+        || log_filters.is_reserved(&module)
+    {
+        return Err(ApiError::InvalidLogFilter);
+    }
+    let level = level
+        .map(|level| LevelFilter::from_str(&level))
+        .transpose()
+        .map_err(|_| ApiError::InvalidLogFilter)?;
+
+    let filters = log_filters
+        .set_filter(module.clone(), level)
+        .map_err(|err| {
+            error!("Could not change the logging configuration: {err}");
+            ApiError::InternalServerError
+        })?;
+
+    match level {
+        Some(level) => info!("Changed the log level of {module} to {level}"),
+        None => info!("Reset the log level of {module}"),
+    }
+
+    Ok(Json(LogFiltersResponse::new(&log_filters, filters)))
+}
@@ -1,9 +1,11 @@
 //! This module holds the handler of runciv
 
 use std::fmt::{Display, Formatter};
+use std::ops::Range;
 
 use actix_toolbox::tb_middleware::actix_session;
 use actix_web::body::BoxBody;
+use actix_web::http::header::RETRY_AFTER;
 use actix_web::HttpResponse;
 use log::{debug, error, info, trace, warn};
 use serde::{Deserialize, Serialize};
@@ -11,28 +13,68 @@ use serde_repr::Serialize_repr;
 use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
+use crate::chan::NotifierError;
+
+pub use crate::server::handler::abandoned_accounts::*;
+pub use crate::server::handler::account_export::*;
 pub use crate::server::handler::accounts::*;
 pub use crate::server::handler::auth::*;
+pub use crate::server::handler::badges::*;
+pub use crate::server::handler::capabilities::*;
 pub use crate::server::handler::chats::*;
+pub use crate::server::handler::devices::*;
+pub use crate::server::handler::download_links::*;
+pub use crate::server::handler::events::*;
 pub use crate::server::handler::friends::*;
+pub use crate::server::handler::game_events::*;
+pub use crate::server::handler::game_snapshots::*;
+pub use crate::server::handler::game_stats::*;
+pub use crate::server::handler::game_transfer::*;
 pub use crate::server::handler::games::*;
 pub use crate::server::handler::health::*;
 pub use crate::server::handler::invites::*;
 pub use crate::server::handler::lobbies::*;
+pub use crate::server::handler::log_filters::*;
+pub use crate::server::handler::moderation::*;
+pub use crate::server::handler::negotiations::*;
+pub use crate::server::handler::observer_tokens::*;
+pub use crate::server::handler::sync::*;
+pub use crate::server::handler::test_notification::*;
 pub use crate::server::handler::version::*;
+pub use crate::server::handler::webhooks::*;
 pub use crate::server::handler::websocket::*;
+pub use crate::server::handler::welcome_message::*;
 pub use crate::server::handler::welcome_page::*;
 
+pub mod abandoned_accounts;
+pub mod account_export;
 pub mod accounts;
 pub mod auth;
+pub mod badges;
+pub mod capabilities;
 pub mod chats;
+pub mod devices;
+pub mod download_links;
+pub mod events;
 pub mod friends;
+pub mod game_events;
+pub mod game_snapshots;
+pub mod game_stats;
+pub mod game_transfer;
 pub mod games;
 pub mod health;
 pub mod invites;
 pub mod lobbies;
+pub mod log_filters;
+pub mod moderation;
+pub mod negotiations;
+pub mod observer_tokens;
+pub mod sync;
+pub mod test_notification;
 pub mod version;
+pub mod webhooks;
 pub mod websocket;
+pub mod welcome_message;
 pub mod welcome_page;
 
 /// The uuid in a path
@@ -41,6 +83,30 @@ pub struct PathUuid {
     pub(crate) uuid: Uuid,
 }
 
+/// The pagination parameters of a query
+///
+/// `limit` defaults to 50 and is capped at 200, `offset` defaults to 0.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PaginationQuery {
+    #[param(example = 50)]
+    limit: Option<u64>,
+    #[param(example = 0)]
+    offset: Option<u64>,
+}
+
+impl PaginationQuery {
+    /// The maximum number of entries to return
+    pub(crate) fn limit(&self) -> u64 {
+        self.limit.unwrap_or(50).min(200)
+    }
+
+    /// The number of entries to skip
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset.unwrap_or(0)
+    }
+}
+
 /// The result that is used throughout the complete api.
 pub type ApiResult<T> = Result<T, ApiError>;
 
@@ -49,6 +115,9 @@ pub type ApiResult<T> = Result<T, ApiError>;
 /// Error codes in the range of 1000..2000 represent client errors
 /// that could be handled by the client.
 /// Error codes in the range of 2000..3000 represent server errors.
+/// Error codes in the range of 3000..4000 are reserved for server-specific policies
+/// and are not used by runciv itself. They are returned as `policy_code` together with
+/// [ApiStatusCode::PolicyViolation], see [ApiError::Policy].
 #[derive(Serialize_repr, ToSchema)]
 #[repr(u16)]
 pub(crate) enum ApiStatusCode {
@@ -78,12 +147,61 @@ pub(crate) enum ApiStatusCode {
     LobbyFull = 1022,
     InvalidPlayerUuid = 1023,
     AlreadyInThisLobby = 1024,
+    InvalidNegotiation = 1025,
+    NegotiationNotPending = 1026,
+    InvalidChecksum = 1027,
+    InvalidGameSettings = 1028,
+    NotInALobby = 1029,
+    InvalidSnapshotLabel = 1030,
+    GameFull = 1031,
+    AlreadyInThisGame = 1032,
+    PlayersNotReady = 1033,
+    InvalidJoinCode = 1034,
+    NotYourTurn = 1035,
+    UploadPending = 1036,
+    NotAllowedToJoin = 1037,
+    LobbyPolicyViolation = 1038,
+    ServerAtCapacity = 1039,
+    NationAlreadyTaken = 1040,
+    InvalidDeviceName = 1041,
+    BannedFromLobby = 1042,
+    InvalidMinPlayersCount = 1043,
+    NotEnoughPlayers = 1044,
+    InvalidTurnNote = 1045,
+    InvalidLanguage = 1046,
+    ServerBusy = 1047,
+    ChatMuted = 1048,
+    InvalidWebhookUrl = 1049,
+    WebhooksDisabled = 1050,
+    InvalidDownloadLink = 1051,
+    InvalidGameBundle = 1052,
+    NoMatchingPlayers = 1053,
+    GameTransferFailed = 1054,
+    DirectMessagesNotAllowed = 1055,
+    InvalidNickname = 1056,
+    InvalidGameSummary = 1057,
+    RateLimited = 1058,
+    FriendRequestsNotAllowed = 1059,
+    TooManyUsernames = 1060,
+    LobbyQuotaReached = 1061,
+    NoGameState = 1062,
+    InvalidLogFilter = 1063,
+    PolicyViolation = 1064,
+    HostCannotLeave = 1065,
+    InvalidObserverToken = 1066,
 
     InternalServerError = 2000,
     DatabaseError = 2001,
     SessionError = 2002,
 }
 
+/// The status codes that are reserved for server-specific policies, see [ApiError::Policy]
+pub(crate) const POLICY_STATUS_CODES: Range<u16> = 3000..4000;
+
+/// The seconds a client should wait before retrying a request that failed with
+/// [ApiError::ServerBusy]
+const SERVER_BUSY_RETRY_AFTER: &str = "5";
+
 /// The Response that is returned in case of an error
 ///
 /// For client errors the HTTP status code will be 400,
@@ -94,6 +212,12 @@ pub(crate) struct ApiErrorResponse {
     message: String,
     #[schema(example = 1000)]
     status_code: ApiStatusCode,
+    /// The code of the server-specific policy that was violated, in the range of 3000..4000
+    ///
+    /// Only set for [ApiStatusCode::PolicyViolation].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = 3001)]
+    policy_code: Option<u16>,
 }
 
 impl ApiErrorResponse {
@@ -101,6 +225,7 @@ impl ApiErrorResponse {
         Self {
             message,
             status_code,
+            policy_code: None,
         }
     }
 }
@@ -160,6 +285,102 @@ pub enum ApiError {
     InvalidPlayerUuid,
     /// The target is already in this lobby
     AlreadyInThisLobby,
+    /// An invalid negotiation was proposed (e.g. empty terms or expiry in the past)
+    InvalidNegotiation,
+    /// The negotiation was already answered or has expired
+    NegotiationNotPending,
+    /// The checksum of the uploaded data did not match
+    InvalidChecksum,
+    /// Invalid game settings were specified (e.g. a turn timer out of range)
+    InvalidGameSettings,
+    /// The executing account is not part of a lobby
+    NotInALobby,
+    /// The label of a snapshot is empty or too long
+    InvalidSnapshotLabel,
+    /// The game has no empty seat
+    GameFull,
+    /// The target player is already playing the game
+    AlreadyInThisGame,
+    /// Not all players of the lobby are ready
+    PlayersNotReady,
+    /// The join code is invalid
+    InvalidJoinCode,
+    /// It's not the turn of the executing account
+    NotYourTurn,
+    /// The game state of the previous turn has not been uploaded yet
+    UploadPending,
+    /// The executing account is not allowed to join the restricted lobby
+    NotAllowedToJoin,
+    /// The lobby violates the lobby policy of the server
+    ///
+    /// The message describes the violated rule.
+    LobbyPolicyViolation(String),
+    /// The server has reached its limit of open lobbies or running games
+    ServerAtCapacity,
+    /// The nation was already chosen by another member of the lobby
+    NationAlreadyTaken,
+    /// The name of a device is empty or too long
+    InvalidDeviceName,
+    /// The account was banned from the lobby
+    BannedFromLobby,
+    /// The minimum number of players is invalid
+    InvalidMinPlayersCount,
+    /// Not enough players joined the lobby to start the game
+    NotEnoughPlayers,
+    /// The note for the next player is longer than 255 characters
+    InvalidTurnNote,
+    /// The language is not a valid language code
+    InvalidLanguage,
+    /// Too many expensive operations are running at the same time, the request should be retried later
+    ServerBusy,
+    /// The account is muted in the chatroom
+    ChatMuted,
+    /// The url of a webhook is invalid
+    InvalidWebhookUrl,
+    /// Personal webhooks are disabled on this server
+    WebhooksDisabled,
+    /// The signature of a download link is invalid or the link expired
+    InvalidDownloadLink,
+    /// The game bundle has an unsupported version
+    InvalidGameBundle,
+    /// None of the players of an imported game has an account on this server
+    NoMatchingPlayers,
+    /// The target server did not accept a transferred game
+    GameTransferFailed,
+    /// The recipient doesn't accept direct messages from the executing account
+    DirectMessagesNotAllowed,
+    /// The nickname is too long
+    InvalidNickname,
+    /// The summary of an uploaded game state is invalid
+    InvalidGameSummary,
+    /// The request budget of the client is used up
+    ///
+    /// Contains the seconds until a request can be sent again.
+    RateLimited(u64),
+    /// The account doesn't accept friend requests from the executing account
+    FriendRequestsNotAllowed,
+    /// More usernames were passed than allowed
+    TooManyUsernames,
+    /// The account owns as many lobbies as the server allows
+    LobbyQuotaReached,
+    /// No game state was uploaded for the game yet
+    NoGameState,
+    /// The request was rejected by the policy validator of the server
+    ///
+    /// `code` is in the range of [POLICY_STATUS_CODES], see
+    /// [PolicyValidator](crate::service::policy::PolicyValidator).
+    Policy {
+        /// The status code of the policy
+        code: u16,
+        /// The message that is passed to the client
+        message: String,
+    },
+    /// The module or the level of a log filter is invalid
+    InvalidLogFilter,
+    /// The host tried to leave a game without transferring the host role
+    HostCannotLeave,
+    /// The observer token is unknown, revoked or expired
+    InvalidObserverToken,
 
     /// Unknown error occurred
     InternalServerError,
@@ -216,6 +437,72 @@ impl Display for ApiError {
             ApiError::LobbyFull => write!(f, "The lobby is full"),
             ApiError::InvalidPlayerUuid => write!(f, "Invalid player uuid was specified"),
             ApiError::AlreadyInThisLobby => write!(f, "The target player is already in this lobby"),
+            ApiError::InvalidNegotiation => write!(f, "Invalid negotiation"),
+            ApiError::NegotiationNotPending => {
+                write!(f, "The negotiation was already answered or has expired")
+            }
+            ApiError::InvalidChecksum => {
+                write!(f, "The checksum does not match the uploaded data")
+            }
+            ApiError::InvalidGameSettings => write!(f, "Invalid game settings"),
+            ApiError::NotInALobby => write!(f, "Not in a lobby"),
+            ApiError::InvalidSnapshotLabel => write!(f, "Invalid snapshot label"),
+            ApiError::GameFull => write!(f, "The game has no empty seat"),
+            ApiError::AlreadyInThisGame => {
+                write!(f, "The target player is already playing this game")
+            }
+            ApiError::PlayersNotReady => write!(f, "Not all players are ready"),
+            ApiError::InvalidJoinCode => write!(f, "The join code is invalid"),
+            ApiError::NotYourTurn => write!(f, "It's not your turn"),
+            ApiError::UploadPending => write!(
+                f,
+                "The game state of the previous turn has not been uploaded yet"
+            ),
+            ApiError::NotAllowedToJoin => write!(f, "You are not allowed to join this lobby"),
+            ApiError::LobbyPolicyViolation(err) => write!(f, "{err}"),
+            ApiError::ServerAtCapacity => write!(f, "The server is at capacity, try again later"),
+            ApiError::NationAlreadyTaken => {
+                write!(f, "The nation was already chosen by another player")
+            }
+            ApiError::InvalidDeviceName => write!(f, "Invalid device name"),
+            ApiError::BannedFromLobby => write!(f, "You are banned from this lobby"),
+            ApiError::InvalidMinPlayersCount => write!(f, "Invalid min players count"),
+            ApiError::NotEnoughPlayers => write!(f, "Not enough players joined the lobby"),
+            ApiError::InvalidTurnNote => write!(f, "Invalid turn note"),
+            ApiError::InvalidLanguage => write!(f, "Invalid language"),
+            ApiError::ServerBusy => write!(f, "The server is busy, please retry later"),
+            ApiError::ChatMuted => write!(f, "You are muted in this chat"),
+            ApiError::InvalidWebhookUrl => write!(
+                f,
+                "The webhook url must be a https url of a public host with at most 2048 characters"
+            ),
+            ApiError::WebhooksDisabled => write!(f, "Webhooks are disabled on this server"),
+            ApiError::InvalidDownloadLink => write!(f, "The download link is invalid or expired"),
+            ApiError::InvalidGameBundle => write!(f, "The game bundle has an unsupported version"),
+            ApiError::NoMatchingPlayers => write!(
+                f,
+                "None of the players of the game has an account on this server"
+            ),
+            ApiError::GameTransferFailed => write!(f, "The target server did not accept the game"),
+            ApiError::DirectMessagesNotAllowed => {
+                write!(f, "The account doesn't accept direct messages from you")
+            }
+            ApiError::InvalidNickname => write!(f, "Invalid nickname"),
+            ApiError::InvalidGameSummary => write!(f, "Invalid game summary"),
+            ApiError::RateLimited(_) => write!(f, "Too many requests, please retry later"),
+            ApiError::FriendRequestsNotAllowed => {
+                write!(f, "The account doesn't accept friend requests from you")
+            }
+            ApiError::TooManyUsernames => write!(f, "Too many usernames"),
+            ApiError::LobbyQuotaReached => write!(f, "You own as many lobbies as allowed"),
+            ApiError::NoGameState => write!(f, "No game state was uploaded yet"),
+            ApiError::Policy { message, .. } => write!(f, "{message}"),
+            ApiError::InvalidLogFilter => write!(f, "The log filter is invalid"),
+            ApiError::HostCannotLeave => write!(
+                f,
+                "The host has to transfer the host role before leaving the game"
+            ),
+            ApiError::InvalidObserverToken => write!(f, "The observer token is invalid"),
         }
     }
 }
@@ -421,16 +708,206 @@ impl actix_web::ResponseError for ApiError {
                 ApiStatusCode::AlreadyInThisLobby,
                 self.to_string(),
             )),
+            ApiError::InvalidNegotiation => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::InvalidNegotiation,
+                self.to_string(),
+            )),
+            ApiError::NegotiationNotPending => HttpResponse::BadRequest().json(
+                ApiErrorResponse::new(ApiStatusCode::NegotiationNotPending, self.to_string()),
+            ),
+            ApiError::InvalidChecksum => {
+                debug!("Checksum of uploaded data did not match");
+
+                HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                    ApiStatusCode::InvalidChecksum,
+                    self.to_string(),
+                ))
+            }
+            ApiError::InvalidGameSettings => HttpResponse::BadRequest().json(
+                ApiErrorResponse::new(ApiStatusCode::InvalidGameSettings, self.to_string()),
+            ),
+            ApiError::NotInALobby => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::NotInALobby,
+                self.to_string(),
+            )),
+            ApiError::InvalidSnapshotLabel => HttpResponse::BadRequest().json(
+                ApiErrorResponse::new(ApiStatusCode::InvalidSnapshotLabel, self.to_string()),
+            ),
+            ApiError::GameFull => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::GameFull,
+                self.to_string(),
+            )),
+            ApiError::AlreadyInThisGame => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::AlreadyInThisGame,
+                self.to_string(),
+            )),
+            ApiError::PlayersNotReady => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::PlayersNotReady,
+                self.to_string(),
+            )),
+            ApiError::InvalidJoinCode => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::InvalidJoinCode,
+                self.to_string(),
+            )),
+            ApiError::NotYourTurn => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::NotYourTurn,
+                self.to_string(),
+            )),
+            ApiError::UploadPending => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::UploadPending,
+                self.to_string(),
+            )),
+            ApiError::NotAllowedToJoin => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::NotAllowedToJoin,
+                self.to_string(),
+            )),
+            ApiError::LobbyPolicyViolation(_) => HttpResponse::BadRequest().json(
+                ApiErrorResponse::new(ApiStatusCode::LobbyPolicyViolation, self.to_string()),
+            ),
+            ApiError::ServerAtCapacity => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::ServerAtCapacity,
+                self.to_string(),
+            )),
+            ApiError::NationAlreadyTaken => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::NationAlreadyTaken,
+                self.to_string(),
+            )),
+            ApiError::InvalidDeviceName => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::InvalidDeviceName,
+                self.to_string(),
+            )),
+            ApiError::BannedFromLobby => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::BannedFromLobby,
+                self.to_string(),
+            )),
+            ApiError::InvalidMinPlayersCount => HttpResponse::BadRequest().json(
+                ApiErrorResponse::new(ApiStatusCode::InvalidMinPlayersCount, self.to_string()),
+            ),
+            ApiError::NotEnoughPlayers => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::NotEnoughPlayers,
+                self.to_string(),
+            )),
+            ApiError::InvalidTurnNote => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::InvalidTurnNote,
+                self.to_string(),
+            )),
+            ApiError::InvalidLanguage => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::InvalidLanguage,
+                self.to_string(),
+            )),
+            ApiError::ServerBusy => HttpResponse::ServiceUnavailable()
+                .insert_header((RETRY_AFTER, SERVER_BUSY_RETRY_AFTER))
+                .json(ApiErrorResponse::new(
+                    ApiStatusCode::ServerBusy,
+                    self.to_string(),
+                )),
+            ApiError::ChatMuted => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::ChatMuted,
+                self.to_string(),
+            )),
+            ApiError::InvalidWebhookUrl => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::InvalidWebhookUrl,
+                self.to_string(),
+            )),
+            ApiError::WebhooksDisabled => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::WebhooksDisabled,
+                self.to_string(),
+            )),
+            ApiError::InvalidDownloadLink => HttpResponse::BadRequest().json(
+                ApiErrorResponse::new(ApiStatusCode::InvalidDownloadLink, self.to_string()),
+            ),
+            ApiError::InvalidGameBundle => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::InvalidGameBundle,
+                self.to_string(),
+            )),
+            ApiError::NoMatchingPlayers => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::NoMatchingPlayers,
+                self.to_string(),
+            )),
+            ApiError::GameTransferFailed => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::GameTransferFailed,
+                self.to_string(),
+            )),
+            ApiError::DirectMessagesNotAllowed => HttpResponse::BadRequest().json(
+                ApiErrorResponse::new(ApiStatusCode::DirectMessagesNotAllowed, self.to_string()),
+            ),
+            ApiError::InvalidNickname => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::InvalidNickname,
+                self.to_string(),
+            )),
+            ApiError::InvalidGameSummary => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::InvalidGameSummary,
+                self.to_string(),
+            )),
+            ApiError::RateLimited(retry_after) => HttpResponse::TooManyRequests()
+                .insert_header((RETRY_AFTER, retry_after.to_string()))
+                .json(ApiErrorResponse::new(
+                    ApiStatusCode::RateLimited,
+                    self.to_string(),
+                )),
+            ApiError::FriendRequestsNotAllowed => HttpResponse::BadRequest().json(
+                ApiErrorResponse::new(ApiStatusCode::FriendRequestsNotAllowed, self.to_string()),
+            ),
+            ApiError::TooManyUsernames => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::TooManyUsernames,
+                self.to_string(),
+            )),
+            ApiError::LobbyQuotaReached => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::LobbyQuotaReached,
+                self.to_string(),
+            )),
+            ApiError::NoGameState => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::NoGameState,
+                self.to_string(),
+            )),
+            ApiError::Policy { code, message } => {
+                debug!("Policy violation {code}: {message}");
+
+                HttpResponse::BadRequest().json(ApiErrorResponse {
+                    message: message.clone(),
+                    status_code: ApiStatusCode::PolicyViolation,
+                    policy_code: Some(*code),
+                })
+            }
+            ApiError::InvalidLogFilter => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::InvalidLogFilter,
+                self.to_string(),
+            )),
+            ApiError::HostCannotLeave => HttpResponse::BadRequest().json(ApiErrorResponse::new(
+                ApiStatusCode::HostCannotLeave,
+                self.to_string(),
+            )),
+            ApiError::InvalidObserverToken => HttpResponse::BadRequest().json(
+                ApiErrorResponse::new(ApiStatusCode::InvalidObserverToken, self.to_string()),
+            ),
         }
     }
 }
 
+impl From<NotifierError> for ApiError {
+    fn from(value: NotifierError) -> Self {
+        error!("{value}");
+        Self::InternalServerError
+    }
+}
+
 impl From<rorm::Error> for ApiError {
     fn from(value: rorm::Error) -> Self {
         Self::DatabaseError(value)
     }
 }
 
+/// Check whether a database error was caused by the violation of a unique constraint
+pub(crate) fn is_unique_violation(err: &rorm::Error) -> bool {
+    match err {
+        rorm::Error::SqlxError(err) => err
+            .as_database_error()
+            .map(|err| err.is_unique_violation())
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
 impl From<argon2::password_hash::Error> for ApiError {
     fn from(value: argon2::password_hash::Error) -> Self {
         Self::InvalidHash(value)
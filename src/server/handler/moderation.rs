@@ -0,0 +1,317 @@
+//! This module holds the endpoints for moderators and the admin endpoints to appoint them
+//! and to moderate chatrooms
+//!
+//! The moderation endpoints live in their own scope, which is restricted to accounts
+//! with the moderator flag by [ModeratorRequired](crate::server::middleware::ModeratorRequired).
+
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{delete, get, put, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::info;
+use rorm::{query, update, Database, FieldAccess, Model};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::chan::Notifier;
+use crate::models::{Account, ChatRoom};
+use crate::server::handler::{
+    stream_chat_history, ApiError, ApiErrorResponse, ApiResult, PathUuid, PlayerKickPath,
+    PlayerKickQuery,
+};
+use crate::service::notify::Outbox;
+use crate::service::{chat, lobby};
+
+/// The request to appoint or dismiss a moderator
+#[derive(Deserialize, ToSchema)]
+pub struct SetModeratorRequest {
+    moderator: bool,
+}
+
+/// Appoint an account as moderator or dismiss it
+///
+/// Moderators may use the endpoints below `/api/v2/moderation` with their session.
+///
+/// If the account doesn't exist, [ApiError::InvalidUuid] is returned.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Moderator flag was set"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = SetModeratorRequest,
+    security(("admin_token" = []))
+)]
+#[put("/accounts/{uuid}/moderator")]
+pub async fn set_moderator(
+    path: Path<PathUuid>,
+    req: Json<SetModeratorRequest>,
+    db: Data<Database>,
+) -> ApiResult<HttpResponse> {
+    let mut tx = db.start_transaction().await?;
+
+    query!(&mut tx, (Account::F.uuid,))
+        .condition(Account::F.uuid.equals(path.uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    update!(&mut tx, Account)
+        .condition(Account::F.uuid.equals(path.uuid))
+        .set(Account::F.moderator, req.moderator)
+        .exec()
+        .await?;
+
+    tx.commit().await?;
+
+    info!(
+        "Moderator flag of account {} set to {}",
+        path.uuid, req.moderator
+    );
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Kick a player from an open lobby as moderator
+///
+/// This works like `DELETE /api/v2/lobbies/{lobby_uuid}/{player_uuid}`, but doesn't
+/// require the executing account to own the lobby. The owner of a lobby can't be kicked,
+/// moderators have to close the lobby instead.
+///
+/// If `ban` is set, the player is banned from the lobby and can't join it again.
+///
+/// All players in the lobby as well as the kicked player will receive a [WsMessage::LobbyKick]
+/// message via websocket on success. A system message is posted to the chatroom of the lobby.
+///
+/// [WsMessage::LobbyKick]: crate::chan::WsMessage::LobbyKick
+#[utoipa::path(
+    tag = "Moderation",
+    context_path = "/api/v2/moderation",
+    responses(
+        (status = 200, description = "Player was kicked"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PlayerKickPath, PlayerKickQuery),
+    security(("session_cookie" = []))
+)]
+#[delete("/lobbies/{lobby_uuid}/{player_uuid}")]
+pub async fn moderate_kick_player(
+    path: Path<PlayerKickPath>,
+    options: Query<PlayerKickQuery>,
+    db: Data<Database>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let mut tx = db.start_transaction().await?;
+
+    let mut outbox = Outbox::new();
+    lobby::kick_player(
+        &mut tx,
+        &mut outbox,
+        path.lobby_uuid,
+        path.player_uuid,
+        options.ban,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    outbox.send(notifier.get_ref()).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The path of a message in a chatroom
+#[derive(Deserialize, IntoParams)]
+pub struct ChatMessagePath {
+    /// The chatroom
+    chat_uuid: Uuid,
+    /// The message
+    message_uuid: Uuid,
+}
+
+/// Delete a message from a chatroom
+///
+/// The message is removed from the history of the chatroom. All members of the chatroom
+/// will receive a [WsMessage::ChatMessageDeleted] message via websocket on success.
+///
+/// If the message doesn't belong to the chatroom, [ApiError::InvalidUuid] is returned.
+///
+/// [WsMessage::ChatMessageDeleted]: crate::chan::WsMessage::ChatMessageDeleted
+#[utoipa::path(
+    tag = "Moderation",
+    context_path = "/api/v2/moderation",
+    responses(
+        (status = 200, description = "Message was deleted"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(ChatMessagePath),
+    security(("session_cookie" = []))
+)]
+#[delete("/chats/{chat_uuid}/messages/{message_uuid}")]
+pub async fn moderate_delete_message(
+    path: Path<ChatMessagePath>,
+    db: Data<Database>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let mut tx = db.start_transaction().await?;
+
+    let mut outbox = Outbox::new();
+    chat::delete_message(&mut tx, &mut outbox, path.chat_uuid, path.message_uuid).await?;
+
+    tx.commit().await?;
+
+    outbox.send(notifier.get_ref()).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Retrieve the history of a chatroom as admin
+///
+/// The messages are streamed as newline delimited JSON (NDJSON), one
+/// [ChatMessage](crate::server::handler::ChatMessage) per line, from the oldest to the
+/// most recent message, like `GET /api/v2/chats/{uuid}/transcript`.
+///
+/// If the chatroom doesn't exist, [ApiError::InvalidUuid] is returned.
+#[utoipa::path(
+    tag = "Chats",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Returns one message per line", content_type = "application/x-ndjson", body = ChatMessage),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("admin_token" = []))
+)]
+#[get("/chats/{uuid}/messages")]
+pub async fn admin_get_chat_messages(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+) -> ApiResult<HttpResponse> {
+    query!(db.as_ref(), (ChatRoom::F.uuid,))
+        .condition(ChatRoom::F.uuid.equals(path.uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    Ok(stream_chat_history(db.into_inner(), path.uuid))
+}
+
+/// Delete a message from a chatroom as admin
+///
+/// This works like `DELETE /api/v2/moderation/chats/{chat_uuid}/messages/{message_uuid}`.
+/// All members of the chatroom will receive a [WsMessage::ChatMessageDeleted] message
+/// via websocket on success.
+///
+/// If the message doesn't belong to the chatroom, [ApiError::InvalidUuid] is returned.
+///
+/// [WsMessage::ChatMessageDeleted]: crate::chan::WsMessage::ChatMessageDeleted
+#[utoipa::path(
+    tag = "Chats",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Message was deleted"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(ChatMessagePath),
+    security(("admin_token" = []))
+)]
+#[delete("/chats/{chat_uuid}/messages/{message_uuid}")]
+pub async fn admin_delete_message(
+    path: Path<ChatMessagePath>,
+    db: Data<Database>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let mut tx = db.start_transaction().await?;
+
+    let mut outbox = Outbox::new();
+    chat::delete_message(&mut tx, &mut outbox, path.chat_uuid, path.message_uuid).await?;
+
+    tx.commit().await?;
+
+    outbox.send(notifier.get_ref()).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The path of a member of a chatroom
+#[derive(Deserialize, IntoParams)]
+pub struct ChatMemberPath {
+    /// The chatroom
+    chat_uuid: Uuid,
+    /// The account of the member
+    account_uuid: Uuid,
+}
+
+/// The request to mute or ban a member of a chatroom
+///
+/// Set `muted_until` to `null` and `banned` to `false` to lift the restrictions.
+#[derive(Deserialize, ToSchema)]
+pub struct SetChatRestrictionRequest {
+    /// The point in time until which the member may not send messages
+    muted_until: Option<DateTime<Utc>>,
+    /// Whether the member may neither send nor receive messages of the chatroom
+    banned: bool,
+}
+
+/// Mute or ban a member of a chatroom
+///
+/// Muted members can't send messages to the chatroom until `muted_until`, their attempts
+/// fail with [ApiError::ChatMuted]. Banned members can neither send messages to the
+/// chatroom nor read or receive its messages, until the ban is lifted. Both stay members
+/// of the chatroom, so their lobbies and games are not affected.
+///
+/// The member will receive a [WsMessage::ChatRestrictionChanged] message via websocket.
+///
+/// If the account is not a member of the chatroom, [ApiError::InvalidUuid] is returned.
+///
+/// [WsMessage::ChatRestrictionChanged]: crate::chan::WsMessage::ChatRestrictionChanged
+#[utoipa::path(
+    tag = "Chats",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Restrictions were set"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(ChatMemberPath),
+    request_body = SetChatRestrictionRequest,
+    security(("admin_token" = []))
+)]
+#[put("/chats/{chat_uuid}/members/{account_uuid}/restriction")]
+pub async fn set_chat_restriction(
+    path: Path<ChatMemberPath>,
+    req: Json<SetChatRestrictionRequest>,
+    db: Data<Database>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    let mut tx = db.start_transaction().await?;
+
+    let mut outbox = Outbox::new();
+    chat::set_restriction(
+        &mut tx,
+        &mut outbox,
+        path.chat_uuid,
+        path.account_uuid,
+        req.muted_until,
+        req.banned,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    outbox.send(notifier.get_ref()).await;
+
+    info!(
+        "Restrictions of account {} in chat {} set to muted until {:?}, banned {}",
+        path.account_uuid, path.chat_uuid, req.muted_until, req.banned
+    );
+
+    Ok(HttpResponse::Ok().finish())
+}
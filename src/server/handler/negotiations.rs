@@ -0,0 +1,374 @@
+//! Handler for negotiations between players of a game
+
+use actix_toolbox::tb_middleware::Session;
+use actix_web::web::{Data, Json, Path};
+use actix_web::{get, post, HttpResponse};
+use chrono::{DateTime, Utc};
+use rorm::fields::types::ForeignModelByField;
+use rorm::{and, insert, or, query, update, Database, FieldAccess, Model};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::chan::{Notifier, WsMessage};
+use crate::models::{
+    Account, GameAccount, Negotiation, NegotiationInsert, NegotiationKind, NegotiationState,
+};
+use crate::server::handler::{AccountResponse, ApiError, ApiErrorResponse, ApiResult, PathUuid};
+
+/// The request to propose a new negotiation to another player of the game
+///
+/// `terms` is defined by the client and must not be empty.
+/// `expires_at` must be in the future.
+#[derive(Deserialize, ToSchema)]
+pub struct CreateNegotiationRequest {
+    to: Uuid,
+    kind: NegotiationKind,
+    #[schema(example = "{\"give\":{\"gold\":100},\"take\":{\"iron\":2}}")]
+    terms: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// The response of a created negotiation
+#[derive(Serialize, ToSchema)]
+pub struct CreateNegotiationResponse {
+    negotiation_uuid: Uuid,
+}
+
+/// Propose a trade or a pact to another player of a game
+///
+/// Both the executing user and the recipient `to` must be players of the game.
+/// The recipient is notified via a [WsMessage::IncomingNegotiation] message.
+#[utoipa::path(
+    tag = "Negotiations",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Negotiation was proposed", body = CreateNegotiationResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    request_body = CreateNegotiationRequest,
+    security(("session_cookie" = []))
+)]
+#[post("/games/{uuid}/negotiations")]
+pub async fn create_negotiation(
+    path: Path<PathUuid>,
+    req: Json<CreateNegotiationRequest>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<CreateNegotiationResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    if req.to == uuid || req.terms.is_empty() || req.terms.len() > 4096 {
+        return Err(ApiError::InvalidNegotiation);
+    }
+
+    if req.expires_at <= Utc::now() {
+        return Err(ApiError::InvalidNegotiation);
+    }
+
+    let mut tx = db.start_transaction().await?;
+
+    // Check if the executing user and the recipient are playing the game
+    let players = query!(&mut tx, (GameAccount::F.player,))
+        .condition(and!(
+            GameAccount::F.game.equals(path.uuid),
+            or!(
+                GameAccount::F.player.equals(uuid),
+                GameAccount::F.player.equals(req.to)
+            )
+        ))
+        .all()
+        .await?;
+
+    if !players.iter().any(|(p,)| *p.key() == uuid) {
+        return Err(ApiError::GameNotFound);
+    }
+    if !players.iter().any(|(p,)| *p.key() == req.to) {
+        return Err(ApiError::InvalidPlayerUuid);
+    }
+
+    let negotiation_uuid = insert!(&mut tx, NegotiationInsert)
+        .return_primary_key()
+        .single(&NegotiationInsert {
+            uuid: Uuid::new_v4(),
+            game: ForeignModelByField::Key(path.uuid),
+            from: ForeignModelByField::Key(uuid),
+            to: ForeignModelByField::Key(req.to),
+            kind: req.kind,
+            terms: req.terms.clone(),
+            state: NegotiationState::Pending,
+            expires_at: req.expires_at.naive_utc(),
+        })
+        .await?;
+
+    let (uuid, username, display_name) = query!(
+        &mut tx,
+        (
+            Account::F.uuid,
+            Account::F.username,
+            Account::F.display_name
+        )
+    )
+    .condition(Account::F.uuid.equals(uuid))
+    .optional()
+    .await?
+    .ok_or(ApiError::SessionCorrupt)?;
+
+    tx.commit().await?;
+
+    let msg = WsMessage::IncomingNegotiation {
+        negotiation_uuid,
+        game_uuid: path.uuid,
+        from: AccountResponse {
+            uuid,
+            username,
+            display_name,
+        },
+        kind: req.kind,
+        terms: req.terms.clone(),
+        expires_at: req.expires_at,
+    };
+
+    notifier.send(req.to, msg).await;
+
+    Ok(Json(CreateNegotiationResponse { negotiation_uuid }))
+}
+
+/// A single negotiation
+///
+/// A negotiation that is still `pending` after `expires_at` has passed
+/// can no longer be answered.
+#[derive(Serialize, ToSchema)]
+pub struct NegotiationResponse {
+    uuid: Uuid,
+    from: AccountResponse,
+    to: AccountResponse,
+    kind: NegotiationKind,
+    #[schema(example = "{\"give\":{\"gold\":100},\"take\":{\"iron\":2}}")]
+    terms: String,
+    state: NegotiationState,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+/// The negotiations of a game the executing user is part of
+#[derive(Serialize, ToSchema)]
+pub struct GetNegotiationsResponse {
+    negotiations: Vec<NegotiationResponse>,
+}
+
+/// Retrieve all negotiations of a game the executing user has proposed or received
+#[utoipa::path(
+    tag = "Negotiations",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the negotiations of the game", body = GetNegotiationsResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[get("/games/{uuid}/negotiations")]
+pub async fn get_negotiations(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<GetNegotiationsResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    let negotiations = query!(
+        &mut tx,
+        (
+            Negotiation::F.uuid,
+            Negotiation::F.from.uuid,
+            Negotiation::F.from.username,
+            Negotiation::F.from.display_name,
+            Negotiation::F.to.uuid,
+            Negotiation::F.to.username,
+            Negotiation::F.to.display_name,
+            Negotiation::F.kind,
+            Negotiation::F.terms,
+            Negotiation::F.state,
+            Negotiation::F.created_at,
+            Negotiation::F.expires_at,
+        )
+    )
+    .condition(and!(
+        Negotiation::F.game.equals(path.uuid),
+        or!(
+            Negotiation::F.from.equals(uuid),
+            Negotiation::F.to.equals(uuid)
+        )
+    ))
+    .all()
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(GetNegotiationsResponse {
+        negotiations: negotiations
+            .into_iter()
+            .map(
+                |(
+                    uuid,
+                    from_uuid,
+                    from_username,
+                    from_display_name,
+                    to_uuid,
+                    to_username,
+                    to_display_name,
+                    kind,
+                    terms,
+                    state,
+                    created_at,
+                    expires_at,
+                )| NegotiationResponse {
+                    uuid,
+                    from: AccountResponse {
+                        uuid: from_uuid,
+                        username: from_username,
+                        display_name: from_display_name,
+                    },
+                    to: AccountResponse {
+                        uuid: to_uuid,
+                        username: to_username,
+                        display_name: to_display_name,
+                    },
+                    kind,
+                    terms,
+                    state,
+                    created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
+                    expires_at: DateTime::from_naive_utc_and_offset(expires_at, Utc),
+                },
+            )
+            .collect(),
+    }))
+}
+
+/// Accept a negotiation
+///
+/// Only the recipient of a pending, not yet expired negotiation can accept it.
+/// The proposer is notified via a [WsMessage::NegotiationChanged] message.
+#[utoipa::path(
+    tag = "Negotiations",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Negotiation was accepted"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[post("/negotiations/{uuid}/accept")]
+pub async fn accept_negotiation(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    answer_negotiation(
+        path.uuid,
+        NegotiationState::Accepted,
+        &db,
+        &session,
+        notifier.get_ref(),
+    )
+    .await
+}
+
+/// Decline a negotiation
+///
+/// Only the recipient of a pending, not yet expired negotiation can decline it.
+/// The proposer is notified via a [WsMessage::NegotiationChanged] message.
+#[utoipa::path(
+    tag = "Negotiations",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Negotiation was declined"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("session_cookie" = []))
+)]
+#[post("/negotiations/{uuid}/decline")]
+pub async fn decline_negotiation(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    session: Session,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<HttpResponse> {
+    answer_negotiation(
+        path.uuid,
+        NegotiationState::Declined,
+        &db,
+        &session,
+        notifier.get_ref(),
+    )
+    .await
+}
+
+async fn answer_negotiation(
+    negotiation_uuid: Uuid,
+    state: NegotiationState,
+    db: &Database,
+    session: &Session,
+    notifier: &dyn Notifier,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    let negotiation = query!(&mut tx, Negotiation)
+        .condition(Negotiation::F.uuid.equals(negotiation_uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    // Check if the executing user is the recipient of the negotiation
+    if *negotiation.to.key() != uuid {
+        return Err(ApiError::MissingPrivileges);
+    }
+
+    if negotiation.state != NegotiationState::Pending
+        || negotiation.expires_at <= Utc::now().naive_utc()
+    {
+        return Err(ApiError::NegotiationNotPending);
+    }
+
+    // Only answer the negotiation if it is still pending, a concurrent request
+    // may have answered it since it was queried
+    let updated = update!(&mut tx, Negotiation)
+        .condition(and!(
+            Negotiation::F.uuid.equals(negotiation.uuid),
+            Negotiation::F.state.equals(NegotiationState::Pending),
+            Negotiation::F
+                .expires_at
+                .greater_than(Utc::now().naive_utc())
+        ))
+        .set(Negotiation::F.state, state)
+        .exec()
+        .await?;
+    if updated == 0 {
+        return Err(ApiError::NegotiationNotPending);
+    }
+
+    tx.commit().await?;
+
+    let msg = WsMessage::NegotiationChanged {
+        negotiation_uuid: negotiation.uuid,
+        game_uuid: *negotiation.game.key(),
+        state,
+    };
+
+    notifier.send(*negotiation.from.key(), msg).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,197 @@
+//! This module holds the endpoints of the observer tokens of former players
+
+use std::path::Path as StdPath;
+
+use actix_toolbox::tb_middleware::Session;
+use actix_web::http::header::{ContentEncoding, ContentType};
+use actix_web::web::{Data, Path, Query};
+use actix_web::{delete, get, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use log::error;
+use rand::{thread_rng, Rng};
+use rorm::db::Transaction;
+use rorm::fields::types::ForeignModelByField;
+use rorm::{and, insert, query, Database, FieldAccess, Model};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::fs::read;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+use crate::models::{Game, ObserverToken, ObserverTokenInsert};
+use crate::server::handler::{game_data_filename, ApiError, ApiErrorResponse, ApiResult, PathUuid};
+use crate::server::middleware::{ConcurrencyLimit, LimitedOperation};
+use crate::server::RuntimeSettings;
+
+/// Only the hash of a token is stored, so a leaked database doesn't grant access to games
+fn hash_observer_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Grant an observer token to a player that left a game
+///
+/// **Parameter**:
+/// - `tx`: The transaction the token should be stored in
+/// - `game`: The game that can be watched with the token
+/// - `account`: The former player
+/// - `lifetime`: The time in seconds the token is valid
+///
+/// Returns the token and the point in time it expires.
+pub(crate) async fn grant_observer_token(
+    tx: &mut Transaction,
+    game: Uuid,
+    account: Uuid,
+    lifetime: u64,
+) -> Result<(String, DateTime<Utc>), rorm::Error> {
+    let token = hex::encode(thread_rng().gen::<[u8; 32]>());
+    let expires_at = Utc::now() + Duration::seconds(lifetime as i64);
+
+    insert!(tx, ObserverTokenInsert)
+        .return_nothing()
+        .single(&ObserverTokenInsert {
+            uuid: Uuid::new_v4(),
+            game: ForeignModelByField::Key(game),
+            account: ForeignModelByField::Key(account),
+            token_hash: hash_observer_token(&token),
+            expires_at: expires_at.naive_utc(),
+        })
+        .await?;
+
+    Ok((token, expires_at))
+}
+
+/// The observer token of a former player
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ObserverTokenQuery {
+    /// The token that was returned when leaving the game
+    token: String,
+}
+
+/// Download the current state of a game with an observer token
+///
+/// The tokens are granted to players leaving a game with `POST /api/v2/games/{uuid}/leave`,
+/// if the `grant_observer_tokens` setting of the game is enabled. They don't require a
+/// session. The state is returned as is, without any JSON wrapping.
+///
+/// If the token is unknown, was revoked by the host or expired,
+/// [ApiError::InvalidObserverToken] is returned. If no game state was uploaded for the game
+/// yet, [ApiError::NoGameState] is returned.
+#[utoipa::path(
+    tag = "Games",
+    responses(
+        (status = 200, description = "Returns the state of the game", content_type = "application/octet-stream", body = String),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+        (status = 503, description = "Too many concurrent requests", body = ApiErrorResponse),
+    ),
+    params(PathUuid, ObserverTokenQuery),
+)]
+#[get(
+    "/api/v2/observe/games/{uuid}",
+    wrap = "ConcurrencyLimit(LimitedOperation::GameDownload)"
+)]
+pub async fn observe_game(
+    path: Path<PathUuid>,
+    observer: Query<ObserverTokenQuery>,
+    db: Data<Database>,
+    settings: Data<RuntimeSettings>,
+) -> ApiResult<HttpResponse> {
+    let game_uuid = path.uuid;
+
+    let (data_id,) = query!(db.as_ref(), (ObserverToken::F.game.data_id,))
+        .condition(and!(
+            ObserverToken::F.game.equals(game_uuid),
+            ObserverToken::F
+                .token_hash
+                .equals(hash_observer_token(&observer.token)),
+            ObserverToken::F
+                .expires_at
+                .greater_than(Utc::now().naive_utc())
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidObserverToken)?;
+
+    let filename = game_data_filename(game_uuid, data_id);
+    let content = match read(StdPath::new(&settings.game_data_path).join(&filename)).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(ApiError::NoGameState);
+        }
+        Err(err) => {
+            error!("Game data expected in '{filename}' couldn't be read: {err}");
+            return Err(ApiError::InternalServerError);
+        }
+    };
+
+    // The game state is compressed by the client already
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::octet_stream())
+        .insert_header(ContentEncoding::Identity)
+        .body(content))
+}
+
+/// The path parameter to revoke the observer token of a former player
+#[derive(Deserialize, IntoParams)]
+pub struct RevokeObserverTokenPath {
+    uuid: Uuid,
+    player_uuid: Uuid,
+}
+
+/// Revoke the observer token of a former player
+///
+/// This endpoint can only be used by the host of the game.
+///
+/// If the former player has no observer token for the game, [ApiError::InvalidUuid]
+/// is returned.
+#[utoipa::path(
+    tag = "Games",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Observer token was revoked"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(RevokeObserverTokenPath),
+    security(("session_cookie" = []))
+)]
+#[delete("/games/{uuid}/observerTokens/{player_uuid}")]
+pub async fn revoke_observer_token(
+    path: Path<RevokeObserverTokenPath>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+    let game_uuid = path.uuid;
+
+    let mut tx = db.start_transaction().await?;
+
+    let (host,) = query!(&mut tx, (Game::F.host,))
+        .condition(and!(
+            Game::F.uuid.equals(game_uuid),
+            Game::F.current_players.player.uuid.equals(uuid)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::GameNotFound)?;
+
+    // Check if the executing user is the host of the game
+    if host.map(|x| *x.key()) != Some(uuid) {
+        return Err(ApiError::MissingPrivileges);
+    }
+
+    let deleted = rorm::delete!(&mut tx, ObserverToken)
+        .condition(and!(
+            ObserverToken::F.game.equals(game_uuid),
+            ObserverToken::F.account.equals(path.player_uuid)
+        ))
+        .await?;
+    if deleted == 0 {
+        return Err(ApiError::InvalidUuid);
+    }
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,254 @@
+//! Handler to synchronize the state of a client
+
+use actix_toolbox::tb_middleware::Session;
+use actix_web::get;
+use actix_web::web::{Data, Json, Query};
+use chrono::{DateTime, Utc};
+use rorm::{and, query, Database, FieldAccess, Model};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::models::{ChatRoomMember, Friend, GameAccount, Invite, Lobby, LobbyAccount};
+use crate::server::handler::{ApiError, ApiErrorResponse, ApiResult};
+
+/// A game the executing account is playing
+///
+/// `your_turn` is `true` if the executing account is the current player of the game.
+/// For games whose current player is unknown, e.g. because no turn was ended yet, it is
+/// `true` if the latest game state was uploaded by another player.
+#[derive(Serialize, ToSchema)]
+pub struct SyncGame {
+    game_uuid: Uuid,
+    #[schema(example = 1337)]
+    game_data_id: u64,
+    last_player_uuid: Uuid,
+    your_turn: bool,
+}
+
+/// A chat room the executing account is member of
+#[derive(Serialize, ToSchema)]
+pub struct SyncChat {
+    chat_uuid: Uuid,
+    last_message_uuid: Option<Uuid>,
+}
+
+/// A compact snapshot of the state of the executing account
+///
+/// - `lobbies` are the lobbies the account owns or has joined
+/// - `games` are the games the account is playing
+/// - `chats` are the chat rooms the account is member of
+/// - `invites` are the uuids of the pending invites for the account
+/// - `friend_requests` are the uuids of the accounts that sent a pending friend request
+#[derive(Serialize, ToSchema)]
+pub struct SyncResponse {
+    lobbies: Vec<Uuid>,
+    games: Vec<SyncGame>,
+    chats: Vec<SyncChat>,
+    invites: Vec<Uuid>,
+    friend_requests: Vec<Uuid>,
+}
+
+/// Retrieve a compact snapshot of the state of the executing account
+///
+/// This is intended to be used by clients after (re)connecting the websocket to
+/// reconcile their local state without querying all endpoints separately.
+///
+/// The server doesn't track which chat messages were read, so clients should compare
+/// `last_message_uuid` of a chat with the last message they know.
+#[utoipa::path(
+    tag = "Sync",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the snapshot of the executing account", body = SyncResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    security(("session_cookie" = []))
+)]
+#[get("/sync")]
+pub async fn get_sync(db: Data<Database>, session: Session) -> ApiResult<Json<SyncResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let mut tx = db.start_transaction().await?;
+
+    let owned_lobbies = query!(&mut tx, (Lobby::F.uuid,))
+        .condition(Lobby::F.owner.equals(uuid))
+        .all()
+        .await?;
+    let joined_lobbies = query!(&mut tx, (LobbyAccount::F.lobby,))
+        .condition(LobbyAccount::F.player.equals(uuid))
+        .all()
+        .await?;
+
+    let games = query!(
+        &mut tx,
+        (
+            GameAccount::F.game.uuid,
+            GameAccount::F.game.data_id,
+            GameAccount::F.game.updated_by,
+            GameAccount::F.game.current_player,
+        )
+    )
+    .condition(GameAccount::F.player.equals(uuid))
+    .all()
+    .await?;
+
+    let chats = query!(
+        &mut tx,
+        (
+            ChatRoomMember::F.chat_room.uuid,
+            ChatRoomMember::F.chat_room.last_message_uuid,
+        )
+    )
+    .condition(ChatRoomMember::F.member.equals(uuid))
+    .all()
+    .await?;
+
+    let invites = query!(&mut tx, (Invite::F.uuid,))
+        .condition(Invite::F.to.equals(uuid))
+        .all()
+        .await?;
+
+    let friend_requests = query!(&mut tx, (Friend::F.from,))
+        .condition(and!(
+            Friend::F.is_request.equals(true),
+            Friend::F.to.equals(uuid)
+        ))
+        .all()
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(SyncResponse {
+        lobbies: owned_lobbies
+            .into_iter()
+            .map(|(lobby,)| lobby)
+            .chain(joined_lobbies.into_iter().map(|(lobby,)| *lobby.key()))
+            .collect(),
+        games: games
+            .into_iter()
+            .map(
+                |(game_uuid, data_id, updated_by, current_player)| SyncGame {
+                    game_uuid,
+                    game_data_id: data_id as u64,
+                    last_player_uuid: *updated_by.key(),
+                    your_turn: match current_player {
+                        Some(current_player) => *current_player.key() == uuid,
+                        None => *updated_by.key() != uuid,
+                    },
+                },
+            )
+            .collect(),
+        chats: chats
+            .into_iter()
+            .map(|(chat_uuid, last_message_uuid)| SyncChat {
+                chat_uuid,
+                last_message_uuid,
+            })
+            .collect(),
+        invites: invites.into_iter().map(|(invite,)| invite).collect(),
+        friend_requests: friend_requests
+            .into_iter()
+            .map(|(from,)| *from.key())
+            .collect(),
+    }))
+}
+
+/// The query parameters to retrieve the changed games
+///
+/// `since` is the `cursor` of the previous response. Clients without a cursor should
+/// use `GET /api/v2/sync` instead.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GameChangesQuery {
+    since: DateTime<Utc>,
+}
+
+/// A game that changed since the cursor
+#[derive(Serialize, ToSchema)]
+pub struct GameChange {
+    game_uuid: Uuid,
+    #[schema(example = 1337)]
+    game_data_id: u64,
+    last_player_uuid: Uuid,
+    updated_at: DateTime<Utc>,
+}
+
+/// The games of the executing account that changed since the cursor
+///
+/// `cursor` should be passed as `since` to the next request. It is the latest `updated_at`
+/// of the returned games, or the passed `since` if no game changed.
+#[derive(Serialize, ToSchema)]
+pub struct GameChangesResponse {
+    games: Vec<GameChange>,
+    cursor: DateTime<Utc>,
+}
+
+/// Retrieve the games of the executing account that changed since a point in time
+///
+/// This allows clients that were offline to catch up with a single request.
+/// A game counts as changed if a new game state was uploaded. Changes of the game
+/// settings are not tracked, they are only announced through the websocket.
+///
+/// Games the executing account left or that were deleted in the meantime are not
+/// part of the response, use `GET /api/v2/sync` to retrieve the current list of games.
+#[utoipa::path(
+    tag = "Sync",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the changed games", body = GameChangesResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(GameChangesQuery),
+    security(("session_cookie" = []))
+)]
+#[get("/games/changes")]
+pub async fn get_game_changes(
+    options: Query<GameChangesQuery>,
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<GameChangesResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let games = query!(
+        db.as_ref(),
+        (
+            GameAccount::F.game.uuid,
+            GameAccount::F.game.data_id,
+            GameAccount::F.game.updated_by,
+            GameAccount::F.game.updated_at,
+        )
+    )
+    .condition(and!(
+        GameAccount::F.player.equals(uuid),
+        GameAccount::F
+            .game
+            .updated_at
+            .greater_than(options.since.naive_utc())
+    ))
+    .all()
+    .await?;
+
+    // The cursor is derived from the database, so it doesn't depend on the clock of the server
+    let cursor = games
+        .iter()
+        .map(|(_, _, _, updated_at)| *updated_at)
+        .max()
+        .map(|x| DateTime::from_naive_utc_and_offset(x, Utc))
+        .unwrap_or(options.since);
+
+    Ok(Json(GameChangesResponse {
+        games: games
+            .into_iter()
+            .map(|(game_uuid, data_id, updated_by, updated_at)| GameChange {
+                game_uuid,
+                game_data_id: data_id as u64,
+                last_player_uuid: *updated_by.key(),
+                updated_at: DateTime::from_naive_utc_and_offset(updated_at, Utc),
+            })
+            .collect(),
+        cursor,
+    }))
+}
@@ -0,0 +1,88 @@
+//! This module holds the admin endpoint to send a test notification to an account
+
+use actix_web::post;
+use actix_web::web::{Data, Json, Path};
+use log::{info, warn};
+use rorm::{query, Database, FieldAccess, Model};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::chan::{Notifier, WsMessage};
+use crate::models::Account;
+use crate::server::handler::{ApiError, ApiErrorResponse, ApiResult, PathUuid};
+
+/// The result of the delivery of a notification through a channel
+#[derive(Serialize, ToSchema, Copy, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum DeliveryResult {
+    /// The notification was handed to at least one connection of the account
+    Delivered,
+    /// The account has no open connection, so the notification was dropped
+    NoConnection,
+    /// The channel is not available on the server
+    Unavailable,
+}
+
+/// The delivery results of a test notification
+#[derive(Serialize, ToSchema)]
+pub struct TestNotificationResponse {
+    /// The delivery through the websocket and server-sent event connections of the account
+    ///
+    /// This is the only channel the server delivers notifications through.
+    websocket: DeliveryResult,
+}
+
+/// Send a test notification to an account
+///
+/// A [WsMessage::TestNotification] is sent through the same path as every other
+/// notification, so this can be used to check why an account doesn't receive
+/// e.g. turn notifications.
+///
+/// If the account doesn't exist, [ApiError::InvalidUuid] is returned.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Returns the delivery results", body = TestNotificationResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    params(PathUuid),
+    security(("admin_token" = []))
+)]
+#[post("/accounts/{uuid}/testNotify")]
+pub async fn send_test_notification(
+    path: Path<PathUuid>,
+    db: Data<Database>,
+    notifier: Data<dyn Notifier>,
+) -> ApiResult<Json<TestNotificationResponse>> {
+    query!(db.as_ref(), (Account::F.uuid,))
+        .condition(Account::F.uuid.equals(path.uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    let websocket = match notifier.is_online(path.uuid).await {
+        Ok(true) => {
+            notifier
+                .send(
+                    path.uuid,
+                    WsMessage::TestNotification {
+                        message: "This is a test notification sent by the server administrator"
+                            .to_string(),
+                    },
+                )
+                .await;
+            DeliveryResult::Delivered
+        }
+        Ok(false) => DeliveryResult::NoConnection,
+        Err(err) => {
+            warn!("Could not send test notification: {err}");
+            DeliveryResult::Unavailable
+        }
+    };
+
+    info!("Test notification for account {}: {websocket:?}", path.uuid);
+
+    Ok(Json(TestNotificationResponse { websocket }))
+}
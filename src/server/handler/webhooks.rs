@@ -0,0 +1,177 @@
+//! This module holds the endpoints to manage the personal webhook of an account
+
+use actix_toolbox::tb_middleware::Session;
+use actix_web::web::{Data, Json};
+use actix_web::{delete, get, put, HttpResponse};
+use chrono::{DateTime, Utc};
+use rand::{thread_rng, Rng};
+use rorm::fields::types::ForeignModelByField;
+use rorm::{insert, query, Database, FieldAccess, Model};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::chan::WebhookTarget;
+use crate::models::{AccountWebhook, AccountWebhookInsert};
+use crate::server::handler::{ApiError, ApiErrorResponse, ApiResult};
+use crate::server::RuntimeSettings;
+
+/// The personal webhook of an account
+#[derive(Serialize, ToSchema)]
+pub struct WebhookResponse {
+    /// The url the events are posted to
+    #[schema(example = "https://automation.example.com/runciv")]
+    url: String,
+    /// The point in time the webhook was registered
+    created_at: DateTime<Utc>,
+}
+
+/// The personal webhook of an account, if it registered one
+#[derive(Serialize, ToSchema)]
+pub struct GetWebhookResponse {
+    webhook: Option<WebhookResponse>,
+}
+
+/// Retrieve the personal webhook of the currently logged-in account
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Returns the webhook", body = GetWebhookResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    security(("session_cookie" = []))
+)]
+#[get("/accounts/me/webhook")]
+pub async fn get_webhook(
+    db: Data<Database>,
+    session: Session,
+) -> ApiResult<Json<GetWebhookResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    let webhook = query!(
+        db.as_ref(),
+        (AccountWebhook::F.url, AccountWebhook::F.created_at)
+    )
+    .condition(AccountWebhook::F.account.equals(uuid))
+    .optional()
+    .await?
+    .map(|(url, created_at)| WebhookResponse {
+        url,
+        created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
+    });
+
+    Ok(Json(GetWebhookResponse { webhook }))
+}
+
+/// The request to register a personal webhook
+#[derive(Deserialize, ToSchema)]
+pub struct SetWebhookRequest {
+    #[schema(example = "https://automation.example.com/runciv")]
+    url: String,
+}
+
+/// The secret of a newly registered webhook
+#[derive(Serialize, ToSchema)]
+pub struct SetWebhookResponse {
+    /// The key the deliveries are signed with
+    ///
+    /// The secret can't be retrieved again, register the webhook again to get a new one.
+    secret: String,
+}
+
+/// Register a personal webhook for the currently logged-in account
+///
+/// The [WsMessage::YourTurn], [WsMessage::IncomingInvite] and [WsMessage::IncomingGameInvite]
+/// messages of the account are posted to `url` as JSON, in addition to their delivery via
+/// websocket. Deliveries are not retried and limited per hour by the server.
+///
+/// Every delivery carries the unix timestamp in the `X-Runciv-Timestamp` header and the
+/// signature `sha256=<hex>` in the `X-Runciv-Signature` header. The signature is the
+/// HMAC-SHA256 of `{timestamp}.{body}` with the returned secret as key.
+///
+/// A previously registered webhook is replaced and its secret becomes invalid.
+///
+/// If webhooks are disabled on the server (see `GET /api/v2/capabilities`),
+/// [ApiError::WebhooksDisabled] is returned. If `url` isn't a https url with at most 2048
+/// characters or its host doesn't resolve to public addresses only,
+/// [ApiError::InvalidWebhookUrl] is returned. The host is checked again before every
+/// delivery and redirects are not followed.
+///
+/// [WsMessage::YourTurn]: crate::chan::WsMessage::YourTurn
+/// [WsMessage::IncomingInvite]: crate::chan::WsMessage::IncomingInvite
+/// [WsMessage::IncomingGameInvite]: crate::chan::WsMessage::IncomingGameInvite
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Webhook was registered", body = SetWebhookResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    request_body = SetWebhookRequest,
+    security(("session_cookie" = []))
+)]
+#[put("/accounts/me/webhook")]
+pub async fn set_webhook(
+    req: Json<SetWebhookRequest>,
+    db: Data<Database>,
+    session: Session,
+    runtime_settings: Data<RuntimeSettings>,
+) -> ApiResult<Json<SetWebhookResponse>> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    if !runtime_settings.account_webhooks {
+        return Err(ApiError::WebhooksDisabled);
+    }
+
+    let url = req.into_inner().url.trim().to_string();
+    if WebhookTarget::resolve(&url).await.is_none() {
+        return Err(ApiError::InvalidWebhookUrl);
+    }
+
+    let secret = hex::encode(thread_rng().gen::<[u8; 32]>());
+
+    let mut tx = db.start_transaction().await?;
+
+    rorm::delete!(&mut tx, AccountWebhook)
+        .condition(AccountWebhook::F.account.equals(uuid))
+        .await?;
+
+    insert!(&mut tx, AccountWebhookInsert)
+        .return_nothing()
+        .single(&AccountWebhookInsert {
+            uuid: Uuid::new_v4(),
+            account: ForeignModelByField::Key(uuid),
+            url,
+            secret: secret.clone(),
+        })
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(SetWebhookResponse { secret }))
+}
+
+/// Remove the personal webhook of the currently logged-in account
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2",
+    responses(
+        (status = 200, description = "Webhook was removed"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    security(("session_cookie" = []))
+)]
+#[delete("/accounts/me/webhook")]
+pub async fn delete_webhook(db: Data<Database>, session: Session) -> ApiResult<HttpResponse> {
+    let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
+
+    rorm::delete!(db.as_ref(), AccountWebhook)
+        .condition(AccountWebhook::F.account.equals(uuid))
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -6,28 +6,266 @@ use std::time::{Duration, Instant};
 use actix_toolbox::tb_middleware::Session;
 use actix_toolbox::ws;
 use actix_toolbox::ws::{MailboxError, Message};
-use actix_web::web::{Data, Payload};
-use actix_web::{get, HttpRequest, HttpResponse};
+use actix_web::body::MessageBody;
+use actix_web::web::{Data, Payload, Query};
+use actix_web::{get, HttpRequest, HttpResponse, ResponseError};
 use bytes::Bytes;
 use bytestring::ByteString;
 use log::{debug, error, warn};
-use once_cell::sync::Lazy;
+use rorm::Database;
+use serde::Deserialize;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
-use crate::chan::{WsManagerChan, WsManagerMessage, WsMessage};
+use crate::chan::{
+    ConnectionOptions, ConnectionSubscriptions, Notifier, WsEnvelope, WsManagerChan,
+    WsManagerMessage, WsMessage,
+};
 use crate::invalid_msg;
-use crate::server::handler::{ApiError, ApiErrorResponse};
+use crate::server::handler::{ApiError, ApiErrorResponse, ApiResult, ApiStatusCode, ChatMessage};
+use crate::service::chat;
+use crate::service::membership::presence_visible_to;
+use crate::service::notify::Outbox;
+use crate::service::translation::{spawn_translations, Translator};
 
-struct CommonMessages {
-    invalid_message: ByteString,
+/// Serialize a new [WsMessage::InvalidMessage]
+///
+/// As every envelope carries a unique id, the message can't be cached.
+fn invalid_message() -> ByteString {
+    serialize(WsMessage::InvalidMessage)
 }
 
-static COMMON: Lazy<CommonMessages> = Lazy::new(|| CommonMessages {
+/// Serialize a message in a new [WsEnvelope]
+fn serialize(msg: WsMessage) -> ByteString {
     // Fine as we can't do anything here, if [WsMessage] does not want to serialize anymore
     #[allow(clippy::unwrap_used)]
-    invalid_message: ByteString::from(serde_json::to_string(&WsMessage::InvalidMessage).unwrap()),
-});
+    ByteString::from(serde_json::to_string(&WsEnvelope::new(msg)).unwrap())
+}
+
+/// The part of an incoming message that is required to identify it
+#[derive(Deserialize)]
+struct IncomingMessage {
+    #[serde(rename = "type")]
+    msg_type: String,
+}
+
+/// The messages a client may send via websocket
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "content", rename_all = "camelCase")]
+enum ClientMessage {
+    /// Subscribe to the presence of the given accounts
+    SubscribePresence { accounts: Vec<Uuid> },
+    /// Unsubscribe from the presence of the given accounts
+    UnsubscribePresence { accounts: Vec<Uuid> },
+    /// Subscribe this connection to the changes of the lobby list
+    SubscribeLobbyList,
+    /// Unsubscribe this connection from the changes of the lobby list
+    UnsubscribeLobbyList,
+    /// Send a message to a chatroom
+    SendChatMessage {
+        chat_uuid: Uuid,
+        message: String,
+        #[serde(default)]
+        request_id: Option<String>,
+    },
+    /// Acknowledge the messages of a chatroom up to a sequence number
+    AckChatMessages { chat_uuid: Uuid, sequence: i64 },
+    /// Resend the messages of all chatrooms that weren't acknowledged yet
+    ResumeChats,
+}
+
+/// The maximum number of accounts in a single presence request
+const MAX_PRESENCE_ACCOUNTS: usize = 256;
+
+/// Handle a message sent by the client
+///
+/// Returns `false` if the message is invalid.
+async fn handle_client_message(
+    msg: ClientMessage,
+    account: Uuid,
+    subscriptions: &ConnectionSubscriptions,
+    db: &Database,
+    ws_manager_chan: &WsManagerChan,
+) -> bool {
+    let manager_msg = match msg {
+        ClientMessage::SubscribePresence { accounts } => {
+            if accounts.len() > MAX_PRESENCE_ACCOUNTS {
+                return false;
+            }
+
+            let visible = match db.start_transaction().await {
+                Ok(mut tx) => match presence_visible_to(&mut tx, account, accounts).await {
+                    Ok(visible) => {
+                        if let Err(err) = tx.commit().await {
+                            error!("Database error: {err}");
+                        }
+                        visible
+                    }
+                    Err(err) => {
+                        error!("Database error: {err}");
+                        return true;
+                    }
+                },
+                Err(err) => {
+                    error!("Database error: {err}");
+                    return true;
+                }
+            };
+
+            WsManagerMessage::SubscribePresence(account, visible)
+        }
+        ClientMessage::UnsubscribePresence { accounts } => {
+            WsManagerMessage::UnsubscribePresence(account, accounts)
+        }
+        ClientMessage::SubscribeLobbyList => {
+            subscriptions.set_lobby_list(true);
+            return true;
+        }
+        ClientMessage::UnsubscribeLobbyList => {
+            subscriptions.set_lobby_list(false);
+            return true;
+        }
+        ClientMessage::AckChatMessages {
+            chat_uuid,
+            sequence,
+        } => {
+            return match db.start_transaction().await {
+                Ok(mut tx) => match chat::ack_messages(&mut tx, chat_uuid, account, sequence).await
+                {
+                    Ok(()) => {
+                        if let Err(err) = tx.commit().await {
+                            error!("Database error: {err}");
+                        }
+                        true
+                    }
+                    Err(ApiError::DatabaseError(err)) => {
+                        error!("Database error: {err}");
+                        true
+                    }
+                    Err(_) => false,
+                },
+                Err(err) => {
+                    error!("Database error: {err}");
+                    true
+                }
+            };
+        }
+        // Chat messages and resumptions are answered by the receive loop
+        ClientMessage::SendChatMessage { .. } | ClientMessage::ResumeChats => return false,
+    };
+
+    if let Err(err) = ws_manager_chan.send(manager_msg).await {
+        warn!("Could not send to ws_manager_chan: {err}");
+    }
+
+    true
+}
+
+/// The body of the HTTP response of an [ApiError]
+#[derive(Deserialize)]
+struct ErrorBody {
+    status_code: u16,
+    message: String,
+}
+
+/// Send a chat message on behalf of the client
+///
+/// Works like `POST /api/v2/chats/{uuid}` and returns the reply to the client.
+async fn handle_chat_message(
+    chat_uuid: Uuid,
+    message: String,
+    request_id: Option<String>,
+    account: Uuid,
+    db: &Data<Database>,
+    notifier: &Data<dyn Notifier>,
+    translator: &Data<Option<Translator>>,
+) -> WsMessage {
+    match send_chat_message(chat_uuid, message, account, db, notifier, translator).await {
+        Ok(message) => WsMessage::ChatMessageSent {
+            request_id,
+            chat_uuid,
+            message,
+        },
+        Err(err) => {
+            // The error is converted like in the HTTP API, so clients can handle both the same
+            let response = err.error_response();
+            let body = response
+                .into_body()
+                .try_into_bytes()
+                .ok()
+                .and_then(|body| serde_json::from_slice::<ErrorBody>(&body).ok())
+                .unwrap_or(ErrorBody {
+                    status_code: ApiStatusCode::InternalServerError as u16,
+                    message: err.to_string(),
+                });
+
+            WsMessage::ChatMessageFailed {
+                request_id,
+                chat_uuid,
+                status_code: body.status_code,
+                message: body.message,
+            }
+        }
+    }
+}
+
+async fn send_chat_message(
+    chat_uuid: Uuid,
+    message: String,
+    account: Uuid,
+    db: &Data<Database>,
+    notifier: &Data<dyn Notifier>,
+    translator: &Data<Option<Translator>>,
+) -> ApiResult<ChatMessage> {
+    let mut tx = db.start_transaction().await?;
+
+    let mut outbox = Outbox::new();
+    let sent = chat::send_message(&mut tx, &mut outbox, chat_uuid, account, message).await?;
+
+    tx.commit().await?;
+
+    outbox.send(notifier.get_ref()).await;
+
+    spawn_translations(
+        translator.clone().into_inner(),
+        db.clone().into_inner(),
+        notifier.clone().into_inner(),
+        chat_uuid,
+        &sent.message,
+        sent.translations,
+    );
+
+    Ok(sent.message)
+}
+
+/// Retrieve the chat messages the client didn't acknowledge yet
+///
+/// The messages are returned as [WsMessage::IncomingChatMessage] messages.
+async fn missed_chat_messages(account: Uuid, db: &Database) -> Vec<WsMessage> {
+    let missed = match db.start_transaction().await {
+        Ok(mut tx) => match chat::missed_messages(&mut tx, account).await {
+            Ok(missed) => {
+                if let Err(err) = tx.commit().await {
+                    error!("Database error: {err}");
+                }
+                missed
+            }
+            Err(err) => {
+                error!("Database error: {err}");
+                return vec![];
+            }
+        },
+        Err(err) => {
+            error!("Database error: {err}");
+            return vec![];
+        }
+    };
+
+    missed
+        .into_iter()
+        .map(|(chat_uuid, message)| WsMessage::IncomingChatMessage { chat_uuid, message })
+        .collect()
+}
 
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
 
@@ -36,6 +274,47 @@ const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
 /// A heartbeat PING packet is sent constantly (every 10s).
 /// If no response is retrieved within 30s of the last transmission, the socket
 /// will be closed.
+///
+/// All messages sent by the server are wrapped in a [WsEnvelope] carrying the
+/// protocol version and a unique message id.
+///
+/// Incoming text messages must be JSON objects with a `type` field.
+/// Messages with a type unknown to the server are ignored, so clients can send
+/// newer message types to older servers. Text messages that can't be parsed and
+/// other message types are answered with [WsMessage::InvalidMessage].
+///
+/// The client may send the following messages:
+/// - `subscribePresence` with the content `{"accounts": [...]}` subscribes to the presence
+///   of at most 256 accounts. Only the presence of friends and of the members of the
+///   client's lobbies is visible, other accounts are ignored. The client receives a
+///   [WsMessage::PresenceChanged] message with the current state of every subscribed
+///   account and whenever one of them comes online or goes offline.
+/// - `unsubscribePresence` with the content `{"accounts": [...]}` ends the subscriptions.
+///
+/// - `subscribeLobbyList` subscribes this connection to [WsMessage::LobbyListUpdated]
+///   messages, which are sent whenever a lobby of `GET /api/v2/lobbies` is created, closed
+///   or its number of players changes.
+/// - `unsubscribeLobbyList` ends the subscription of this connection.
+///
+/// - `sendChatMessage` with the content `{"chat_uuid": ..., "message": ..., "request_id": ...}`
+///   sends a message to a chatroom like `POST /api/v2/chats/{uuid}`. The client receives a
+///   [WsMessage::ChatMessageSent] message with the created message or a
+///   [WsMessage::ChatMessageFailed] message with the error. Both carry the optional
+///   `request_id`, so the client can match them to its requests.
+///
+/// - `ackChatMessages` with the content `{"chat_uuid": ..., "sequence": ...}` acknowledges
+///   the messages of a chatroom up to the `sequence` of a [ChatMessage].
+/// - `resumeChats` resends the messages of all chatrooms of the client that weren't
+///   acknowledged yet as [WsMessage::IncomingChatMessage] messages through this connection,
+///   at most 100 per chatroom. Clients use this after reconnecting to catch up on the
+///   messages they missed, and acknowledge the messages they have applied.
+///
+/// Presence subscriptions end when the account goes offline. The lobby list subscription
+/// only applies to the connection it was made on, so clients that are in a game don't
+/// receive the updates through their other connections.
+///
+/// If `compact_game_updates` is set, the full game states are not sent through this
+/// connection, only [WsMessage::GameMetaChanged] messages.
 #[utoipa::path(
     tag = "Websocket",
     context_path = "/api/v2",
@@ -44,18 +323,25 @@ const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
         (status = 400, description = "Client error", body = ApiErrorResponse),
         (status = 500, description = "Server error", body = ApiErrorResponse),
     ),
+    params(ConnectionOptions),
     security(("session_cookie" = []))
 )]
 #[get("/ws")]
 pub async fn websocket(
     req: HttpRequest,
     payload: Payload,
+    options: Query<ConnectionOptions>,
     session: Session,
+    db: Data<Database>,
     ws_manager_chan: Data<WsManagerChan>,
+    notifier: Data<dyn Notifier>,
+    translator: Data<Option<Translator>>,
 ) -> actix_web::Result<HttpResponse> {
     let uuid: Uuid = session.get("uuid")?.ok_or(ApiError::SessionCorrupt)?;
 
     let (tx, mut rx, response) = ws::start(&req, payload)?;
+    // Identifies this connection among the other connections of the account
+    let connection = Uuid::new_v4();
 
     debug!("Initializing websocket connection");
     let last_hb = Arc::new(Mutex::new(Instant::now()));
@@ -79,7 +365,7 @@ pub async fn websocket(
                 if let MailboxError::Closed = err {
                     debug!("Could not send ping to ws: ws closed");
                     if let Err(err) = hb_ws_manager
-                        .send(WsManagerMessage::WebsocketClosed(hb_uuid))
+                        .send(WsManagerMessage::WebsocketClosed(hb_uuid, connection))
                         .await
                     {
                         warn!("Could not send to ws_manager_chan: {err}");
@@ -94,6 +380,9 @@ pub async fn websocket(
     let rx_tx = tx.clone();
     let rx_ws_manager = ws_manager_chan.clone();
     let rx_uuid = uuid;
+    let rx_db = db.clone();
+    let subscriptions = ConnectionSubscriptions::default();
+    let rx_subscriptions = subscriptions.clone();
     tokio::spawn(async move {
         while let Some(res) = rx.recv().await {
             match res {
@@ -115,6 +404,91 @@ pub async fn websocket(
                         debug!("Client closed websocket");
                         break;
                     }
+                    Message::Text(txt) => match serde_json::from_str::<IncomingMessage>(&txt) {
+                        Ok(incoming) => match incoming.msg_type.as_str() {
+                            "subscribePresence"
+                            | "unsubscribePresence"
+                            | "subscribeLobbyList"
+                            | "unsubscribeLobbyList"
+                            | "ackChatMessages" => {
+                                let valid = match serde_json::from_str::<ClientMessage>(&txt) {
+                                    Ok(msg) => {
+                                        handle_client_message(
+                                            msg,
+                                            rx_uuid,
+                                            &rx_subscriptions,
+                                            &rx_db,
+                                            &rx_ws_manager,
+                                        )
+                                        .await
+                                    }
+                                    Err(err) => {
+                                        debug!("Received invalid message via websocket: {err}");
+                                        false
+                                    }
+                                };
+                                if !valid {
+                                    invalid_msg!(rx_tx);
+                                }
+                            }
+                            "sendChatMessage" => {
+                                let reply = match serde_json::from_str::<ClientMessage>(&txt) {
+                                    Ok(ClientMessage::SendChatMessage {
+                                        chat_uuid,
+                                        message,
+                                        request_id,
+                                    }) => {
+                                        handle_chat_message(
+                                            chat_uuid,
+                                            message,
+                                            request_id,
+                                            rx_uuid,
+                                            &rx_db,
+                                            &notifier,
+                                            &translator,
+                                        )
+                                        .await
+                                    }
+                                    Ok(_) => WsMessage::InvalidMessage,
+                                    Err(err) => {
+                                        debug!("Received invalid message via websocket: {err}");
+                                        WsMessage::InvalidMessage
+                                    }
+                                };
+                                if let Err(err) = rx_tx.send(Message::Text(serialize(reply))).await
+                                {
+                                    if let MailboxError::Closed = err {
+                                        debug!("Websocket closed");
+                                        break;
+                                    }
+                                    debug!("Sending to ran into tx timeout");
+                                }
+                            }
+                            "resumeChats" => {
+                                for msg in missed_chat_messages(rx_uuid, &rx_db).await {
+                                    if let Err(err) =
+                                        rx_tx.send(Message::Text(serialize(msg))).await
+                                    {
+                                        if let MailboxError::Closed = err {
+                                            debug!("Websocket closed");
+                                            break;
+                                        }
+                                        debug!("Sending to ran into tx timeout");
+                                    }
+                                }
+                            }
+                            _ => {
+                                warn!(
+                                    "Ignoring websocket message of unknown type: {}",
+                                    incoming.msg_type
+                                );
+                            }
+                        },
+                        Err(err) => {
+                            invalid_msg!(rx_tx);
+                            debug!("Received invalid message via websocket: {err}");
+                        }
+                    },
                     _ => {
                         invalid_msg!(rx_tx);
                         debug!("Received invalid message type via websocket");
@@ -128,7 +502,7 @@ pub async fn websocket(
 
         debug!("Websocket closed");
         if let Err(err) = rx_ws_manager
-            .send(WsManagerMessage::WebsocketClosed(rx_uuid))
+            .send(WsManagerMessage::WebsocketClosed(rx_uuid, connection))
             .await
         {
             warn!("Could not send to ws_manager_chan: {err}");
@@ -137,7 +511,13 @@ pub async fn websocket(
 
     // Give sender to ws manager
     if let Err(err) = ws_manager_chan
-        .send(WsManagerMessage::OpenedSocket(uuid, tx.clone()))
+        .send(WsManagerMessage::OpenedSocket(
+            uuid,
+            connection,
+            tx.clone(),
+            *options,
+            subscriptions,
+        ))
         .await
     {
         error!("Could not send ws tx to ws manager: {err}. Closing websocket");
@@ -156,10 +536,7 @@ pub async fn websocket(
 #[macro_export]
 macro_rules! invalid_msg {
     ($tx:expr) => {
-        if let Err(err) = $tx
-            .send(Message::Text(COMMON.invalid_message.clone()))
-            .await
-        {
+        if let Err(err) = $tx.send(Message::Text(invalid_message())).await {
             if let MailboxError::Closed = err {
                 debug!("Websocket closed");
                 break;
@@ -0,0 +1,136 @@
+//! This module holds the admin endpoints to configure the welcome message of new accounts
+
+use actix_web::web::{Data, Json};
+use actix_web::{delete, get, put, HttpResponse};
+use chrono::{DateTime, Utc};
+use rorm::{insert, query, update, Database, FieldAccess, Model};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::{WelcomeMessage, WelcomeMessageInsert};
+use crate::server::handler::{ApiError, ApiErrorResponse, ApiResult};
+
+/// The welcome message of new accounts
+#[derive(Serialize, ToSchema)]
+pub struct WelcomeMessageResponse {
+    /// The template of the message, if a welcome message is configured
+    #[schema(example = "Welcome {display_name}! Please read the rules at https://example.com")]
+    message: Option<String>,
+    /// The point in time the message was changed the last time
+    updated_at: Option<DateTime<Utc>>,
+}
+
+/// Retrieve the welcome message of new accounts
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Returns the welcome message", body = WelcomeMessageResponse),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/welcome-message")]
+pub async fn get_welcome_message(db: Data<Database>) -> ApiResult<Json<WelcomeMessageResponse>> {
+    let welcome_message = query!(
+        db.as_ref(),
+        (WelcomeMessage::F.message, WelcomeMessage::F.updated_at)
+    )
+    .optional()
+    .await?;
+
+    Ok(Json(match welcome_message {
+        Some((message, updated_at)) => WelcomeMessageResponse {
+            message: Some(message),
+            updated_at: Some(DateTime::from_naive_utc_and_offset(updated_at, Utc)),
+        },
+        None => WelcomeMessageResponse {
+            message: None,
+            updated_at: None,
+        },
+    }))
+}
+
+/// The request to set the welcome message of new accounts
+#[derive(Deserialize, ToSchema)]
+pub struct SetWelcomeMessageRequest {
+    #[schema(example = "Welcome {display_name}! Please read the rules at https://example.com")]
+    message: String,
+}
+
+/// Set the welcome message of new accounts
+///
+/// After their registration, new accounts find the message as system message in a chatroom
+/// of their own. `{display_name}` and `{username}` in the message are replaced with the data
+/// of the new account. Accounts that already exist don't receive the message.
+///
+/// If `message` is empty or longer than 1024 characters, [ApiError::InvalidMessage] is returned.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Welcome message was set"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    request_body = SetWelcomeMessageRequest,
+    security(("admin_token" = []))
+)]
+#[put("/welcome-message")]
+pub async fn set_welcome_message(
+    req: Json<SetWelcomeMessageRequest>,
+    db: Data<Database>,
+) -> ApiResult<HttpResponse> {
+    let message = req.into_inner().message;
+    if message.trim().is_empty() || message.chars().count() > 1024 {
+        return Err(ApiError::InvalidMessage);
+    }
+
+    let mut tx = db.start_transaction().await?;
+
+    if let Some((uuid,)) = query!(&mut tx, (WelcomeMessage::F.uuid,))
+        .optional()
+        .await?
+    {
+        update!(&mut tx, WelcomeMessage)
+            .condition(WelcomeMessage::F.uuid.equals(uuid))
+            .set(WelcomeMessage::F.message, message)
+            .exec()
+            .await?;
+    } else {
+        insert!(&mut tx, WelcomeMessageInsert)
+            .return_nothing()
+            .single(&WelcomeMessageInsert {
+                uuid: Uuid::new_v4(),
+                message,
+            })
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Remove the welcome message of new accounts
+///
+/// New accounts don't receive a welcome message anymore. The chatrooms of accounts
+/// that already received it are kept.
+#[utoipa::path(
+    tag = "Accounts",
+    context_path = "/api/v2/admin",
+    responses(
+        (status = 200, description = "Welcome message was removed"),
+        (status = 400, description = "Client error", body = ApiErrorResponse),
+        (status = 500, description = "Server error", body = ApiErrorResponse),
+    ),
+    security(("admin_token" = []))
+)]
+#[delete("/welcome-message")]
+pub async fn delete_welcome_message(db: Data<Database>) -> ApiResult<HttpResponse> {
+    rorm::delete!(db.as_ref(), WelcomeMessage).all().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
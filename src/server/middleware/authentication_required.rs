@@ -2,9 +2,13 @@ use std::future::{ready, Ready};
 
 use actix_toolbox::tb_middleware::actix_session::SessionExt;
 use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::web::Data;
 use futures::future::LocalBoxFuture;
+use rorm::Database;
+use uuid::Uuid;
 
 use crate::server::handler::ApiError;
+use crate::service::device;
 
 pub(crate) struct AuthenticationRequired;
 
@@ -47,6 +51,8 @@ where
         let logged_in = session
             .get("logged_in")
             .map(|logged_in_maybe| logged_in_maybe.map_or(false, |v| v));
+        let device = session.get::<Uuid>("device");
+        let db = req.app_data::<Data<Database>>().cloned();
 
         let next = self.service.call(req);
         Box::pin(async move {
@@ -54,6 +60,22 @@ where
                 return Err(ApiError::Unauthenticated.into());
             }
 
+            // Sessions from before devices were introduced don't refer to one
+            if let Some(device) = device.map_err(ApiError::SessionGet)? {
+                let db = db.ok_or(ApiError::InternalServerError)?;
+
+                let mut tx = db.start_transaction().await.map_err(ApiError::from)?;
+                let valid = device::touch(&mut tx, device)
+                    .await
+                    .map_err(ApiError::from)?;
+                tx.commit().await.map_err(ApiError::from)?;
+
+                if !valid {
+                    session.purge();
+                    return Err(ApiError::Unauthenticated.into());
+                }
+            }
+
             next.await
         })
     }
@@ -0,0 +1,88 @@
+use std::future::{ready, Ready};
+use std::sync::Arc;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderValue, ACCEPT_ENCODING};
+
+use crate::config::CompressionAlgorithm;
+
+/// Restricts the `Accept-Encoding` header of requests to the configured algorithms
+///
+/// Has to be wrapped around the `Compress` middleware, which then only picks from the
+/// algorithms that are left. Responses that set a `Content-Encoding` themselves, like
+/// `identity` for game states, are not compressed at all.
+pub(crate) struct NegotiateCompression(pub(crate) Arc<[CompressionAlgorithm]>);
+
+impl<S, B> Transform<S, ServiceRequest> for NegotiateCompression
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = NegotiateCompressionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(NegotiateCompressionMiddleware {
+            service,
+            algorithms: self.0.clone(),
+        }))
+    }
+}
+
+pub(crate) struct NegotiateCompressionMiddleware<S> {
+    service: S,
+    algorithms: Arc<[CompressionAlgorithm]>,
+}
+
+impl<S, B> Service<ServiceRequest> for NegotiateCompressionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = S::Future;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        if let Some(accepted) = req.headers().get(ACCEPT_ENCODING) {
+            // Unreadable headers are dropped, which leaves the response uncompressed
+            let allowed = accepted
+                .to_str()
+                .map(|accepted| {
+                    accepted
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|x| {
+                            let coding = x.split(';').next().unwrap_or_default().trim();
+                            coding.eq_ignore_ascii_case("identity")
+                                || self
+                                    .algorithms
+                                    .iter()
+                                    .any(|a| coding.eq_ignore_ascii_case(a.as_str()))
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                })
+                .unwrap_or_default();
+
+            let headers = req.headers_mut();
+            match HeaderValue::from_str(&allowed) {
+                Ok(value) if !allowed.is_empty() => {
+                    headers.insert(ACCEPT_ENCODING, value);
+                }
+                _ => {
+                    headers.remove(ACCEPT_ENCODING);
+                }
+            }
+        }
+
+        self.service.call(req)
+    }
+}
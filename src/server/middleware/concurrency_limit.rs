@@ -0,0 +1,132 @@
+use std::future::{ready, Ready};
+use std::sync::Arc;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::web::Data;
+use futures::future::LocalBoxFuture;
+use tokio::sync::Semaphore;
+
+use crate::config::ServerConfig;
+use crate::server::handler::ApiError;
+use crate::server::middleware::rate_limit::{RateClass, RateLimits};
+
+/// The expensive operations that may only run a limited number of times at once
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum LimitedOperation {
+    /// Uploads of game states
+    GameUpload,
+    /// Downloads of game states
+    GameDownload,
+    /// Exports of game saves
+    GameExport,
+}
+
+/// The semaphores of the [LimitedOperation]s
+///
+/// Operations without a semaphore are not limited.
+pub struct ConcurrencyLimits {
+    game_uploads: Option<Arc<Semaphore>>,
+    game_downloads: Option<Arc<Semaphore>>,
+    game_exports: Option<Arc<Semaphore>>,
+}
+
+impl ConcurrencyLimits {
+    /// Create the semaphores with the limits of the configuration
+    pub fn new(config: &ServerConfig) -> Self {
+        let semaphore = |limit: usize| (limit > 0).then(|| Arc::new(Semaphore::new(limit)));
+        Self {
+            game_uploads: semaphore(config.max_concurrent_game_uploads),
+            game_downloads: semaphore(config.max_concurrent_game_downloads),
+            game_exports: semaphore(config.max_concurrent_game_exports),
+        }
+    }
+
+    fn semaphore(&self, operation: LimitedOperation) -> Option<&Arc<Semaphore>> {
+        match operation {
+            LimitedOperation::GameUpload => self.game_uploads.as_ref(),
+            LimitedOperation::GameDownload => self.game_downloads.as_ref(),
+            LimitedOperation::GameExport => self.game_exports.as_ref(),
+        }
+    }
+}
+
+/// Limits the number of concurrent requests of an endpoint
+///
+/// The permit is acquired before the request body is read, so saturated endpoints
+/// don't buffer the uploads. If no permit is available, [ApiError::ServerBusy] is
+/// returned immediately.
+///
+/// The requests are charged to the heavy budget of their client in the [RateLimits]
+/// of the app as well.
+pub(crate) struct ConcurrencyLimit(pub(crate) LimitedOperation);
+
+impl<S, B> Transform<S, ServiceRequest> for ConcurrencyLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = ConcurrencyLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ConcurrencyLimitMiddleware {
+            service,
+            operation: self.0,
+        }))
+    }
+}
+
+pub(crate) struct ConcurrencyLimitMiddleware<S> {
+    service: S,
+    operation: LimitedOperation,
+}
+
+impl<S, B> Service<ServiceRequest> for ConcurrencyLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let quota = match req
+            .app_data::<Data<RateLimits>>()
+            .map(|limits| limits.check(RateClass::Heavy, &req))
+        {
+            Some(Ok(quota)) => quota,
+            Some(Err(err)) => return Box::pin(async { Err(err.into()) }),
+            None => None,
+        };
+
+        let permit = match req
+            .app_data::<Data<ConcurrencyLimits>>()
+            .and_then(|limits| limits.semaphore(self.operation))
+        {
+            Some(semaphore) => match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => return Box::pin(async { Err(ApiError::ServerBusy.into()) }),
+            },
+            None => None,
+        };
+
+        let next = self.service.call(req);
+        Box::pin(async move {
+            let res = next.await;
+            drop(permit);
+            let mut res = res?;
+            if let Some(quota) = quota {
+                quota.insert_headers(res.headers_mut());
+            }
+            Ok(res)
+        })
+    }
+}
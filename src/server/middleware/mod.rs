@@ -1,11 +1,24 @@
 //! This module holds the middleware definitions
 
 pub(crate) use authentication_required::AuthenticationRequired;
+pub(crate) use compression::NegotiateCompression;
+pub use concurrency_limit::ConcurrencyLimits;
+pub(crate) use concurrency_limit::{ConcurrencyLimit, LimitedOperation};
 pub(crate) use handle_not_found::handle_not_found;
 pub(crate) use json_extractor_error::json_extractor_error;
+pub(crate) use moderator_required::ModeratorRequired;
+pub(crate) use rate_limit::RateLimit;
+pub use rate_limit::RateLimits;
+pub(crate) use slow_requests::TrackSlowRequests;
+pub use slow_requests::{SlowRequest, SlowRequests, SlowStatement};
 pub(crate) use token_required::TokenRequired;
 
 mod authentication_required;
+mod compression;
+mod concurrency_limit;
 mod handle_not_found;
 mod json_extractor_error;
+mod moderator_required;
+mod rate_limit;
+mod slow_requests;
 mod token_required;
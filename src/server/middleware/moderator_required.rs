@@ -0,0 +1,76 @@
+use std::future::{ready, Ready};
+
+use actix_toolbox::tb_middleware::actix_session::SessionExt;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::web::Data;
+use futures::future::LocalBoxFuture;
+use rorm::{query, Database, FieldAccess, Model};
+use uuid::Uuid;
+
+use crate::models::Account;
+use crate::server::handler::ApiError;
+
+/// Restricts a scope to accounts with the moderator flag
+///
+/// The middleware doesn't check the login itself, so it has to be wrapped
+/// by [AuthenticationRequired](super::AuthenticationRequired).
+pub(crate) struct ModeratorRequired;
+
+impl<S, B> Transform<S, ServiceRequest> for ModeratorRequired
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = ModeratorRequiredMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ModeratorRequiredMiddleware { service }))
+    }
+}
+
+pub(crate) struct ModeratorRequiredMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for ModeratorRequiredMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let uuid = req.get_session().get::<Uuid>("uuid");
+        let db = req.app_data::<Data<Database>>().cloned();
+
+        let next = self.service.call(req);
+        Box::pin(async move {
+            let uuid = uuid
+                .map_err(ApiError::SessionGet)?
+                .ok_or(ApiError::SessionCorrupt)?;
+            let db = db.ok_or(ApiError::InternalServerError)?;
+
+            let moderator = query!(db.as_ref(), (Account::F.moderator,))
+                .condition(Account::F.uuid.equals(uuid))
+                .optional()
+                .await
+                .map_err(ApiError::from)?
+                .map_or(false, |(moderator,)| moderator);
+            if !moderator {
+                return Err(ApiError::MissingPrivileges.into());
+            }
+
+            next.await
+        })
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_toolbox::tb_middleware::actix_session::SessionExt;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::web::Data;
+use futures::future::LocalBoxFuture;
+use uuid::Uuid;
+
+use crate::config::RateLimitsConfig;
+use crate::server::handler::{ApiError, ApiResult};
+
+/// The interval in which the budgets of inactive clients are dropped
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The classes of requests that have separate budgets
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum RateClass {
+    /// Requests that only read data
+    Read,
+    /// Requests that change data
+    Write,
+    /// Uploads, downloads and exports of game states
+    Heavy,
+}
+
+impl RateClass {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RateClass::Read => "read",
+            RateClass::Write => "write",
+            RateClass::Heavy => "heavy",
+        }
+    }
+}
+
+/// The client a budget belongs to
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Client {
+    /// A logged-in account, regardless of the address it connects from
+    Account(Uuid),
+    /// A client without a login
+    Address(IpAddr),
+}
+
+impl Client {
+    /// The client that sent a request, `None` if it can't be identified
+    fn of(req: &ServiceRequest) -> Option<Self> {
+        if let Ok(Some(uuid)) = req.get_session().get::<Uuid>("uuid") {
+            return Some(Client::Account(uuid));
+        }
+        req.peer_addr().map(|addr| Client::Address(addr.ip()))
+    }
+}
+
+/// The remaining budget of a client, which is refilled continuously
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    buckets: HashMap<(RateClass, Client), Bucket>,
+    last_cleanup: Instant,
+}
+
+/// The budget of a client in a class after a request
+pub(crate) struct Quota {
+    class: RateClass,
+    limit: u32,
+    remaining: u32,
+}
+
+impl Quota {
+    /// Advertise the budget in the headers of a response
+    ///
+    /// Headers that are already set are kept, so the budget of the most limited class,
+    /// which is checked last, is the one that is advertised.
+    pub(crate) fn insert_headers(&self, headers: &mut HeaderMap) {
+        for (name, value) in [
+            (
+                "x-ratelimit-class",
+                HeaderValue::from_static(self.class.as_str()),
+            ),
+            ("x-ratelimit-limit", HeaderValue::from(self.limit)),
+            ("x-ratelimit-remaining", HeaderValue::from(self.remaining)),
+        ] {
+            let name = HeaderName::from_static(name);
+            if !headers.contains_key(&name) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+/// The request budgets of all clients
+pub struct RateLimits {
+    config: Option<RateLimitsConfig>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimits {
+    /// Create the budgets of the configuration, requests are not limited without one
+    pub fn new(config: Option<RateLimitsConfig>) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                last_cleanup: Instant::now(),
+            }),
+        }
+    }
+
+    /// The requests per second and the burst of a class, `None` if it is not limited
+    fn budget(&self, class: RateClass) -> Option<(f64, f64)> {
+        let config = self.config.as_ref()?;
+        let (per_minute, burst) = match class {
+            RateClass::Read => (config.read_per_minute, config.read_burst),
+            RateClass::Write => (config.write_per_minute, config.write_burst),
+            RateClass::Heavy => (config.heavy_per_minute, config.heavy_burst),
+        };
+        (per_minute > 0 && burst > 0).then(|| (per_minute as f64 / 60.0, burst as f64))
+    }
+
+    /// Charge a request to the budget of its client
+    ///
+    /// Returns [ApiError::RateLimited] if the budget is used up and the remaining
+    /// budget otherwise, which is `None` if the request is not limited.
+    pub(crate) fn check(&self, class: RateClass, req: &ServiceRequest) -> ApiResult<Option<Quota>> {
+        let Some((rate, burst)) = self.budget(class) else {
+            return Ok(None);
+        };
+        let Some(client) = Client::of(req) else {
+            return Ok(None);
+        };
+
+        let now = Instant::now();
+        // Ok as the lock is never held across an await point or a panic
+        #[allow(clippy::unwrap_used)]
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // Budgets that are full again are the same as new ones
+        if now.duration_since(buckets.last_cleanup) > CLEANUP_INTERVAL {
+            buckets.buckets.retain(|(class, _), bucket| {
+                self.budget(*class)
+                    .is_some_and(|(class_rate, class_burst)| {
+                        bucket.tokens
+                            + now.duration_since(bucket.updated).as_secs_f64() * class_rate
+                            < class_burst
+                    })
+            });
+            buckets.last_cleanup = now;
+        }
+
+        let bucket = buckets.buckets.entry((class, client)).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens =
+            (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            let retry_after = ((1.0 - bucket.tokens) / rate).ceil() as u64;
+            return Err(ApiError::RateLimited(retry_after.max(1)));
+        }
+        bucket.tokens -= 1.0;
+
+        Ok(Some(Quota {
+            class,
+            limit: burst as u32,
+            remaining: bucket.tokens as u32,
+        }))
+    }
+}
+
+/// Charges requests to the read or write budget of their client in the [RateLimits] of the app
+///
+/// Requests with the methods `GET`, `HEAD` and `OPTIONS` are reads, all others writes.
+/// The remaining budget is advertised in the `X-RateLimit-Class`, `X-RateLimit-Limit` and
+/// `X-RateLimit-Remaining` headers. If the budget is used up, [ApiError::RateLimited]
+/// is returned.
+///
+/// Has to be wrapped by the session middleware to charge logged-in accounts instead
+/// of their addresses.
+pub(crate) struct RateLimit;
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware { service }))
+    }
+}
+
+pub(crate) struct RateLimitMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let class = match *req.method() {
+            Method::GET | Method::HEAD | Method::OPTIONS => RateClass::Read,
+            _ => RateClass::Write,
+        };
+
+        let quota = match req
+            .app_data::<Data<RateLimits>>()
+            .map(|limits| limits.check(class, &req))
+        {
+            Some(Ok(quota)) => quota,
+            Some(Err(err)) => return Box::pin(async { Err(err.into()) }),
+            None => None,
+        };
+
+        let next = self.service.call(req);
+        Box::pin(async move {
+            let mut res = next.await?;
+            if let Some(quota) = quota {
+                quota.insert_headers(res.headers_mut());
+            }
+            Ok(res)
+        })
+    }
+}
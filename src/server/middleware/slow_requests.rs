@@ -0,0 +1,249 @@
+use std::future::{ready, Ready};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::web::Data;
+use chrono::{DateTime, Utc};
+use futures::future::LocalBoxFuture;
+use log::warn;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::config::ServerConfig;
+
+/// A request that took longer than the threshold of the [SlowRequests]
+///
+/// Only the route pattern of the request is recorded, so neither path parameters nor the
+/// query string, which may contain ids or signatures, end up in the log.
+#[derive(Serialize, ToSchema, Clone, Debug)]
+pub struct SlowRequest {
+    #[schema(example = "GET")]
+    method: String,
+    /// The route pattern, e.g. `/api/v2/games/{uuid}`, or `unmatched` for unknown routes
+    #[schema(example = "/api/v2/games/{uuid}")]
+    route: String,
+    #[schema(example = 200)]
+    status: u16,
+    /// The time the request took in milliseconds
+    #[schema(example = 2345)]
+    duration_ms: u64,
+    /// The point in time the request finished
+    finished_at: DateTime<Utc>,
+}
+
+/// A database statement that took longer than the threshold of the [SlowRequests]
+///
+/// Only the summary of the statement is recorded, its parameters are bound separately
+/// and never end up in the log.
+#[derive(Serialize, ToSchema, Clone, Debug)]
+pub struct SlowStatement {
+    /// The first words of the statement
+    #[schema(example = "SELECT \"game\".\"uuid\" AS game__uuid, …")]
+    summary: String,
+    /// The time the statement took in milliseconds
+    #[schema(example = 1234)]
+    duration_ms: u64,
+    /// The point in time the statement finished
+    finished_at: DateTime<Utc>,
+}
+
+/// An entry of the slow log that can be ranked by its duration
+trait SlowEntry {
+    fn duration_ms(&self) -> u64;
+}
+
+impl SlowEntry for SlowRequest {
+    fn duration_ms(&self) -> u64 {
+        self.duration_ms
+    }
+}
+
+impl SlowEntry for SlowStatement {
+    fn duration_ms(&self) -> u64 {
+        self.duration_ms
+    }
+}
+
+/// The slowest requests and database statements that exceeded the configured threshold
+pub struct SlowRequests {
+    threshold: Option<Duration>,
+    capacity: usize,
+    requests: Mutex<Vec<SlowRequest>>,
+    statements: Mutex<Vec<SlowStatement>>,
+}
+
+impl SlowRequests {
+    /// Create the log with the threshold and size of the configuration
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            threshold: (config.slow_request_threshold > 0)
+                .then(|| Duration::from_millis(config.slow_request_threshold)),
+            capacity: config.slow_request_log_size,
+            requests: Mutex::new(Vec::with_capacity(config.slow_request_log_size)),
+            statements: Mutex::new(Vec::with_capacity(config.slow_request_log_size)),
+        }
+    }
+
+    /// The threshold in milliseconds, `None` if tracking is disabled
+    pub fn threshold_ms(&self) -> Option<u64> {
+        self.threshold.map(|x| x.as_millis() as u64)
+    }
+
+    /// The recorded requests, the slowest first
+    pub fn slowest(&self) -> Vec<SlowRequest> {
+        Self::sorted(&self.requests)
+    }
+
+    /// The recorded database statements, the slowest first
+    pub fn slowest_statements(&self) -> Vec<SlowStatement> {
+        Self::sorted(&self.statements)
+    }
+
+    /// Record a database statement if it took longer than the threshold
+    pub fn record_statement(&self, summary: String, duration: Duration) {
+        if self.threshold.is_some_and(|x| duration > x) {
+            self.record(
+                &self.statements,
+                SlowStatement {
+                    summary,
+                    duration_ms: duration.as_millis() as u64,
+                    finished_at: Utc::now(),
+                },
+            );
+        }
+    }
+
+    fn sorted<T: SlowEntry + Clone>(entries: &Mutex<Vec<T>>) -> Vec<T> {
+        // Ok as the lock is never held across an await point or a panic
+        #[allow(clippy::unwrap_used)]
+        let mut entries = entries.lock().unwrap().clone();
+        entries.sort_by(|a, b| b.duration_ms().cmp(&a.duration_ms()));
+        entries
+    }
+
+    /// Add an entry, replacing the fastest one if the log is full
+    ///
+    /// This keeps the worst offenders, a burst of barely slow entries doesn't push them out.
+    fn record<T: SlowEntry>(&self, entries: &Mutex<Vec<T>>, entry: T) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        // Ok as the lock is never held across an await point or a panic
+        #[allow(clippy::unwrap_used)]
+        let mut entries = entries.lock().unwrap();
+        if entries.len() < self.capacity {
+            entries.push(entry);
+            return;
+        }
+
+        if let Some(fastest) = entries
+            .iter_mut()
+            .min_by_key(|x| x.duration_ms())
+            .filter(|x| x.duration_ms() < entry.duration_ms())
+        {
+            *fastest = entry;
+        }
+    }
+}
+
+/// Records requests that take longer than the threshold in the [SlowRequests] of the app
+pub(crate) struct TrackSlowRequests;
+
+impl<S, B> Transform<S, ServiceRequest> for TrackSlowRequests
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = TrackSlowRequestsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TrackSlowRequestsMiddleware { service }))
+    }
+}
+
+pub(crate) struct TrackSlowRequestsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for TrackSlowRequestsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let slow_requests = req.app_data::<Data<SlowRequests>>().cloned();
+
+        let start = Instant::now();
+        let next = self.service.call(req);
+        Box::pin(async move {
+            let res = next.await?;
+
+            let duration = start.elapsed();
+            let Some(slow_requests) = slow_requests else {
+                return Ok(res);
+            };
+            if slow_requests.threshold.is_some_and(|x| duration > x) {
+                let request = SlowRequest {
+                    method: res.request().method().to_string(),
+                    route: res
+                        .request()
+                        .match_pattern()
+                        .unwrap_or_else(|| "unmatched".to_string()),
+                    status: res.status().as_u16(),
+                    duration_ms: duration.as_millis() as u64,
+                    finished_at: Utc::now(),
+                };
+                warn!(
+                    "Slow request: {} {} took {} ms",
+                    request.method, request.route, request.duration_ms
+                );
+                slow_requests.record(&slow_requests.requests, request);
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use super::SlowRequests;
+
+    #[test]
+    fn the_fastest_statement_is_evicted() {
+        let slow_requests = SlowRequests {
+            threshold: Some(Duration::from_millis(100)),
+            capacity: 2,
+            requests: Mutex::new(Vec::new()),
+            statements: Mutex::new(Vec::new()),
+        };
+
+        for (summary, ms) in [("a", 300), ("b", 500), ("c", 200), ("d", 50), ("e", 400)] {
+            slow_requests.record_statement(summary.to_string(), Duration::from_millis(ms));
+        }
+
+        let summaries: Vec<_> = slow_requests
+            .slowest_statements()
+            .into_iter()
+            .map(|x| x.summary)
+            .collect();
+        assert_eq!(summaries, vec!["b", "e"]);
+    }
+}
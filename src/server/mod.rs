@@ -4,14 +4,15 @@ use std::fs::{create_dir_all, set_permissions, Permissions};
 use std::net::SocketAddr;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
+use std::sync::Arc;
 
 use actix_toolbox::tb_middleware::{
     setup_logging_mw, DBSessionStore, LoggingMiddlewareConfig, PersistentSession, SessionMiddleware,
 };
 use actix_web::cookie::time::Duration;
 use actix_web::cookie::Key;
-use actix_web::http::StatusCode;
-use actix_web::middleware::{Compress, ErrorHandlers};
+use actix_web::http::{KeepAlive, StatusCode};
+use actix_web::middleware::{Compress, Condition, ErrorHandlers};
 use actix_web::web::{scope, Data, JsonConfig, PayloadConfig};
 use actix_web::{App, HttpServer};
 use base64::prelude::BASE64_STANDARD;
@@ -21,25 +22,52 @@ use rorm::Database;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::{SwaggerUi, Url};
 
-use crate::chan::WsManagerChan;
-use crate::config::Config;
+use crate::chan::{ChatDigests, Notifier, WsManagerChan};
+use crate::config::{AbandonedAccountsConfig, Config};
+use crate::logging::LogFilters;
+use crate::server::download_links::DownloadLinks;
 use crate::server::error::StartServerError;
 use crate::server::handler::{
-    accept_friend_request, accept_invite, close_lobby, create_friend_request, create_invite,
-    create_lobby, delete_friend, delete_invite, delete_me, get_all_chats, get_all_lobbies,
-    get_chat, get_friends, get_game, get_invites, get_lobby, get_me, get_open_games, health,
-    join_lobby, kick_player_from_lobby, leave_lobby, login, logout, lookup_account_by_username,
-    lookup_account_by_uuid, push_game_update, register_account, send_message, set_password,
-    start_game, update_me, version, websocket, welcome_page,
+    accept_friend_request, accept_game_invite, accept_invite, accept_negotiation,
+    add_lobby_co_host, admin_delete_message, admin_get_chat_messages, capabilities, clone_game,
+    close_lobby, create_bulk_friend_requests, create_download_link, create_friend_request,
+    create_game_invite, create_game_snapshot, create_invite, create_lobby, create_negotiation,
+    decline_negotiation, delete_device, delete_friend, delete_game_invite, delete_invite,
+    delete_me, delete_webhook, delete_welcome_message, download_game_data, end_turn, events,
+    export_accounts, export_chat_transcript, export_game, export_game_bundle,
+    get_abandoned_accounts, get_all_chats, get_all_lobbies, get_chat, get_devices, get_friends,
+    get_game, get_game_changes, get_game_events, get_game_snapshots, get_game_stats, get_invites,
+    get_lobby, get_lobby_bans, get_lobby_by_join_code, get_log_filters, get_me, get_mutual_friends,
+    get_my_lobbies, get_negotiations, get_open_games, get_slow_log, get_sync, get_webhook,
+    get_welcome_message, grant_badge, health, import_game_bundle, join_lobby, join_lobby_by_code,
+    kick_player_from_lobby, leave_game, leave_lobby, login, logout, lookup_account_by_username,
+    lookup_account_by_uuid, moderate_delete_message, moderate_kick_player, observe_game,
+    push_game_update, register_account, remove_lobby_co_host, restore_game_snapshot,
+    reveal_nations, revoke_badge, revoke_observer_token, search_accounts, send_direct_message,
+    send_message, send_test_notification, set_chat_digest, set_chat_language, set_chat_restriction,
+    set_direct_message_policy, set_friend_nickname, set_friend_request_policy, set_lobby_nation,
+    set_lobby_ready, set_log_filter, set_moderator, set_mutual_friends_visibility, set_password,
+    set_webhook, set_welcome_message, start_game, transfer_game, transfer_game_host,
+    unban_player_from_lobby, update_device, update_friend, update_game_settings, update_lobby,
+    update_me, utilization, verify_game_data_files, version, websocket, welcome_page,
 };
 use crate::server::middleware::{
-    handle_not_found, json_extractor_error, AuthenticationRequired, TokenRequired,
+    handle_not_found, json_extractor_error, AuthenticationRequired, ConcurrencyLimits,
+    ModeratorRequired, NegotiateCompression, RateLimit, RateLimits, SlowRequests, TokenRequired,
+    TrackSlowRequests,
 };
 use crate::server::swagger::{AdminApiDoc, ApiDoc};
+use crate::service::account_stats::AccountStatsCache;
+use crate::service::policy::PolicyValidator;
+use crate::service::translation::Translator;
+use crate::storage::{write_storage_version, STORAGE_VERSION};
+use crate::tasks::GameDataCheck;
 
+pub mod download_links;
 pub mod error;
 pub mod handler;
 pub mod middleware;
+pub mod ndjson;
 pub mod swagger;
 
 /// Collection of settings and configs used by endpoint implementations during runtime
@@ -47,6 +75,34 @@ pub mod swagger;
 pub struct RuntimeSettings {
     /// The directory on the local filesystem where to store game data files
     pub game_data_path: String,
+    /// The maximum number of lobbies a single account may own at the same time
+    pub max_owned_lobbies: u16,
+    /// Whether accounts may be a member of multiple lobbies at the same time
+    pub allow_multiple_lobbies: bool,
+    /// The maximum size of uploaded game data in bytes
+    pub max_game_data_size: usize,
+    /// The time in seconds a player has to upload the game state after ending a turn
+    pub pending_upload_timeout: u64,
+    /// The maximum number of players a lobby may have, including the owner
+    pub max_lobby_players: u8,
+    /// Whether every lobby must be protected by a password
+    pub require_lobby_password: bool,
+    /// The mods lobbies may require, `None` if all mods are allowed
+    pub allowed_mods: Option<Vec<String>>,
+    /// The maximum number of open lobbies on this server, `0` if unlimited
+    pub max_open_lobbies: u64,
+    /// The maximum number of running games on this server, `0` if unlimited
+    pub max_running_games: u64,
+    /// Whether chat messages are translated into the chat languages of the accounts
+    pub chat_translation: bool,
+    /// The policy for abandoned accounts, `None` if they are kept
+    pub abandoned_accounts: Option<AbandonedAccountsConfig>,
+    /// Whether accounts may register personal webhooks
+    pub account_webhooks: bool,
+    /// Whether the server runs with a throwaway database, which is dropped on shutdown
+    pub ephemeral: bool,
+    /// The time in seconds former players can watch a game with their observer token
+    pub observer_token_lifetime: u64,
 }
 
 /// Start the runciv server
@@ -55,10 +111,20 @@ pub struct RuntimeSettings {
 /// - `config`: Reference to a [Config] struct
 /// - `db`: [Database]
 /// - `ws_manager_chan`: [WsManagerChan] : The channel to manage websocket connections
+/// - `notifier`: [Notifier] : The transport the handlers use to notify accounts
+/// - `chat_digests`: [ChatDigests] : The notifier that collects messages for chat digests
+/// - `game_data_check`: The result of the startup check of the game data files, if it was run
+/// - `log_filters`: [LogFilters] : The handle to change the log levels of single modules
+/// - `slow_requests`: [SlowRequests] : The log of slow requests and database statements
 pub async fn start_server(
     config: &Config,
     db: Database,
     ws_manager_chan: WsManagerChan,
+    notifier: Arc<dyn Notifier>,
+    chat_digests: Arc<ChatDigests>,
+    game_data_check: Option<GameDataCheck>,
+    log_filters: Arc<LogFilters>,
+    slow_requests: Arc<SlowRequests>,
 ) -> Result<(), StartServerError> {
     let key = Key::try_from(
         BASE64_STANDARD
@@ -79,28 +145,75 @@ pub async fn start_server(
         );
         create_dir_all(game_data)?;
         set_permissions(game_data, Permissions::from_mode(0o700))?;
+        write_storage_version(game_data, STORAGE_VERSION)?;
     }
 
     let runtime_settings = RuntimeSettings {
         game_data_path: config.server.game_data_path.clone(),
+        max_owned_lobbies: config.server.max_owned_lobbies,
+        allow_multiple_lobbies: config.server.allow_multiple_lobbies,
+        max_game_data_size: config.server.max_game_data_size,
+        pending_upload_timeout: config.server.pending_upload_timeout,
+        max_lobby_players: config.server.max_lobby_players.clamp(2, 34),
+        require_lobby_password: config.server.require_lobby_password,
+        allowed_mods: config.server.allowed_mods.clone(),
+        max_open_lobbies: config.server.max_open_lobbies,
+        max_running_games: config.server.max_running_games,
+        chat_translation: config.translation.is_some(),
+        abandoned_accounts: config.abandoned_accounts.clone(),
+        account_webhooks: config.account_webhooks.is_some(),
+        ephemeral: config.server.ephemeral,
+        observer_token_lifetime: config.server.observer_token_lifetime,
     };
 
+    // Leave some room for the rest of the upload request besides the game data
+    let payload_limit = (config.server.max_game_data_size + 64 * 1024).max(1_000_000);
+
     let s_addr = SocketAddr::new(config.server.listen_address, config.server.listen_port);
     info!("Starting to listen on {}", s_addr);
 
+    // Shared by all workers, so the counts are only computed once per account
+    let account_stats_cache = Data::new(AccountStatsCache::default());
+    let concurrency_limits = Data::new(ConcurrencyLimits::new(&config.server));
+    let translator = Data::new(config.translation.clone().map(Translator::new));
+    let policy_validator = Data::new(config.policy_validator.clone().map(PolicyValidator::new));
+    let rate_limits = Data::new(RateLimits::new(config.rate_limits.clone()));
+    let compression_algorithms: Arc<[_]> = config.server.compression_algorithms.clone().into();
+    let download_links = Data::new(DownloadLinks::new(
+        key.signing(),
+        config.server.download_link_lifetime,
+    ));
+
     HttpServer::new(move || {
         App::new()
-            .app_data(PayloadConfig::default().limit(1_000_000))
+            .app_data(PayloadConfig::default().limit(payload_limit))
             .app_data(
                 JsonConfig::default()
-                    .limit(1_000_000)
+                    .limit(payload_limit)
                     .error_handler(json_extractor_error),
             )
             .app_data(Data::new(runtime_settings.clone()))
             .app_data(Data::new(db.clone()))
             .app_data(Data::new(ws_manager_chan.clone()))
+            .app_data(Data::from(notifier.clone()))
+            .app_data(Data::from(chat_digests.clone()))
+            .app_data(concurrency_limits.clone())
+            .app_data(Data::new(game_data_check.clone()))
+            .app_data(Data::from(log_filters.clone()))
+            .app_data(account_stats_cache.clone())
+            .app_data(translator.clone())
+            .app_data(policy_validator.clone())
+            .app_data(download_links.clone())
+            .app_data(Data::from(slow_requests.clone()))
+            .app_data(rate_limits.clone())
+            .wrap(TrackSlowRequests)
+            .wrap(RateLimit)
             .wrap(setup_logging_mw(LoggingMiddlewareConfig::default()))
-            .wrap(Compress::default())
+            .wrap(Condition::new(
+                !compression_algorithms.is_empty(),
+                Compress::default(),
+            ))
+            .wrap(NegotiateCompression(compression_algorithms.clone()))
             .wrap(
                 SessionMiddleware::builder(DBSessionStore::new(db.clone()), key.clone())
                     .session_lifecycle(PersistentSession::session_ttl(
@@ -122,47 +235,137 @@ pub async fn start_server(
                 ),
             ]))
             .service(register_account)
+            .service(download_game_data)
+            .service(observe_game)
             .service(version)
+            .service(capabilities)
             .service(scope("/api/v2/auth").service(login).service(logout))
             .service(
                 scope("/api/v2/admin")
                     .wrap(TokenRequired(admin_token.clone()))
-                    .service(health),
+                    .service(health)
+                    .service(utilization)
+                    .service(get_slow_log)
+                    .service(get_log_filters)
+                    .service(set_log_filter)
+                    .service(verify_game_data_files)
+                    .service(grant_badge)
+                    .service(revoke_badge)
+                    .service(set_moderator)
+                    .service(get_abandoned_accounts)
+                    .service(export_accounts)
+                    .service(send_test_notification)
+                    .service(admin_get_chat_messages)
+                    .service(admin_delete_message)
+                    .service(set_chat_restriction)
+                    .service(export_game_bundle)
+                    .service(import_game_bundle)
+                    .service(transfer_game)
+                    .service(get_welcome_message)
+                    .service(set_welcome_message)
+                    .service(delete_welcome_message),
+            )
+            .service(
+                scope("/api/v2/moderation")
+                    .wrap(ModeratorRequired)
+                    .wrap(AuthenticationRequired)
+                    .service(moderate_kick_player)
+                    .service(moderate_delete_message),
             )
             .service(
                 scope("/api/v2")
                     .wrap(AuthenticationRequired)
                     .service(websocket)
+                    .service(events)
                     .service(get_me)
+                    .service(get_sync)
                     .service(delete_me)
                     .service(update_me)
+                    .service(get_devices)
+                    .service(update_device)
+                    .service(delete_device)
                     .service(set_password)
+                    .service(set_chat_language)
+                    .service(set_direct_message_policy)
+                    .service(set_mutual_friends_visibility)
+                    .service(set_friend_request_policy)
+                    .service(get_webhook)
+                    .service(set_webhook)
+                    .service(delete_webhook)
+                    .service(search_accounts)
                     .service(lookup_account_by_uuid)
+                    .service(get_mutual_friends)
                     .service(lookup_account_by_username)
                     .service(create_friend_request)
+                    .service(create_bulk_friend_requests)
                     .service(accept_friend_request)
                     .service(get_friends)
                     .service(delete_friend)
+                    .service(update_friend)
+                    .service(set_friend_nickname)
                     .service(get_all_lobbies)
+                    .service(get_my_lobbies)
+                    .service(get_lobby_by_join_code)
                     .service(get_lobby)
                     .service(create_lobby)
                     .service(join_lobby)
+                    .service(join_lobby_by_code)
+                    .service(set_lobby_ready)
+                    .service(set_lobby_nation)
                     .service(leave_lobby)
+                    .service(update_lobby)
                     .service(close_lobby)
                     .service(kick_player_from_lobby)
+                    .service(get_lobby_bans)
+                    .service(unban_player_from_lobby)
+                    .service(add_lobby_co_host)
+                    .service(remove_lobby_co_host)
                     .service(get_chat)
+                    .service(export_chat_transcript)
                     .service(get_all_chats)
                     .service(send_message)
+                    .service(send_direct_message)
+                    .service(set_chat_digest)
                     .service(create_invite)
                     .service(get_invites)
                     .service(delete_invite)
+                    .service(get_game_changes)
                     .service(get_game)
+                    .service(export_game)
+                    .service(create_download_link)
                     .service(get_open_games)
+                    .service(get_game_events)
+                    .service(get_game_stats)
+                    .service(update_game_settings)
+                    .service(transfer_game_host)
+                    .service(leave_game)
+                    .service(revoke_observer_token)
+                    .service(reveal_nations)
+                    .service(clone_game)
+                    .service(create_game_snapshot)
+                    .service(get_game_snapshots)
+                    .service(restore_game_snapshot)
                     .service(push_game_update)
+                    .service(end_turn)
                     .service(start_game)
-                    .service(accept_invite),
+                    .service(accept_invite)
+                    .service(create_game_invite)
+                    .service(delete_game_invite)
+                    .service(accept_game_invite)
+                    .service(create_negotiation)
+                    .service(get_negotiations)
+                    .service(accept_negotiation)
+                    .service(decline_negotiation),
             )
     })
+    .keep_alive(match config.server.keep_alive {
+        0 => KeepAlive::Disabled,
+        secs => KeepAlive::Timeout(std::time::Duration::from_secs(secs)),
+    })
+    .client_request_timeout(std::time::Duration::from_secs(
+        config.server.client_request_timeout,
+    ))
+    .shutdown_timeout(config.server.shutdown_timeout)
     .bind(s_addr)?
     .run()
     .await?;
@@ -0,0 +1,74 @@
+//! Streaming of large lists as newline delimited JSON
+//!
+//! Instead of collecting a list into a [Vec] and serializing it as a whole, the items are
+//! produced by a background task, e.g. from a database stream, and written to the response
+//! one line at a time.
+
+use std::future::Future;
+
+use actix_web::HttpResponse;
+use bytes::Bytes;
+use futures::stream;
+use log::error;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+/// The content type of NDJSON responses
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// The number of lines that are buffered before the producer has to wait for the client
+const BUFFERED_LINES: usize = 64;
+
+/// Hands the items of a [ndjson_response] to the response
+pub struct NdjsonSender {
+    tx: mpsc::Sender<Result<Bytes, actix_web::Error>>,
+}
+
+impl NdjsonSender {
+    /// Write an item as line of the response
+    ///
+    /// Returns `false` if the client closed the connection, so the producer can stop.
+    pub async fn send<T: Serialize>(&self, item: &T) -> bool {
+        let mut line = match serde_json::to_vec(item) {
+            Ok(line) => line,
+            Err(err) => {
+                error!("Could not serialize NDJSON item: {err}");
+                return false;
+            }
+        };
+        line.push(b'\n');
+
+        self.tx.send(Ok(Bytes::from(line))).await.is_ok()
+    }
+}
+
+/// Create a response whose lines are produced by `produce`
+///
+/// `produce` runs in a background task. If it fails, the error is logged and the response is
+/// aborted, so clients can tell a truncated response from a complete one.
+pub fn ndjson_response<F, Fut>(produce: F) -> HttpResponse
+where
+    F: FnOnce(NdjsonSender) -> Fut,
+    Fut: Future<Output = Result<(), rorm::Error>> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(BUFFERED_LINES);
+
+    let error_tx = tx.clone();
+    let producer = produce(NdjsonSender { tx });
+    tokio::spawn(async move {
+        if let Err(err) = producer.await {
+            error!("Error while streaming NDJSON response: {err}");
+            let _ = error_tx
+                .send(Err(actix_web::error::ErrorInternalServerError(
+                    "Response aborted",
+                )))
+                .await;
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type(NDJSON_CONTENT_TYPE)
+        .streaming(stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|item| (item, rx))
+        }))
+}
@@ -3,7 +3,9 @@
 use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
 use utoipa::{Modify, OpenApi};
 
-use crate::server::handler;
+use crate::models;
+use crate::server::{handler, middleware};
+use crate::tasks;
 
 struct CookieSecurity;
 
@@ -26,35 +28,92 @@ impl Modify for CookieSecurity {
         handler::get_me,
         handler::delete_me,
         handler::update_me,
+        handler::get_devices,
+        handler::update_device,
+        handler::delete_device,
         handler::set_password,
+        handler::set_chat_language,
+        handler::set_direct_message_policy,
+        handler::set_mutual_friends_visibility,
+        handler::set_friend_request_policy,
+        handler::get_webhook,
+        handler::set_webhook,
+        handler::delete_webhook,
         handler::login,
         handler::logout,
         handler::websocket,
+        handler::events,
         handler::version,
+        handler::capabilities,
         handler::create_friend_request,
+        handler::create_bulk_friend_requests,
         handler::accept_friend_request,
         handler::get_friends,
         handler::delete_friend,
+        handler::update_friend,
+        handler::set_friend_nickname,
         handler::get_all_lobbies,
         handler::create_lobby,
         handler::lookup_account_by_uuid,
+        handler::get_mutual_friends,
         handler::lookup_account_by_username,
+        handler::search_accounts,
         handler::get_chat,
+        handler::export_chat_transcript,
         handler::get_all_chats,
         handler::create_invite,
         handler::get_invites,
         handler::get_open_games,
         handler::get_game,
+        handler::export_game,
+        handler::create_download_link,
+        handler::download_game_data,
+        handler::observe_game,
+        handler::get_game_events,
+        handler::get_game_stats,
+        handler::update_game_settings,
+        handler::transfer_game_host,
+        handler::leave_game,
+        handler::revoke_observer_token,
+        handler::reveal_nations,
+        handler::clone_game,
+        handler::create_game_snapshot,
+        handler::get_game_snapshots,
+        handler::restore_game_snapshot,
+        handler::create_game_invite,
+        handler::delete_game_invite,
+        handler::accept_game_invite,
         handler::push_game_update,
+        handler::end_turn,
         handler::start_game,
         handler::send_message,
+        handler::send_direct_message,
+        handler::set_chat_digest,
         handler::join_lobby,
+        handler::join_lobby_by_code,
+        handler::set_lobby_ready,
+        handler::set_lobby_nation,
         handler::delete_invite,
+        handler::update_lobby,
         handler::close_lobby,
         handler::leave_lobby,
         handler::kick_player_from_lobby,
+        handler::get_lobby_bans,
+        handler::unban_player_from_lobby,
+        handler::add_lobby_co_host,
+        handler::remove_lobby_co_host,
         handler::get_lobby,
+        handler::get_lobby_by_join_code,
+        handler::get_my_lobbies,
+        handler::get_sync,
+        handler::get_game_changes,
         handler::accept_invite,
+        handler::create_negotiation,
+        handler::get_negotiations,
+        handler::accept_negotiation,
+        handler::decline_negotiation,
+        handler::moderate_kick_player,
+        handler::moderate_delete_message,
     ),
     components(schemas(
         handler::AccountRegistrationRequest,
@@ -62,23 +121,55 @@ impl Modify for CookieSecurity {
         handler::ApiStatusCode,
         handler::LoginRequest,
         handler::AccountResponse,
+        handler::AccountProfileResponse,
+        handler::AccountStatsResponse,
+        models::Badge,
         handler::SetPasswordRequest,
+        handler::SetChatLanguageRequest,
+        handler::SetDirectMessagePolicyRequest,
+        handler::SetFriendRequestPolicyRequest,
+        handler::SetMutualFriendsVisibilityRequest,
+        handler::GetMutualFriendsResponse,
+        models::DirectMessagePolicy,
+        models::FriendRequestPolicy,
+        handler::GetWebhookResponse,
+        handler::WebhookResponse,
+        handler::SetWebhookRequest,
+        handler::SetWebhookResponse,
+        handler::DownloadLinkResponse,
+        handler::DeviceResponse,
+        handler::GetDevicesResponse,
+        handler::UpdateDeviceRequest,
         handler::UpdateAccountRequest,
         handler::VersionResponse,
+        handler::CapabilitiesResponse,
         handler::CreateFriendRequest,
+        handler::CreateBulkFriendRequest,
+        handler::CreateBulkFriendResponse,
+        handler::BulkFriendRequestResult,
+        handler::BulkFriendRequestStatus,
         handler::GetFriendResponse,
         handler::FriendResponse,
+        handler::UpdateFriendRequest,
+        handler::SetFriendNicknameRequest,
         handler::LobbyResponse,
         handler::GetLobbiesResponse,
+        handler::LobbySortOrder,
         handler::CreateLobbyResponse,
         handler::CreateLobbyRequest,
         handler::OnlineAccountResponse,
         handler::FriendRequestResponse,
         handler::LookupAccountUsernameRequest,
+        handler::SearchAccountsResponse,
+        handler::SearchAccountResponse,
+        handler::AccountRelation,
         handler::ChatSmall,
+        handler::ChatRoomKind,
+        handler::ChatMessagePreview,
         handler::ChatFull,
         handler::ChatMessage,
         handler::ChatMember,
+        handler::ChatMemberRole,
         handler::GetAllChatsResponse,
         handler::CreateInviteRequest,
         handler::GetInvitesResponse,
@@ -88,10 +179,49 @@ impl Modify for CookieSecurity {
         handler::GetGameOverviewResponse,
         handler::GameUploadResponse,
         handler::GameUploadRequest,
+        handler::EndTurnRequest,
+        handler::EndTurnResponse,
         handler::StartGameResponse,
+        handler::CloneGameResponse,
         handler::SendMessageRequest,
+        handler::DirectMessageResponse,
+        handler::SetChatDigestRequest,
         handler::JoinLobbyRequest,
-        handler::GetLobbyResponse
+        handler::JoinLobbyByCodeRequest,
+        handler::JoinLobbyByCodeResponse,
+        handler::SetReadyRequest,
+        handler::SetNationRequest,
+        handler::PlayerNation,
+        handler::GetLobbyResponse,
+        handler::LobbyBanResponse,
+        handler::GetLobbyBansResponse,
+        handler::UpdateLobbyRequest,
+        handler::CreateNegotiationRequest,
+        handler::CreateNegotiationResponse,
+        handler::NegotiationResponse,
+        handler::GetNegotiationsResponse,
+        models::NegotiationKind,
+        models::NegotiationState,
+        handler::GameEventResponse,
+        handler::GetGameEventsResponse,
+        handler::GameStatsResponse,
+        handler::GamePlayerStatsResponse,
+        models::GameEventKind,
+        models::ChatMessageType,
+        handler::GameSettingsResponse,
+        handler::LeaveGameResponse,
+        handler::UpdateGameSettingsRequest,
+        handler::GetMyLobbiesResponse,
+        handler::SyncResponse,
+        handler::SyncGame,
+        handler::SyncChat,
+        handler::GameChange,
+        handler::GameChangesResponse,
+        handler::GameSnapshotResponse,
+        handler::GetGameSnapshotsResponse,
+        handler::CreateGameSnapshotRequest,
+        handler::GetGameInvite,
+        handler::CreateGameInviteRequest,
     )),
     modifiers(&CookieSecurity)
 )]
@@ -123,11 +253,58 @@ impl Modify for TokenSecurity {
 #[openapi(
     paths(
         handler::health,
+        handler::utilization,
+        handler::get_slow_log,
+        handler::get_log_filters,
+        handler::set_log_filter,
+        handler::verify_game_data_files,
+        handler::grant_badge,
+        handler::revoke_badge,
+        handler::set_moderator,
+        handler::get_welcome_message,
+        handler::set_welcome_message,
+        handler::delete_welcome_message,
+        handler::get_abandoned_accounts,
+        handler::export_accounts,
+        handler::send_test_notification,
+        handler::admin_get_chat_messages,
+        handler::admin_delete_message,
+        handler::set_chat_restriction,
+        handler::export_game_bundle,
+        handler::import_game_bundle,
+        handler::transfer_game,
     ),
     components(schemas(
         handler::ApiErrorResponse,
         handler::ApiStatusCode,
         handler::HealthResponse,
+        handler::UtilizationResponse,
+        handler::SlowLogResponse,
+        middleware::SlowRequest,
+        middleware::SlowStatement,
+        handler::LogFiltersResponse,
+        handler::SetLogFilterRequest,
+        models::Badge,
+        handler::SetModeratorRequest,
+        handler::WelcomeMessageResponse,
+        handler::SetWelcomeMessageRequest,
+        handler::GetAbandonedAccountsResponse,
+        handler::AbandonedAccountResponse,
+        handler::ExportedAccount,
+        handler::TestNotificationResponse,
+        handler::DeliveryResult,
+        handler::SetChatRestrictionRequest,
+        handler::GameBundle,
+        handler::GameBundlePlayer,
+        handler::GameBundleSettings,
+        handler::ImportGameResponse,
+        handler::TransferGameRequest,
+        handler::ChatMessage,
+        models::ChatMessageType,
+        handler::AccountResponse,
+        tasks::GameDataCheck,
+        tasks::GameDataVerification,
+        tasks::RestoredGame,
     )),
     modifiers(&TokenSecurity)
 )]
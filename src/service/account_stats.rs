@@ -0,0 +1,88 @@
+//! Aggregated game counts of accounts for their public profiles
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rorm::{and, query, Database, FieldAccess, Model};
+use uuid::Uuid;
+
+use crate::models::{GameAccount, GamePlayerStats};
+
+/// The time the counts of an account are reused before they are computed again
+const CACHE_DURATION: Duration = Duration::from_secs(300);
+
+/// The game counts of an account
+#[derive(Copy, Clone, Debug)]
+pub struct AccountStats {
+    /// The number of games the account uploaded at least one game state to
+    ///
+    /// Games the account left are included, deleted games are not.
+    pub games_played: u64,
+    /// The number of games the account is currently a player of
+    pub running_games: u64,
+}
+
+/// Count the games of an account
+pub async fn query_account_stats(
+    db: &Database,
+    account: Uuid,
+) -> Result<AccountStats, rorm::Error> {
+    let (games_played,) = query!(db, (GamePlayerStats::F.uuid.count(),))
+        .condition(and!(
+            GamePlayerStats::F.player.equals(account),
+            GamePlayerStats::F.uploads.greater_than(0)
+        ))
+        .one()
+        .await?;
+
+    let (running_games,) = query!(db, (GameAccount::F.uuid.count(),))
+        .condition(GameAccount::F.player.equals(account))
+        .one()
+        .await?;
+
+    Ok(AccountStats {
+        games_played: games_played as u64,
+        running_games: running_games as u64,
+    })
+}
+
+/// Cache of the [AccountStats] of the accounts whose profiles were looked up recently
+///
+/// Counting the games of an account requires a scan of the players of all games,
+/// so the counts are reused for five minutes.
+#[derive(Default)]
+pub struct AccountStatsCache {
+    entries: Mutex<HashMap<Uuid, (Instant, AccountStats)>>,
+}
+
+impl AccountStatsCache {
+    /// Retrieve the counts of an account, computing them if they are not cached
+    pub async fn get(&self, db: &Database, account: Uuid) -> Result<AccountStats, rorm::Error> {
+        if let Some(stats) = self.cached(account) {
+            return Ok(stats);
+        }
+
+        let stats = query_account_stats(db, account).await?;
+
+        // Ok as the lock is never held across an await point or a panic
+        #[allow(clippy::unwrap_used)]
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, (computed_at, _)| now.duration_since(*computed_at) < CACHE_DURATION);
+        entries.insert(account, (now, stats));
+
+        Ok(stats)
+    }
+
+    /// Retrieve the counts of an account, if they are cached and not outdated
+    fn cached(&self, account: Uuid) -> Option<AccountStats> {
+        // Ok as the lock is never held across an await point or a panic
+        #[allow(clippy::unwrap_used)]
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&account)
+            .filter(|(computed_at, _)| computed_at.elapsed() < CACHE_DURATION)
+            .map(|(_, stats)| *stats)
+    }
+}
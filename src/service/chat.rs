@@ -0,0 +1,747 @@
+//! Sending of chat messages, the handling of mentions and the acknowledgment of messages
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use rorm::db::Transaction;
+use rorm::fields::types::ForeignModelByField;
+use rorm::{and, insert, or, query, update, FieldAccess, Model};
+use uuid::Uuid;
+
+use crate::chan::WsMessage;
+use crate::models::{
+    Account, ChatMessageMention, ChatMessageMentionInsert, ChatMessageType, ChatRoom,
+    ChatRoomInsert, ChatRoomMember, ChatRoomMemberInsert, ChatRoomMessage, ChatRoomMessageInsert,
+    DirectChat, DirectChatInsert, DirectMessagePolicy, Friend, Lobby,
+};
+use crate::server::handler::{
+    normalize_username, AccountResponse, ApiError, ApiResult, ChatMessage,
+};
+use crate::service::notify::NotificationSink;
+
+/// Make a message the most recent one of a chatroom and assign its sequence number
+///
+/// The chatroom is updated before its sequence number is read, so its row stays locked
+/// until the transaction ends and concurrent messages get consecutive sequence numbers.
+async fn advance_chat_room(
+    tx: &mut Transaction,
+    chat_room: Uuid,
+    message: Uuid,
+) -> Result<i64, rorm::Error> {
+    update!(&mut *tx, ChatRoom)
+        .condition(ChatRoom::F.uuid.equals(chat_room))
+        .set(ChatRoom::F.last_message_uuid, Some(message))
+        .exec()
+        .await?;
+
+    let (last_sequence,) = query!(&mut *tx, (ChatRoom::F.last_sequence,))
+        .condition(ChatRoom::F.uuid.equals(chat_room))
+        .one()
+        .await?;
+    let sequence = last_sequence + 1;
+
+    update!(&mut *tx, ChatRoom)
+        .condition(ChatRoom::F.uuid.equals(chat_room))
+        .set(ChatRoom::F.last_sequence, sequence)
+        .exec()
+        .await?;
+
+    Ok(sequence)
+}
+
+/// Post a system message to a chatroom
+///
+/// System messages don't have a sender. They are stored like every other message, so
+/// members that were offline still find them in the history of the chatroom.
+///
+/// All current members of the chatroom receive a [WsMessage::IncomingChatMessage] message.
+pub async fn post_system_message(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    chat_room: Uuid,
+    message: String,
+) -> ApiResult<()> {
+    let uuid = Uuid::new_v4();
+    let sequence = advance_chat_room(tx, chat_room, uuid).await?;
+
+    let chat_room_message = insert!(&mut *tx, ChatRoomMessageInsert)
+        .single(&ChatRoomMessageInsert {
+            uuid,
+            chat_room: ForeignModelByField::Key(chat_room),
+            sender: None,
+            message_type: ChatMessageType::System,
+            message,
+            sequence,
+        })
+        .await?;
+
+    let members = query!(&mut *tx, (ChatRoomMember::F.member,))
+        .condition(and!(
+            ChatRoomMember::F.chat_room.equals(chat_room),
+            ChatRoomMember::F.banned.equals(false)
+        ))
+        .all()
+        .await?;
+
+    notifications.notify_all(
+        members.into_iter().map(|(member,)| *member.key()),
+        WsMessage::IncomingChatMessage {
+            chat_uuid: chat_room,
+            message: ChatMessage {
+                uuid: chat_room_message.uuid,
+                sender: None,
+                message_type: ChatMessageType::System,
+                message: chat_room_message.message,
+                created_at: DateTime::from_naive_utc_and_offset(chat_room_message.created_at, Utc),
+                mentions: vec![],
+                sequence,
+            },
+        },
+    );
+
+    Ok(())
+}
+
+/// Delete a message from a chatroom
+///
+/// If the message was the most recent one of the chatroom, `last_message_uuid` of the
+/// chatroom falls back to the message before it.
+///
+/// Returns [ApiError::InvalidUuid] if the message doesn't belong to the chatroom.
+///
+/// All current members of the chatroom receive a [WsMessage::ChatMessageDeleted] message.
+pub async fn delete_message(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    chat_room: Uuid,
+    message: Uuid,
+) -> ApiResult<()> {
+    query!(&mut *tx, (ChatRoomMessage::F.uuid,))
+        .condition(and!(
+            ChatRoomMessage::F.uuid.equals(message),
+            ChatRoomMessage::F.chat_room.equals(chat_room)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    rorm::delete!(&mut *tx, ChatRoomMessage)
+        .condition(ChatRoomMessage::F.uuid.equals(message))
+        .await?;
+
+    let (last_message_uuid,) = query!(&mut *tx, (ChatRoom::F.last_message_uuid,))
+        .condition(ChatRoom::F.uuid.equals(chat_room))
+        .one()
+        .await?;
+    if last_message_uuid == Some(message) {
+        let previous = query!(&mut *tx, (ChatRoomMessage::F.uuid,))
+            .condition(ChatRoomMessage::F.chat_room.equals(chat_room))
+            .order_desc(ChatRoomMessage::F.created_at)
+            .optional()
+            .await?
+            .map(|(uuid,)| uuid);
+
+        update!(&mut *tx, ChatRoom)
+            .condition(ChatRoom::F.uuid.equals(chat_room))
+            .set(ChatRoom::F.last_message_uuid, previous)
+            .exec()
+            .await?;
+    }
+
+    let members = query!(&mut *tx, (ChatRoomMember::F.member,))
+        .condition(and!(
+            ChatRoomMember::F.chat_room.equals(chat_room),
+            ChatRoomMember::F.banned.equals(false)
+        ))
+        .all()
+        .await?;
+
+    notifications.notify_all(
+        members.into_iter().map(|(member,)| *member.key()),
+        WsMessage::ChatMessageDeleted {
+            chat_uuid: chat_room,
+            message_uuid: message,
+        },
+    );
+
+    Ok(())
+}
+
+/// A mention in a chat message, either by username or by account uuid
+#[derive(Debug, PartialEq, Eq)]
+enum Mention {
+    Username(String),
+    Account(Uuid),
+}
+
+/// Find the words of a message that start with `@`
+///
+/// Punctuation directly following a mention is not part of it.
+fn parse_mentions(message: &str) -> Vec<Mention> {
+    message
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|word| word.trim_end_matches(|c: char| c.is_ascii_punctuation() && c != '_'))
+        .filter(|word| !word.is_empty())
+        .map(|word| match Uuid::parse_str(word) {
+            Ok(account) => Mention::Account(account),
+            Err(_) => Mention::Username(normalize_username(word)),
+        })
+        .collect()
+}
+
+/// Store the accounts mentioned in a message of a chatroom
+///
+/// Only members of the chatroom can be mentioned, mentions of other accounts and
+/// of the sender are ignored. Usernames are matched case-insensitively.
+///
+/// Returns the mentioned accounts.
+pub async fn store_mentions(
+    tx: &mut Transaction,
+    chat_room: Uuid,
+    message: Uuid,
+    sender: Uuid,
+    text: &str,
+) -> Result<Vec<Uuid>, rorm::Error> {
+    let mentions = parse_mentions(text);
+    if mentions.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let members = query!(
+        &mut *tx,
+        (
+            ChatRoomMember::F.member.uuid,
+            ChatRoomMember::F.member.normalized_username
+        )
+    )
+    .condition(and!(
+        ChatRoomMember::F.chat_room.equals(chat_room),
+        ChatRoomMember::F.banned.equals(false)
+    ))
+    .all()
+    .await?;
+
+    let mentioned: Vec<Uuid> = members
+        .into_iter()
+        .filter(|(member, username)| {
+            *member != sender
+                && mentions.iter().any(|mention| match mention {
+                    Mention::Username(mentioned) => mentioned == username,
+                    Mention::Account(mentioned) => mentioned == member,
+                })
+        })
+        .map(|(member, _)| member)
+        .collect();
+
+    if !mentioned.is_empty() {
+        insert!(&mut *tx, ChatMessageMentionInsert)
+            .return_nothing()
+            .bulk(
+                &mentioned
+                    .iter()
+                    .map(|account| ChatMessageMentionInsert {
+                        uuid: Uuid::new_v4(),
+                        message: ForeignModelByField::Key(message),
+                        account: ForeignModelByField::Key(*account),
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .await?;
+    }
+
+    Ok(mentioned)
+}
+
+/// A message an account sent to a chatroom
+pub struct SentMessage {
+    /// The stored message
+    pub message: ChatMessage,
+    /// The members that chose a chat language, the message has to be translated for
+    pub translations: Vec<(Uuid, String)>,
+}
+
+/// Send a message of an account to a chatroom
+///
+/// Returns [ApiError::InvalidMessage] if the message is empty,
+/// [ApiError::MissingPrivileges] if the sender is not a member of the chatroom or was banned
+/// from it and [ApiError::ChatMuted] if the sender is muted in the chatroom.
+///
+/// If the chatroom is a direct chat the sender opened, the recipient must still accept
+/// direct messages from the sender, otherwise [ApiError::DirectMessagesNotAllowed]
+/// is returned.
+///
+/// All members of the chatroom receive a [WsMessage::IncomingChatMessage] message,
+/// mentioned members a [WsMessage::ChatMention] message in addition.
+/// The caller is responsible to start the translations of the message.
+pub async fn send_message(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    chat_room: Uuid,
+    sender: Uuid,
+    text: String,
+) -> ApiResult<SentMessage> {
+    if text.is_empty() {
+        return Err(ApiError::InvalidMessage);
+    }
+
+    // Check if the sender is member of the chatroom
+    let (sender_uuid, sender_username, sender_display_name, muted_until, banned) = query!(
+        &mut *tx,
+        (
+            ChatRoomMember::F.member.uuid,
+            ChatRoomMember::F.member.username,
+            ChatRoomMember::F.member.display_name,
+            ChatRoomMember::F.muted_until,
+            ChatRoomMember::F.banned
+        )
+    )
+    .condition(and!(
+        ChatRoomMember::F.chat_room.equals(chat_room),
+        ChatRoomMember::F.member.equals(sender)
+    ))
+    .optional()
+    .await?
+    .ok_or(ApiError::MissingPrivileges)?;
+
+    if banned {
+        return Err(ApiError::MissingPrivileges);
+    }
+    if matches!(muted_until, Some(muted_until) if muted_until > Utc::now().naive_utc()) {
+        return Err(ApiError::ChatMuted);
+    }
+
+    // The policy of the recipient applies to direct chats, no matter how the message is sent
+    if let Some((from, to)) = query!(&mut *tx, (DirectChat::F.from, DirectChat::F.to))
+        .condition(DirectChat::F.chat_room.equals(chat_room))
+        .optional()
+        .await?
+    {
+        if *from.key() == sender {
+            check_direct_message_policy(tx, sender, *to.key()).await?;
+        }
+    }
+
+    let uuid = Uuid::new_v4();
+    let sequence = advance_chat_room(tx, chat_room, uuid).await?;
+
+    let chat_room_message = insert!(&mut *tx, ChatRoomMessageInsert)
+        .single(&ChatRoomMessageInsert {
+            uuid,
+            sender: Some(ForeignModelByField::Key(sender)),
+            message_type: ChatMessageType::User,
+            message: text,
+            chat_room: ForeignModelByField::Key(chat_room),
+            sequence,
+        })
+        .await?;
+
+    // A message in the chatroom of a lobby counts as activity in the lobby
+    update!(&mut *tx, Lobby)
+        .condition(Lobby::F.chat_room.equals(chat_room))
+        .set(Lobby::F.last_activity, Utc::now().naive_utc())
+        .exec()
+        .await?;
+
+    let mentions = store_mentions(
+        tx,
+        chat_room,
+        chat_room_message.uuid,
+        sender,
+        &chat_room_message.message,
+    )
+    .await?;
+
+    let members = query!(
+        &mut *tx,
+        (
+            ChatRoomMember::F.member.uuid,
+            ChatRoomMember::F.member.chat_language
+        )
+    )
+    .condition(and!(
+        ChatRoomMember::F.chat_room.equals(chat_room),
+        ChatRoomMember::F.banned.equals(false)
+    ))
+    .all()
+    .await?;
+
+    let message = ChatMessage {
+        uuid: chat_room_message.uuid,
+        message: chat_room_message.message,
+        message_type: ChatMessageType::User,
+        sender: Some(AccountResponse {
+            uuid: sender_uuid,
+            display_name: sender_display_name,
+            username: sender_username,
+        }),
+        created_at: DateTime::from_naive_utc_and_offset(chat_room_message.created_at, Utc),
+        mentions,
+        sequence,
+    };
+
+    notifications.notify_all(
+        members.iter().map(|(member, _)| *member),
+        WsMessage::IncomingChatMessage {
+            chat_uuid: chat_room,
+            message: message.clone(),
+        },
+    );
+    notifications.notify_all(
+        message.mentions.clone(),
+        WsMessage::ChatMention {
+            chat_uuid: chat_room,
+            message: message.clone(),
+        },
+    );
+
+    let translations = members
+        .into_iter()
+        .filter(|(member, _)| *member != sender)
+        .filter_map(|(member, language)| language.map(|language| (member, language)))
+        .collect();
+
+    Ok(SentMessage {
+        message,
+        translations,
+    })
+}
+
+/// Check if an account accepts direct messages from another account
+///
+/// Friends may always send direct messages, other accounts only if the recipient allows
+/// direct messages from everyone.
+///
+/// Returns [ApiError::InvalidUuid] if the recipient doesn't exist and
+/// [ApiError::DirectMessagesNotAllowed] if the recipient doesn't accept direct messages
+/// from the sender.
+async fn check_direct_message_policy(
+    tx: &mut Transaction,
+    sender: Uuid,
+    recipient: Uuid,
+) -> ApiResult<()> {
+    let (allow_dms_from,) = query!(&mut *tx, (Account::F.allow_dms_from,))
+        .condition(Account::F.uuid.equals(recipient))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    if allow_dms_from == DirectMessagePolicy::Everyone {
+        return Ok(());
+    }
+
+    let friends = query!(&mut *tx, (Friend::F.uuid,))
+        .condition(and!(
+            Friend::F.is_request.equals(false),
+            Friend::F.from.equals(sender),
+            Friend::F.to.equals(recipient)
+        ))
+        .optional()
+        .await?;
+    if friends.is_none() {
+        return Err(ApiError::DirectMessagesNotAllowed);
+    }
+
+    Ok(())
+}
+
+/// Find or create the chatroom for direct messages from one account to another
+///
+/// If both accounts are friends, their friend chatroom is used. Otherwise the direct chat
+/// between both accounts is used, which is created with the first message. A direct chat
+/// may be used if the recipient allows direct messages from everyone or opened the direct
+/// chat themselves. There is at most one direct chat per pair of accounts.
+///
+/// Returns [ApiError::InvalidUuid] if the recipient doesn't exist and
+/// [ApiError::DirectMessagesNotAllowed] if the recipient doesn't accept direct messages
+/// from the sender.
+pub async fn direct_chat_room(
+    tx: &mut Transaction,
+    sender: Uuid,
+    recipient: Uuid,
+) -> ApiResult<Uuid> {
+    if sender == recipient {
+        return Err(ApiError::DirectMessagesNotAllowed);
+    }
+
+    if let Some((Some(chat_room),)) = query!(&mut *tx, (Friend::F.chat_room,))
+        .condition(and!(
+            Friend::F.is_request.equals(false),
+            Friend::F.from.equals(sender),
+            Friend::F.to.equals(recipient)
+        ))
+        .optional()
+        .await?
+    {
+        return Ok(*chat_room.key());
+    }
+
+    let existing = query!(&mut *tx, (DirectChat::F.chat_room, DirectChat::F.from))
+        .condition(or!(
+            and!(
+                DirectChat::F.from.equals(sender),
+                DirectChat::F.to.equals(recipient)
+            ),
+            and!(
+                DirectChat::F.from.equals(recipient),
+                DirectChat::F.to.equals(sender)
+            )
+        ))
+        .optional()
+        .await?;
+
+    let opened_by_recipient = matches!(&existing, Some((_, from)) if *from.key() == recipient);
+    if !opened_by_recipient {
+        check_direct_message_policy(tx, sender, recipient).await?;
+    }
+
+    if let Some((chat_room, _)) = existing {
+        return Ok(*chat_room.key());
+    }
+
+    let chat_room = insert!(&mut *tx, ChatRoomInsert)
+        .return_primary_key()
+        .single(&ChatRoomInsert {
+            uuid: Uuid::new_v4(),
+            last_message_uuid: None,
+        })
+        .await?;
+
+    insert!(&mut *tx, ChatRoomMemberInsert)
+        .return_nothing()
+        .bulk(&[
+            ChatRoomMemberInsert {
+                uuid: Uuid::new_v4(),
+                chat_room: ForeignModelByField::Key(chat_room),
+                member: ForeignModelByField::Key(sender),
+            },
+            ChatRoomMemberInsert {
+                uuid: Uuid::new_v4(),
+                chat_room: ForeignModelByField::Key(chat_room),
+                member: ForeignModelByField::Key(recipient),
+            },
+        ])
+        .await?;
+
+    insert!(&mut *tx, DirectChatInsert)
+        .return_nothing()
+        .single(&DirectChatInsert {
+            uuid: Uuid::new_v4(),
+            from: ForeignModelByField::Key(sender),
+            to: ForeignModelByField::Key(recipient),
+            chat_room: ForeignModelByField::Key(chat_room),
+        })
+        .await?;
+
+    Ok(chat_room)
+}
+
+/// Acknowledge the messages of a chatroom up to a sequence number
+///
+/// Sequence numbers are never lowered and are capped at the most recent message.
+///
+/// Returns [ApiError::MissingPrivileges] if the account is not a member of the chatroom.
+pub async fn ack_messages(
+    tx: &mut Transaction,
+    chat_room: Uuid,
+    account: Uuid,
+    sequence: i64,
+) -> ApiResult<()> {
+    let (member, acked_sequence, last_sequence) = query!(
+        &mut *tx,
+        (
+            ChatRoomMember::F.uuid,
+            ChatRoomMember::F.acked_sequence,
+            ChatRoomMember::F.chat_room.last_sequence
+        )
+    )
+    .condition(and!(
+        ChatRoomMember::F.chat_room.equals(chat_room),
+        ChatRoomMember::F.member.equals(account)
+    ))
+    .optional()
+    .await?
+    .ok_or(ApiError::MissingPrivileges)?;
+
+    let sequence = sequence.min(last_sequence);
+    if sequence > acked_sequence {
+        update!(&mut *tx, ChatRoomMember)
+            .condition(ChatRoomMember::F.uuid.equals(member))
+            .set(ChatRoomMember::F.acked_sequence, sequence)
+            .exec()
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// The maximum number of missed messages that are resent per chatroom
+///
+/// Clients that missed more messages have to retrieve them via `GET /api/v2/chats/{uuid}`.
+const MAX_MISSED_MESSAGES: u64 = 100;
+
+/// Retrieve the messages of all chatrooms of an account that it didn't acknowledge yet
+///
+/// At most [MAX_MISSED_MESSAGES] of the most recent messages are returned per chatroom,
+/// ordered by their sequence number.
+pub async fn missed_messages(
+    tx: &mut Transaction,
+    account: Uuid,
+) -> Result<Vec<(Uuid, ChatMessage)>, rorm::Error> {
+    let rooms = query!(
+        &mut *tx,
+        (
+            ChatRoomMember::F.chat_room,
+            ChatRoomMember::F.acked_sequence,
+            ChatRoomMember::F.chat_room.last_sequence
+        )
+    )
+    .condition(and!(
+        ChatRoomMember::F.member.equals(account),
+        ChatRoomMember::F.banned.equals(false)
+    ))
+    .all()
+    .await?;
+
+    let mut senders: HashMap<Uuid, Option<AccountResponse>> = HashMap::new();
+    let mut missed = vec![];
+    for (chat_room, acked_sequence, last_sequence) in rooms {
+        if last_sequence <= acked_sequence {
+            continue;
+        }
+        let chat_room = *chat_room.key();
+
+        let mut messages = query!(
+            &mut *tx,
+            (
+                ChatRoomMessage::F.uuid,
+                ChatRoomMessage::F.message,
+                ChatRoomMessage::F.message_type,
+                ChatRoomMessage::F.created_at,
+                ChatRoomMessage::F.sender,
+                ChatRoomMessage::F.sequence,
+            )
+        )
+        .condition(and!(
+            ChatRoomMessage::F.chat_room.equals(chat_room),
+            ChatRoomMessage::F.sequence.greater_than(acked_sequence)
+        ))
+        .order_desc(ChatRoomMessage::F.sequence)
+        .limit(MAX_MISSED_MESSAGES)
+        .all()
+        .await?;
+        messages.reverse();
+
+        let mut mentions: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        for (message, mentioned) in query!(
+            &mut *tx,
+            (ChatMessageMention::F.message, ChatMessageMention::F.account)
+        )
+        .condition(and!(
+            ChatMessageMention::F.message.chat_room.equals(chat_room),
+            ChatMessageMention::F
+                .message
+                .sequence
+                .greater_than(acked_sequence)
+        ))
+        .all()
+        .await?
+        {
+            mentions
+                .entry(*message.key())
+                .or_default()
+                .push(*mentioned.key());
+        }
+
+        for (uuid, message, message_type, created_at, sender, sequence) in messages {
+            let sender = match sender {
+                Some(sender) => {
+                    let sender = *sender.key();
+                    if !senders.contains_key(&sender) {
+                        let account = query!(
+                            &mut *tx,
+                            (
+                                Account::F.uuid,
+                                Account::F.username,
+                                Account::F.display_name
+                            )
+                        )
+                        .condition(Account::F.uuid.equals(sender))
+                        .optional()
+                        .await?
+                        .map(|(uuid, username, display_name)| AccountResponse {
+                            uuid,
+                            username,
+                            display_name,
+                        });
+                        senders.insert(sender, account);
+                    }
+                    senders.get(&sender).cloned().flatten()
+                }
+                None => None,
+            };
+
+            missed.push((
+                chat_room,
+                ChatMessage {
+                    uuid,
+                    sender,
+                    message_type,
+                    message,
+                    created_at: DateTime::from_naive_utc_and_offset(created_at, Utc),
+                    mentions: mentions.remove(&uuid).unwrap_or_default(),
+                    sequence,
+                },
+            ));
+        }
+    }
+
+    Ok(missed)
+}
+
+/// Mute or ban a member of a chatroom or lift the restrictions
+///
+/// Muted members may not send messages until `muted_until`. Banned members can neither send
+/// nor receive messages of the chatroom, until the ban is lifted.
+///
+/// Returns [ApiError::InvalidUuid] if the account is not a member of the chatroom.
+///
+/// The member receives a [WsMessage::ChatRestrictionChanged] message.
+pub async fn set_restriction(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    chat_room: Uuid,
+    account: Uuid,
+    muted_until: Option<DateTime<Utc>>,
+    banned: bool,
+) -> ApiResult<()> {
+    let (member,) = query!(&mut *tx, (ChatRoomMember::F.uuid,))
+        .condition(and!(
+            ChatRoomMember::F.chat_room.equals(chat_room),
+            ChatRoomMember::F.member.equals(account)
+        ))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    update!(&mut *tx, ChatRoomMember)
+        .condition(ChatRoomMember::F.uuid.equals(member))
+        .set(
+            ChatRoomMember::F.muted_until,
+            muted_until.map(|x| x.naive_utc()),
+        )
+        .set(ChatRoomMember::F.banned, banned)
+        .exec()
+        .await?;
+
+    notifications.notify(
+        account,
+        WsMessage::ChatRestrictionChanged {
+            chat_uuid: chat_room,
+            muted_until,
+            banned,
+        },
+    );
+
+    Ok(())
+}
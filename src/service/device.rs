@@ -0,0 +1,37 @@
+//! The clients accounts are logged in with
+
+use chrono::{Duration, Utc};
+use rorm::db::Transaction;
+use rorm::{query, update, FieldAccess, Model};
+use uuid::Uuid;
+
+use crate::models::Device;
+
+/// The time after which the last use of a device is updated again
+///
+/// This keeps authenticated requests from writing to the database every time.
+const LAST_SEEN_INTERVAL: i64 = 60;
+
+/// Mark a device as used
+///
+/// Returns `false` if the device doesn't exist anymore, e.g. because it was revoked.
+pub async fn touch(tx: &mut Transaction, device: Uuid) -> Result<bool, rorm::Error> {
+    let Some((last_seen,)) = query!(&mut *tx, (Device::F.last_seen,))
+        .condition(Device::F.uuid.equals(device))
+        .optional()
+        .await?
+    else {
+        return Ok(false);
+    };
+
+    let now = Utc::now().naive_utc();
+    if now - last_seen >= Duration::seconds(LAST_SEEN_INTERVAL) {
+        update!(&mut *tx, Device)
+            .condition(Device::F.uuid.equals(device))
+            .set(Device::F.last_seen, now)
+            .exec()
+            .await?;
+    }
+
+    Ok(true)
+}
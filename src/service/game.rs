@@ -0,0 +1,831 @@
+//! Starting games, ending turns and uploading new game states
+
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use log::{error, warn};
+use rorm::db::Transaction;
+use rorm::fields::types::ForeignModelByField;
+use rorm::{and, insert, query, update, FieldAccess, Model};
+use sha2::{Digest, Sha256};
+use tokio::fs::{remove_file, write};
+use uuid::Uuid;
+
+use crate::chan::{LobbyListChange, WsMessage};
+use crate::models::{
+    ChatRoomInsert, ChatRoomMember, ChatRoomMessage, Game, GameAccountWithNationInsert,
+    GameEventKind, GameInsert, GameSettingsInsert, Lobby, LobbyAccount,
+};
+use crate::server::handler::{
+    game_data_filename, record_game_event, record_game_upload, ApiError, ApiResult,
+};
+use crate::service::lobby_list::announce;
+use crate::service::membership::{GameMembers, LobbyMembers};
+use crate::service::notify::NotificationSink;
+
+/// The game that was created from a lobby
+pub struct StartedGame {
+    /// The uuid of the new game
+    pub game_uuid: Uuid,
+    /// The uuid of the chatroom of the new game
+    pub game_chat_uuid: Uuid,
+}
+
+/// Check whether another game may be created on this server
+///
+/// `max_running_games` is the server-wide limit of games, `0` means unlimited.
+/// Returns [ApiError::ServerAtCapacity] if the limit is reached.
+pub async fn check_capacity(tx: &mut Transaction, max_running_games: u64) -> ApiResult<()> {
+    if max_running_games == 0 {
+        return Ok(());
+    }
+
+    let (games,) = query!(&mut *tx, (Game::F.uuid.count(),)).one().await?;
+    if games as u64 >= max_running_games {
+        return Err(ApiError::ServerAtCapacity);
+    }
+
+    Ok(())
+}
+
+/// Start a game from an existing lobby
+///
+/// `account` must be the owner of the lobby and becomes the host of the game.
+/// The lobby is deleted, its messages and members are moved to a new chatroom.
+///
+/// If the lobby hides the identities of its members and two of them chose the same nation,
+/// [ApiError::NationAlreadyTaken] is returned.
+///
+/// All players of the lobby, except the owner, receive a [WsMessage::GameStarted] message.
+/// The lobby is removed from the lobby list, see [announce].
+pub async fn start_game(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    lobby: Uuid,
+    account: Uuid,
+) -> ApiResult<StartedGame> {
+    let lobby = query!(&mut *tx, Lobby)
+        .condition(Lobby::F.uuid.equals(lobby))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    // Check if the executing user owns the lobby
+    if *lobby.owner.key() != account {
+        return Err(ApiError::MissingPrivileges);
+    }
+
+    let lobby_players = query!(
+        &mut *tx,
+        (
+            LobbyAccount::F.player,
+            LobbyAccount::F.ready,
+            LobbyAccount::F.nation,
+        )
+    )
+    .condition(LobbyAccount::F.lobby.equals(lobby.uuid))
+    .all()
+    .await?;
+
+    // Check if enough players joined the lobby, the owner occupies a slot as well
+    if lobby
+        .min_player
+        .is_some_and(|x| lobby_players.len() + 1 < x as usize)
+    {
+        return Err(ApiError::NotEnoughPlayers);
+    }
+
+    // Check if all players are ready, if the lobby requires it
+    if lobby.require_ready && lobby_players.iter().any(|(_, ready, _)| !ready) {
+        return Err(ApiError::PlayersNotReady);
+    }
+
+    let players: Vec<Uuid> = lobby_players
+        .iter()
+        .map(|(player, _, _)| *player.key())
+        .collect();
+
+    // The chosen nations of the owner and all players
+    let nations: Vec<(Uuid, Option<String>)> = iter::once((account, lobby.owner_nation))
+        .chain(
+            lobby_players
+                .into_iter()
+                .map(|(player, _, nation)| (*player.key(), nation)),
+        )
+        .collect();
+
+    // Lobbies that hide the identities of their members don't reject taken nations
+    // when they are chosen, so conflicts are reported here instead
+    if lobby.hidden_identities {
+        let mut chosen: Vec<String> = nations
+            .iter()
+            .filter_map(|(_, nation)| nation.as_ref().map(|x| x.to_lowercase()))
+            .collect();
+        let count = chosen.len();
+        chosen.sort_unstable();
+        chosen.dedup();
+        if chosen.len() != count {
+            return Err(ApiError::NationAlreadyTaken);
+        }
+    }
+
+    // Create chatroom for the game
+    let game_chat_uuid = insert!(&mut *tx, ChatRoomInsert)
+        .return_primary_key()
+        .single(&ChatRoomInsert {
+            uuid: Uuid::new_v4(),
+            last_message_uuid: None,
+        })
+        .await?;
+
+    // Move messages from lobby chat to game chat
+    update!(&mut *tx, ChatRoomMessage)
+        .condition(ChatRoomMessage::F.chat_room.equals(*lobby.chat_room.key()))
+        .set(
+            ChatRoomMessage::F.chat_room,
+            ForeignModelByField::Key(game_chat_uuid),
+        )
+        .exec()
+        .await?;
+
+    // Move chatroom member to new chatroom
+    update!(&mut *tx, ChatRoomMember)
+        .condition(ChatRoomMember::F.chat_room.equals(*lobby.chat_room.key()))
+        .set(
+            ChatRoomMember::F.chat_room,
+            ForeignModelByField::Key(game_chat_uuid),
+        )
+        .exec()
+        .await?;
+
+    // Create new game and attach lobby chat
+    let game_uuid = insert!(&mut *tx, GameInsert)
+        .return_primary_key()
+        .single(&GameInsert {
+            uuid: Uuid::new_v4(),
+            chat_room: ForeignModelByField::Key(game_chat_uuid),
+            max_players: lobby.max_player,
+            name: lobby.name,
+            updated_by: ForeignModelByField::Key(account),
+            host: Some(ForeignModelByField::Key(account)),
+            hidden_identities: lobby.hidden_identities,
+        })
+        .await?;
+
+    // Apply the game settings chosen in the lobby
+    insert!(&mut *tx, GameSettingsInsert)
+        .return_nothing()
+        .single(&GameSettingsInsert {
+            uuid: Uuid::new_v4(),
+            game: ForeignModelByField::Key(game_uuid),
+            turn_timer: lobby.turn_timer,
+            allow_spectators: lobby.allow_spectators,
+            allow_late_joins: lobby.allow_late_joins,
+            public: lobby.public_game,
+            grant_observer_tokens: false,
+        })
+        .await?;
+
+    // Attach the owner and all players from lobby to game with their chosen nations
+    insert!(&mut *tx, GameAccountWithNationInsert)
+        .return_nothing()
+        .bulk(
+            &nations
+                .into_iter()
+                .map(|(player, nation)| GameAccountWithNationInsert {
+                    uuid: Uuid::new_v4(),
+                    game: ForeignModelByField::Key(game_uuid),
+                    player: ForeignModelByField::Key(player),
+                    nation,
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+
+    record_game_event(
+        tx,
+        game_uuid,
+        GameEventKind::Started,
+        Some(account),
+        None,
+        None,
+        None,
+    )
+    .await?;
+
+    announce(tx, notifications, lobby.uuid, LobbyListChange::Closed).await?;
+
+    // Delete lobby
+    rorm::delete!(&mut *tx, Lobby)
+        .condition(Lobby::F.uuid.equals(lobby.uuid))
+        .await?;
+
+    notifications.notify_all(
+        players,
+        WsMessage::GameStarted {
+            game_uuid,
+            game_chat_uuid,
+            lobby_uuid: lobby.uuid,
+            lobby_chat_uuid: *lobby.chat_room.key(),
+        },
+    );
+
+    Ok(StartedGame {
+        game_uuid,
+        game_chat_uuid,
+    })
+}
+
+/// Start the game of a full lobby that has `auto_start` set
+///
+/// The game is started on behalf of the owner, who becomes the host. Besides the messages
+/// of [start_game], the owner receives a [WsMessage::GameStarted] message as well,
+/// as they didn't start the game themselves.
+///
+/// If the lobby is not full, doesn't start automatically or the game can't be started yet,
+/// because not all players are ready or the server is at capacity, the lobby stays open
+/// and `None` is returned.
+pub async fn auto_start(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    lobby: Uuid,
+    max_running_games: u64,
+) -> ApiResult<Option<StartedGame>> {
+    let Some((auto_start, owner, lobby_chat)) = query!(
+        &mut *tx,
+        (Lobby::F.auto_start, Lobby::F.owner, Lobby::F.chat_room)
+    )
+    .condition(Lobby::F.uuid.equals(lobby))
+    .optional()
+    .await?
+    else {
+        return Ok(None);
+    };
+    if !auto_start {
+        return Ok(None);
+    }
+
+    let members = LobbyMembers::query(tx, lobby)
+        .await?
+        .ok_or(ApiError::InternalServerError)?;
+    if !members.is_full() {
+        return Ok(None);
+    }
+
+    // The checks of start_game happen before anything is changed
+    let started = match check_capacity(tx, max_running_games).await {
+        Ok(()) => start_game(tx, notifications, lobby, *owner.key()).await,
+        Err(err) => Err(err),
+    };
+    let game = match started {
+        Ok(game) => game,
+        Err(ApiError::PlayersNotReady | ApiError::ServerAtCapacity) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    notifications.notify(
+        *owner.key(),
+        WsMessage::GameStarted {
+            game_uuid: game.game_uuid,
+            game_chat_uuid: game.game_chat_uuid,
+            lobby_uuid: lobby,
+            lobby_chat_uuid: *lobby_chat.key(),
+        },
+    );
+
+    Ok(Some(game))
+}
+
+/// A new game state uploaded by a player
+pub struct GameStateUpload {
+    /// The serialized game state
+    pub game_data: String,
+    /// The optional hex encoded SHA-256 checksum of `game_data`
+    pub game_data_checksum: Option<String>,
+    /// The optional player whose turn it is in the uploaded state
+    pub next_player: Option<Uuid>,
+    /// The optional turn of the uploaded state
+    pub turn: Option<u32>,
+    /// The optional in-game year of the uploaded state
+    pub year: Option<i32>,
+    /// The optional era of the uploaded state
+    pub era: Option<String>,
+    /// The optional number of players that are not defeated in the uploaded state
+    pub alive_players: Option<u16>,
+    /// The optional note for the next player
+    pub note: Option<String>,
+}
+
+/// The game state that was stored by [upload_game_state]
+pub struct UploadedGameState {
+    /// The new data identifier of the game
+    pub data_id: u64,
+    /// The hex encoded SHA-256 checksum of the stored game state
+    pub checksum: String,
+    /// The point in time the game state was stored
+    pub updated_at: DateTime<Utc>,
+    /// The players that were notified about the new game state
+    pub notified_players: Vec<Uuid>,
+    /// The file of the previous game state
+    outdated_file: PathBuf,
+}
+
+impl UploadedGameState {
+    /// Remove the file of the previous game state
+    ///
+    /// This should be called after the transaction was committed, so the previous
+    /// state is still available if the upload is rolled back.
+    pub async fn remove_outdated_file(&self) {
+        if let Err(e) = remove_file(&self.outdated_file).await {
+            if self.data_id != 1 {
+                warn!(
+                    "Outdated data in '{}' could not be removed and may leak: {e}",
+                    self.outdated_file.display()
+                );
+            }
+        }
+    }
+}
+
+/// Upload a new game state for an existing game
+///
+/// `account` must be a player of the game. The state is written to a new file in
+/// `game_data_path`, the file of the previous state is kept until
+/// [UploadedGameState::remove_outdated_file] is called.
+///
+/// All other players receive a [WsMessage::UpdateGameData] and a [WsMessage::GameMetaChanged]
+/// message, the next player additionally a [WsMessage::YourTurn] message with the note
+/// of the upload. The note is recorded in the event of the upload as well.
+///
+/// Returns [ApiError::InvalidTurnNote] if the note is longer than 255 characters and
+/// [ApiError::InvalidGameSummary] if the turn is larger than `i32::MAX`, the era is longer
+/// than 64 characters or more players are alive than the game has.
+///
+/// If `account` ended its turn before with [end_turn], the pending upload is resolved.
+pub async fn upload_game_state(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    game_data_path: &str,
+    max_game_data_size: usize,
+    game: Uuid,
+    account: Uuid,
+    upload: GameStateUpload,
+) -> ApiResult<UploadedGameState> {
+    if upload.game_data.len() > max_game_data_size {
+        return Err(ApiError::PayloadOverflow(format!(
+            "The game data exceeds the maximum size of {max_game_data_size} bytes"
+        )));
+    }
+
+    let note = match upload.note.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(note) if note.chars().count() > 255 => return Err(ApiError::InvalidTurnNote),
+        Some(note) => Some(note.to_string()),
+    };
+    let era = match upload.era.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(era) if era.chars().count() > 64 => return Err(ApiError::InvalidGameSummary),
+        Some(era) => Some(era.to_string()),
+    };
+    let turn = upload
+        .turn
+        .map(i32::try_from)
+        .transpose()
+        .map_err(|_| ApiError::InvalidGameSummary)?;
+
+    // Verify the checksum before touching anything else
+    let checksum = hex::encode(Sha256::digest(upload.game_data.as_bytes()));
+    if let Some(expected) = &upload.game_data_checksum {
+        if !expected.eq_ignore_ascii_case(&checksum) {
+            return Err(ApiError::InvalidChecksum);
+        }
+    }
+
+    // Lookup the game and verify that the player is actually participating in it
+    let (data_id, pending_upload_by, current_player, max_players) = query!(
+        &mut *tx,
+        (
+            Game::F.data_id,
+            Game::F.pending_upload_by,
+            Game::F.current_player,
+            Game::F.max_players
+        )
+    )
+    .condition(and!(
+        Game::F.uuid.equals(game),
+        Game::F.current_players.player.uuid.equals(account)
+    ))
+    .optional()
+    .await?
+    .ok_or(ApiError::GameNotFound)?;
+
+    if upload
+        .alive_players
+        .is_some_and(|x| i32::from(x) > i32::from(max_players))
+    {
+        return Err(ApiError::InvalidGameSummary);
+    }
+
+    let members = GameMembers::query(tx, game)
+        .await?
+        .ok_or(ApiError::GameNotFound)?;
+
+    // Check if the next player is actually participating in the game
+    if let Some(next_player) = upload.next_player {
+        if !members.is_player(next_player) {
+            return Err(ApiError::InvalidUuid);
+        }
+    }
+
+    // Increment the data identifier used to determine whether a game state has changed
+    let new_data_id = data_id + 1;
+
+    // Save a new file with the updated game state to disk
+    let new_filename = game_data_filename(game, new_data_id);
+    let new_path = Path::new(game_data_path).join(&new_filename);
+    if let Err(e) = write(&new_path, &upload.game_data).await {
+        error!("Game data could not be saved to '{new_filename}': {e}");
+        return Err(ApiError::InternalServerError);
+    }
+
+    // Update the game state identifier and last player in the database,
+    // which also updates the last access time automatically
+    update!(&mut *tx, Game)
+        .set(Game::F.data_id, new_data_id)
+        .set(Game::F.data_checksum, Some(checksum.clone()))
+        .set(Game::F.updated_by, ForeignModelByField::Key(account))
+        .set(Game::F.turn, turn)
+        .set(Game::F.year, upload.year)
+        .set(Game::F.era, era)
+        .set(
+            Game::F.alive_players,
+            upload.alive_players.map(|x| x as i16),
+        )
+        .condition(Game::F.uuid.equals(game))
+        .await?;
+
+    let (updated_at,) = query!(&mut *tx, (Game::F.updated_at,))
+        .condition(Game::F.uuid.equals(game))
+        .one()
+        .await?;
+    let updated_at = DateTime::from_naive_utc_and_offset(updated_at, Utc);
+
+    if let Some(next_player) = upload.next_player {
+        update!(&mut *tx, Game)
+            .set(
+                Game::F.current_player,
+                Some(ForeignModelByField::Key(next_player)),
+            )
+            .condition(Game::F.uuid.equals(game))
+            .await?;
+    }
+
+    // The upload that was deferred by ending the turn has arrived
+    if pending_upload_by.map(|x| *x.key()) == Some(account) {
+        update!(&mut *tx, Game)
+            .set(Game::F.pending_upload_by, None)
+            .set(Game::F.pending_upload_until, None)
+            .condition(Game::F.uuid.equals(game))
+            .await?;
+    }
+
+    record_game_event(
+        tx,
+        game,
+        GameEventKind::Uploaded,
+        Some(account),
+        upload.next_player,
+        Some(new_data_id),
+        note.clone(),
+    )
+    .await?;
+    record_game_upload(tx, game, account).await?;
+
+    // Notify all remaining players about the new game data
+    let others: Vec<Uuid> = members
+        .players
+        .into_iter()
+        .filter(|x| *x != account)
+        .collect();
+    notifications.notify_all(
+        others.iter().copied(),
+        WsMessage::GameMetaChanged {
+            game_uuid: game,
+            turn: upload.turn,
+            current_player: upload.next_player.or(current_player.map(|x| *x.key())),
+            updated_at,
+        },
+    );
+    notifications.notify_all(
+        others.iter().copied(),
+        WsMessage::UpdateGameData {
+            game_uuid: game,
+            game_data_id: new_data_id as u64,
+            game_data: upload.game_data,
+        },
+    );
+
+    // Notify the next player separately, so the client can show a dedicated notification
+    if let Some(next_player) = upload.next_player {
+        notifications.notify(
+            next_player,
+            WsMessage::YourTurn {
+                game_uuid: game,
+                game_data_id: new_data_id as u64,
+                note,
+            },
+        );
+    }
+
+    Ok(UploadedGameState {
+        data_id: new_data_id as u64,
+        checksum,
+        updated_at,
+        notified_players: others,
+        outdated_file: Path::new(game_data_path).join(game_data_filename(game, data_id)),
+    })
+}
+
+/// End the turn of `account` without uploading the game state yet
+///
+/// `account` must be a player of the game and, if the server knows whose turn it is,
+/// the current player. The turn passes to `next_player` and the upload of the game
+/// state is marked as pending until `upload_timeout` passed.
+///
+/// While the upload of another player is pending, [ApiError::UploadPending] is returned.
+/// Once its deadline passed, the pending upload is ignored and replaced by the one
+/// of `account`, so a player that never uploads can't block the game.
+///
+/// All other players receive a [WsMessage::TurnEnded] message.
+///
+/// Returns the deadline of the upload.
+pub async fn end_turn(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    upload_timeout: Duration,
+    game: Uuid,
+    account: Uuid,
+    next_player: Uuid,
+) -> ApiResult<DateTime<Utc>> {
+    let (current_player, pending_upload_by, pending_upload_until) = query!(
+        &mut *tx,
+        (
+            Game::F.current_player,
+            Game::F.pending_upload_by,
+            Game::F.pending_upload_until
+        )
+    )
+    .condition(and!(
+        Game::F.uuid.equals(game),
+        Game::F.current_players.player.uuid.equals(account)
+    ))
+    .optional()
+    .await?
+    .ok_or(ApiError::GameNotFound)?;
+
+    if current_player.is_some_and(|x| *x.key() != account) {
+        return Err(ApiError::NotYourTurn);
+    }
+
+    // The game state of the last turn is required to play this turn,
+    // unless its upload deadline already passed
+    let now = Utc::now();
+    let pending_expired = pending_upload_until.is_some_and(|x| x < now.naive_utc());
+    if pending_upload_by.is_some_and(|x| *x.key() != account) && !pending_expired {
+        return Err(ApiError::UploadPending);
+    }
+
+    let members = GameMembers::query(tx, game)
+        .await?
+        .ok_or(ApiError::GameNotFound)?;
+
+    if !members.is_player(next_player) {
+        return Err(ApiError::InvalidUuid);
+    }
+
+    let upload_deadline = now + upload_timeout;
+
+    update!(&mut *tx, Game)
+        .set(
+            Game::F.current_player,
+            Some(ForeignModelByField::Key(next_player)),
+        )
+        .set(
+            Game::F.pending_upload_by,
+            Some(ForeignModelByField::Key(account)),
+        )
+        .set(
+            Game::F.pending_upload_until,
+            Some(upload_deadline.naive_utc()),
+        )
+        .condition(Game::F.uuid.equals(game))
+        .await?;
+
+    notifications.notify_all(
+        members.players.into_iter().filter(|x| *x != account),
+        WsMessage::TurnEnded {
+            game_uuid: game,
+            player_uuid: account,
+            next_player_uuid: next_player,
+            upload_deadline,
+        },
+    );
+
+    Ok(upload_deadline)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use rorm::{query, update, FieldAccess, Model};
+    use uuid::Uuid;
+
+    use super::{start_game, upload_game_state, GameStateUpload};
+    use crate::chan::WsMessage;
+    use crate::models::{Game, GameAccount, Lobby, LobbyAccount};
+    use crate::server::handler::ApiError;
+    use crate::service::testing::{
+        create_account, create_game, create_lobby, test_db, RecordingSink,
+    };
+
+    fn upload(next_player: Option<Uuid>) -> GameStateUpload {
+        GameStateUpload {
+            game_data: "game data".to_string(),
+            game_data_checksum: None,
+            next_player,
+            turn: Some(2),
+            year: None,
+            era: None,
+            alive_players: None,
+            note: Some("Your move".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs RUNCIV_TEST_CONFIG"]
+    async fn start_game_notifies_the_players() {
+        let db = test_db().await;
+        let mut tx = db.start_transaction().await.unwrap();
+
+        let owner = create_account(&mut tx).await;
+        let player = create_account(&mut tx).await;
+        let lobby = create_lobby(&mut tx, owner, &[player], 4).await;
+
+        let mut sink = RecordingSink::default();
+        let started = start_game(&mut tx, &mut sink, lobby, owner).await.unwrap();
+
+        let players = query!(&mut tx, (GameAccount::F.player,))
+            .condition(GameAccount::F.game.equals(started.game_uuid))
+            .all()
+            .await
+            .unwrap();
+        assert_eq!(players.len(), 2);
+        let lobby_left = query!(&mut tx, (Lobby::F.uuid,))
+            .condition(Lobby::F.uuid.equals(lobby))
+            .optional()
+            .await
+            .unwrap();
+        assert!(lobby_left.is_none());
+
+        // The owner started the game themselves
+        assert!(sink.messages_to(owner).is_empty());
+        let messages = sink.messages_to(player);
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(
+            messages[0],
+            WsMessage::GameStarted { game_uuid, lobby_uuid, .. }
+                if *game_uuid == started.game_uuid && *lobby_uuid == lobby
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs RUNCIV_TEST_CONFIG"]
+    async fn start_game_requires_the_owner() {
+        let db = test_db().await;
+        let mut tx = db.start_transaction().await.unwrap();
+
+        let owner = create_account(&mut tx).await;
+        let player = create_account(&mut tx).await;
+        let lobby = create_lobby(&mut tx, owner, &[player], 4).await;
+
+        let mut sink = RecordingSink::default();
+        let result = start_game(&mut tx, &mut sink, lobby, player).await;
+
+        assert!(matches!(result, Err(ApiError::MissingPrivileges)));
+        assert!(sink.messages.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs RUNCIV_TEST_CONFIG"]
+    async fn start_game_rejects_taken_nations_with_hidden_identities() {
+        let db = test_db().await;
+        let mut tx = db.start_transaction().await.unwrap();
+
+        let owner = create_account(&mut tx).await;
+        let player = create_account(&mut tx).await;
+        let lobby = create_lobby(&mut tx, owner, &[player], 4).await;
+        update!(&mut tx, Lobby)
+            .condition(Lobby::F.uuid.equals(lobby))
+            .set(Lobby::F.hidden_identities, true)
+            .set(Lobby::F.owner_nation, Some("Babylon".to_string()))
+            .exec()
+            .await
+            .unwrap();
+        update!(&mut tx, LobbyAccount)
+            .condition(LobbyAccount::F.lobby.equals(lobby))
+            .set(LobbyAccount::F.nation, Some("babylon".to_string()))
+            .exec()
+            .await
+            .unwrap();
+
+        let mut sink = RecordingSink::default();
+        let result = start_game(&mut tx, &mut sink, lobby, owner).await;
+
+        assert!(matches!(result, Err(ApiError::NationAlreadyTaken)));
+        assert!(sink.messages.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs RUNCIV_TEST_CONFIG"]
+    async fn upload_game_state_notifies_the_other_players() {
+        let db = test_db().await;
+        let mut tx = db.start_transaction().await.unwrap();
+
+        let host = create_account(&mut tx).await;
+        let next = create_account(&mut tx).await;
+        let other = create_account(&mut tx).await;
+        let game = create_game(&mut tx, host, &[next, other]).await;
+
+        let mut sink = RecordingSink::default();
+        let game_data_path = std::env::temp_dir();
+        let uploaded = upload_game_state(
+            &mut tx,
+            &mut sink,
+            game_data_path.to_str().unwrap(),
+            1024,
+            game,
+            host,
+            upload(Some(next)),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(uploaded.data_id, 1);
+        let (data_id, current_player) = query!(&mut tx, (Game::F.data_id, Game::F.current_player))
+            .condition(Game::F.uuid.equals(game))
+            .one()
+            .await
+            .unwrap();
+        assert_eq!(data_id, 1);
+        assert_eq!(current_player.map(|x| *x.key()), Some(next));
+
+        assert!(sink.messages_to(host).is_empty());
+        let messages = sink.messages_to(other);
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().any(|msg| matches!(
+            msg,
+            WsMessage::UpdateGameData { game_uuid, game_data_id: 1, .. } if *game_uuid == game
+        )));
+        assert!(!messages
+            .iter()
+            .any(|msg| matches!(msg, WsMessage::YourTurn { .. })));
+        assert!(sink.messages_to(next).iter().any(|msg| matches!(
+            msg,
+            WsMessage::YourTurn { game_uuid, game_data_id: 1, note: Some(note) }
+                if *game_uuid == game && note == "Your move"
+        )));
+
+        let _ = tokio::fs::remove_file(
+            game_data_path.join(super::game_data_filename(game, uploaded.data_id as i64)),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs RUNCIV_TEST_CONFIG"]
+    async fn upload_game_state_requires_a_player() {
+        let db = test_db().await;
+        let mut tx = db.start_transaction().await.unwrap();
+
+        let host = create_account(&mut tx).await;
+        let outsider = create_account(&mut tx).await;
+        let game = create_game(&mut tx, host, &[]).await;
+
+        let mut sink = RecordingSink::default();
+        let game_data_path = std::env::temp_dir();
+        let result = upload_game_state(
+            &mut tx,
+            &mut sink,
+            game_data_path.to_str().unwrap(),
+            1024,
+            game,
+            outsider,
+            upload(None),
+        )
+        .await;
+
+        assert!(matches!(result, Err(ApiError::GameNotFound)));
+        assert!(sink.messages.is_empty());
+    }
+}
@@ -0,0 +1,290 @@
+//! Accepting invites to lobbies
+
+use chrono::{DateTime, Utc};
+use log::info;
+use rorm::db::Transaction;
+use rorm::fields::types::ForeignModelByField;
+use rorm::{insert, query, update, FieldAccess, Model};
+use uuid::Uuid;
+
+use crate::chan::{LobbyListChange, WsMessage};
+use crate::models::{Account, ChatRoomMemberInsert, Invite, Lobby, LobbyAccountWithInviteInsert};
+use crate::server::handler::{AccountResponse, ApiError, ApiResult};
+use crate::service::chat::post_system_message;
+use crate::service::lobby_list::announce;
+use crate::service::membership::{is_banned, may_join, LobbyMembers};
+use crate::service::notify::NotificationSink;
+
+/// Accept an invite to a lobby
+///
+/// `account` must be the receiver of the invite and allowed to join the lobby.
+/// The invite is consumed.
+///
+/// The caller is responsible for checking that the account has an active websocket
+/// connection, as the service layer doesn't know about connections.
+///
+/// Returns the lobby that was joined.
+pub async fn accept_invite(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    invite: Uuid,
+    account: Uuid,
+) -> ApiResult<Uuid> {
+    // Check if the invite exists
+    let invite = query!(&mut *tx, Invite)
+        .condition(Invite::F.uuid.equals(invite))
+        .optional()
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+
+    // Check if executing user is the receiver of the invite
+    if *invite.to.key() != account {
+        return Err(ApiError::MissingPrivileges);
+    }
+
+    let lobby = query!(&mut *tx, Lobby)
+        .condition(Lobby::F.uuid.equals(*invite.lobby.key()))
+        .optional()
+        .await?
+        .ok_or(ApiError::InternalServerError)?;
+    let members = LobbyMembers::query(tx, lobby.uuid)
+        .await?
+        .ok_or(ApiError::InternalServerError)?;
+
+    // Check if lobby is full
+    if members.is_full() {
+        return Err(ApiError::LobbyFull);
+    }
+
+    // Check if player is part of the lobby
+    if members.is_member(account) {
+        return Err(ApiError::AlreadyInThisLobby);
+    }
+
+    // An invite doesn't bypass a ban or the restriction of a lobby
+    if is_banned(tx, lobby.uuid, account).await? {
+        return Err(ApiError::BannedFromLobby);
+    }
+    if !may_join(tx, &lobby, account).await? {
+        return Err(ApiError::NotAllowedToJoin);
+    }
+
+    join_lobby_by_invite(tx, notifications, &lobby, &members, &invite).await?;
+
+    Ok(lobby.uuid)
+}
+
+/// Add the receiver of an invite to the lobby of the invite
+///
+/// The invite is consumed and the acceptance is recorded in the audit log.
+/// The owner of the lobby receives a [WsMessage::LobbyInviteAccepted] message,
+/// all `members` of the lobby a [WsMessage::LobbyJoin] message.
+///
+/// Returns the account that joined the lobby and the account that invited it.
+pub async fn join_lobby_by_invite(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    lobby: &Lobby,
+    members: &LobbyMembers,
+    invite: &Invite,
+) -> ApiResult<(AccountResponse, AccountResponse)> {
+    // Add player to lobby and remember who invited them
+    insert!(&mut *tx, LobbyAccountWithInviteInsert)
+        .return_nothing()
+        .single(&LobbyAccountWithInviteInsert {
+            uuid: Uuid::new_v4(),
+            lobby: ForeignModelByField::Key(lobby.uuid),
+            player: ForeignModelByField::Key(*invite.to.key()),
+            invited_by: Some(ForeignModelByField::Key(*invite.from.key())),
+        })
+        .await?;
+
+    // The invite was consumed
+    rorm::delete!(&mut *tx, Invite).single(invite).await?;
+
+    update!(&mut *tx, Lobby)
+        .condition(Lobby::F.uuid.equals(lobby.uuid))
+        .set(Lobby::F.last_activity, Utc::now().naive_utc())
+        .exec()
+        .await?;
+
+    // Add player to chatroom
+    insert!(&mut *tx, ChatRoomMemberInsert)
+        .single(&ChatRoomMemberInsert {
+            uuid: Uuid::new_v4(),
+            member: ForeignModelByField::Key(*invite.to.key()),
+            chat_room: ForeignModelByField::Key(*lobby.chat_room.key()),
+        })
+        .await?;
+
+    let player = query_account(tx, *invite.to.key())
+        .await?
+        .ok_or(ApiError::SessionCorrupt)?;
+    let inviter = query_account(tx, *invite.from.key())
+        .await?
+        .ok_or(ApiError::InternalServerError)?;
+
+    post_system_message(
+        tx,
+        notifications,
+        *lobby.chat_room.key(),
+        format!("{} joined the lobby", player.display_name),
+    )
+    .await?;
+
+    let password_bypassed = lobby.password_hash.is_some();
+    info!(
+        "Account {player} joined lobby {lobby} by accepting the invite {invite} of account {from} \
+         created at {created_at}, password bypassed: {password_bypassed}",
+        player = player.uuid,
+        lobby = lobby.uuid,
+        invite = invite.uuid,
+        from = inviter.uuid,
+        created_at = invite.created_at,
+    );
+
+    announce(tx, notifications, lobby.uuid, LobbyListChange::Updated).await?;
+
+    // Let the owner know how the player got into the lobby
+    notifications.notify(
+        members.owner,
+        WsMessage::LobbyInviteAccepted {
+            lobby_uuid: lobby.uuid,
+            player: player.clone(),
+            invited_by: inviter.clone(),
+            invited_at: DateTime::from_naive_utc_and_offset(invite.created_at, Utc),
+            password_bypassed,
+        },
+    );
+
+    // Notify the players that were in the lobby before
+    notifications.notify_all(
+        members.members(),
+        WsMessage::LobbyJoin {
+            lobby_uuid: lobby.uuid,
+            player: player.clone(),
+        },
+    );
+
+    Ok((player, inviter))
+}
+
+/// Retrieve the public information of an account
+async fn query_account(
+    tx: &mut Transaction,
+    uuid: Uuid,
+) -> Result<Option<AccountResponse>, rorm::Error> {
+    Ok(query!(
+        tx,
+        (
+            Account::F.uuid,
+            Account::F.username,
+            Account::F.display_name
+        )
+    )
+    .condition(Account::F.uuid.equals(uuid))
+    .optional()
+    .await?
+    .map(|(uuid, username, display_name)| AccountResponse {
+        uuid,
+        username,
+        display_name,
+    }))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use rorm::{query, FieldAccess, Model};
+
+    use super::accept_invite;
+    use crate::chan::WsMessage;
+    use crate::models::{Invite, LobbyAccount};
+    use crate::server::handler::ApiError;
+    use crate::service::testing::{
+        create_account, create_invite, create_lobby, test_db, RecordingSink,
+    };
+
+    #[tokio::test]
+    #[ignore = "needs RUNCIV_TEST_CONFIG"]
+    async fn accept_invite_joins_the_lobby() {
+        let db = test_db().await;
+        let mut tx = db.start_transaction().await.unwrap();
+
+        let owner = create_account(&mut tx).await;
+        let player = create_account(&mut tx).await;
+        let invited = create_account(&mut tx).await;
+        let lobby = create_lobby(&mut tx, owner, &[player], 4).await;
+        let invite = create_invite(&mut tx, owner, invited, lobby).await;
+
+        let mut sink = RecordingSink::default();
+        let joined = accept_invite(&mut tx, &mut sink, invite, invited)
+            .await
+            .unwrap();
+        assert_eq!(joined, lobby);
+
+        let membership = query!(&mut tx, (LobbyAccount::F.uuid,))
+            .condition(LobbyAccount::F.player.equals(invited))
+            .optional()
+            .await
+            .unwrap();
+        assert!(membership.is_some());
+        let invite_left = query!(&mut tx, (Invite::F.uuid,))
+            .condition(Invite::F.uuid.equals(invite))
+            .optional()
+            .await
+            .unwrap();
+        assert!(invite_left.is_none());
+
+        assert!(sink.messages_to(owner).iter().any(|msg| matches!(
+            msg,
+            WsMessage::LobbyInviteAccepted { lobby_uuid, player, invited_by, .. }
+                if *lobby_uuid == lobby && player.uuid == invited && invited_by.uuid == owner
+        )));
+        for member in [owner, player] {
+            assert!(sink.messages_to(member).iter().any(|msg| matches!(
+                msg,
+                WsMessage::LobbyJoin { lobby_uuid, player }
+                    if *lobby_uuid == lobby && player.uuid == invited
+            )));
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs RUNCIV_TEST_CONFIG"]
+    async fn accept_invite_rejects_a_full_lobby() {
+        let db = test_db().await;
+        let mut tx = db.start_transaction().await.unwrap();
+
+        let owner = create_account(&mut tx).await;
+        let player = create_account(&mut tx).await;
+        let invited = create_account(&mut tx).await;
+        let lobby = create_lobby(&mut tx, owner, &[player], 2).await;
+        let invite = create_invite(&mut tx, owner, invited, lobby).await;
+
+        let mut sink = RecordingSink::default();
+        let result = accept_invite(&mut tx, &mut sink, invite, invited).await;
+
+        assert!(matches!(result, Err(ApiError::LobbyFull)));
+        assert!(sink.messages.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs RUNCIV_TEST_CONFIG"]
+    async fn accept_invite_requires_the_receiver() {
+        let db = test_db().await;
+        let mut tx = db.start_transaction().await.unwrap();
+
+        let owner = create_account(&mut tx).await;
+        let invited = create_account(&mut tx).await;
+        let stranger = create_account(&mut tx).await;
+        let lobby = create_lobby(&mut tx, owner, &[], 4).await;
+        let invite = create_invite(&mut tx, owner, invited, lobby).await;
+
+        let mut sink = RecordingSink::default();
+        let result = accept_invite(&mut tx, &mut sink, invite, stranger).await;
+
+        assert!(matches!(result, Err(ApiError::MissingPrivileges)));
+        assert!(sink.messages.is_empty());
+    }
+}
@@ -0,0 +1,115 @@
+//! Removing players from lobbies
+
+use rorm::db::Transaction;
+use rorm::fields::types::ForeignModelByField;
+use rorm::{and, insert, query, FieldAccess, Model};
+use uuid::Uuid;
+
+use crate::chan::{LobbyListChange, WsMessage};
+use crate::models::{Account, ChatRoomMember, Invite, Lobby, LobbyAccount, LobbyBanInsert};
+use crate::server::handler::{AccountResponse, ApiError, ApiResult};
+use crate::service::chat::post_system_message;
+use crate::service::lobby_list;
+use crate::service::membership::{is_banned, LobbyMembers};
+use crate::service::notify::NotificationSink;
+
+/// Kick a player from a lobby
+///
+/// If `ban` is set, the player is banned from the lobby as well.
+/// The caller has to check that the executing account may kick players from the lobby.
+///
+/// Returns [ApiError::InvalidUuid] if the lobby doesn't exist and
+/// [ApiError::InvalidPlayerUuid] if the player has not joined the lobby.
+///
+/// A system message is posted to the chatroom of the lobby. All players in the lobby
+/// as well as the kicked player receive a [WsMessage::LobbyKick] message.
+pub async fn kick_player(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    lobby: Uuid,
+    player: Uuid,
+    ban: bool,
+) -> ApiResult<()> {
+    let members = LobbyMembers::query(tx, lobby)
+        .await?
+        .ok_or(ApiError::InvalidUuid)?;
+    if !members.is_player(player) {
+        return Err(ApiError::InvalidPlayerUuid);
+    }
+
+    let (chat_room,) = query!(&mut *tx, (Lobby::F.chat_room,))
+        .condition(Lobby::F.uuid.equals(lobby))
+        .one()
+        .await?;
+
+    rorm::delete!(&mut *tx, LobbyAccount)
+        .condition(and!(
+            LobbyAccount::F.lobby.equals(lobby),
+            LobbyAccount::F.player.equals(player)
+        ))
+        .await?;
+
+    if ban && !is_banned(tx, lobby, player).await? {
+        insert!(&mut *tx, LobbyBanInsert)
+            .return_nothing()
+            .single(&LobbyBanInsert {
+                uuid: Uuid::new_v4(),
+                lobby: ForeignModelByField::Key(lobby),
+                account: ForeignModelByField::Key(player),
+            })
+            .await?;
+    }
+
+    rorm::delete!(&mut *tx, ChatRoomMember)
+        .condition(and!(
+            ChatRoomMember::F.chat_room.equals(chat_room.key()),
+            ChatRoomMember::F.member.equals(player),
+        ))
+        .await?;
+
+    // Delete all invites of this player
+    rorm::delete!(&mut *tx, Invite)
+        .condition(Invite::F.from.equals(player))
+        .await?;
+
+    let (uuid, username, display_name) = query!(
+        &mut *tx,
+        (
+            Account::F.uuid,
+            Account::F.username,
+            Account::F.display_name
+        )
+    )
+    .condition(Account::F.uuid.equals(player))
+    .optional()
+    .await?
+    .ok_or(ApiError::InvalidPlayerUuid)?;
+
+    post_system_message(
+        tx,
+        notifications,
+        *chat_room.key(),
+        if ban {
+            format!("{display_name} was kicked and banned from the lobby")
+        } else {
+            format!("{display_name} was kicked from the lobby")
+        },
+    )
+    .await?;
+    lobby_list::announce(tx, notifications, lobby, LobbyListChange::Updated).await?;
+
+    // Notify joined players and kicked player
+    notifications.notify_all(
+        members.players,
+        WsMessage::LobbyKick {
+            lobby_uuid: lobby,
+            player: AccountResponse {
+                uuid,
+                username,
+                display_name,
+            },
+        },
+    );
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! Announcing changes of the list of open lobbies
+
+use rorm::db::Transaction;
+use rorm::{query, FieldAccess, Model};
+use uuid::Uuid;
+
+use crate::chan::{LobbyListChange, WsMessage};
+use crate::models::Lobby;
+use crate::service::membership::LobbyMembers;
+use crate::service::notify::NotificationSink;
+
+/// Announce a change of a lobby to the connections that subscribed to the lobby list
+///
+/// Hidden lobbies are not part of the lobby list, so their changes are not announced.
+/// As the lobby is looked up, [LobbyListChange::Closed] must be announced before
+/// the lobby is deleted.
+pub async fn announce(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    lobby: Uuid,
+    change: LobbyListChange,
+) -> Result<(), rorm::Error> {
+    let Some((hidden, max_player)) = query!(&mut *tx, (Lobby::F.hidden, Lobby::F.max_player))
+        .condition(Lobby::F.uuid.equals(lobby))
+        .optional()
+        .await?
+    else {
+        return Ok(());
+    };
+    if hidden {
+        return Ok(());
+    }
+
+    let (current_players, max_players) = match change {
+        LobbyListChange::Closed => (0, 0),
+        LobbyListChange::Created | LobbyListChange::Updated => {
+            let occupancy = LobbyMembers::query(tx, lobby)
+                .await?
+                .map_or(0, |members| members.occupancy());
+            (occupancy as u8, max_player as u8)
+        }
+    };
+
+    notifications.notify_lobby_list(WsMessage::LobbyListUpdated {
+        lobby_uuid: lobby,
+        change,
+        current_players,
+        max_players,
+    });
+
+    Ok(())
+}
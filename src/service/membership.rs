@@ -0,0 +1,296 @@
+//! Membership and occupancy of lobbies and games
+//!
+//! Handlers, the websocket manager and background tasks should use these helpers
+//! instead of querying the relations on their own, so the rules about who is part
+//! of a lobby or game and when it is full stay the same everywhere.
+
+use std::collections::HashSet;
+
+use rorm::db::Transaction;
+use rorm::{and, query, FieldAccess, Model};
+use uuid::Uuid;
+
+use crate::models::{
+    Friend, Game, GameAccount, Lobby, LobbyAccount, LobbyAllowedAccount, LobbyBan,
+};
+
+/// The owner and the joined players of a lobby
+#[derive(Clone, Debug)]
+pub struct LobbyMembers {
+    /// The uuid of the lobby
+    pub lobby: Uuid,
+    /// The owner of the lobby
+    pub owner: Uuid,
+    /// The joined players of the lobby, the owner is not included
+    pub players: Vec<Uuid>,
+    /// The joined players the owner made co-hosts
+    pub co_hosts: Vec<Uuid>,
+    /// The maximum number of players in the lobby, including the owner
+    pub max_players: usize,
+}
+
+impl LobbyMembers {
+    /// Query the members of a lobby
+    ///
+    /// Returns `None` if the lobby doesn't exist.
+    pub async fn query(tx: &mut Transaction, lobby: Uuid) -> Result<Option<Self>, rorm::Error> {
+        let Some((owner, max_player)) = query!(&mut *tx, (Lobby::F.owner, Lobby::F.max_player))
+            .condition(Lobby::F.uuid.equals(lobby))
+            .optional()
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let joined = query!(&mut *tx, (LobbyAccount::F.player, LobbyAccount::F.co_host))
+            .condition(LobbyAccount::F.lobby.equals(lobby))
+            .all()
+            .await?;
+        let co_hosts = joined
+            .iter()
+            .filter(|(_, co_host)| *co_host)
+            .map(|(player, _)| *player.key())
+            .collect();
+        let players = joined
+            .into_iter()
+            .map(|(player, _)| *player.key())
+            .collect();
+
+        Ok(Some(Self {
+            lobby,
+            owner: *owner.key(),
+            players,
+            co_hosts,
+            max_players: max_player as usize,
+        }))
+    }
+
+    /// Check if the account owns the lobby
+    pub fn is_owner(&self, account: Uuid) -> bool {
+        self.owner == account
+    }
+
+    /// Check if the account joined the lobby
+    pub fn is_player(&self, account: Uuid) -> bool {
+        self.players.contains(&account)
+    }
+
+    /// Check if the account is a co-host of the lobby
+    pub fn is_co_host(&self, account: Uuid) -> bool {
+        self.co_hosts.contains(&account)
+    }
+
+    /// Check if the account may change the settings of the lobby
+    ///
+    /// This is the case for the owner and the co-hosts.
+    pub fn may_manage(&self, account: Uuid) -> bool {
+        self.is_owner(account) || self.is_co_host(account)
+    }
+
+    /// Check if the account may kick a player from the lobby
+    ///
+    /// The owner may kick every player, co-hosts only players that are not co-hosts.
+    pub fn may_kick(&self, account: Uuid, player: Uuid) -> bool {
+        self.is_owner(account) || (self.is_co_host(account) && !self.is_co_host(player))
+    }
+
+    /// Check if the account owns or joined the lobby
+    pub fn is_member(&self, account: Uuid) -> bool {
+        self.is_owner(account) || self.is_player(account)
+    }
+
+    /// The number of occupied seats, including the owner
+    pub fn occupancy(&self) -> usize {
+        self.players.len() + 1
+    }
+
+    /// Check if no other player can join the lobby
+    pub fn is_full(&self) -> bool {
+        self.occupancy() >= self.max_players
+    }
+
+    /// All members of the lobby, starting with the owner
+    pub fn members(&self) -> impl Iterator<Item = Uuid> + '_ {
+        std::iter::once(self.owner).chain(self.players.iter().copied())
+    }
+}
+
+/// The host and the players of a game
+#[derive(Clone, Debug)]
+pub struct GameMembers {
+    /// The uuid of the game
+    pub game: Uuid,
+    /// The host of the game, `None` if the host deleted their account
+    pub host: Option<Uuid>,
+    /// The players of the game, including the host
+    pub players: Vec<Uuid>,
+    /// The maximum number of players in the game
+    pub max_players: usize,
+}
+
+impl GameMembers {
+    /// Query the members of a game
+    ///
+    /// Returns `None` if the game doesn't exist.
+    pub async fn query(tx: &mut Transaction, game: Uuid) -> Result<Option<Self>, rorm::Error> {
+        let Some((host, max_players)) = query!(&mut *tx, (Game::F.host, Game::F.max_players))
+            .condition(Game::F.uuid.equals(game))
+            .optional()
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let players = query!(&mut *tx, (GameAccount::F.player,))
+            .condition(GameAccount::F.game.equals(game))
+            .all()
+            .await?
+            .into_iter()
+            .map(|(player,)| *player.key())
+            .collect();
+
+        Ok(Some(Self {
+            game,
+            host: host.map(|x| *x.key()),
+            players,
+            max_players: max_players as usize,
+        }))
+    }
+
+    /// Check if the account is the host of the game
+    pub fn is_host(&self, account: Uuid) -> bool {
+        self.host == Some(account)
+    }
+
+    /// Check if the account is playing the game
+    pub fn is_player(&self, account: Uuid) -> bool {
+        self.players.contains(&account)
+    }
+
+    /// Check if the game has no empty seat
+    pub fn is_full(&self) -> bool {
+        self.players.len() >= self.max_players
+    }
+}
+
+/// Retrieve all lobbies an account owns or joined
+///
+/// The owned lobbies come first.
+pub async fn lobbies_of(tx: &mut Transaction, account: Uuid) -> Result<Vec<Uuid>, rorm::Error> {
+    let mut lobbies: Vec<Uuid> = query!(&mut *tx, (Lobby::F.uuid,))
+        .condition(Lobby::F.owner.equals(account))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(lobby,)| lobby)
+        .collect();
+
+    lobbies.extend(
+        query!(&mut *tx, (LobbyAccount::F.lobby,))
+            .condition(LobbyAccount::F.player.equals(account))
+            .all()
+            .await?
+            .into_iter()
+            .map(|(lobby,)| *lobby.key()),
+    );
+
+    Ok(lobbies)
+}
+
+/// Check if an account owns or joined any lobby
+pub async fn is_in_a_lobby(tx: &mut Transaction, account: Uuid) -> Result<bool, rorm::Error> {
+    Ok(query!(&mut *tx, (Lobby::F.uuid,))
+        .condition(Lobby::F.owner.equals(account))
+        .optional()
+        .await?
+        .is_some()
+        || query!(&mut *tx, (LobbyAccount::F.uuid,))
+            .condition(LobbyAccount::F.player.equals(account))
+            .optional()
+            .await?
+            .is_some())
+}
+
+/// Check if an account may join a lobby
+///
+/// Lobbies that aren't restricted may be joined by everyone. Restricted lobbies may only
+/// be joined by the allowed accounts and, if enabled, by the friends of the owner.
+pub async fn may_join(
+    tx: &mut Transaction,
+    lobby: &Lobby,
+    account: Uuid,
+) -> Result<bool, rorm::Error> {
+    if !lobby.restricted || *lobby.owner.key() == account {
+        return Ok(true);
+    }
+
+    let allowed = query!(&mut *tx, (LobbyAllowedAccount::F.uuid,))
+        .condition(and!(
+            LobbyAllowedAccount::F.lobby.equals(lobby.uuid),
+            LobbyAllowedAccount::F.account.equals(account)
+        ))
+        .optional()
+        .await?
+        .is_some();
+    if allowed || !lobby.allow_friends_of_owner {
+        return Ok(allowed);
+    }
+
+    Ok(query!(&mut *tx, (Friend::F.uuid,))
+        .condition(and!(
+            Friend::F.is_request.equals(false),
+            Friend::F.from.equals(*lobby.owner.key()),
+            Friend::F.to.equals(account)
+        ))
+        .optional()
+        .await?
+        .is_some())
+}
+
+/// Check if an account was banned from a lobby
+pub async fn is_banned(
+    tx: &mut Transaction,
+    lobby: Uuid,
+    account: Uuid,
+) -> Result<bool, rorm::Error> {
+    Ok(query!(&mut *tx, (LobbyBan::F.uuid,))
+        .condition(and!(
+            LobbyBan::F.lobby.equals(lobby),
+            LobbyBan::F.account.equals(account)
+        ))
+        .optional()
+        .await?
+        .is_some())
+}
+
+/// Filter the accounts whose presence an account may see
+///
+/// The presence of friends and of the members of the lobbies the account is part of
+/// is visible, the presence of all other accounts is not.
+pub async fn presence_visible_to(
+    tx: &mut Transaction,
+    account: Uuid,
+    accounts: Vec<Uuid>,
+) -> Result<Vec<Uuid>, rorm::Error> {
+    let mut visible: HashSet<Uuid> = query!(&mut *tx, (Friend::F.to,))
+        .condition(and!(
+            Friend::F.is_request.equals(false),
+            Friend::F.from.equals(account)
+        ))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(friend,)| *friend.key())
+        .collect();
+
+    for lobby in lobbies_of(tx, account).await? {
+        if let Some(members) = LobbyMembers::query(tx, lobby).await? {
+            visible.extend(members.members());
+        }
+    }
+
+    Ok(accounts
+        .into_iter()
+        .filter(|x| visible.contains(x))
+        .collect())
+}
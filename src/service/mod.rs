@@ -0,0 +1,20 @@
+//! Business logic shared between the handlers of the server and the background tasks
+//!
+//! The functions of this module work on a [rorm::db::Transaction] and hand their websocket
+//! messages to a [notify::NotificationSink], so they don't depend on actix or a running
+//! websocket manager.
+
+pub mod account_stats;
+pub mod chat;
+pub mod device;
+pub mod game;
+pub mod invite;
+pub mod lobby;
+pub mod lobby_list;
+pub mod membership;
+pub mod notify;
+pub mod policy;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod translation;
+pub mod welcome;
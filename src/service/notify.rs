@@ -0,0 +1,65 @@
+//! Notifications of accounts about the results of the business logic
+//!
+//! The service functions don't send their messages directly. Instead, they hand them
+//! to a [NotificationSink], which decides what happens with them. The handlers use an
+//! [Outbox] and send its messages with a [Notifier] after the transaction was committed,
+//! so no account is notified about changes that were rolled back.
+
+use uuid::Uuid;
+
+use crate::chan::{Notifier, WsMessage};
+
+/// Receiver of the messages produced by the service functions
+pub trait NotificationSink {
+    /// Notify a single account
+    fn notify(&mut self, account: Uuid, msg: WsMessage);
+
+    /// Notify all connections that subscribed to the lobby list
+    fn notify_lobby_list(&mut self, msg: WsMessage);
+
+    /// Notify multiple accounts with the same message
+    fn notify_all(&mut self, accounts: impl IntoIterator<Item = Uuid>, msg: WsMessage)
+    where
+        Self: Sized,
+    {
+        for account in accounts {
+            self.notify(account, msg.clone());
+        }
+    }
+}
+
+/// Collects messages until they can be sent
+#[derive(Default)]
+pub struct Outbox {
+    messages: Vec<(Uuid, WsMessage)>,
+    lobby_list: Vec<WsMessage>,
+}
+
+impl Outbox {
+    /// Create an empty outbox
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send all collected messages
+    ///
+    /// This should be called after the transaction was committed.
+    pub async fn send(self, notifier: &dyn Notifier) {
+        for (account, msg) in self.messages {
+            notifier.send(account, msg).await;
+        }
+        for msg in self.lobby_list {
+            notifier.broadcast_lobby_list(msg).await;
+        }
+    }
+}
+
+impl NotificationSink for Outbox {
+    fn notify(&mut self, account: Uuid, msg: WsMessage) {
+        self.messages.push((account, msg));
+    }
+
+    fn notify_lobby_list(&mut self, msg: WsMessage) {
+        self.lobby_list.push(msg);
+    }
+}
@@ -0,0 +1,170 @@
+//! Validation of actions against the server-specific policies of the operator
+//!
+//! The operator can run a service that is asked before accounts are registered or lobbies
+//! are created. It answers with a [PolicyDecision], a rejection is passed to the client as
+//! [ApiError::Policy], so client mods can react to the code of the policy.
+
+use std::time::Duration;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::PolicyValidatorConfig;
+use crate::server::handler::{ApiError, ApiResult, POLICY_STATUS_CODES};
+
+/// An action that is validated, it is posted as JSON to the validator
+#[derive(Serialize, Debug)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum PolicyAction<'a> {
+    /// A new account is registered
+    #[serde(rename_all = "camelCase")]
+    RegisterAccount {
+        /// The requested username
+        username: &'a str,
+        /// The requested display name
+        display_name: &'a str,
+    },
+    /// A new lobby is created
+    #[serde(rename_all = "camelCase")]
+    CreateLobby {
+        /// The account that creates the lobby
+        account: Uuid,
+        /// The name of the lobby
+        name: &'a str,
+        /// The maximum number of players, including the owner
+        max_players: u8,
+        /// The mods the lobby requires
+        mods: &'a [String],
+    },
+}
+
+/// The answer of the validator
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyDecision {
+    /// Whether the action is allowed
+    allow: bool,
+    /// The code of the violated policy, in the range of 3000..4000
+    #[serde(default)]
+    code: Option<u16>,
+    /// The message that is shown to the user
+    #[serde(default)]
+    message: Option<String>,
+}
+
+impl PolicyDecision {
+    /// Convert the answer into the result of the validation
+    ///
+    /// A rejection without a valid code is a misconfiguration of the validator and
+    /// reported as [ApiError::InternalServerError].
+    fn into_result(self) -> ApiResult<()> {
+        if self.allow {
+            return Ok(());
+        }
+
+        match self.code {
+            Some(code) if POLICY_STATUS_CODES.contains(&code) => Err(ApiError::Policy {
+                code,
+                message: self
+                    .message
+                    .unwrap_or_else(|| "The action violates a policy of the server".to_string()),
+            }),
+            code => {
+                error!("The policy validator rejected an action with the invalid code {code:?}");
+                Err(ApiError::InternalServerError)
+            }
+        }
+    }
+}
+
+/// Client of the configured policy validator
+pub struct PolicyValidator {
+    client: reqwest::Client,
+    config: PolicyValidatorConfig,
+}
+
+impl PolicyValidator {
+    /// Create a client for the validator
+    pub fn new(config: PolicyValidatorConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    /// Ask the validator whether an action is allowed
+    ///
+    /// Returns [ApiError::Policy] if the validator rejects the action. If it can't be
+    /// reached or its answer is invalid, the action is rejected with
+    /// [ApiError::InternalServerError].
+    pub async fn check(&self, action: PolicyAction<'_>) -> ApiResult<()> {
+        let decision: PolicyDecision = self
+            .client
+            .post(&self.config.url)
+            .timeout(Duration::from_secs(self.config.timeout))
+            .json(&action)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| {
+                error!("Request to the policy validator failed: {err}");
+                ApiError::InternalServerError
+            })?
+            .json()
+            .await
+            .map_err(|err| {
+                error!("Invalid response of the policy validator: {err}");
+                ApiError::InternalServerError
+            })?;
+
+        decision.into_result()
+    }
+}
+
+/// Check an action if a policy validator is configured
+pub async fn check_policy(
+    validator: &Option<PolicyValidator>,
+    action: PolicyAction<'_>,
+) -> ApiResult<()> {
+    match validator {
+        Some(validator) => validator.check(action).await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::PolicyDecision;
+    use crate::server::handler::ApiError;
+
+    fn decision(json: &str) -> PolicyDecision {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn allowed_actions_pass() {
+        assert!(decision(r#"{"allow": true}"#).into_result().is_ok());
+    }
+
+    #[test]
+    fn rejections_carry_the_policy_code() {
+        let result =
+            decision(r#"{"allow": false, "code": 3001, "message": "No mods"}"#).into_result();
+        assert!(matches!(
+            result,
+            Err(ApiError::Policy { code: 3001, message }) if message == "No mods"
+        ));
+    }
+
+    #[test]
+    fn rejections_outside_of_the_reserved_range_are_server_errors() {
+        for json in [r#"{"allow": false, "code": 1000}"#, r#"{"allow": false}"#] {
+            assert!(matches!(
+                decision(json).into_result(),
+                Err(ApiError::InternalServerError)
+            ));
+        }
+    }
+}
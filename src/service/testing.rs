@@ -0,0 +1,230 @@
+//! Helpers for the tests of the service functions
+//!
+//! The tests need a PostgreSQL database. Its configuration is read from the file in the
+//! `RUNCIV_TEST_CONFIG` environment variable. The tests are ignored by default, run them
+//! with `cargo test -- --ignored` once the variable is set.
+//! The migrations are applied once, every test then works in its own transaction,
+//! which is rolled back at the end, so the database stays unchanged.
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::env;
+
+use rorm::db::Transaction;
+use rorm::fields::types::ForeignModelByField;
+use rorm::{insert, Database};
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+use crate::chan::WsMessage;
+use crate::models::{
+    AccountInsert, ChatRoomInsert, ChatRoomMemberInsert, GameAccountInsert, GameInsert,
+    InviteInsert, LobbyAccountInsert, LobbyInsert,
+};
+use crate::service::notify::NotificationSink;
+
+/// The environment variable that holds the path to the configuration of the test database
+const TEST_CONFIG_VAR: &str = "RUNCIV_TEST_CONFIG";
+
+static MIGRATED: OnceCell<()> = OnceCell::const_new();
+
+/// Connect to the test database
+///
+/// Panics if `RUNCIV_TEST_CONFIG` is not set, so a test can't pass without running.
+pub(crate) async fn test_db() -> Database {
+    let config_path = env::var(TEST_CONFIG_VAR)
+        .unwrap_or_else(|_| panic!("{TEST_CONFIG_VAR} must point to the test configuration"));
+    let config = crate::get_conf(&config_path).expect("invalid test configuration");
+
+    MIGRATED
+        .get_or_init(|| async {
+            crate::migrate(
+                &config,
+                concat!(env!("CARGO_MANIFEST_DIR"), "/migrations").to_string(),
+            )
+            .await
+            .expect("could not migrate the test database");
+        })
+        .await;
+
+    crate::get_db(&config)
+        .await
+        .expect("could not connect to the test database")
+}
+
+/// A [NotificationSink] that keeps all messages for later inspection
+#[derive(Default)]
+pub(crate) struct RecordingSink {
+    pub(crate) messages: Vec<(Uuid, WsMessage)>,
+    pub(crate) lobby_list: Vec<WsMessage>,
+}
+
+impl RecordingSink {
+    /// The messages that were sent to an account
+    pub(crate) fn messages_to(&self, account: Uuid) -> Vec<&WsMessage> {
+        self.messages
+            .iter()
+            .filter(|(to, _)| *to == account)
+            .map(|(_, msg)| msg)
+            .collect()
+    }
+}
+
+impl NotificationSink for RecordingSink {
+    fn notify(&mut self, account: Uuid, msg: WsMessage) {
+        self.messages.push((account, msg));
+    }
+
+    fn notify_lobby_list(&mut self, msg: WsMessage) {
+        self.lobby_list.push(msg);
+    }
+}
+
+/// Create an account with a random username
+pub(crate) async fn create_account(tx: &mut Transaction) -> Uuid {
+    let uuid = Uuid::new_v4();
+    let username = format!("test-{}", uuid.simple());
+    insert!(&mut *tx, AccountInsert)
+        .return_nothing()
+        .single(&AccountInsert {
+            uuid,
+            normalized_username: username.clone(),
+            display_name: username.clone(),
+            username,
+            password_hash: String::new(),
+            last_login: None,
+        })
+        .await
+        .unwrap();
+    uuid
+}
+
+/// Create a chatroom with the given members
+pub(crate) async fn create_chat_room(tx: &mut Transaction, members: &[Uuid]) -> Uuid {
+    let chat_room = insert!(&mut *tx, ChatRoomInsert)
+        .return_primary_key()
+        .single(&ChatRoomInsert {
+            uuid: Uuid::new_v4(),
+            last_message_uuid: None,
+        })
+        .await
+        .unwrap();
+
+    for member in members {
+        insert!(&mut *tx, ChatRoomMemberInsert)
+            .return_nothing()
+            .single(&ChatRoomMemberInsert {
+                uuid: Uuid::new_v4(),
+                chat_room: ForeignModelByField::Key(chat_room),
+                member: ForeignModelByField::Key(*member),
+            })
+            .await
+            .unwrap();
+    }
+
+    chat_room
+}
+
+/// Create a public lobby of `owner` with the given players
+pub(crate) async fn create_lobby(
+    tx: &mut Transaction,
+    owner: Uuid,
+    players: &[Uuid],
+    max_player: i16,
+) -> Uuid {
+    let members: Vec<Uuid> = std::iter::once(owner)
+        .chain(players.iter().copied())
+        .collect();
+    let chat_room = create_chat_room(tx, &members).await;
+
+    let lobby = insert!(&mut *tx, LobbyInsert)
+        .return_primary_key()
+        .single(&LobbyInsert {
+            uuid: Uuid::new_v4(),
+            name: "Test lobby".to_string(),
+            owner: ForeignModelByField::Key(owner),
+            password_hash: None,
+            chat_room: ForeignModelByField::Key(chat_room),
+            max_player,
+            min_player: None,
+            turn_timer: None,
+            allow_spectators: false,
+            allow_late_joins: false,
+            public_game: true,
+            require_ready: false,
+            auto_start: false,
+            hidden: false,
+            join_code: None,
+            ruleset: None,
+            map_size: None,
+            game_speed: None,
+            restricted: false,
+            allow_friends_of_owner: false,
+            hidden_identities: false,
+        })
+        .await
+        .unwrap();
+
+    for player in players {
+        insert!(&mut *tx, LobbyAccountInsert)
+            .return_nothing()
+            .single(&LobbyAccountInsert {
+                uuid: Uuid::new_v4(),
+                lobby: ForeignModelByField::Key(lobby),
+                player: ForeignModelByField::Key(*player),
+            })
+            .await
+            .unwrap();
+    }
+
+    lobby
+}
+
+/// Create an invite of `from` for `to` to a lobby
+pub(crate) async fn create_invite(tx: &mut Transaction, from: Uuid, to: Uuid, lobby: Uuid) -> Uuid {
+    insert!(&mut *tx, InviteInsert)
+        .return_primary_key()
+        .single(&InviteInsert {
+            uuid: Uuid::new_v4(),
+            from: ForeignModelByField::Key(from),
+            to: ForeignModelByField::Key(to),
+            lobby: ForeignModelByField::Key(lobby),
+        })
+        .await
+        .unwrap()
+}
+
+/// Create a running game of `host` with the given players and without a game state
+pub(crate) async fn create_game(tx: &mut Transaction, host: Uuid, players: &[Uuid]) -> Uuid {
+    let members: Vec<Uuid> = std::iter::once(host)
+        .chain(players.iter().copied())
+        .collect();
+    let chat_room = create_chat_room(tx, &members).await;
+
+    let game = insert!(&mut *tx, GameInsert)
+        .return_primary_key()
+        .single(&GameInsert {
+            uuid: Uuid::new_v4(),
+            name: "Test game".to_string(),
+            max_players: members.len() as i16,
+            updated_by: ForeignModelByField::Key(host),
+            chat_room: ForeignModelByField::Key(chat_room),
+            host: Some(ForeignModelByField::Key(host)),
+            hidden_identities: false,
+        })
+        .await
+        .unwrap();
+
+    for player in members {
+        insert!(&mut *tx, GameAccountInsert)
+            .return_nothing()
+            .single(&GameAccountInsert {
+                uuid: Uuid::new_v4(),
+                game: ForeignModelByField::Key(game),
+                player: ForeignModelByField::Key(player),
+            })
+            .await
+            .unwrap();
+    }
+
+    game
+}
@@ -0,0 +1,183 @@
+//! Translation of chat messages into the languages the members chose
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use log::warn;
+use rorm::fields::types::ForeignModelByField;
+use rorm::{and, insert, query, Database, FieldAccess, Model};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::chan::{Notifier, WsMessage};
+use crate::config::TranslationConfig;
+use crate::models::{ChatMessageTranslation, ChatMessageTranslationInsert};
+use crate::server::handler::ChatMessage;
+
+/// The request body of the `/translate` endpoint of LibreTranslate
+#[derive(Serialize)]
+struct TranslateRequest<'a> {
+    q: &'a str,
+    source: &'a str,
+    target: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api_key: Option<&'a str>,
+}
+
+/// The response body of the `/translate` endpoint of LibreTranslate
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TranslateResponse {
+    translated_text: String,
+}
+
+/// Client of the configured translation provider
+pub struct Translator {
+    client: reqwest::Client,
+    config: TranslationConfig,
+}
+
+impl Translator {
+    /// Create a client for the provider
+    pub fn new(config: TranslationConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    /// Translate a chat message into a language
+    ///
+    /// Translations are stored in the database, so the provider is only asked once
+    /// per message and language.
+    pub async fn translate(
+        &self,
+        db: &Database,
+        message: Uuid,
+        text: &str,
+        language: &str,
+    ) -> Result<String, String> {
+        if let Some((text,)) = query!(db, (ChatMessageTranslation::F.text,))
+            .condition(and!(
+                ChatMessageTranslation::F.message.equals(message),
+                ChatMessageTranslation::F.language.equals(language)
+            ))
+            .optional()
+            .await
+            .map_err(|err| format!("Database error: {err}"))?
+        {
+            return Ok(text);
+        }
+
+        let response: TranslateResponse = self
+            .client
+            .post(format!(
+                "{}/translate",
+                self.config.api_url.trim_end_matches('/')
+            ))
+            .json(&TranslateRequest {
+                q: text,
+                source: "auto",
+                target: language,
+                api_key: self.config.api_key.as_deref(),
+            })
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| format!("Request to the translation provider failed: {err}"))?
+            .json()
+            .await
+            .map_err(|err| format!("Invalid response of the translation provider: {err}"))?;
+
+        insert!(db, ChatMessageTranslationInsert)
+            .return_nothing()
+            .single(&ChatMessageTranslationInsert {
+                uuid: Uuid::new_v4(),
+                message: ForeignModelByField::Key(message),
+                language: language.to_string(),
+                text: response.translated_text.clone(),
+            })
+            .await
+            .map_err(|err| format!("Database error: {err}"))?;
+
+        Ok(response.translated_text)
+    }
+
+    /// Translate a new chat message for the members that chose a chat language
+    ///
+    /// Every member receives a [WsMessage::ChatMessageTranslated] message with the
+    /// translation into their language. Failed translations are logged and skipped.
+    ///
+    /// **Parameter**:
+    /// - `recipients`: The members of the chatroom with their chat language
+    pub async fn deliver(
+        &self,
+        db: &Database,
+        notifier: &dyn Notifier,
+        chat_room: Uuid,
+        message: Uuid,
+        text: &str,
+        recipients: Vec<(Uuid, String)>,
+    ) {
+        let mut languages: HashMap<String, Vec<Uuid>> = HashMap::new();
+        for (member, language) in recipients {
+            languages.entry(language).or_default().push(member);
+        }
+
+        for (language, members) in languages {
+            let translation = match self.translate(db, message, text, &language).await {
+                Ok(translation) => translation,
+                Err(err) => {
+                    warn!("Could not translate message {message} to '{language}': {err}");
+                    continue;
+                }
+            };
+
+            let msg = WsMessage::ChatMessageTranslated {
+                chat_uuid: chat_room,
+                message_uuid: message,
+                language,
+                translation,
+            };
+            for member in members {
+                notifier.send(member, msg.clone()).await;
+            }
+        }
+    }
+}
+
+/// Translate a sent message in the background
+///
+/// Nothing happens, if the server doesn't support translations or nobody chose a chat language.
+///
+/// **Parameter**:
+/// - `recipients`: The members of the chatroom with their chat language
+pub fn spawn_translations(
+    translator: Arc<Option<Translator>>,
+    db: Arc<Database>,
+    notifier: Arc<dyn Notifier>,
+    chat_room: Uuid,
+    message: &ChatMessage,
+    recipients: Vec<(Uuid, String)>,
+) {
+    if translator.is_none() || recipients.is_empty() {
+        return;
+    }
+
+    let message_uuid = message.uuid;
+    let text = message.message.clone();
+    tokio::spawn(async move {
+        if let Some(translator) = translator.as_ref() {
+            translator
+                .deliver(
+                    &db,
+                    notifier.as_ref(),
+                    chat_room,
+                    message_uuid,
+                    &text,
+                    recipients,
+                )
+                .await;
+        }
+    });
+}
@@ -0,0 +1,82 @@
+//! The welcome message new accounts receive after their registration
+
+use rorm::db::Transaction;
+use rorm::fields::types::ForeignModelByField;
+use rorm::{insert, query, update, FieldAccess, Model};
+use uuid::Uuid;
+
+use crate::models::{Account, ChatRoomInsert, ChatRoomMemberInsert, WelcomeMessage};
+use crate::server::handler::ApiResult;
+use crate::service::chat::post_system_message;
+use crate::service::notify::NotificationSink;
+
+/// The maximum length of a chat message
+const MAX_MESSAGE_LENGTH: usize = 2048;
+
+/// Fill in the data of an account into the template of the welcome message
+///
+/// `{display_name}` and `{username}` are replaced. The result is truncated to the
+/// maximum length of a chat message.
+pub fn render_welcome_message(template: &str, username: &str, display_name: &str) -> String {
+    template
+        .replace("{display_name}", display_name)
+        .replace("{username}", username)
+        .chars()
+        .take(MAX_MESSAGE_LENGTH)
+        .collect()
+}
+
+/// Post the welcome message to a new chatroom of a freshly registered account
+///
+/// Nothing happens, if no welcome message is configured.
+/// The chatroom is stored as [Account::welcome_chat_room] of the account.
+pub async fn post_welcome_message(
+    tx: &mut Transaction,
+    notifications: &mut impl NotificationSink,
+    account: Uuid,
+    username: &str,
+    display_name: &str,
+) -> ApiResult<()> {
+    let Some((template,)) = query!(&mut *tx, (WelcomeMessage::F.message,))
+        .optional()
+        .await?
+    else {
+        return Ok(());
+    };
+
+    let chat_room = insert!(&mut *tx, ChatRoomInsert)
+        .return_primary_key()
+        .single(&ChatRoomInsert {
+            uuid: Uuid::new_v4(),
+            last_message_uuid: None,
+        })
+        .await?;
+
+    insert!(&mut *tx, ChatRoomMemberInsert)
+        .return_nothing()
+        .single(&ChatRoomMemberInsert {
+            uuid: Uuid::new_v4(),
+            chat_room: ForeignModelByField::Key(chat_room),
+            member: ForeignModelByField::Key(account),
+        })
+        .await?;
+
+    post_system_message(
+        tx,
+        notifications,
+        chat_room,
+        render_welcome_message(&template, username, display_name),
+    )
+    .await?;
+
+    update!(&mut *tx, Account)
+        .condition(Account::F.uuid.equals(account))
+        .set(
+            Account::F.welcome_chat_room,
+            Some(ForeignModelByField::Key(chat_room)),
+        )
+        .exec()
+        .await?;
+
+    Ok(())
+}
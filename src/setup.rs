@@ -0,0 +1,280 @@
+//! The interactive first-run setup of a server
+//!
+//! The setup creates the configuration file from `example.config.toml`, generates the
+//! secret key and the admin token, applies the database migrations, creates the first
+//! account and checks that the server can start with the new configuration.
+
+use std::fs::{create_dir_all, write};
+use std::io::{stdin, stdout, BufRead, Write};
+use std::net::{IpAddr, TcpListener};
+use std::path::Path;
+
+use actix_web::cookie::Key;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHasher};
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use clap::Args;
+use rand::{thread_rng, Rng};
+use rorm::{insert, query, update, FieldAccess, Model};
+use uuid::Uuid;
+
+use crate::config::{quote_config_value, set_config_value, Config, CONFIG_TEMPLATE};
+use crate::models::{Account, AccountInsert};
+use crate::server::handler::normalize_username;
+use crate::storage::{check_storage_version, write_storage_version, STORAGE_VERSION};
+
+/// The options of the setup
+///
+/// Options that are not passed are asked for interactively, unless `--non-interactive`
+/// is set, in which case their defaults are used. Passwords passed as options are visible
+/// to other users of the system in the process list.
+#[derive(Args)]
+pub struct SetupArgs {
+    /// Don't ask for missing options, fail if a required option is missing
+    #[clap(long)]
+    non_interactive: bool,
+    /// Overwrite an existing configuration file
+    #[clap(long)]
+    force: bool,
+    /// The directory where the migrations are located
+    #[clap(long, default_value_t = String::from("migrations"))]
+    migration_dir: String,
+    /// The address the server should bind to
+    #[clap(long)]
+    listen_address: Option<IpAddr>,
+    /// The port the server should bind to
+    #[clap(long)]
+    listen_port: Option<u16>,
+    /// The directory where to store game data files
+    #[clap(long)]
+    game_data_path: Option<String>,
+    /// Host the database is located on
+    #[clap(long)]
+    db_host: Option<String>,
+    /// Port the database is located on
+    #[clap(long)]
+    db_port: Option<u16>,
+    /// The name of the database
+    #[clap(long)]
+    db_name: Option<String>,
+    /// The username of the database user
+    #[clap(long)]
+    db_user: Option<String>,
+    /// The password of the database user
+    #[clap(long)]
+    db_password: Option<String>,
+    /// The username of the first account, no account is created if it is empty
+    #[clap(long)]
+    account_username: Option<String>,
+    /// The password of the first account
+    #[clap(long)]
+    account_password: Option<String>,
+}
+
+/// Ask for a value that wasn't passed as option
+///
+/// An empty answer selects the default, if there is one.
+fn prompt(
+    non_interactive: bool,
+    value: Option<String>,
+    question: &str,
+    default: Option<&str>,
+) -> Result<String, String> {
+    if let Some(value) = value {
+        return Ok(value);
+    }
+    if non_interactive {
+        return default
+            .map(str::to_string)
+            .ok_or(format!("Missing option for: {question}"));
+    }
+
+    loop {
+        match default {
+            Some(default) => print!("{question} [{default}]: "),
+            None => print!("{question}: "),
+        }
+        stdout()
+            .flush()
+            .map_err(|err| format!("Could not write to stdout: {err}"))?;
+
+        let mut answer = String::new();
+        stdin()
+            .lock()
+            .read_line(&mut answer)
+            .map_err(|err| format!("Could not read from stdin: {err}"))?;
+        let answer = answer.trim();
+
+        match (answer.is_empty(), default) {
+            (false, _) => return Ok(answer.to_string()),
+            (true, Some(default)) => return Ok(default.to_string()),
+            (true, None) => continue,
+        }
+    }
+}
+
+/// Run the setup
+pub async fn run_setup(config_path: &str, args: SetupArgs) -> Result<(), String> {
+    let SetupArgs {
+        non_interactive,
+        force,
+        migration_dir,
+        listen_address,
+        listen_port,
+        game_data_path,
+        db_host,
+        db_port,
+        db_name,
+        db_user,
+        db_password,
+        account_username,
+        account_password,
+    } = args;
+
+    if Path::new(config_path).exists() && !force {
+        return Err(format!(
+            "{config_path} already exists, pass --force to overwrite it"
+        ));
+    }
+
+    let ask = |value: Option<String>, question: &str, default: Option<&str>| {
+        prompt(non_interactive, value, question, default)
+    };
+
+    println!("Server");
+    let listen_address = ask(
+        listen_address.map(|x| x.to_string()),
+        "Listen address",
+        Some("127.0.0.1"),
+    )?;
+    let listen_port = ask(
+        listen_port.map(|x| x.to_string()),
+        "Listen port",
+        Some("8080"),
+    )?;
+    let game_data_path = ask(
+        game_data_path,
+        "Game data directory",
+        Some("/var/lib/runciv"),
+    )?;
+
+    println!("Database");
+    let db_host = ask(db_host, "Host", Some("127.0.0.1"))?;
+    let db_port = ask(db_port.map(|x| x.to_string()), "Port", Some("5432"))?;
+    let db_name = ask(db_name, "Name", Some("runciv"))?;
+    let db_user = ask(db_user, "User", Some("runciv"))?;
+    let db_password = ask(db_password, "Password (the input is shown)", None)?;
+
+    let secret_key = BASE64_STANDARD.encode(Key::generate().master());
+    let admin_token = hex::encode(thread_rng().gen::<[u8; 24]>());
+
+    let mut config = CONFIG_TEMPLATE.to_string();
+    for (section, key, value) in [
+        (
+            "Server",
+            "GameDataPath",
+            quote_config_value(&game_data_path),
+        ),
+        (
+            "Server",
+            "ListenAddress",
+            quote_config_value(&listen_address),
+        ),
+        ("Server", "ListenPort", listen_port),
+        ("Server", "SecretKey", quote_config_value(&secret_key)),
+        ("Server", "AdminToken", quote_config_value(&admin_token)),
+        ("Database", "Host", quote_config_value(&db_host)),
+        ("Database", "Port", db_port),
+        ("Database", "Name", quote_config_value(&db_name)),
+        ("Database", "User", quote_config_value(&db_user)),
+        ("Database", "Password", quote_config_value(&db_password)),
+    ] {
+        config = set_config_value(&config, section, key, &value);
+    }
+
+    let conf: Config =
+        toml::from_str(&config).map_err(|err| format!("Invalid configuration: {err}"))?;
+
+    if let Some(parent) = Path::new(config_path).parent() {
+        create_dir_all(parent).map_err(|err| format!("Could not create {parent:?}: {err}"))?;
+    }
+    write(config_path, &config).map_err(|err| format!("Could not write {config_path}: {err}"))?;
+    println!("Wrote configuration to {config_path}");
+
+    crate::migrate(&conf, migration_dir).await?;
+    println!("Applied database migrations");
+
+    let db = crate::get_db(&conf).await?;
+
+    println!("First account");
+    let username = ask(account_username, "Username, empty to skip", Some(""))?;
+    if !username.is_empty() {
+        let password = ask(account_password, "Password (the input is shown)", None)?;
+        create_account(&db, &username, &password).await?;
+        println!("Created account {username}, it may use the moderation endpoints");
+    }
+
+    let game_data = Path::new(&conf.server.game_data_path);
+    if game_data.exists() {
+        check_storage_version(game_data).map_err(|err| err.to_string())?;
+    } else {
+        create_dir_all(game_data)
+            .map_err(|err| format!("Could not create the game data directory: {err}"))?;
+        write_storage_version(game_data, STORAGE_VERSION).map_err(|err| err.to_string())?;
+    }
+
+    TcpListener::bind((conf.server.listen_address, conf.server.listen_port)).map_err(|err| {
+        format!(
+            "Could not bind to {}:{}: {err}",
+            conf.server.listen_address, conf.server.listen_port
+        )
+    })?;
+
+    println!("The server is ready to start with: runciv --config-path {config_path} start");
+    println!("The admin token is stored in the configuration file as AdminToken");
+
+    Ok(())
+}
+
+/// Create an account that may use the moderation endpoints
+async fn create_account(db: &rorm::Database, username: &str, password: &str) -> Result<(), String> {
+    let normalized_username = normalize_username(username);
+    if query!(db, (Account::F.uuid,))
+        .condition(Account::F.normalized_username.equals(&normalized_username))
+        .optional()
+        .await
+        .map_err(|err| err.to_string())?
+        .is_some()
+    {
+        return Err(format!("The account {username} already exists"));
+    }
+
+    let salt = SaltString::generate(&mut thread_rng());
+    let password_hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|err| err.to_string())?
+        .to_string();
+
+    let uuid = Uuid::new_v4();
+    insert!(db, AccountInsert)
+        .single(&AccountInsert {
+            uuid,
+            username: username.to_string(),
+            normalized_username,
+            display_name: username.to_string(),
+            password_hash,
+            last_login: None,
+        })
+        .await
+        .map_err(|err| err.to_string())?;
+
+    update!(db, Account)
+        .condition(Account::F.uuid.equals(uuid))
+        .set(Account::F.moderator, true)
+        .exec()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    Ok(())
+}
@@ -0,0 +1,202 @@
+//! Versioning of the layout of the game data directory
+//!
+//! The version of the layout is stored in the file [VERSION_FILE] in the game data directory.
+//! When the layout changes, [STORAGE_VERSION] is increased and a [FileMigration] is added,
+//! which converts the files of the previous version. The server refuses to start, if the
+//! directory has a different version than [STORAGE_VERSION], the files have to be converted
+//! with `runciv storage migrate` first.
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::fs::{read_dir, read_to_string, remove_file, write, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use log::info;
+
+/// The version of the layout of the game data directory this server uses
+pub const STORAGE_VERSION: u32 = 1;
+
+/// The file in the game data directory that holds the version of its layout
+pub const VERSION_FILE: &str = ".storage_version";
+
+/// A conversion of the game data files from one version of the layout to the next
+pub trait FileMigration {
+    /// The version the files are converted from, they have `from_version + 1` afterwards
+    fn from_version(&self) -> u32;
+
+    /// Convert a single file
+    ///
+    /// If the migration is interrupted right after a file was converted, the file is
+    /// converted again on resumption, so the conversion has to detect converted files.
+    fn migrate_file(&self, path: &Path) -> io::Result<()>;
+}
+
+/// The migrations between the versions, ordered by [FileMigration::from_version]
+const MIGRATIONS: &[&dyn FileMigration] = &[];
+
+/// The errors that can occur while checking or migrating the game data directory
+#[derive(Debug)]
+pub enum StorageError {
+    /// The directory or a file could not be accessed
+    Io(io::Error),
+    /// The version file doesn't contain a version
+    InvalidVersionFile(String),
+    /// The directory was written by a newer server
+    UnknownVersion(u32),
+    /// The directory has to be migrated
+    Outdated(u32),
+    /// There is no migration from the version
+    MissingMigration(u32),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Io(err) => write!(f, "Could not access the game data directory: {err}"),
+            StorageError::InvalidVersionFile(content) => {
+                write!(f, "Invalid content of {VERSION_FILE}: {content}")
+            }
+            StorageError::UnknownVersion(version) => write!(
+                f,
+                "The game data directory has the unknown storage version {version}, \
+                    this server supports version {STORAGE_VERSION}"
+            ),
+            StorageError::Outdated(version) => write!(
+                f,
+                "The game data directory has the storage version {version}, \
+                    run `runciv storage migrate` to convert it to version {STORAGE_VERSION}"
+            ),
+            StorageError::MissingMigration(version) => {
+                write!(f, "There is no migration from storage version {version}")
+            }
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Read the version of the layout of the game data directory
+///
+/// Directories without a version file were written before the layout was versioned,
+/// they have the version `1`.
+pub fn read_storage_version(path: &Path) -> Result<u32, StorageError> {
+    match read_to_string(path.join(VERSION_FILE)) {
+        Ok(content) => content
+            .trim()
+            .parse()
+            .map_err(|_| StorageError::InvalidVersionFile(content)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(1),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Write the version of the layout of the game data directory
+pub fn write_storage_version(path: &Path, version: u32) -> Result<(), StorageError> {
+    write(path.join(VERSION_FILE), format!("{version}\n"))?;
+    Ok(())
+}
+
+/// Check that the game data directory has the layout of this server
+///
+/// Directories that don't exist yet are created by the server with the current layout.
+pub fn check_storage_version(path: &Path) -> Result<(), StorageError> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    match read_storage_version(path)? {
+        STORAGE_VERSION => {
+            if !path.join(VERSION_FILE).exists() {
+                write_storage_version(path, STORAGE_VERSION)?;
+            }
+            Ok(())
+        }
+        version if version > STORAGE_VERSION => Err(StorageError::UnknownVersion(version)),
+        version => Err(StorageError::Outdated(version)),
+    }
+}
+
+/// Convert the game data directory to the layout of this server
+///
+/// The migrations are applied one after another. The files that were converted are recorded
+/// in a progress file, so an interrupted migration continues where it stopped.
+pub fn migrate_storage(path: &Path) -> Result<(), StorageError> {
+    let mut version = read_storage_version(path)?;
+    if version > STORAGE_VERSION {
+        return Err(StorageError::UnknownVersion(version));
+    }
+    if version == STORAGE_VERSION {
+        info!("The game data directory already has storage version {STORAGE_VERSION}");
+        return Ok(());
+    }
+
+    while version < STORAGE_VERSION {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.from_version() == version)
+            .ok_or(StorageError::MissingMigration(version))?;
+
+        run_migration(path, *migration)?;
+
+        version += 1;
+        write_storage_version(path, version)?;
+        info!("Migrated the game data directory to storage version {version}");
+    }
+
+    Ok(())
+}
+
+/// Apply a migration to all files of the game data directory
+fn run_migration(path: &Path, migration: &dyn FileMigration) -> Result<(), StorageError> {
+    let from = migration.from_version();
+    let progress_path = path.join(format!(".storage_migration_{from}"));
+
+    let done: HashSet<String> = match read_to_string(&progress_path) {
+        Ok(content) => content.lines().map(str::to_string).collect(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => HashSet::new(),
+        Err(err) => return Err(err.into()),
+    };
+    if !done.is_empty() {
+        info!(
+            "Resuming the migration from storage version {from}, {} files are already converted",
+            done.len()
+        );
+    }
+
+    let mut progress = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&progress_path)?;
+
+    let mut converted = 0;
+    for entry in read_dir(path)? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with('.') || !entry.file_type()?.is_file() || done.contains(&name) {
+            continue;
+        }
+
+        migration.migrate_file(&entry.path())?;
+
+        writeln!(progress, "{name}")?;
+        progress.sync_data()?;
+
+        converted += 1;
+        if converted % 1000 == 0 {
+            info!("Converted {converted} files");
+        }
+    }
+
+    drop(progress);
+    remove_file(progress_path)?;
+
+    Ok(())
+}
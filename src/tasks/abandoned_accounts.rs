@@ -0,0 +1,181 @@
+//! Flagging and cleanup of accounts that didn't log in for a long time
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHasher};
+use chrono::{NaiveDateTime, Utc};
+use log::{error, info};
+use rand::thread_rng;
+use rorm::{or, query, update, Database, FieldAccess, Model};
+use tokio::time::{interval, MissedTickBehavior};
+use uuid::Uuid;
+
+use crate::chan::Notifier;
+use crate::config::{AbandonedAccountAction, AbandonedAccountsConfig};
+use crate::models::{Account, Device, Friend, GameAccount};
+
+/// The interval in which abandoned accounts are searched
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Start the periodic cleanup of abandoned accounts
+///
+/// Accounts without a login for [AbandonedAccountsConfig::inactive_months] months are
+/// flagged as abandoned. If they don't log in within [AbandonedAccountsConfig::grace_days]
+/// days after the flagging, they are anonymized or deleted.
+///
+/// Players of running games and accounts with an open connection are exempt.
+/// Accounts that never logged in are not considered.
+///
+/// **Parameter**:
+/// - `db`: [Database]
+/// - `notifier`: [Notifier] : The transport to check the online state and close connections
+/// - `config`: The policy, the task is not started if it is not set
+pub fn start_abandoned_account_cleanup(
+    db: Database,
+    notifier: Arc<dyn Notifier>,
+    config: Option<AbandonedAccountsConfig>,
+) {
+    let Some(config) = config else {
+        info!("Cleanup of abandoned accounts is disabled");
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut timer = interval(CHECK_INTERVAL);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            timer.tick().await;
+
+            if let Err(err) = cleanup_abandoned_accounts(&db, notifier.as_ref(), &config).await {
+                error!("Error while cleaning up abandoned accounts: {err}");
+            }
+        }
+    });
+}
+
+/// The point in time an abandoned account is anonymized or deleted
+pub fn abandoned_account_deadline(
+    config: &AbandonedAccountsConfig,
+    abandoned_since: NaiveDateTime,
+) -> NaiveDateTime {
+    abandoned_since + chrono::Duration::days(config.grace_days as i64)
+}
+
+/// Flag inactive accounts and clean up the accounts whose grace period has expired
+async fn cleanup_abandoned_accounts(
+    db: &Database,
+    notifier: &dyn Notifier,
+    config: &AbandonedAccountsConfig,
+) -> Result<(), rorm::Error> {
+    let now = Utc::now().naive_utc();
+    let cutoff = now - chrono::Duration::days(config.inactive_months as i64 * 30);
+
+    let players: HashSet<Uuid> = query!(db, (GameAccount::F.player,))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(player,)| *player.key())
+        .collect();
+
+    let accounts = query!(
+        db,
+        (
+            Account::F.uuid,
+            Account::F.last_login,
+            Account::F.abandoned_since
+        )
+    )
+    .all()
+    .await?;
+
+    for (uuid, last_login, abandoned_since) in accounts {
+        let inactive = last_login.map_or(false, |last_login| last_login < cutoff);
+        let exempt =
+            !inactive || players.contains(&uuid) || notifier.is_online(uuid).await.unwrap_or(true);
+
+        match (abandoned_since, exempt) {
+            (Some(_), true) => {
+                update!(db, Account)
+                    .condition(Account::F.uuid.equals(uuid))
+                    .set(Account::F.abandoned_since, None)
+                    .exec()
+                    .await?;
+            }
+            (None, false) => {
+                info!("Flagged account {uuid} as abandoned");
+                update!(db, Account)
+                    .condition(Account::F.uuid.equals(uuid))
+                    .set(Account::F.abandoned_since, Some(now))
+                    .exec()
+                    .await?;
+            }
+            (Some(abandoned_since), false)
+                if abandoned_account_deadline(config, abandoned_since) <= now =>
+            {
+                match config.action {
+                    AbandonedAccountAction::Anonymize => anonymize_account(db, uuid).await?,
+                    AbandonedAccountAction::Delete => {
+                        rorm::delete!(db, Account)
+                            .condition(Account::F.uuid.equals(uuid))
+                            .await?;
+                        info!("Deleted abandoned account {uuid}");
+                    }
+                }
+                notifier.disconnect(uuid).await;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the personal data of an account and make the login impossible
+///
+/// The username and display name are replaced, the password is replaced by a random one.
+/// Friends and devices of the account are deleted.
+async fn anonymize_account(db: &Database, uuid: Uuid) -> Result<(), rorm::Error> {
+    let salt = SaltString::generate(&mut thread_rng());
+    let password_hash =
+        match Argon2::default().hash_password(Uuid::new_v4().to_string().as_bytes(), &salt) {
+            Ok(hash) => hash.to_string(),
+            Err(err) => {
+                error!("Could not anonymize account {uuid}: {err}");
+                return Ok(());
+            }
+        };
+
+    let username = format!("deleted-{}", uuid.simple());
+
+    let mut tx = db.start_transaction().await?;
+
+    update!(&mut tx, Account)
+        .condition(Account::F.uuid.equals(uuid))
+        .set(Account::F.username, username.clone())
+        .set(Account::F.normalized_username, username)
+        .set(Account::F.display_name, "Deleted account".to_string())
+        .set(Account::F.password_hash, password_hash)
+        .set(Account::F.chat_language, None)
+        .set(Account::F.abandoned_since, None)
+        .set(Account::F.last_login, None)
+        .exec()
+        .await?;
+
+    rorm::delete!(&mut tx, Friend)
+        .condition(or!(Friend::F.from.equals(uuid), Friend::F.to.equals(uuid)))
+        .await?;
+
+    rorm::delete!(&mut tx, Device)
+        .condition(Device::F.account.equals(uuid))
+        .await?;
+
+    tx.commit().await?;
+
+    info!("Anonymized abandoned account {uuid}");
+
+    Ok(())
+}
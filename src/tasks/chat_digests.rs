@@ -0,0 +1,44 @@
+//! Periodic sending of chat digests
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{error, info};
+use rorm::Database;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::chan::ChatDigests;
+
+/// Start the periodic sending of chat digests
+///
+/// Every `interval_secs` seconds, the digest settings of the chatroom members are reloaded
+/// and the collected messages are sent as [WsMessage::ChatDigest] messages.
+///
+/// If `interval_secs` is `0`, the task is not started.
+///
+/// **Parameter**:
+/// - `db`: [Database]
+/// - `digests`: [ChatDigests] : The notifier that collects the messages
+/// - `interval_secs`: The interval in seconds in which digests are sent
+///
+/// [WsMessage::ChatDigest]: crate::chan::WsMessage::ChatDigest
+pub fn start_chat_digests(db: Database, digests: Arc<ChatDigests>, interval_secs: u64) {
+    if interval_secs == 0 {
+        info!("Chat digests are disabled");
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut timer = interval(Duration::from_secs(interval_secs));
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            timer.tick().await;
+
+            if let Err(err) = digests.reload(&db).await {
+                error!("Could not load the digest settings of chat members: {err}");
+            }
+            digests.flush().await;
+        }
+    });
+}
@@ -0,0 +1,88 @@
+//! Deletion of chat messages after their retention period
+
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info};
+use rorm::{and, delete, query, Database, FieldAccess, Model};
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::config::ChatRetentionConfig;
+use crate::models::{ChatRoomMessage, Game};
+
+/// The interval in which expired chat messages are searched
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Start the periodic deletion of expired chat messages
+///
+/// Messages of the chatroom of a game without activity for
+/// [ChatRetentionConfig::game_chat_days] days are deleted once they are older than that
+/// as well. All other messages are deleted after [ChatRetentionConfig::message_days] days.
+///
+/// **Parameter**:
+/// - `db`: [Database]
+/// - `config`: The retention periods, the task is not started if it is not set
+pub fn start_chat_retention(db: Database, config: Option<ChatRetentionConfig>) {
+    let Some(config) = config else {
+        info!("Deletion of expired chat messages is disabled");
+        return;
+    };
+    if config.game_chat_days == 0 && config.message_days == 0 {
+        info!("Deletion of expired chat messages is disabled");
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut timer = interval(CHECK_INTERVAL);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            timer.tick().await;
+
+            if let Err(err) = delete_expired_messages(&db, &config).await {
+                error!("Error while deleting expired chat messages: {err}");
+            }
+        }
+    });
+}
+
+/// Delete all messages whose retention period has expired
+async fn delete_expired_messages(
+    db: &Database,
+    config: &ChatRetentionConfig,
+) -> Result<(), rorm::Error> {
+    let now = Utc::now().naive_utc();
+    let mut deleted = 0;
+
+    if config.game_chat_days != 0 {
+        let cutoff = now - chrono::Duration::days(config.game_chat_days as i64);
+
+        let chat_rooms = query!(db, (Game::F.chat_room,))
+            .condition(Game::F.updated_at.less_than(cutoff))
+            .all()
+            .await?;
+
+        for (chat_room,) in chat_rooms {
+            deleted += delete!(db, ChatRoomMessage)
+                .condition(and!(
+                    ChatRoomMessage::F.chat_room.equals(*chat_room.key()),
+                    ChatRoomMessage::F.created_at.less_than(cutoff)
+                ))
+                .await?;
+        }
+    }
+
+    if config.message_days != 0 {
+        let cutoff = now - chrono::Duration::days(config.message_days as i64);
+
+        deleted += delete!(db, ChatRoomMessage)
+            .condition(ChatRoomMessage::F.created_at.less_than(cutoff))
+            .await?;
+    }
+
+    if deleted > 0 {
+        info!("Deleted {deleted} expired chat messages");
+    }
+
+    Ok(())
+}
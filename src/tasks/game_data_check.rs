@@ -0,0 +1,278 @@
+//! Consistency check between the game table and the game data files
+
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use rorm::{and, query, update, Database, FieldAccess, Model};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::fs::{read_to_string, remove_file, write, File};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::{Game, GameSnapshot};
+use crate::server::handler::{game_data_filename, snapshot_filename};
+
+/// The result of the consistency check between the database and the game data files
+///
+/// `missing_games` are the games whose current game data file is missing or not readable.
+#[derive(Serialize, ToSchema, Clone, Debug)]
+pub struct GameDataCheck {
+    checked_at: DateTime<Utc>,
+    #[schema(example = 42)]
+    checked_games: u64,
+    missing_games: Vec<Uuid>,
+}
+
+/// Verify that the current game data file of every game exists and is readable
+///
+/// Games with missing files are logged. Games without an uploaded game state have no
+/// file yet and are skipped.
+///
+/// **Parameter**:
+/// - `db`: [Database]
+/// - `game_data_path`: The directory the game data files are stored in
+pub async fn check_game_data(db: &Database, game_data_path: &str) -> Result<GameDataCheck, String> {
+    info!("Checking game data files for consistency");
+
+    let games = query!(db, (Game::F.uuid, Game::F.data_id))
+        .condition(Game::F.data_id.greater_than(0))
+        .all()
+        .await
+        .map_err(|err| format!("Database error: {err}"))?;
+
+    let mut missing_games = vec![];
+    for (game_uuid, data_id) in &games {
+        let filename = game_data_filename(*game_uuid, *data_id);
+        let path = Path::new(game_data_path).join(&filename);
+        if let Err(err) = File::open(&path).await {
+            warn!("Game data of game {game_uuid} expected in '{filename}' is not readable: {err}");
+            missing_games.push(*game_uuid);
+        }
+    }
+
+    if missing_games.is_empty() {
+        info!("Game data files of all {} games are readable", games.len());
+    } else {
+        warn!(
+            "Game data files of {} of {} games are missing",
+            missing_games.len(),
+            games.len()
+        );
+    }
+
+    Ok(GameDataCheck {
+        checked_at: Utc::now(),
+        checked_games: games.len() as u64,
+        missing_games,
+    })
+}
+
+/// A game whose current game state was restored from a snapshot
+#[derive(Serialize, ToSchema, Clone, Debug)]
+pub struct RestoredGame {
+    game_uuid: Uuid,
+    snapshot_uuid: Uuid,
+    /// The state identifier of the restored game state
+    #[schema(example = 43)]
+    game_data_id: u64,
+}
+
+/// The result of the verification of the game data files
+///
+/// `missing_games` are the games whose current game data file is missing or not readable,
+/// `corrupt_games` are the games whose current game data file doesn't match its checksum.
+/// Games that were restored from a snapshot are part of `restored_games` as well.
+#[derive(Serialize, ToSchema, Clone, Debug)]
+pub struct GameDataVerification {
+    checked_at: DateTime<Utc>,
+    #[schema(example = 42)]
+    checked_games: u64,
+    missing_games: Vec<Uuid>,
+    corrupt_games: Vec<Uuid>,
+    restored_games: Vec<RestoredGame>,
+}
+
+impl GameDataVerification {
+    /// Check if every game state is intact, either from the start or after its restoration
+    pub fn is_intact(&self) -> bool {
+        self.missing_games.len() + self.corrupt_games.len() == self.restored_games.len()
+    }
+
+    /// Log a summary of the verification
+    fn log_summary(&self) {
+        if self.missing_games.is_empty() && self.corrupt_games.is_empty() {
+            info!(
+                "Game data files of all {} games are valid",
+                self.checked_games
+            );
+        } else {
+            warn!(
+                "Game data files of {} of {} games are missing and {} are corrupt, {} games were restored",
+                self.missing_games.len(),
+                self.checked_games,
+                self.corrupt_games.len(),
+                self.restored_games.len()
+            );
+        }
+    }
+}
+
+/// Verify the current game data file of every game against its stored checksum
+///
+/// Games uploaded before checksums were introduced are only checked for readability.
+/// Games without an uploaded game state have no file yet and are skipped.
+///
+/// If `restore` is set, missing or corrupt game states are restored from the most recent
+/// snapshot of the game whose file is intact. The restored state is stored with a new
+/// state identifier, connected players are not notified.
+///
+/// **Parameter**:
+/// - `db`: [Database]
+/// - `game_data_path`: The directory the game data files are stored in
+/// - `restore`: Whether to restore broken game states from snapshots
+pub async fn verify_game_data(
+    db: &Database,
+    game_data_path: &str,
+    restore: bool,
+) -> Result<GameDataVerification, String> {
+    info!("Verifying game data files");
+
+    let games = query!(db, (Game::F.uuid, Game::F.data_id, Game::F.data_checksum))
+        .condition(Game::F.data_id.greater_than(0))
+        .all()
+        .await
+        .map_err(|err| format!("Database error: {err}"))?;
+
+    let mut missing_games = vec![];
+    let mut corrupt_games = vec![];
+    let mut restored_games = vec![];
+    for (game_uuid, data_id, data_checksum) in &games {
+        let filename = game_data_filename(*game_uuid, *data_id);
+        match read_to_string(Path::new(game_data_path).join(&filename)).await {
+            Err(err) => {
+                warn!(
+                    "Game data of game {game_uuid} expected in '{filename}' is not readable: {err}"
+                );
+                missing_games.push(*game_uuid);
+            }
+            Ok(game_data) => {
+                let valid = data_checksum.as_ref().map_or(true, |expected| {
+                    *expected == hex::encode(Sha256::digest(game_data.as_bytes()))
+                });
+                if valid {
+                    continue;
+                }
+
+                warn!("Game data of game {game_uuid} in '{filename}' doesn't match its checksum");
+                corrupt_games.push(*game_uuid);
+            }
+        }
+
+        if restore {
+            match restore_from_snapshot(db, game_data_path, *game_uuid, *data_id).await {
+                Ok(Some(restored)) => restored_games.push(restored),
+                Ok(None) => warn!("No intact snapshot found to restore game {game_uuid}"),
+                Err(err) => error!("Could not restore game {game_uuid}: {err}"),
+            }
+        }
+    }
+
+    let verification = GameDataVerification {
+        checked_at: Utc::now(),
+        checked_games: games.len() as u64,
+        missing_games,
+        corrupt_games,
+        restored_games,
+    };
+    verification.log_summary();
+
+    Ok(verification)
+}
+
+/// Restore the game state of a game from its most recent intact snapshot
+///
+/// Returns `None` if the game has no snapshot whose file matches its checksum.
+async fn restore_from_snapshot(
+    db: &Database,
+    game_data_path: &str,
+    game_uuid: Uuid,
+    data_id: i64,
+) -> Result<Option<RestoredGame>, String> {
+    let snapshots = query!(db, (GameSnapshot::F.uuid, GameSnapshot::F.data_checksum))
+        .condition(GameSnapshot::F.game.equals(game_uuid))
+        .order_desc(GameSnapshot::F.created_at)
+        .all()
+        .await
+        .map_err(|err| format!("Database error: {err}"))?;
+
+    for (snapshot_uuid, snapshot_checksum) in snapshots {
+        let snapshot_filename = snapshot_filename(game_uuid, snapshot_uuid);
+        let Ok(game_data) =
+            read_to_string(Path::new(game_data_path).join(&snapshot_filename)).await
+        else {
+            continue;
+        };
+        let checksum = hex::encode(Sha256::digest(game_data.as_bytes()));
+        if snapshot_checksum.is_some_and(|expected| expected != checksum) {
+            continue;
+        }
+
+        let new_data_id = data_id + 1;
+        let new_filename = game_data_filename(game_uuid, new_data_id);
+        write(Path::new(game_data_path).join(&new_filename), &game_data)
+            .await
+            .map_err(|err| format!("Game data could not be saved to '{new_filename}': {err}"))?;
+
+        let mut tx = db
+            .start_transaction()
+            .await
+            .map_err(|err| format!("Database error: {err}"))?;
+
+        // The game could have been updated in the meantime
+        let unchanged = query!(&mut tx, (Game::F.uuid,))
+            .condition(and!(
+                Game::F.uuid.equals(game_uuid),
+                Game::F.data_id.equals(data_id)
+            ))
+            .optional()
+            .await
+            .map_err(|err| format!("Database error: {err}"))?
+            .is_some();
+        if !unchanged {
+            if let Err(err) = remove_file(Path::new(game_data_path).join(&new_filename)).await {
+                warn!("Unused data in '{new_filename}' could not be removed and may leak: {err}");
+            }
+            return Err("The game was updated during the verification".to_string());
+        }
+
+        update!(&mut tx, Game)
+            .condition(Game::F.uuid.equals(game_uuid))
+            .set(Game::F.data_id, new_data_id)
+            .set(Game::F.data_checksum, Some(checksum))
+            .exec()
+            .await
+            .map_err(|err| format!("Database error: {err}"))?;
+
+        tx.commit()
+            .await
+            .map_err(|err| format!("Database error: {err}"))?;
+
+        // The broken file isn't needed anymore, if it exists at all
+        let old_filename = game_data_filename(game_uuid, data_id);
+        if let Err(err) = remove_file(Path::new(game_data_path).join(&old_filename)).await {
+            debug!("Broken data in '{old_filename}' was not removed: {err}");
+        }
+
+        info!("Restored game {game_uuid} from snapshot {snapshot_uuid}");
+
+        return Ok(Some(RestoredGame {
+            game_uuid,
+            snapshot_uuid,
+            game_data_id: new_data_id as u64,
+        }));
+    }
+
+    Ok(None)
+}
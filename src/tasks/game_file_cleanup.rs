@@ -0,0 +1,164 @@
+//! Cleanup of game data files that don't belong to any game state
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use log::{debug, error, info, warn};
+use rorm::{query, Database, Model};
+use tokio::fs::{read_dir, remove_file};
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+use uuid::Uuid;
+
+use crate::models::{Game, GameSnapshot};
+
+/// Files younger than this are never removed, as they may belong to an upload
+/// whose transaction has not been committed yet
+const GRACE_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// Start the periodic cleanup of orphaned game data files
+///
+/// Game data is stored as `game_{uuid}_{data_id}.txt` in `game_data_path`.
+/// If the server crashes between writing a file and committing the database
+/// transaction (or between committing and removing the outdated file),
+/// files are left behind that don't match the current state of any game.
+/// The same applies to the files of snapshots of deleted games.
+/// This task scans the directory every `interval_secs` seconds and removes them.
+///
+/// If `interval_secs` is `0`, the task is not started.
+///
+/// **Parameter**:
+/// - `db`: [Database]
+/// - `game_data_path`: The directory the game data files are stored in
+/// - `interval_secs`: The interval between two scans in seconds
+pub fn start_game_file_cleanup(db: Database, game_data_path: String, interval_secs: u64) {
+    if interval_secs == 0 {
+        info!("Cleanup of orphaned game files is disabled");
+        return;
+    }
+
+    tokio::spawn(async move {
+        let period = Duration::from_secs(interval_secs);
+        let mut timer = interval_at(Instant::now() + period, period);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            timer.tick().await;
+
+            match cleanup_game_files(&db, Path::new(&game_data_path)).await {
+                Ok(0) => debug!("No orphaned game files found"),
+                Ok(removed) => info!("Removed {removed} orphaned game files"),
+                Err(err) => error!("Error while cleaning up orphaned game files: {err}"),
+            }
+        }
+    });
+}
+
+/// Remove all game data files in `game_data_path` that don't match the current
+/// `data_id` of an existing game and all snapshot files that don't belong to an
+/// existing snapshot.
+///
+/// Returns the number of removed files.
+async fn cleanup_game_files(db: &Database, game_data_path: &Path) -> Result<usize, String> {
+    let games: HashMap<Uuid, i64> = query!(db, (Game::F.uuid, Game::F.data_id))
+        .all()
+        .await
+        .map_err(|err| format!("Database error: {err}"))?
+        .into_iter()
+        .collect();
+
+    let snapshots: HashSet<Uuid> = query!(db, (GameSnapshot::F.uuid,))
+        .all()
+        .await
+        .map_err(|err| format!("Database error: {err}"))?
+        .into_iter()
+        .map(|(snapshot,)| snapshot)
+        .collect();
+
+    let mut entries = read_dir(game_data_path)
+        .await
+        .map_err(|err| format!("Could not read game data directory: {err}"))?;
+
+    let now = SystemTime::now();
+    let mut orphaned: Vec<PathBuf> = vec![];
+
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|err| format!("Could not read game data directory: {err}"))?
+    {
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+
+        if let Some((game_uuid, data_id)) = parse_game_file_name(file_name) {
+            if games.get(&game_uuid) == Some(&data_id) {
+                continue;
+            }
+        } else if let Some(snapshot_uuid) = parse_snapshot_file_name(file_name) {
+            if snapshots.contains(&snapshot_uuid) {
+                continue;
+            }
+        } else {
+            continue;
+        }
+
+        let metadata = match entry.metadata().await {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                warn!("Could not read metadata of {:?}: {err}", entry.path());
+                continue;
+            }
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+
+        // Skip files that could belong to an upload that is currently in progress
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok());
+        if !matches!(age, Some(age) if age >= GRACE_PERIOD) {
+            continue;
+        }
+
+        orphaned.push(entry.path());
+    }
+
+    let mut removed = 0;
+    for path in orphaned {
+        match remove_file(&path).await {
+            Ok(_) => {
+                debug!("Removed orphaned game file {path:?}");
+                removed += 1;
+            }
+            Err(err) => warn!("Could not remove orphaned game file {path:?}: {err}"),
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Parse a file name of the form `game_{uuid}_{data_id}.txt`
+fn parse_game_file_name(file_name: &str) -> Option<(Uuid, i64)> {
+    let (game_uuid, data_id) = file_name
+        .strip_prefix("game_")?
+        .strip_suffix(".txt")?
+        .rsplit_once('_')?;
+
+    Some((Uuid::parse_str(game_uuid).ok()?, data_id.parse().ok()?))
+}
+
+/// Parse a file name of the form `snapshot_{game_uuid}_{snapshot_uuid}.txt`
+///
+/// Returns the uuid of the snapshot.
+fn parse_snapshot_file_name(file_name: &str) -> Option<Uuid> {
+    let (_, snapshot_uuid) = file_name
+        .strip_prefix("snapshot_")?
+        .strip_suffix(".txt")?
+        .rsplit_once('_')?;
+
+    Uuid::parse_str(snapshot_uuid).ok()
+}
@@ -0,0 +1,138 @@
+//! Closing of lobbies that have been idle for too long
+
+use std::iter;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info};
+use rorm::{delete, query, Database, FieldAccess, Model};
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::chan::{LobbyListChange, Notifier, WsMessage};
+use crate::models::{ChatRoom, Lobby};
+use crate::service::lobby_list::announce;
+use crate::service::notify::Outbox;
+
+/// The interval in which idle lobbies are searched
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Start the periodic closing of idle lobbies
+///
+/// A lobby is idle, if no player joined it, no player changed their ready state and no
+/// message was sent to its chatroom for `timeout_secs` seconds. Idle lobbies are closed,
+/// regardless of the connection state of their owner. Every closing is recorded in the
+/// audit log with the owner, the players and the last activity of the lobby.
+/// The owner and all players of the lobby receive a
+/// [WsMessage::LobbyClosed] message, the connections that subscribed to the lobby list
+/// a [WsMessage::LobbyListUpdated] message.
+///
+/// If `timeout_secs` is `0`, the task is not started.
+///
+/// **Parameter**:
+/// - `db`: [Database]
+/// - `notifier`: [Notifier] : The transport to notify the members of closed lobbies
+/// - `timeout_secs`: The time in seconds after which an idle lobby is closed
+pub fn start_lobby_idle_timeout(db: Database, notifier: Arc<dyn Notifier>, timeout_secs: u64) {
+    if timeout_secs == 0 {
+        info!("Closing of idle lobbies is disabled");
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut timer = interval(CHECK_INTERVAL);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            timer.tick().await;
+
+            if let Err(err) = close_idle_lobbies(&db, notifier.as_ref(), timeout_secs).await {
+                error!("Error while closing idle lobbies: {err}");
+            }
+        }
+    });
+}
+
+/// Close all lobbies without activity in the last `timeout_secs` seconds
+async fn close_idle_lobbies(
+    db: &Database,
+    notifier: &dyn Notifier,
+    timeout_secs: u64,
+) -> Result<(), rorm::Error> {
+    let cutoff = Utc::now().naive_utc() - chrono::Duration::seconds(timeout_secs as i64);
+
+    let mut tx = db.start_transaction().await?;
+
+    let mut lobbies = query!(&mut tx, Lobby)
+        .condition(Lobby::F.last_activity.less_than(cutoff))
+        .all()
+        .await?;
+
+    if lobbies.is_empty() {
+        return tx.commit().await;
+    }
+
+    Lobby::F
+        .current_player
+        .populate_bulk(&mut tx, &mut lobbies)
+        .await?;
+
+    let mut outbox = Outbox::new();
+    for lobby in &lobbies {
+        // Ok as current_player is populated before
+        #[allow(clippy::unwrap_used)]
+        let players: Vec<_> = lobby
+            .current_player
+            .cached
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|x| x.player.key().to_string())
+            .collect();
+        info!(
+            "Closing lobby {lobby} of owner {owner} with the players [{players}] as it has been \
+             idle since {last_activity}",
+            lobby = lobby.uuid,
+            owner = lobby.owner.key(),
+            players = players.join(", "),
+            last_activity = lobby.last_activity,
+        );
+
+        announce(&mut tx, &mut outbox, lobby.uuid, LobbyListChange::Closed).await?;
+
+        delete!(&mut tx, ChatRoom)
+            .condition(ChatRoom::F.uuid.equals(*lobby.chat_room.key()))
+            .await?;
+
+        delete!(&mut tx, Lobby)
+            .condition(Lobby::F.uuid.equals(lobby.uuid))
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    outbox.send(notifier).await;
+
+    for lobby in lobbies {
+        let msg = WsMessage::LobbyClosed {
+            lobby_uuid: lobby.uuid,
+        };
+
+        // Ok as current_player is populated before
+        #[allow(clippy::unwrap_used)]
+        let players = iter::once(*lobby.owner.key()).chain(
+            lobby
+                .current_player
+                .cached
+                .unwrap()
+                .into_iter()
+                .map(|x| *x.player.key()),
+        );
+
+        for player in players {
+            notifier.send(player, msg.clone()).await;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,15 @@
+//! This module holds periodic maintenance tasks that run alongside the server
+
+pub use abandoned_accounts::*;
+pub use chat_digests::*;
+pub use chat_retention::*;
+pub use game_data_check::*;
+pub use game_file_cleanup::*;
+pub use lobby_idle_timeout::*;
+
+mod abandoned_accounts;
+mod chat_digests;
+mod chat_retention;
+mod game_data_check;
+mod game_file_cleanup;
+mod lobby_idle_timeout;